@@ -0,0 +1,853 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::camera::CameraModel;
+use crate::geometry::SE3;
+use crate::imu::{ImuBias, Preintegrated};
+use crate::linalg::{DMatrix, Mat3, Mat6, Matrix, Vec2, Vec3, Vec6, Vector};
+use crate::map::{KeyFrameId, Map, MapPointId, SharedMap};
+use crate::optimizer::{damped_step, Huber, CHI2_MONO};
+
+/// Observation of point `point` by camera `pose` of a `BundleProblem`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BundleObservation {
+    pub pose: usize,
+    pub point: usize,
+    pub pixel: Vec2,
+    /// Inverse variance of the keypoint position, from its octave
+    pub inv_sigma2: f64
+}
+
+/// World to camera poses and world points observed by them. Fixed poses
+/// constrain the points but are not optimised.
+pub struct BundleProblem {
+    pub poses: Vec<SE3>,
+    pub fixed: Vec<bool>,
+    pub cameras: Vec<Arc<dyn CameraModel>>,
+    pub points: Vec<Vec3>,
+    pub observations: Vec<BundleObservation>,
+    /// Inertial constraints between the poses of visual-inertial maps
+    pub inertial: Option<InertialTerms>
+}
+
+/// Body velocity in world coordinates and biases at a pose of a
+/// `BundleProblem`, optimised with it unless the pose is fixed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InertialState {
+    pub velocity: Vec3,
+    pub bias: ImuBias
+}
+
+/// Preintegrated motion from pose `from` to pose `to`, evaluated with the
+/// biases of `from`. The change of the biases between them is constrained
+/// by their random walk over the integrated time.
+#[derive(Clone, Debug)]
+pub struct InertialEdge {
+    pub from: usize,
+    pub to: usize,
+    pub preintegration: Preintegrated
+}
+
+/// Inertial part of a problem, as in ORB-SLAM3's visual-inertial bundle
+/// adjustment. `states[i]` is the state of pose `i`, and edges join poses
+/// with a state.
+#[derive(Clone, Debug)]
+pub struct InertialTerms {
+    pub gravity: Vec3,
+    pub states: Vec<Option<InertialState>>,
+    pub edges: Vec<InertialEdge>
+}
+
+impl InertialTerms {
+    /// Whitened squared error of the edges for the given poses and states
+    pub fn chi2(&self, poses: &[SE3], states: &[Option<InertialState>]) -> f64 {
+        self.edges.iter()
+            .filter_map(|edge| {
+                let (i, j) = (states[edge.from].as_ref()?, states[edge.to].as_ref()?);
+                let preintegration = &edge.preintegration;
+                let walk = bias_change(&i.bias, &j.bias);
+
+                Some(
+                    preintegration.chi2(&poses[edge.from], &i.velocity, &poses[edge.to], &j.velocity, &i.bias, &self.gravity) +
+                        (walk.transpose() * preintegration.walk_information() * walk)[(0, 0)]
+                )
+            })
+            .sum()
+    }
+
+    /// Gauss-Newton blocks of the edges at the given poses and states, over
+    /// the system whose first `6 * free_count` unknowns are the free poses,
+    /// followed by the velocity and biases of each free state
+    fn normal_equations(
+        &self,
+        poses: &[SE3],
+        states: &[Option<InertialState>],
+        slots: &[Option<usize>],
+        state_slots: &[Option<usize>],
+        free_count: usize
+    ) -> (DMatrix, Vec<f64>) {
+        let dimension = 6 * free_count + 9 * state_slots.iter().flatten().count();
+        let mut hessian = DMatrix::zeros(dimension, dimension);
+        let mut gradient = vec![0.0; dimension];
+
+        let span = |start: Option<usize>, len: usize| (0..len).map(move |k| start.map(|start| start + k));
+        let pose = |i: usize| slots[i].map(|slot| 6 * slot);
+        let state = |i: usize| state_slots[i].map(|slot| 6 * free_count + 9 * slot);
+
+        for edge in &self.edges {
+            let (Some(i), Some(j)) = (&states[edge.from], &states[edge.to]) else {
+                continue;
+            };
+
+            let preintegration = &edge.preintegration;
+            let linear = preintegration.linearize(&poses[edge.from], &i.velocity, &poses[edge.to], &j.velocity, &i.bias, &self.gravity);
+
+            // Unknowns `[pose_i, velocity_i, bias_i, pose_j, velocity_j]`
+            let mut jacobian = Matrix::<9, 24>::zeros();
+            jacobian.set_block(0, 0, &linear.pose_i);
+            jacobian.set_block(0, 6, &linear.velocity_i);
+            jacobian.set_block(0, 9, &linear.bias);
+            jacobian.set_block(0, 15, &linear.pose_j);
+            jacobian.set_block(0, 21, &linear.velocity_j);
+
+            let columns: Vec<Option<usize>> = span(pose(edge.from), 6)
+                .chain(span(state(edge.from), 9))
+                .chain(span(pose(edge.to), 6))
+                .chain(span(state(edge.to), 3))
+                .collect();
+
+            accumulate(&mut hessian, &mut gradient, &jacobian, &preintegration.information(), &linear.residual, &columns);
+
+            // Unknowns `[bias_i, bias_j]`
+            let mut jacobian = Matrix::<6, 12>::zeros();
+            jacobian.set_block(0, 0, &(-Mat6::identity()));
+            jacobian.set_block(0, 6, &Mat6::identity());
+
+            let columns: Vec<Option<usize>> = span(state(edge.from).map(|start| start + 3), 6)
+                .chain(span(state(edge.to).map(|start| start + 3), 6))
+                .collect();
+
+            accumulate(&mut hessian, &mut gradient, &jacobian, &preintegration.walk_information(), &bias_change(&i.bias, &j.bias), &columns);
+        }
+
+        (hessian, gradient)
+    }
+}
+
+/// Change `[gyro, accel]` from bias `i` to bias `j`
+fn bias_change(i: &ImuBias, j: &ImuBias) -> Vec6 {
+    let mut change = Vec6::zeros();
+    change.set_segment(0, &(j.gyro - i.gyro));
+    change.set_segment(3, &(j.accel - i.accel));
+    change
+}
+
+/// Adds `J^T W J` and `J^T W e` of a residual to the unknowns `columns` of
+/// the system, skipping columns of fixed unknowns
+fn accumulate<const R: usize, const C: usize>(
+    hessian: &mut DMatrix,
+    gradient: &mut [f64],
+    jacobian: &Matrix<R, C>,
+    information: &Matrix<R, R>,
+    residual: &Vector<R>,
+    columns: &[Option<usize>]
+) {
+    let jacobian_t = jacobian.transpose() * *information;
+    let (block, rhs) = (jacobian_t * *jacobian, jacobian_t * *residual);
+
+    for (a, row) in columns.iter().enumerate() {
+        let Some(row) = *row else {
+            continue;
+        };
+
+        gradient[row] += rhs[a];
+
+        for (b, column) in columns.iter().enumerate() {
+            if let Some(column) = *column {
+                hessian[(row, column)] += block[(a, b)];
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BundleSummary {
+    pub initial_cost: f64,
+    pub final_cost: f64,
+    /// Iterations whose step was accepted
+    pub iterations: usize
+}
+
+#[derive(Clone, Debug)]
+pub struct BundleAdjustmentConfig {
+    /// Iterations with the robust kernel, after which outliers are removed
+    pub robust_iterations: usize,
+    /// Iterations without the kernel over the remaining observations
+    pub iterations: usize,
+    /// Observations with a larger whitened squared error are outliers
+    pub chi2_threshold: f64,
+    pub kernel: Huber
+}
+
+impl Default for BundleAdjustmentConfig {
+    fn default() -> Self {
+        // Values used by ORB-SLAM's local bundle adjustment
+        Self {
+            robust_iterations: 5,
+            iterations: 10,
+            chi2_threshold: CHI2_MONO,
+            kernel: Huber { delta: CHI2_MONO.sqrt() }
+        }
+    }
+}
+
+/// Reprojection error of an observation and its derivatives with respect
+/// to the left perturbation `[omega, v]` of the pose and to the point
+struct Linearization {
+    residual: Vec2,
+    pose_jacobian: Matrix<2, 6>,
+    point_jacobian: Matrix<2, 3>
+}
+
+/// Blocks of the Gauss-Newton normal equations of a problem, weighted by
+/// the inverse variances and the robust kernel. Outliers and observations
+/// behind their camera contribute nothing.
+pub struct NormalEquations {
+    /// Reprojection error of each observation, `None` behind the camera
+    pub residuals: Vec<Option<Vec2>>,
+    /// `J^T W J` and `J^T W e` of each pose, fixed poses included
+    pub pose_blocks: Vec<Mat6>,
+    pub pose_gradients: Vec<Vec6>,
+    pub point_blocks: Vec<Mat3>,
+    pub point_gradients: Vec<Vec3>,
+    /// Pose-point block `J_pose^T W J_point` of each contributing observation
+    pub coupling: Vec<Option<Matrix<6, 3>>>
+}
+
+/// Evaluates the normal equations of a bundle adjustment problem. The
+/// solver only depends on this step, so it can run on the GPU with
+/// `BundleProgram`.
+pub trait Linearizer {
+    fn normal_equations(&self, problem: &BundleProblem, outliers: &[bool], kernel: Option<Huber>) -> NormalEquations;
+}
+
+/// Reference implementation of `Linearizer`
+pub struct CpuLinearizer;
+
+impl Linearizer for CpuLinearizer {
+    fn normal_equations(&self, problem: &BundleProblem, outliers: &[bool], kernel: Option<Huber>) -> NormalEquations {
+        let mut equations = NormalEquations {
+            residuals: vec![None; problem.observations.len()],
+            pose_blocks: vec![Mat6::zeros(); problem.poses.len()],
+            pose_gradients: vec![Vec6::zeros(); problem.poses.len()],
+            point_blocks: vec![Mat3::zeros(); problem.points.len()],
+            point_gradients: vec![Vec3::zeros(); problem.points.len()],
+            coupling: vec![None; problem.observations.len()]
+        };
+
+        for (i, observation) in problem.observations.iter().enumerate() {
+            let Some(linear) = problem.linearize(observation) else {
+                continue;
+            };
+
+            equations.residuals[i] = Some(linear.residual);
+
+            if outliers[i] {
+                continue;
+            }
+
+            let chi2 = linear.residual.norm_squared() * observation.inv_sigma2;
+            let weight = observation.inv_sigma2 * kernel.map_or(1.0, |k| k.weight(chi2));
+
+            let point_jacobian_t = linear.point_jacobian.transpose() * weight;
+            equations.point_blocks[observation.point] += point_jacobian_t * linear.point_jacobian;
+            equations.point_gradients[observation.point] += point_jacobian_t * linear.residual;
+
+            let pose_jacobian_t = linear.pose_jacobian.transpose() * weight;
+            equations.pose_blocks[observation.pose] += pose_jacobian_t * linear.pose_jacobian;
+            equations.pose_gradients[observation.pose] += pose_jacobian_t * linear.residual;
+            equations.coupling[i] = Some(pose_jacobian_t * linear.point_jacobian);
+        }
+
+        equations
+    }
+}
+
+impl BundleProblem {
+    /// Whitened squared reprojection error, infinite for points behind
+    /// the camera
+    pub fn chi2(&self, observation: &BundleObservation) -> f64 {
+        chi2(&self.poses, &self.points, &self.cameras, observation)
+    }
+
+    fn linearize(&self, observation: &BundleObservation) -> Option<Linearization> {
+        let pose = &self.poses[observation.pose];
+        let camera = &self.cameras[observation.pose];
+        let point = pose.transform(&self.points[observation.point]);
+
+        if point.z() <= 0.0 {
+            return None;
+        }
+
+        let projection = camera.project_jacobian(&point);
+
+        let mut motion = Matrix::<3, 6>::zeros();
+        motion.set_block(0, 0, &(-point.hat()));
+        motion.set_block(0, 3, &Mat3::identity());
+
+        Some(Linearization {
+            residual: camera.project(&point) - observation.pixel,
+            pose_jacobian: projection * motion,
+            point_jacobian: projection * pose.rotation.matrix()
+        })
+    }
+}
+
+fn chi2(poses: &[SE3], points: &[Vec3], cameras: &[Arc<dyn CameraModel>], observation: &BundleObservation) -> f64 {
+    let point = poses[observation.pose].transform(&points[observation.point]);
+
+    if point.z() <= 0.0 {
+        return f64::INFINITY;
+    }
+
+    (cameras[observation.pose].project(&point) - observation.pixel).norm_squared() * observation.inv_sigma2
+}
+
+/// Sparse Levenberg-Marquardt over the poses and points of a problem,
+/// ignoring observations marked as outliers. Each iteration marginalises
+/// the points with the Schur complement, solves the reduced camera system
+/// for the free poses and back-substitutes the point updates. The normal
+/// equations come from `linearizer`. Inertial terms join the velocities
+/// and biases of free poses to the reduced system. Stops early when
+/// `abort` is set.
+pub fn solve_bundle(
+    linearizer: &dyn Linearizer,
+    problem: &mut BundleProblem,
+    outliers: &[bool],
+    kernel: Option<Huber>,
+    iterations: usize,
+    abort: &AtomicBool
+) -> BundleSummary {
+    assert_eq!(outliers.len(), problem.observations.len());
+
+    // Slot of each free pose in the reduced camera system
+    let mut slots = vec![None; problem.poses.len()];
+    let mut free_count = 0;
+
+    for (slot, &fixed) in slots.iter_mut().zip(&problem.fixed) {
+        if !fixed {
+            *slot = Some(free_count);
+            free_count += 1;
+        }
+    }
+
+    // Slot of each free inertial state, after the free poses
+    let mut state_slots = vec![None; problem.poses.len()];
+    let mut state_count = 0;
+
+    // Taken out while solving, with the states updated separately
+    let inertial = problem.inertial.take();
+    let mut states = inertial.as_ref().map_or(Vec::new(), |inertial| inertial.states.clone());
+
+    if let Some(inertial) = &inertial {
+        for (slot, (state, pose_slot)) in state_slots.iter_mut().zip(inertial.states.iter().zip(&slots)) {
+            if state.is_some() && pose_slot.is_some() {
+                *slot = Some(state_count);
+                state_count += 1;
+            }
+        }
+    }
+
+    let mut point_observations = vec![Vec::new(); problem.points.len()];
+
+    for (i, observation) in problem.observations.iter().enumerate() {
+        if !outliers[i] {
+            point_observations[observation.point].push(i);
+        }
+    }
+
+    let cost = |poses: &[SE3], points: &[Vec3], states: &[Option<InertialState>]| -> f64 {
+        let visual: f64 = problem.observations.iter()
+            .zip(outliers)
+            .filter(|(_, &outlier)| !outlier)
+            .map(|(observation, _)| {
+                let chi2 = chi2(poses, points, &problem.cameras, observation).min(1e12);
+                kernel.map_or(chi2, |k| k.cost(chi2))
+            })
+            .sum();
+
+        visual + inertial.as_ref().map_or(0.0, |inertial| inertial.chi2(poses, states))
+    };
+
+    let initial_cost = cost(&problem.poses, &problem.points, &states);
+    let mut current_cost = initial_cost;
+    let mut accepted = 0;
+    let mut lambda = 1e-5;
+
+    for _ in 0..iterations {
+        if abort.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let equations = linearizer.normal_equations(problem, outliers, kernel);
+        let inertial_equations = inertial.as_ref()
+            .map(|inertial| inertial.normal_equations(&problem.poses, &states, &slots, &state_slots, free_count));
+
+        let solve = |lambda: f64| {
+            let steps = schur_step(
+                problem, &slots, free_count, state_count, &point_observations, &equations, inertial_equations.as_ref(), lambda
+            )?;
+
+            let (pose_steps, point_steps, state_steps) = &steps;
+            let norm_squared = pose_steps.iter().map(|s| s.norm_squared()).sum::<f64>() +
+                point_steps.iter().map(|s| s.norm_squared()).sum::<f64>() +
+                state_steps.iter().map(|s| s.norm_squared()).sum::<f64>();
+
+            Some((steps, norm_squared))
+        };
+
+        let apply = |(pose_steps, point_steps, state_steps): &(Vec<Vec6>, Vec<Vec3>, Vec<Vector<9>>)| {
+            let poses: Vec<SE3> = problem.poses.iter()
+                .zip(&slots)
+                .map(|(pose, slot)| slot.map_or(*pose, |slot| pose.retract(&pose_steps[slot])))
+                .collect();
+
+            let points: Vec<Vec3> = problem.points.iter()
+                .zip(point_steps)
+                .map(|(point, step)| *point + *step)
+                .collect();
+
+            let states: Vec<Option<InertialState>> = states.iter()
+                .zip(&state_slots)
+                .map(|(state, slot)| match (state, slot) {
+                    (Some(state), Some(slot)) => Some(retract_state(state, &state_steps[*slot])),
+                    _ => *state
+                })
+                .collect();
+
+            (poses, points, states)
+        };
+
+        let Some(step) = damped_step(&mut lambda, current_cost, solve, apply, |(poses, points, states)| cost(poses, points, states)) else {
+            break;
+        };
+
+        (problem.poses, problem.points, states) = step.candidate;
+        current_cost = step.cost;
+        accepted += 1;
+
+        if step.converged {
+            break;
+        }
+    }
+
+    problem.inertial = inertial.map(|inertial| InertialTerms { states, ..inertial });
+
+    BundleSummary { initial_cost, final_cost: current_cost, iterations: accepted }
+}
+
+/// State with the velocity and biases moved by `step`, ordered as
+/// `[velocity, gyro, accel]`
+fn retract_state(state: &InertialState, step: &Vector<9>) -> InertialState {
+    InertialState {
+        velocity: state.velocity + step.segment::<3>(0),
+        bias: ImuBias { gyro: state.bias.gyro + step.segment::<3>(3), accel: state.bias.accel + step.segment::<3>(6) }
+    }
+}
+
+/// Damped Gauss-Newton step for all free poses, points and inertial states
+#[allow(clippy::too_many_arguments)]
+fn schur_step(
+    problem: &BundleProblem,
+    slots: &[Option<usize>],
+    free_count: usize,
+    state_count: usize,
+    point_observations: &[Vec<usize>],
+    equations: &NormalEquations,
+    inertial: Option<&(DMatrix, Vec<f64>)>,
+    lambda: f64
+) -> Option<(Vec<Vec6>, Vec<Vec3>, Vec<Vector<9>>)> {
+    let dimension = 6 * free_count + 9 * state_count;
+    let mut reduced = DMatrix::zeros(dimension, dimension);
+    let mut rhs = vec![0.0; dimension];
+
+    for (pose, slot) in slots.iter().enumerate() {
+        let Some(slot) = *slot else {
+            continue;
+        };
+
+        let (block, gradient) = (&equations.pose_blocks[pose], &equations.pose_gradients[pose]);
+
+        for r in 0..6 {
+            for c in 0..6 {
+                reduced[(6 * slot + r, 6 * slot + c)] = block[(r, c)];
+            }
+            rhs[6 * slot + r] = -gradient[r];
+        }
+    }
+
+    if let Some((hessian, gradient)) = inertial {
+        for (value, inertial) in reduced.data.iter_mut().zip(&hessian.data) {
+            *value += inertial;
+        }
+
+        for (value, inertial) in rhs.iter_mut().zip(gradient) {
+            *value -= inertial;
+        }
+    }
+
+    for k in 0..dimension {
+        reduced[(k, k)] += lambda * reduced[(k, k)].max(1e-9);
+    }
+
+    // Inverse of each damped point block, `None` for unconstrained points
+    let point_inverses: Vec<Option<Mat3>> = equations.point_blocks.iter()
+        .zip(point_observations)
+        .map(|(block, observations)| {
+            if observations.is_empty() {
+                return None;
+            }
+
+            let mut damped = *block;
+            for k in 0..3 {
+                damped[(k, k)] += lambda * block[(k, k)].max(1e-9);
+            }
+
+            damped.try_inverse()
+        })
+        .collect();
+
+    for (point, observations) in point_observations.iter().enumerate() {
+        let Some(inverse) = point_inverses[point] else {
+            continue;
+        };
+
+        let coupled: Vec<(usize, Matrix<6, 3>)> = observations.iter()
+            .filter_map(|&i| Some((slots[problem.observations[i].pose]?, equations.coupling[i]?)))
+            .collect();
+
+        for &(a, w_a) in &coupled {
+            let w_a_inverse = w_a * inverse;
+            let correction = w_a_inverse * equations.point_gradients[point];
+
+            for r in 0..6 {
+                rhs[6 * a + r] += correction[r];
+            }
+
+            for &(b, w_b) in &coupled {
+                let block = w_a_inverse * w_b.transpose();
+
+                for r in 0..6 {
+                    for c in 0..6 {
+                        reduced[(6 * a + r, 6 * b + c)] -= block[(r, c)];
+                    }
+                }
+            }
+        }
+    }
+
+    let solution = if dimension > 0 { reduced.cholesky_solve(&rhs)? } else { Vec::new() };
+
+    let pose_steps: Vec<Vec6> = (0..free_count)
+        .map(|slot| Vec6::from_array(std::array::from_fn(|r| solution[6 * slot + r])))
+        .collect();
+
+    let state_steps: Vec<Vector<9>> = (0..state_count)
+        .map(|slot| Vector::<9>::from_array(std::array::from_fn(|r| solution[6 * free_count + 9 * slot + r])))
+        .collect();
+
+    let point_steps: Vec<Vec3> = point_observations.iter()
+        .enumerate()
+        .map(|(point, observations)| {
+            let Some(inverse) = point_inverses[point] else {
+                return Vec3::zeros();
+            };
+
+            let mut rhs = -equations.point_gradients[point];
+
+            for &i in observations {
+                if let (Some(slot), Some(w)) = (slots[problem.observations[i].pose], equations.coupling[i]) {
+                    rhs -= w.transpose() * pose_steps[slot];
+                }
+            }
+
+            inverse * rhs
+        })
+        .collect();
+
+    let finite = pose_steps.iter().all(|s| s.is_finite()) &&
+        point_steps.iter().all(|s| s.is_finite()) &&
+        state_steps.iter().all(|s| s.is_finite());
+    finite.then_some((pose_steps, point_steps, state_steps))
+}
+
+/// Bundle adjustment with ORB-SLAM's schedule: robust iterations, removal
+/// of outliers and points behind the cameras, then plain iterations over
+/// the remaining observations. Returns the outlier flags after a final
+/// classification.
+pub fn adjust_bundle(
+    config: &BundleAdjustmentConfig,
+    linearizer: &dyn Linearizer,
+    problem: &mut BundleProblem,
+    abort: &AtomicBool
+) -> Vec<bool> {
+    let mut outliers = vec![false; problem.observations.len()];
+
+    solve_bundle(linearizer, problem, &outliers, Some(config.kernel), config.robust_iterations, abort);
+
+    if abort.load(Ordering::SeqCst) {
+        return outliers;
+    }
+
+    for (outlier, observation) in outliers.iter_mut().zip(&problem.observations) {
+        *outlier = problem.chi2(observation) > config.chi2_threshold;
+    }
+
+    solve_bundle(linearizer, problem, &outliers, None, config.iterations, abort);
+
+    problem.observations.iter()
+        .map(|observation| problem.chi2(observation) > config.chi2_threshold)
+        .collect()
+}
+
+/// World to camera poses and positions of the given keyframes and every
+/// point they observe, with the other keyframes observing those points
+/// added as fixed poses. The origin is always fixed. Once the map has a
+/// gravity, given keyframes with a preintegration are joined to their
+/// predecessor in the inertial chain, added as a fixed pose if needed.
+/// Returns the problem with the keyframe and point id of each pose and
+/// point.
+fn map_problem(map: &Map, keyframes: &BTreeSet<KeyFrameId>) -> (BundleProblem, Vec<KeyFrameId>, Vec<MapPointId>) {
+    let point_ids: Vec<MapPointId> = keyframes.iter()
+        .filter_map(|&id| map.keyframe(id))
+        .flat_map(|kf| kf.frame.map_points.iter().flatten().copied())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let mut keyframe_ids: Vec<KeyFrameId> = keyframes.iter().copied().filter(|&id| map.keyframe(id).is_some()).collect();
+    let mut slots: BTreeMap<KeyFrameId, usize> = keyframe_ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+
+    let mut problem = BundleProblem {
+        poses: Vec::new(),
+        fixed: Vec::new(),
+        cameras: Vec::new(),
+        points: Vec::new(),
+        observations: Vec::new(),
+        inertial: None
+    };
+
+    for &id in &keyframe_ids {
+        let frame = &map.keyframe(id).unwrap().frame;
+        problem.poses.push(frame.pose.unwrap());
+        problem.fixed.push(map.origin() == Some(id));
+        problem.cameras.push(frame.camera.clone());
+    }
+
+    for (index, &point_id) in point_ids.iter().enumerate() {
+        let point = map.point(point_id).unwrap();
+        problem.points.push(point.position);
+
+        for (&observer, &keypoint) in &point.observations {
+            let Some(observer_frame) = map.keyframe(observer).map(|kf| &kf.frame) else {
+                continue;
+            };
+
+            let pose = *slots.entry(observer).or_insert_with(|| {
+                // Keyframe outside the optimised set
+                keyframe_ids.push(observer);
+                problem.poses.push(observer_frame.pose.unwrap());
+                problem.fixed.push(true);
+                problem.cameras.push(observer_frame.camera.clone());
+                problem.poses.len() - 1
+            });
+
+            problem.observations.push(BundleObservation {
+                pose,
+                point: index,
+                pixel: observer_frame.keypoints[keypoint],
+                inv_sigma2: observer_frame.scale.inv_level_sigma2[observer_frame.octave(keypoint) as usize]
+            });
+        }
+    }
+
+    if let Some(gravity) = map.gravity() {
+        let mut edges = Vec::new();
+
+        for &id in keyframes {
+            let preintegration = map.keyframe(id)
+                .and_then(|kf| kf.frame.imu.as_ref()?.preintegration.clone());
+
+            let (Some(preintegration), Some(previous)) = (preintegration, map.previous_inertial(id)) else {
+                continue;
+            };
+
+            let from = *slots.entry(previous).or_insert_with(|| {
+                let frame = &map.keyframe(previous).unwrap().frame;
+                keyframe_ids.push(previous);
+                problem.poses.push(frame.pose.unwrap());
+                problem.fixed.push(true);
+                problem.cameras.push(frame.camera.clone());
+                problem.poses.len() - 1
+            });
+
+            edges.push(InertialEdge { from, to: slots[&id], preintegration });
+        }
+
+        let states = keyframe_ids.iter()
+            .map(|&id| {
+                let imu = map.keyframe(id).unwrap().frame.imu.as_ref()?;
+                Some(InertialState { velocity: imu.velocity, bias: imu.bias })
+            })
+            .collect();
+
+        problem.inertial = Some(InertialTerms { gravity, states, edges });
+    }
+
+    (problem, keyframe_ids, point_ids)
+}
+
+/// Copies the free poses, their inertial states and the points of a solved
+/// problem back into the map, skipping keyframes and points erased in the
+/// meantime
+fn write_back(map: &mut Map, problem: &BundleProblem, keyframe_ids: &[KeyFrameId], point_ids: &[MapPointId]) {
+    for (index, (&id, pose)) in keyframe_ids.iter().zip(&problem.poses).enumerate() {
+        let Some(keyframe) = map.keyframe_mut(id).filter(|_| !problem.fixed[index]) else {
+            continue;
+        };
+
+        keyframe.frame.pose = Some(*pose);
+
+        let state = problem.inertial.as_ref().and_then(|inertial| inertial.states[index]);
+
+        if let (Some(state), Some(imu)) = (state, keyframe.frame.imu.as_mut()) {
+            imu.velocity = state.velocity;
+            imu.bias = state.bias;
+        }
+    }
+
+    for (&id, position) in point_ids.iter().zip(&problem.points) {
+        if let Some(point) = map.point_mut(id) {
+            point.position = *position;
+        }
+
+        map.update_normal_and_depth(id);
+    }
+}
+
+/// Local bundle adjustment around a keyframe, as in ORB-SLAM: the keyframe
+/// and its covisible keyframes are optimised together with every point they
+/// observe, while the other keyframes observing those points stay fixed.
+/// The problem is built and the results written back under short map locks,
+/// so tracking can proceed while it is solved. Observations that remain
+/// outliers are removed from the map. Returns `false` if aborted, which
+/// includes `abort` being set by a loop correction before the write back.
+pub fn local_bundle_adjustment(
+    config: &BundleAdjustmentConfig,
+    linearizer: &dyn Linearizer,
+    map: &SharedMap,
+    keyframe: KeyFrameId,
+    abort: &AtomicBool
+) -> bool {
+    let (mut problem, keyframe_ids, point_ids) = {
+        let map = map.read().unwrap();
+
+        let Some(current) = map.keyframe(keyframe) else {
+            return true;
+        };
+
+        let local: BTreeSet<KeyFrameId> = std::iter::once(keyframe)
+            .chain(current.covisibles().iter().map(|&(id, _)| id))
+            .collect();
+
+        map_problem(&map, &local)
+    };
+
+    let outliers = adjust_bundle(config, linearizer, &mut problem, abort);
+
+    if abort.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    let mut map = map.write().unwrap();
+
+    // The map may have been corrected while solving
+    if abort.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    for (observation, _) in problem.observations.iter().zip(&outliers).filter(|(_, &outlier)| outlier) {
+        map.remove_observation(keyframe_ids[observation.pose], point_ids[observation.point]);
+    }
+
+    write_back(&mut map, &problem, &keyframe_ids, &point_ids);
+
+    true
+}
+
+/// Bundle adjustment of the whole map with the origin fixed, as run by
+/// ORB-SLAM after closing a loop. Only the robust iterations are run and no
+/// observation is removed. Keyframes and points created while solving are
+/// moved with the correction of their parent in the spanning tree and of
+/// their reference keyframe. Returns `None` if aborted.
+pub fn global_bundle_adjustment(
+    config: &BundleAdjustmentConfig,
+    linearizer: &dyn Linearizer,
+    map: &SharedMap,
+    abort: &AtomicBool
+) -> Option<BundleSummary> {
+    let (mut problem, keyframe_ids, point_ids) = {
+        let map = map.read().unwrap();
+        let keyframes: BTreeSet<KeyFrameId> = map.keyframes().map(|kf| kf.id).collect();
+        map_problem(&map, &keyframes)
+    };
+
+    let outliers = vec![false; problem.observations.len()];
+    let summary = solve_bundle(linearizer, &mut problem, &outliers, Some(config.kernel), config.robust_iterations, abort);
+
+    if abort.load(Ordering::SeqCst) {
+        return None;
+    }
+
+    let mut map = map.write().unwrap();
+
+    let old_poses: BTreeMap<KeyFrameId, SE3> = map.keyframes().map(|kf| (kf.id, kf.frame.pose.unwrap())).collect();
+
+    write_back(&mut map, &problem, &keyframe_ids, &point_ids);
+
+    // Propagate down the spanning tree to keyframes that were not optimised
+    let optimised: BTreeSet<KeyFrameId> = keyframe_ids.iter().copied().collect();
+    let mut queue: Vec<KeyFrameId> = map.origin().into_iter().collect();
+
+    while let Some(parent) = queue.pop() {
+        let parent_pose = map.keyframe(parent).unwrap().frame.pose.unwrap();
+        let children: Vec<KeyFrameId> = map.keyframe(parent).unwrap().children().iter().copied().collect();
+
+        for &child in &children {
+            if !optimised.contains(&child) {
+                let relative = old_poses[&child] * old_poses[&parent].inverse();
+                map.keyframe_mut(child).unwrap().frame.pose = Some(relative * parent_pose);
+            }
+        }
+
+        queue.extend(children);
+    }
+
+    let optimised: BTreeSet<MapPointId> = point_ids.iter().copied().collect();
+    let moved: Vec<MapPointId> = map.points().map(|point| point.id).filter(|id| !optimised.contains(id)).collect();
+
+    for id in moved {
+        let point = map.point(id).unwrap();
+        let reference = point.reference_keyframe;
+        let (Some(old), Some(new)) = (old_poses.get(&reference), map.keyframe(reference).and_then(|kf| kf.frame.pose)) else {
+            continue;
+        };
+
+        let position = new.inverse().transform(&old.transform(&point.position));
+        map.point_mut(id).unwrap().position = position;
+        map.update_normal_and_depth(id);
+    }
+
+    Some(summary)
+}
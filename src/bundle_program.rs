@@ -0,0 +1,396 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    BufferUsages, ShaderStages
+};
+
+use tiny_wgpu::{
+    Storage, Compute, ComputeProgram, BindGroupItem, ComputeKernel
+};
+
+use crate::bundle_adjustment::{BundleProblem, Linearizer, NormalEquations};
+use crate::camera::CameraParameters;
+use crate::linalg::{Matrix, Vec2, Vec3, Vec6};
+use crate::optimizer::Huber;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PoseData {
+    /// Row major world to camera rotation
+    rotation: [f32; 9],
+    translation: [f32; 3],
+    model: u32,
+    intrinsics: [f32; 4],
+    distortion: [f32; 5]
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ObservationData {
+    pose: u32,
+    point: u32,
+    pixel: [f32; 2],
+    inv_sigma2: f32,
+    outlier: u32
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LinearizedData {
+    residual: [f32; 2],
+    valid: u32,
+    weight: f32,
+    /// Upper triangle of `J^T W J` followed by `J^T W e`
+    pose_block: [f32; 27],
+    point_block: [f32; 9],
+    /// Row major 6x3 pose-point block
+    coupling: [f32; 18]
+}
+
+unsafe impl Zeroable for PoseData {
+    fn zeroed() -> Self {
+        Self { rotation: [0.0; 9], translation: [0.0; 3], model: 0, intrinsics: [0.0; 4], distortion: [0.0; 5] }
+    }
+}
+
+unsafe impl Zeroable for ObservationData {
+    fn zeroed() -> Self {
+        Self { pose: 0, point: 0, pixel: [0.0; 2], inv_sigma2: 0.0, outlier: 0 }
+    }
+}
+
+unsafe impl Zeroable for LinearizedData {
+    fn zeroed() -> Self {
+        Self { residual: [0.0; 2], valid: 0, weight: 0.0, pose_block: [0.0; 27], point_block: [0.0; 9], coupling: [0.0; 18] }
+    }
+}
+
+unsafe impl Pod for PoseData {}
+unsafe impl Pod for ObservationData {}
+unsafe impl Pod for LinearizedData {}
+
+/// Offsets of the pose and point blocks in `LinearizedData`, in floats
+const POSE_BLOCK_OFFSET: u32 = 4;
+const POSE_BLOCK_SIZE: u32 = 27;
+const POINT_BLOCK_OFFSET: u32 = 31;
+const POINT_BLOCK_SIZE: u32 = 9;
+
+impl PoseData {
+    fn new(problem: &BundleProblem, pose: usize) -> Self {
+        let rotation = problem.poses[pose].rotation.matrix();
+        let translation = problem.poses[pose].translation;
+
+        let (model, pinhole, distortion) = match problem.cameras[pose].parameters() {
+            CameraParameters::Pinhole(pinhole) => (0, pinhole, [0.0; 5]),
+            CameraParameters::RadTan(c) => (1, c.pinhole, [c.k1, c.k2, c.p1, c.p2, c.k3]),
+            CameraParameters::KannalaBrandt(c) => (2, c.pinhole, [c.k1, c.k2, c.k3, c.k4, 0.0])
+        };
+
+        Self {
+            rotation: std::array::from_fn(|i| rotation[(i / 3, i % 3)] as f32),
+            translation: translation.to_array().map(|v| v as f32),
+            model,
+            intrinsics: [pinhole.fx, pinhole.fy, pinhole.cx, pinhole.cy].map(|v| v as f32),
+            distortion: distortion.map(|v| v as f32)
+        }
+    }
+}
+
+pub struct BundleProgramConfig {
+    pub max_poses: u32,
+    pub max_points: u32,
+    pub max_observations: u32
+}
+
+/// Evaluates the normal equations of bundle adjustment with compute
+/// shaders: one invocation per observation projects its point and writes
+/// its residual, robust weight and Jacobian blocks, then one invocation per
+/// pose and per point sums the blocks of its observations. Computations
+/// are in single precision. The reduced camera system is still solved on
+/// the CPU by `solve_bundle`.
+pub struct BundleProgram {
+    pub config: BundleProgramConfig,
+    pub compute: Compute,
+    pub storage: Storage
+}
+
+impl ComputeProgram for BundleProgram {
+    fn compute(&self) -> &Compute {
+        &self.compute
+    }
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+impl BundleProgram {
+    /// Shares the device and queue of `compute`, such as the one of an
+    /// `OrbProgram`
+    pub fn new(config: BundleProgramConfig, compute: &Compute) -> Self {
+        let compute = Compute {
+            instance: compute.instance.clone(),
+            adapter: compute.adapter.clone(),
+            device: compute.device.clone(),
+            queue: compute.queue.clone()
+        };
+
+        let mut program = Self { config, compute, storage: Storage::default() };
+        program.init();
+        program
+    }
+
+    pub fn init(&mut self) {
+        self.add_module("bundle_linearize", wgpu::include_wgsl!("shaders/bundle_linearize.wgsl"));
+        self.add_module("bundle_accumulate", wgpu::include_wgsl!("shaders/bundle_accumulate.wgsl"));
+
+        let max_poses = self.config.max_poses as u64;
+        let max_points = self.config.max_points as u64;
+        let max_observations = self.config.max_observations as u64;
+
+        let pose_size = std::mem::size_of::<PoseData>() as u64;
+        let observation_size = std::mem::size_of::<ObservationData>() as u64;
+        let linearized_size = std::mem::size_of::<LinearizedData>() as u64;
+
+        self.add_buffer("bundle_poses", BufferUsages::STORAGE | BufferUsages::COPY_DST, max_poses * pose_size);
+        self.add_buffer("bundle_points", BufferUsages::STORAGE | BufferUsages::COPY_DST, max_points * 3 * 4);
+        self.add_buffer("bundle_observations", BufferUsages::STORAGE | BufferUsages::COPY_DST, max_observations * observation_size);
+        self.add_buffer("linearized", BufferUsages::STORAGE | BufferUsages::COPY_SRC, max_observations * linearized_size);
+
+        self.add_bind_group("bundle_linearize", &[
+            BindGroupItem::StorageBuffer { label: "bundle_poses", min_binding_size: pose_size, read_only: true },
+            BindGroupItem::StorageBuffer { label: "bundle_points", min_binding_size: 3 * 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "bundle_observations", min_binding_size: observation_size, read_only: true },
+            BindGroupItem::StorageBuffer { label: "linearized", min_binding_size: linearized_size, read_only: false },
+        ]);
+
+        self.add_compute_pipelines(
+            "bundle_linearize",
+            &[ "bundle_linearize" ],
+            &[ComputeKernel { label: "linearize", entry_point: "linearize" }],
+            &[wgpu::PushConstantRange { range: 0..8, stages: ShaderStages::COMPUTE }],
+            None
+        );
+
+        // Observations of each pose and point, as offsets into a list of
+        // observation indices
+        self.add_buffer("pose_offsets", BufferUsages::STORAGE | BufferUsages::COPY_DST, (max_poses + 1) * 4);
+        self.add_buffer("pose_observations", BufferUsages::STORAGE | BufferUsages::COPY_DST, max_observations * 4);
+        self.add_buffer("point_offsets", BufferUsages::STORAGE | BufferUsages::COPY_DST, (max_points + 1) * 4);
+        self.add_buffer("point_observations", BufferUsages::STORAGE | BufferUsages::COPY_DST, max_observations * 4);
+
+        self.add_buffer("pose_blocks", BufferUsages::STORAGE | BufferUsages::COPY_SRC, max_poses * POSE_BLOCK_SIZE as u64 * 4);
+        self.add_buffer("point_blocks", BufferUsages::STORAGE | BufferUsages::COPY_SRC, max_points * POINT_BLOCK_SIZE as u64 * 4);
+
+        self.add_bind_group("accumulate_poses", &[
+            BindGroupItem::StorageBuffer { label: "linearized", min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "pose_offsets", min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "pose_observations", min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "pose_blocks", min_binding_size: 4, read_only: false },
+        ]);
+
+        self.add_bind_group("accumulate_points", &[
+            BindGroupItem::StorageBuffer { label: "linearized", min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "point_offsets", min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "point_observations", min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "point_blocks", min_binding_size: 4, read_only: false },
+        ]);
+
+        self.add_compute_pipelines(
+            "bundle_accumulate",
+            &[ "accumulate_poses" ],
+            &[ComputeKernel { label: "accumulate_poses", entry_point: "accumulate" }],
+            &[wgpu::PushConstantRange { range: 0..12, stages: ShaderStages::COMPUTE }],
+            None
+        );
+
+        self.add_compute_pipelines(
+            "bundle_accumulate",
+            &[ "accumulate_points" ],
+            &[ComputeKernel { label: "accumulate_points", entry_point: "accumulate" }],
+            &[wgpu::PushConstantRange { range: 0..12, stages: ShaderStages::COMPUTE }],
+            None
+        );
+
+        self.add_staging_buffer("linearized");
+        self.add_staging_buffer("pose_blocks");
+        self.add_staging_buffer("point_blocks");
+    }
+
+    /// Uploads the problem and the observations of each pose and point
+    fn write_problem(&self, problem: &BundleProblem, outliers: &[bool]) {
+        let poses: Vec<PoseData> = (0..problem.poses.len()).map(|i| PoseData::new(problem, i)).collect();
+
+        let points: Vec<[f32; 3]> = problem.points.iter().map(|p| p.to_array().map(|v| v as f32)).collect();
+
+        let observations: Vec<ObservationData> = problem.observations.iter()
+            .zip(outliers)
+            .map(|(observation, &outlier)| ObservationData {
+                pose: observation.pose as u32,
+                point: observation.point as u32,
+                pixel: [observation.pixel.x() as f32, observation.pixel.y() as f32],
+                inv_sigma2: observation.inv_sigma2 as f32,
+                outlier: outlier as u32
+            })
+            .collect();
+
+        let (pose_offsets, pose_observations) = adjacency(problem.poses.len(), problem.observations.iter().map(|o| o.pose));
+        let (point_offsets, point_observations) = adjacency(problem.points.len(), problem.observations.iter().map(|o| o.point));
+
+        let write = |label: &'static str, bytes: &[u8]| {
+            if !bytes.is_empty() {
+                self.compute().queue.write_buffer(&self.storage().buffers[label], 0, bytes);
+            }
+        };
+
+        write("bundle_poses", bytemuck::cast_slice(&poses));
+        write("bundle_points", bytemuck::cast_slice(&points));
+        write("bundle_observations", bytemuck::cast_slice(&observations));
+        write("pose_offsets", bytemuck::cast_slice(&pose_offsets));
+        write("pose_observations", bytemuck::cast_slice(&pose_observations));
+        write("point_offsets", bytemuck::cast_slice(&point_offsets));
+        write("point_observations", bytemuck::cast_slice(&point_observations));
+    }
+}
+
+impl Linearizer for BundleProgram {
+    fn normal_equations(&self, problem: &BundleProblem, outliers: &[bool], kernel: Option<Huber>) -> NormalEquations {
+        let pose_count = problem.poses.len();
+        let point_count = problem.points.len();
+        let observation_count = problem.observations.len();
+
+        assert!(pose_count <= self.config.max_poses as usize);
+        assert!(point_count <= self.config.max_points as usize);
+        assert!(observation_count <= self.config.max_observations as usize);
+        assert_eq!(outliers.len(), observation_count);
+
+        self.write_problem(problem, outliers);
+
+        let mut encoder = self.compute().device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: None
+        });
+
+        {
+            let huber_delta = kernel.map_or(0.0, |k| k.delta as f32);
+
+            let mut cpass = encoder.begin_compute_pass(&Default::default());
+
+            cpass.set_pipeline(&self.storage().compute_pipelines["linearize"]);
+            cpass.set_bind_group(0, &self.storage().bind_groups["bundle_linearize"], &[]);
+            cpass.set_push_constants(0, bytemuck::cast_slice(&[observation_count as u32, huber_delta.to_bits()]));
+
+            cpass.dispatch_workgroups(
+                (observation_count as u32).div_ceil(64),
+                1,
+                1
+            );
+        }
+
+        for (pipeline, count, first, length) in [
+            ("accumulate_poses", pose_count, POSE_BLOCK_OFFSET, POSE_BLOCK_SIZE),
+            ("accumulate_points", point_count, POINT_BLOCK_OFFSET, POINT_BLOCK_SIZE)
+        ] {
+            let mut cpass = encoder.begin_compute_pass(&Default::default());
+
+            cpass.set_pipeline(&self.storage().compute_pipelines[pipeline]);
+            cpass.set_bind_group(0, &self.storage().bind_groups[pipeline], &[]);
+            cpass.set_push_constants(0, bytemuck::cast_slice(&[count as u32, first, length]));
+
+            cpass.dispatch_workgroups(
+                (count as u32).div_ceil(64),
+                1,
+                1
+            );
+        }
+
+        self.copy_buffer_to_staging(&mut encoder, "linearized");
+        self.copy_buffer_to_staging(&mut encoder, "pose_blocks");
+        self.copy_buffer_to_staging(&mut encoder, "point_blocks");
+
+        self.compute().queue.submit(Some(encoder.finish()));
+
+        self.prepare_staging_buffer("linearized");
+        self.prepare_staging_buffer("pose_blocks");
+        self.prepare_staging_buffer("point_blocks");
+
+        self.compute().device.poll(wgpu::MaintainBase::Wait);
+
+        // Staging buffers cannot be read with an empty range
+        let mut linearized = vec![LinearizedData::zeroed(); observation_count.max(1)];
+        let mut pose_blocks = vec![[0f32; POSE_BLOCK_SIZE as usize]; pose_count.max(1)];
+        let mut point_blocks = vec![[0f32; POINT_BLOCK_SIZE as usize]; point_count.max(1)];
+
+        self.read_staging_buffer("linearized", &mut linearized);
+        self.read_staging_buffer("pose_blocks", &mut pose_blocks);
+        self.read_staging_buffer("point_blocks", &mut point_blocks);
+
+        linearized.truncate(observation_count);
+        pose_blocks.truncate(pose_count);
+        point_blocks.truncate(point_count);
+
+        NormalEquations {
+            residuals: linearized.iter()
+                .map(|l| (l.valid != 0).then(|| Vec2::new(l.residual[0] as f64, l.residual[1] as f64)))
+                .collect(),
+            pose_blocks: pose_blocks.iter().map(|block| symmetric::<6>(&block[..21])).collect(),
+            pose_gradients: pose_blocks.iter().map(|block| Vec6::from_array(std::array::from_fn(|i| block[21 + i] as f64))).collect(),
+            point_blocks: point_blocks.iter().map(|block| symmetric::<3>(&block[..6])).collect(),
+            point_gradients: point_blocks.iter().map(|block| Vec3::from_array(std::array::from_fn(|i| block[6 + i] as f64))).collect(),
+            coupling: linearized.iter()
+                .zip(outliers)
+                .map(|(l, &outlier)| {
+                    (l.valid != 0 && !outlier).then(|| {
+                        let mut block = Matrix::<6, 3>::zeros();
+
+                        for (i, &value) in l.coupling.iter().enumerate() {
+                            block[(i / 3, i % 3)] = value as f64;
+                        }
+
+                        block
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+/// Symmetric matrix from its upper triangle, row by row
+fn symmetric<const N: usize>(upper: &[f32]) -> Matrix<N, N> {
+    let mut matrix = Matrix::<N, N>::zeros();
+    let mut k = 0;
+
+    for i in 0..N {
+        for j in i..N {
+            matrix[(i, j)] = upper[k] as f64;
+            matrix[(j, i)] = upper[k] as f64;
+            k += 1;
+        }
+    }
+
+    matrix
+}
+
+/// Observations grouped by the pose or point they belong to: those of node
+/// `n` are `indices[offsets[n]..offsets[n + 1]]`
+fn adjacency(node_count: usize, nodes: impl Iterator<Item = usize> + Clone) -> (Vec<u32>, Vec<u32>) {
+    let mut offsets = vec![0u32; node_count + 1];
+
+    for node in nodes.clone() {
+        offsets[node + 1] += 1;
+    }
+
+    for n in 0..node_count {
+        offsets[n + 1] += offsets[n];
+    }
+
+    let mut next = offsets.clone();
+    let mut indices = vec![0u32; offsets[node_count] as usize];
+
+    for (i, node) in nodes.enumerate() {
+        indices[next[node] as usize] = i as u32;
+        next[node] += 1;
+    }
+
+    (offsets, indices)
+}
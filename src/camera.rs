@@ -0,0 +1,378 @@
+use crate::linalg::{Mat2, Mat2x3, Mat3, Vec2, Vec3};
+use crate::orb::CornerData;
+
+/// Maps points in the camera frame (z forward) to pixels and back.
+pub trait CameraModel: Send + Sync {
+    /// Pixel coordinates of a point in front of the camera
+    fn project(&self, point: &Vec3) -> Vec2;
+
+    /// Unit bearing vector of the ray through a pixel
+    fn unproject(&self, pixel: &Vec2) -> Vec3;
+
+    /// Derivative of `project` with respect to the point
+    fn project_jacobian(&self, point: &Vec3) -> Mat2x3;
+
+    /// The linear part of the model, used as the target of undistortion
+    fn pinhole(&self) -> Pinhole;
+
+    /// The model and its coefficients, for evaluating it in shaders
+    fn parameters(&self) -> CameraParameters;
+
+    /// Pixel coordinates the ray through `pixel` would have in an ideal
+    /// pinhole camera with the same intrinsics
+    fn undistort(&self, pixel: &Vec2) -> Vec2 {
+        self.pinhole().project(&self.unproject(pixel))
+    }
+}
+
+/// Camera models that compute shaders can evaluate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraParameters {
+    Pinhole(Pinhole),
+    RadTan(RadTan),
+    KannalaBrandt(KannalaBrandt)
+}
+
+/// Ideal perspective camera
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pinhole {
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64
+}
+
+impl Pinhole {
+    pub fn new(fx: f64, fy: f64, cx: f64, cy: f64) -> Self {
+        Self { fx, fy, cx, cy }
+    }
+
+    /// Intrinsic matrix K
+    pub fn matrix(&self) -> Mat3 {
+        Mat3::from_rows([
+            [self.fx, 0.0, self.cx],
+            [0.0, self.fy, self.cy],
+            [0.0, 0.0, 1.0]
+        ])
+    }
+
+    /// Pixel to normalised image coordinates (x / z, y / z)
+    pub fn normalize(&self, pixel: &Vec2) -> Vec2 {
+        Vec2::new((pixel.x() - self.cx) / self.fx, (pixel.y() - self.cy) / self.fy)
+    }
+
+    /// Normalised image coordinates to pixel
+    pub fn denormalize(&self, point: &Vec2) -> Vec2 {
+        Vec2::new(self.fx * point.x() + self.cx, self.fy * point.y() + self.cy)
+    }
+}
+
+impl CameraModel for Pinhole {
+    fn project(&self, point: &Vec3) -> Vec2 {
+        self.denormalize(&point.hnormalize())
+    }
+
+    fn unproject(&self, pixel: &Vec2) -> Vec3 {
+        let p = self.normalize(pixel);
+        Vec3::new(p.x(), p.y(), 1.0).normalize()
+    }
+
+    fn project_jacobian(&self, point: &Vec3) -> Mat2x3 {
+        let inv_z = 1.0 / point.z();
+        let inv_z2 = inv_z * inv_z;
+
+        Mat2x3::from_rows([
+            [self.fx * inv_z, 0.0, -self.fx * point.x() * inv_z2],
+            [0.0, self.fy * inv_z, -self.fy * point.y() * inv_z2]
+        ])
+    }
+
+    fn pinhole(&self) -> Pinhole {
+        *self
+    }
+
+    fn parameters(&self) -> CameraParameters {
+        CameraParameters::Pinhole(*self)
+    }
+}
+
+/// Jacobian of normalised image coordinates with respect to the point
+fn normalize_jacobian(point: &Vec3) -> Mat2x3 {
+    let inv_z = 1.0 / point.z();
+
+    Mat2x3::from_rows([
+        [inv_z, 0.0, -point.x() * inv_z * inv_z],
+        [0.0, inv_z, -point.y() * inv_z * inv_z]
+    ])
+}
+
+/// Pinhole camera with radial-tangential distortion, using the coefficient
+/// conventions of OpenCV (k1, k2, p1, p2, k3).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RadTan {
+    pub pinhole: Pinhole,
+    pub k1: f64,
+    pub k2: f64,
+    pub p1: f64,
+    pub p2: f64,
+    pub k3: f64
+}
+
+impl RadTan {
+    pub fn new(pinhole: Pinhole, [k1, k2, p1, p2, k3]: [f64; 5]) -> Self {
+        Self { pinhole, k1, k2, p1, p2, k3 }
+    }
+
+    /// Distorts normalised image coordinates
+    pub fn distort(&self, p: &Vec2) -> Vec2 {
+        let (x, y) = (p.x(), p.y());
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+
+        Vec2::new(
+            x * radial + 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x),
+            y * radial + self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y
+        )
+    }
+
+    /// Derivative of `distort` with respect to the undistorted coordinates
+    pub fn distort_jacobian(&self, p: &Vec2) -> Mat2 {
+        let (x, y) = (p.x(), p.y());
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+        let d_radial = 2.0 * self.k1 + r2 * (4.0 * self.k2 + 6.0 * self.k3 * r2);
+
+        Mat2::from_rows([
+            [
+                radial + d_radial * x * x + 2.0 * self.p1 * y + 6.0 * self.p2 * x,
+                d_radial * x * y + 2.0 * self.p1 * x + 2.0 * self.p2 * y
+            ],
+            [
+                d_radial * x * y + 2.0 * self.p1 * x + 2.0 * self.p2 * y,
+                radial + d_radial * y * y + 6.0 * self.p1 * y + 2.0 * self.p2 * x
+            ]
+        ])
+    }
+
+    /// Inverts `distort` with Gauss-Newton iterations
+    pub fn undistort_normalized(&self, distorted: &Vec2) -> Vec2 {
+        let mut p = *distorted;
+
+        for _ in 0..20 {
+            let error = self.distort(&p) - *distorted;
+
+            if error.norm_squared() < 1e-24 {
+                break;
+            }
+
+            let Some(inverse) = self.distort_jacobian(&p).try_inverse() else {
+                break;
+            };
+
+            p -= inverse * error;
+        }
+
+        p
+    }
+}
+
+impl CameraModel for RadTan {
+    fn project(&self, point: &Vec3) -> Vec2 {
+        self.pinhole.denormalize(&self.distort(&point.hnormalize()))
+    }
+
+    fn unproject(&self, pixel: &Vec2) -> Vec3 {
+        let p = self.undistort_normalized(&self.pinhole.normalize(pixel));
+        Vec3::new(p.x(), p.y(), 1.0).normalize()
+    }
+
+    fn project_jacobian(&self, point: &Vec3) -> Mat2x3 {
+        let focal = Mat2::from_rows([[self.pinhole.fx, 0.0], [0.0, self.pinhole.fy]]);
+        focal * self.distort_jacobian(&point.hnormalize()) * normalize_jacobian(point)
+    }
+
+    fn pinhole(&self) -> Pinhole {
+        self.pinhole
+    }
+
+    fn parameters(&self) -> CameraParameters {
+        CameraParameters::RadTan(*self)
+    }
+}
+
+/// Kannala-Brandt fisheye model with four coefficients, as used by
+/// ORB-SLAM3 and OpenCV's fisheye module.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KannalaBrandt {
+    pub pinhole: Pinhole,
+    pub k1: f64,
+    pub k2: f64,
+    pub k3: f64,
+    pub k4: f64
+}
+
+impl KannalaBrandt {
+    pub fn new(pinhole: Pinhole, [k1, k2, k3, k4]: [f64; 4]) -> Self {
+        Self { pinhole, k1, k2, k3, k4 }
+    }
+
+    /// Distorted angle from the optical axis
+    fn theta_d(&self, theta: f64) -> f64 {
+        let t2 = theta * theta;
+        theta * (1.0 + t2 * (self.k1 + t2 * (self.k2 + t2 * (self.k3 + t2 * self.k4))))
+    }
+
+    fn theta_d_derivative(&self, theta: f64) -> f64 {
+        let t2 = theta * theta;
+        1.0 + t2 * (3.0 * self.k1 + t2 * (5.0 * self.k2 + t2 * (7.0 * self.k3 + t2 * 9.0 * self.k4)))
+    }
+}
+
+impl CameraModel for KannalaBrandt {
+    fn project(&self, point: &Vec3) -> Vec2 {
+        let r = (point.x() * point.x() + point.y() * point.y()).sqrt();
+
+        if r < 1e-12 {
+            return Vec2::new(self.pinhole.cx, self.pinhole.cy);
+        }
+
+        let theta = r.atan2(point.z());
+        let scale = self.theta_d(theta) / r;
+
+        self.pinhole.denormalize(&Vec2::new(point.x() * scale, point.y() * scale))
+    }
+
+    fn unproject(&self, pixel: &Vec2) -> Vec3 {
+        let p = self.pinhole.normalize(pixel);
+        let theta_d = p.norm().min(std::f64::consts::PI);
+
+        if theta_d < 1e-12 {
+            return Vec3::new(0.0, 0.0, 1.0);
+        }
+
+        // Solve theta_d(theta) = theta_d with Newton's method
+        let mut theta = theta_d;
+        for _ in 0..20 {
+            let step = (self.theta_d(theta) - theta_d) / self.theta_d_derivative(theta);
+            theta -= step;
+
+            if step.abs() < 1e-12 {
+                break;
+            }
+        }
+
+        let scale = theta.sin() / theta_d;
+        Vec3::new(p.x() * scale, p.y() * scale, theta.cos())
+    }
+
+    fn project_jacobian(&self, point: &Vec3) -> Mat2x3 {
+        let (x, y, z) = (point.x(), point.y(), point.z());
+        let r2 = x * x + y * y;
+        let r = r2.sqrt();
+
+        // Near the optical axis the model is a pinhole camera
+        if r < 1e-9 {
+            return self.pinhole.project_jacobian(point);
+        }
+
+        let theta = r.atan2(z);
+        let theta_d = self.theta_d(theta);
+        let d_theta_d = self.theta_d_derivative(theta);
+
+        let rho2 = r2 + z * z;
+        let d_theta = [x * z / (r * rho2), y * z / (r * rho2), -r / rho2];
+
+        // Derivatives of theta_d / r
+        let psi = theta_d / r;
+        let d_psi = [
+            d_theta_d * d_theta[0] / r - theta_d * x / (r2 * r),
+            d_theta_d * d_theta[1] / r - theta_d * y / (r2 * r),
+            d_theta_d * d_theta[2] / r
+        ];
+
+        let (fx, fy) = (self.pinhole.fx, self.pinhole.fy);
+
+        Mat2x3::from_rows([
+            [fx * (psi + x * d_psi[0]), fx * x * d_psi[1], fx * x * d_psi[2]],
+            [fy * y * d_psi[0], fy * (psi + y * d_psi[1]), fy * y * d_psi[2]]
+        ])
+    }
+
+    fn pinhole(&self) -> Pinhole {
+        self.pinhole
+    }
+
+    fn parameters(&self) -> CameraParameters {
+        CameraParameters::KannalaBrandt(*self)
+    }
+}
+
+/// Undistorted level-0 pixel coordinates of each corner
+pub fn undistort_corners(camera: &dyn CameraModel, corners: &[CornerData]) -> Vec<Vec2> {
+    corners.iter()
+        .map(|corner| {
+            let [x, y] = corner.scaled_position();
+            camera.undistort(&Vec2::new(x as f64, y as f64))
+        })
+        .collect()
+}
+
+/// Unit bearing vector of each corner
+pub fn bearing_vectors(camera: &dyn CameraModel, corners: &[CornerData]) -> Vec<Vec3> {
+    corners.iter()
+        .map(|corner| {
+            let [x, y] = corner.scaled_position();
+            camera.unproject(&Vec2::new(x as f64, y as f64))
+        })
+        .collect()
+}
+
+/// Remaps images from a distorted camera to an ideal pinhole camera,
+/// optionally rotated, e.g. by a stereo rectification.
+pub struct Undistortion {
+    pub camera: Box<dyn CameraModel>,
+    /// Intrinsics of the output image
+    pub target: Pinhole,
+    /// Rotation from the output camera frame to the input camera frame
+    pub rotation: Mat3
+}
+
+impl Undistortion {
+    /// Undistorts to a pinhole camera with the same intrinsics
+    pub fn new(camera: Box<dyn CameraModel>) -> Self {
+        let target = camera.pinhole();
+        Self { camera, target, rotation: Mat3::identity() }
+    }
+
+    pub fn rectified(camera: Box<dyn CameraModel>, target: Pinhole, rotation: Mat3) -> Self {
+        Self { camera, target, rotation }
+    }
+
+    /// For each output pixel, the texture coordinates in [0, 1] to sample
+    /// the input image at, or -1 where the ray falls behind the camera.
+    pub fn map(&self, width: u32, height: u32) -> Vec<[f32; 2]> {
+        let mut map = Vec::with_capacity((width * height) as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let p = self.target.normalize(&Vec2::new(x as f64, y as f64));
+                let ray = self.rotation * Vec3::new(p.x(), p.y(), 1.0);
+
+                if ray.z() <= 0.0 {
+                    map.push([-1.0, -1.0]);
+                    continue;
+                }
+
+                let source = self.camera.project(&ray);
+
+                // Pixel centers sit at half-texel offsets
+                map.push([
+                    ((source.x() + 0.5) / width as f64) as f32,
+                    ((source.y() + 0.5) / height as f64) as f32
+                ]);
+            }
+        }
+
+        map
+    }
+}
@@ -0,0 +1,414 @@
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::camera::CameraModel;
+use crate::frame::Frame;
+use crate::geometry::{SE3, SO3};
+use crate::image::{self, Image};
+use crate::keyframe_selection::{KeyFrameSelection, KeyFrameSelectionConfig, Sensor, TrackingStatistics};
+use crate::linalg::Vec3;
+use crate::local_mapping::{LocalMapper, LocalMappingConfig};
+use crate::map::{KeyFrameId, Map, SharedMap};
+use crate::orb::OrbProgram;
+use crate::rgbd::{extract_rgbd_frame, DepthImage, RgbdConfig};
+use crate::stereo::{initialize_stereo_map, StereoConfig};
+use crate::tracking::{Tracker, TrackerConfig, TrackingState};
+use crate::vocabulary::Vocabulary;
+
+/// Largest timestamp difference, in seconds, at which colour and depth
+/// images are associated, as in the TUM benchmark's `associate.py`
+pub const TUM_MAX_DIFFERENCE: f64 = 0.02;
+
+/// Colour image and the depth image registered to it
+#[derive(Clone, Debug)]
+pub struct TumFrame {
+    /// Seconds, of the colour image
+    pub timestamp: f64,
+    pub rgb: PathBuf,
+    pub depth: PathBuf
+}
+
+/// RGB-D sequence of the TUM benchmark in a local directory
+pub struct TumSequence {
+    pub directory: PathBuf,
+    pub frames: Vec<TumFrame>,
+    /// World to camera poses of `groundtruth.txt`, empty when missing
+    pub groundtruth: Vec<(f64, SE3)>
+}
+
+impl TumSequence {
+    /// Reads the frames of `associations.txt` when present, with lines
+    /// `timestamp rgb_file timestamp depth_file`. Otherwise `rgb.txt` and
+    /// `depth.txt` are associated within `max_difference` seconds.
+    pub fn open(directory: impl AsRef<Path>, max_difference: f64) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        let associations = directory.join("associations.txt");
+
+        let frames = if associations.exists() {
+            read_lines(&associations)?
+                .into_iter()
+                .map(|fields| match &fields[..] {
+                    [timestamp, rgb, _, depth] => Ok(TumFrame {
+                        timestamp: parse_timestamp(timestamp)?,
+                        rgb: directory.join(rgb),
+                        depth: directory.join(depth)
+                    }),
+                    _ => Err(invalid("invalid TUM association"))
+                })
+                .collect::<io::Result<_>>()?
+        } else {
+            let rgb = read_file_list(directory.join("rgb.txt"))?;
+            let depth = read_file_list(directory.join("depth.txt"))?;
+
+            let rgb_timestamps: Vec<f64> = rgb.iter().map(|(t, _)| *t).collect();
+            let depth_timestamps: Vec<f64> = depth.iter().map(|(t, _)| *t).collect();
+
+            associate(&rgb_timestamps, &depth_timestamps, max_difference)
+                .into_iter()
+                .map(|(i, j)| TumFrame {
+                    timestamp: rgb[i].0,
+                    rgb: directory.join(&rgb[i].1),
+                    depth: directory.join(&depth[j].1)
+                })
+                .collect()
+        };
+
+        let groundtruth = directory.join("groundtruth.txt");
+
+        let groundtruth = if groundtruth.exists() {
+            load_trajectory(groundtruth)?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self { directory, frames, groundtruth })
+    }
+
+    /// Decodes the colour image of frame `i`, in the layout expected by
+    /// `OrbProgram::write_input_image`, and its depth map
+    pub fn load_frame(&self, i: usize, depth_scale: f64) -> io::Result<(Image<u8>, DepthImage)> {
+        let frame = &self.frames[i];
+        let color = image::load(&frame.rgb)?.to_rgba8();
+        let depth = DepthImage::load_tum(&frame.depth, depth_scale)?;
+
+        Ok((color, depth))
+    }
+}
+
+/// Reads a TUM file list such as `rgb.txt`, with lines `timestamp file`.
+/// File names are relative to the sequence directory.
+pub fn read_file_list(path: impl AsRef<Path>) -> io::Result<Vec<(f64, String)>> {
+    read_lines(path.as_ref())?
+        .into_iter()
+        .map(|fields| match &fields[..] {
+            [timestamp, file] => Ok((parse_timestamp(timestamp)?, file.clone())),
+            _ => Err(invalid("invalid TUM file list entry"))
+        })
+        .collect()
+}
+
+/// Pairs timestamps of `a` and `b` closer than `max_difference`, closest
+/// pairs first, each timestamp at most once. Pairs are ordered by `a`.
+pub fn associate(a: &[f64], b: &[f64], max_difference: f64) -> Vec<(usize, usize)> {
+    let mut candidates: Vec<(f64, usize, usize)> = Vec::new();
+
+    for (i, ta) in a.iter().enumerate() {
+        for (j, tb) in b.iter().enumerate() {
+            let difference = (ta - tb).abs();
+
+            if difference < max_difference {
+                candidates.push((difference, i, j));
+            }
+        }
+    }
+
+    candidates.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    let mut used_a = vec![false; a.len()];
+    let mut used_b = vec![false; b.len()];
+    let mut pairs = Vec::new();
+
+    for (_, i, j) in candidates {
+        if !used_a[i] && !used_b[j] {
+            used_a[i] = true;
+            used_b[j] = true;
+            pairs.push((i, j));
+        }
+    }
+
+    pairs.sort_by(|x, y| a[x.0].total_cmp(&a[y.0]));
+    pairs
+}
+
+/// Reads a trajectory in the TUM format, see `read_trajectory`
+pub fn load_trajectory(path: impl AsRef<Path>) -> io::Result<Vec<(f64, SE3)>> {
+    let file = std::fs::File::open(path)?;
+    read_trajectory(io::BufReader::new(file))
+}
+
+/// Lines are `timestamp tx ty tz qx qy qz qw` with the camera to world pose,
+/// which is inverted into the world to camera pose of `Frame::pose`
+pub fn read_trajectory(reader: impl BufRead) -> io::Result<Vec<(f64, SE3)>> {
+    parse_lines(reader)?
+        .into_iter()
+        .map(|fields| {
+            let values: Vec<f64> = fields.iter()
+                .map(|v| v.parse().map_err(|_| invalid("invalid TUM trajectory value")))
+                .collect::<io::Result<_>>()?;
+
+            let [timestamp, x, y, z, qx, qy, qz, qw] = values[..] else {
+                return Err(invalid("invalid TUM trajectory pose"));
+            };
+
+            let pose = SE3::new(SO3::from_quaternion([qw, qx, qy, qz]), Vec3::new(x, y, z));
+            Ok((timestamp, pose.inverse()))
+        })
+        .collect()
+}
+
+/// Writes world to camera poses in the TUM format read by `read_trajectory`
+pub fn save_trajectory(path: impl AsRef<Path>, trajectory: &[(f64, SE3)]) -> io::Result<()> {
+    let file = std::fs::File::create(path)?;
+    let mut writer = io::BufWriter::new(file);
+    write_trajectory(&mut writer, trajectory)?;
+    writer.flush()
+}
+
+pub fn write_trajectory(mut writer: impl Write, trajectory: &[(f64, SE3)]) -> io::Result<()> {
+    for (timestamp, pose) in trajectory {
+        let pose = pose.inverse();
+        let [qw, qx, qy, qz] = pose.rotation.to_quaternion();
+        let [x, y, z] = pose.translation.to_array();
+
+        writeln!(writer, "{timestamp:.6} {x} {y} {z} {qx} {qy} {qz} {qw}")?;
+    }
+
+    Ok(())
+}
+
+fn read_lines(path: &Path) -> io::Result<Vec<Vec<String>>> {
+    let file = std::fs::File::open(path)?;
+    parse_lines(io::BufReader::new(file))
+}
+
+/// Whitespace separated fields of each line, without comments and blank lines
+fn parse_lines(reader: impl BufRead) -> io::Result<Vec<Vec<String>>> {
+    let mut lines = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<String> = line.split_whitespace().map(str::to_owned).collect();
+
+        if fields.first().is_some_and(|field| !field.starts_with('#')) {
+            lines.push(fields);
+        }
+    }
+
+    Ok(lines)
+}
+
+fn parse_timestamp(value: &str) -> io::Result<f64> {
+    value.parse().map_err(|_| invalid("invalid TUM timestamp"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Default)]
+pub struct RgbdRunnerConfig {
+    pub rgbd: RgbdConfig,
+    /// Map initialisation from the first frame with enough depths
+    pub stereo: StereoConfig,
+    pub tracker: TrackerConfig,
+    pub keyframe_selection: KeyFrameSelectionConfig,
+    pub local_mapping: LocalMappingConfig
+}
+
+/// Tracked frame of the trajectory, kept relative to its reference keyframe
+/// so later refinements of the keyframe carry over
+struct TrajectoryEntry {
+    timestamp: f64,
+    reference: KeyFrameId,
+    relative: SE3,
+    /// Pose as tracked, used once the reference keyframe was culled
+    pose: SE3
+}
+
+/// Runs RGB-D frames through feature extraction, tracking and local
+/// mapping on the calling thread, so that offline runs are reproducible.
+/// Keyframes are processed as soon as they are inserted.
+pub struct RgbdRunner {
+    pub rgbd: RgbdConfig,
+    pub stereo: StereoConfig,
+    program: OrbProgram,
+    camera: Arc<dyn CameraModel>,
+    vocabulary: Arc<Vocabulary>,
+    tracker: Tracker,
+    selection: KeyFrameSelection,
+    mapper: LocalMapper,
+    next_frame_id: u64,
+    last_keyframe_frame_id: u64,
+    last_relocalisation_frame_id: Option<u64>,
+    trajectory: Vec<TrajectoryEntry>
+}
+
+impl RgbdRunner {
+    /// `camera` must be the model of the colour camera. The program must
+    /// not undistort, see `extract_rgbd_frame`.
+    pub fn new(config: RgbdRunnerConfig, program: OrbProgram, camera: Arc<dyn CameraModel>, vocabulary: Arc<Vocabulary>) -> Self {
+        let RgbdRunnerConfig { rgbd, stereo, tracker, keyframe_selection, local_mapping } = config;
+
+        Self {
+            rgbd,
+            stereo,
+            program,
+            camera,
+            tracker: Tracker::new(tracker, vocabulary.clone()),
+            selection: KeyFrameSelection::new(keyframe_selection, Sensor::Rgbd),
+            mapper: LocalMapper::new(local_mapping, Map::new().shared(), vocabulary.clone()),
+            vocabulary,
+            next_frame_id: 0,
+            last_keyframe_frame_id: 0,
+            last_relocalisation_frame_id: None,
+            trajectory: Vec::new()
+        }
+    }
+
+    pub fn map(&self) -> &SharedMap {
+        self.mapper.map()
+    }
+
+    pub fn state(&self) -> TrackingState {
+        self.tracker.state()
+    }
+
+    /// Runs every frame of `sequence` in order
+    pub fn run(&mut self, sequence: &TumSequence) -> io::Result<()> {
+        for i in 0..sequence.frames.len() {
+            let (color, depth) = sequence.load_frame(i, self.rgbd.depth_scale)?;
+            self.process(sequence.frames[i].timestamp, &color, &depth);
+        }
+
+        Ok(())
+    }
+
+    /// Extracts and tracks a frame, initialising the map from the first
+    /// frame with enough depths, and inserts it as a keyframe when needed
+    pub fn process(&mut self, timestamp: f64, color: &Image<u8>, depth: &DepthImage) -> TrackingState {
+        let id = self.next_frame_id;
+        self.next_frame_id += 1;
+
+        let frame = extract_rgbd_frame(&self.rgbd, &self.program, id, timestamp, self.camera.clone(), color, depth);
+
+        if self.tracker.state() == TrackingState::NotInitialised {
+            return self.initialize(frame);
+        }
+
+        let map = self.mapper.map().clone();
+        let mut guard = map.write().unwrap();
+
+        let (frame, state) = self.tracker.track(frame, &guard);
+
+        if state == TrackingState::Relocalised {
+            self.last_relocalisation_frame_id = Some(id);
+        }
+
+        let Some(pose) = frame.pose else {
+            return state;
+        };
+
+        let inliers: Vec<_> = frame.map_points.iter()
+            .zip(&frame.outliers)
+            .filter_map(|(point, &outlier)| point.filter(|_| !outlier))
+            .collect();
+
+        guard.increase_visible(self.tracker.visible_points().iter().copied());
+        guard.increase_found(inliers.iter().copied());
+
+        let reference = self.tracker.reference_keyframe().expect("tracking without a reference keyframe");
+
+        if let Some(keyframe) = guard.keyframe(reference) {
+            let reference_pose = keyframe.frame.pose.unwrap();
+            self.trajectory.push(TrajectoryEntry { timestamp, reference, relative: pose * reference_pose.inverse(), pose });
+        }
+
+        if state != TrackingState::Ok {
+            return state;
+        }
+
+        let keyframes_in_map = guard.keyframe_count();
+        let (tracked_close, untracked_close) = frame.close_point_counts();
+
+        let stats = TrackingStatistics {
+            frame_id: id,
+            last_keyframe_frame_id: self.last_keyframe_frame_id,
+            last_relocalisation_frame_id: self.last_relocalisation_frame_id,
+            keyframes_in_map,
+            tracked_inliers: inliers.len(),
+            reference_tracked_points: guard.tracked_points(reference, self.selection.min_observations(keyframes_in_map)),
+            local_mapping_idle: true,
+            local_mapping_stopped: false,
+            queued_keyframes: 0,
+            tracked_close,
+            untracked_close
+        };
+
+        drop(guard);
+
+        if self.selection.decide(&stats).insert {
+            let keyframe = self.mapper.process_keyframe(frame, true);
+            self.tracker.set_reference_keyframe(keyframe);
+            self.tracker.keyframe_inserted();
+            self.last_keyframe_frame_id = id;
+        }
+
+        state
+    }
+
+    fn initialize(&mut self, mut frame: Frame) -> TrackingState {
+        frame.compute_bow(&self.vocabulary, self.tracker.config.levels_up);
+
+        let timestamp = frame.timestamp;
+        let id = frame.id;
+
+        let mut map = self.mapper.map().write().unwrap();
+
+        let Some(keyframe) = initialize_stereo_map(&self.stereo, &mut map, frame) else {
+            return TrackingState::NotInitialised;
+        };
+
+        let frame = map.keyframe(keyframe).unwrap().frame.clone();
+        drop(map);
+
+        self.tracker.initialize(frame, keyframe);
+        self.last_keyframe_frame_id = id;
+
+        let pose = SE3::identity();
+        self.trajectory.push(TrajectoryEntry { timestamp, reference: keyframe, relative: pose, pose });
+
+        self.tracker.state()
+    }
+
+    /// World to camera poses of the tracked frames, composed with the
+    /// current poses of their reference keyframes
+    pub fn trajectory(&self) -> Vec<(f64, SE3)> {
+        let map = self.mapper.map().read().unwrap();
+
+        self.trajectory.iter()
+            .map(|entry| {
+                let pose = map.keyframe(entry.reference)
+                    .and_then(|keyframe| keyframe.frame.pose)
+                    .map_or(entry.pose, |reference| entry.relative * reference);
+
+                (entry.timestamp, pose)
+            })
+            .collect()
+    }
+
+    /// Writes `trajectory` in the TUM format
+    pub fn save_trajectory(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        save_trajectory(path, &self.trajectory())
+    }
+}
@@ -0,0 +1,420 @@
+use crate::geometry::{SE3, SO3};
+use crate::linalg::{DMatrix, Mat3, Vec3};
+use crate::random::Rng;
+use crate::ransac::{ransac, Estimator, RansacConfig};
+
+/// Exponents of x, y and z of the monomials of degree up to three, in the
+/// order of Nistér's paper. The first ten are eliminated by Gauss-Jordan.
+const MONOMIALS: [(u8, u8, u8); 20] = [
+    (3, 0, 0), (0, 3, 0), (2, 1, 0), (1, 2, 0), (2, 0, 1),
+    (2, 0, 0), (0, 2, 1), (0, 2, 0), (1, 1, 1), (1, 1, 0),
+    (1, 0, 2), (1, 0, 1), (1, 0, 0), (0, 1, 2), (0, 1, 1),
+    (0, 1, 0), (0, 0, 3), (0, 0, 2), (0, 0, 1), (0, 0, 0)
+];
+
+const X: usize = 12;
+const Y: usize = 15;
+const Z: usize = 18;
+const ONE: usize = 19;
+
+/// Polynomial in x, y and z of degree at most three
+#[derive(Clone, Copy)]
+struct Poly3([f64; 20]);
+
+impl Poly3 {
+    fn zero() -> Self {
+        Self([0.0; 20])
+    }
+
+    fn add(&self, other: &Poly3) -> Poly3 {
+        Poly3(std::array::from_fn(|i| self.0[i] + other.0[i]))
+    }
+
+    fn sub(&self, other: &Poly3) -> Poly3 {
+        Poly3(std::array::from_fn(|i| self.0[i] - other.0[i]))
+    }
+
+    fn scale(&self, s: f64) -> Poly3 {
+        Poly3(self.0.map(|c| c * s))
+    }
+
+    /// Product, which must have degree at most three
+    fn mul(&self, other: &Poly3) -> Poly3 {
+        let mut result = Poly3::zero();
+
+        for (i, a) in self.0.iter().enumerate().filter(|(_, a)| **a != 0.0) {
+            for (j, b) in other.0.iter().enumerate().filter(|(_, b)| **b != 0.0) {
+                let (ai, bi, ci) = MONOMIALS[i];
+                let (aj, bj, cj) = MONOMIALS[j];
+                let exponents = (ai + aj, bi + bj, ci + cj);
+
+                let k = MONOMIALS.iter()
+                    .position(|&m| m == exponents)
+                    .expect("product of degree above three");
+
+                result.0[k] += a * b;
+            }
+        }
+
+        result
+    }
+}
+
+/// Univariate polynomial, coefficients in increasing degree
+fn poly_mul(a: &[f64], b: &[f64]) -> Vec<f64> {
+    let mut result = vec![0.0; a.len() + b.len() - 1];
+    for (i, a) in a.iter().enumerate() {
+        for (j, b) in b.iter().enumerate() {
+            result[i + j] += a * b;
+        }
+    }
+    result
+}
+
+fn poly_add(a: &[f64], b: &[f64], sign: f64) -> Vec<f64> {
+    let mut result = vec![0.0; a.len().max(b.len())];
+    for (i, a) in a.iter().enumerate() {
+        result[i] += a;
+    }
+    for (i, b) in b.iter().enumerate() {
+        result[i] += sign * b;
+    }
+    result
+}
+
+fn poly_eval(p: &[f64], x: f64) -> f64 {
+    p.iter().rev().fold(0.0, |acc, c| acc * x + c)
+}
+
+/// Real roots of a univariate polynomial. Roots are isolated between the
+/// real roots of the derivative and refined by bisection, so roots of even
+/// multiplicity may be missed.
+fn real_roots(p: &[f64]) -> Vec<f64> {
+    let scale = p.iter().fold(0.0f64, |m, c| m.max(c.abs()));
+    if scale == 0.0 {
+        return Vec::new();
+    }
+
+    let degree = match p.iter().rposition(|c| c.abs() > 1e-14 * scale) {
+        Some(degree) => degree,
+        None => return Vec::new()
+    };
+    let p = &p[..=degree];
+
+    match degree {
+        0 => return Vec::new(),
+        1 => return vec![-p[0] / p[1]],
+        _ => {}
+    }
+
+    // Cauchy bound on the magnitude of the roots
+    let bound = 1.0 + p[..degree].iter().map(|c| (c / p[degree]).abs()).fold(0.0, f64::max);
+
+    let derivative: Vec<f64> = (1..=degree).map(|i| i as f64 * p[i]).collect();
+    let mut points = vec![-bound];
+    points.extend(real_roots(&derivative).into_iter().filter(|x| x.abs() < bound));
+    points.push(bound);
+    points.sort_by(f64::total_cmp);
+
+    let mut roots = Vec::new();
+
+    for interval in points.windows(2) {
+        let (mut a, mut b) = (interval[0], interval[1]);
+        let (mut fa, fb) = (poly_eval(p, a), poly_eval(p, b));
+
+        if fa == 0.0 {
+            roots.push(a);
+            continue;
+        }
+
+        if fa.signum() == fb.signum() {
+            continue;
+        }
+
+        for _ in 0..100 {
+            let middle = 0.5 * (a + b);
+            let fm = poly_eval(p, middle);
+
+            if fm == 0.0 || b - a <= 1e-15 * middle.abs().max(1.0) {
+                a = middle;
+                b = middle;
+                break;
+            }
+
+            if fm.signum() == fa.signum() {
+                a = middle;
+                fa = fm;
+            } else {
+                b = middle;
+            }
+        }
+
+        roots.push(0.5 * (a + b));
+    }
+
+    roots
+}
+
+/// Nistér's five-point solver. Finds the up to ten essential matrices
+/// with `b2^T E b1 = 0` for five pairs of bearing vectors.
+pub fn five_point(bearings1: &[Vec3], bearings2: &[Vec3]) -> Vec<Mat3> {
+    assert!(bearings1.len() == 5 && bearings2.len() == 5);
+
+    // Four dimensional null space of the epipolar constraints
+    let mut a = DMatrix::zeros(5, 9);
+    for (row, (b1, b2)) in bearings1.iter().zip(bearings2).enumerate() {
+        for i in 0..3 {
+            for j in 0..3 {
+                a[(row, 3 * i + j)] = b2[i] * b1[j];
+            }
+        }
+    }
+
+    let svd = a.svd();
+    let basis: Vec<Vec<f64>> = (5..9).map(|j| svd.v.column(j)).collect();
+
+    // E = x X + y Y + z Z + W
+    let e: [[Poly3; 3]; 3] = std::array::from_fn(|i| {
+        std::array::from_fn(|j| {
+            let mut p = Poly3::zero();
+            p.0[X] = basis[0][3 * i + j];
+            p.0[Y] = basis[1][3 * i + j];
+            p.0[Z] = basis[2][3 * i + j];
+            p.0[ONE] = basis[3][3 * i + j];
+            p
+        })
+    });
+
+    let mut constraints = Vec::with_capacity(10);
+
+    let minor = |a: usize, b: usize, c: usize, d: usize| e[1][a].mul(&e[2][b]).sub(&e[1][c].mul(&e[2][d]));
+    let determinant = e[0][0].mul(&minor(1, 2, 2, 1))
+        .sub(&e[0][1].mul(&minor(0, 2, 2, 0)))
+        .add(&e[0][2].mul(&minor(0, 1, 1, 0)));
+    constraints.push(determinant);
+
+    // 2 E E^T E - trace(E E^T) E = 0
+    let eet: [[Poly3; 3]; 3] = std::array::from_fn(|i| {
+        std::array::from_fn(|j| {
+            (0..3).fold(Poly3::zero(), |acc, k| acc.add(&e[i][k].mul(&e[j][k])))
+        })
+    });
+    let trace = eet[0][0].add(&eet[1][1]).add(&eet[2][2]);
+
+    for (eet_row, e_row) in eet.iter().zip(&e) {
+        for (j, entry) in e_row.iter().enumerate() {
+            let product = (0..3).fold(Poly3::zero(), |acc, k| acc.add(&eet_row[k].mul(&e[k][j])));
+            constraints.push(product.scale(2.0).sub(&trace.mul(entry)));
+        }
+    }
+
+    // Gauss-Jordan elimination of the first ten monomials
+    let mut m: Vec<[f64; 20]> = constraints.iter().map(|c| c.0).collect();
+
+    for column in 0..10 {
+        let pivot = (column..10)
+            .max_by(|&a, &b| m[a][column].abs().total_cmp(&m[b][column].abs()))
+            .unwrap();
+
+        if m[pivot][column].abs() < 1e-12 {
+            return Vec::new();
+        }
+
+        m.swap(column, pivot);
+
+        let inverse = 1.0 / m[column][column];
+        for value in m[column].iter_mut() {
+            *value *= inverse;
+        }
+
+        let pivot_row = m[column];
+        for (row, values) in m.iter_mut().enumerate() {
+            let factor = values[column];
+            if row != column && factor != 0.0 {
+                for (value, p) in values[column..].iter_mut().zip(&pivot_row[column..]) {
+                    *value -= factor * p;
+                }
+            }
+        }
+    }
+
+    // Rows <e> - z<f>, <g> - z<h> and <i> - z<j> are linear in x and y with
+    // coefficients polynomial in z
+    let b = |row: usize, k: usize| m[row][10 + k];
+    let reduced: Vec<[Vec<f64>; 3]> = [(4, 5), (6, 7), (8, 9)]
+        .iter()
+        .map(|&(r, s)| [
+            vec![b(r, 2), b(r, 1) - b(s, 2), b(r, 0) - b(s, 1), -b(s, 0)],
+            vec![b(r, 5), b(r, 4) - b(s, 5), b(r, 3) - b(s, 4), -b(s, 3)],
+            vec![b(r, 9), b(r, 8) - b(s, 9), b(r, 7) - b(s, 8), b(r, 6) - b(s, 7), -b(s, 6)]
+        ])
+        .collect();
+
+    // The system has a solution where its determinant, of degree ten, vanishes
+    let cofactor = |c1: usize, c2: usize| {
+        poly_add(&poly_mul(&reduced[1][c1], &reduced[2][c2]), &poly_mul(&reduced[1][c2], &reduced[2][c1]), -1.0)
+    };
+    let determinant = poly_add(
+        &poly_add(&poly_mul(&reduced[0][0], &cofactor(1, 2)), &poly_mul(&reduced[0][1], &cofactor(0, 2)), -1.0),
+        &poly_mul(&reduced[0][2], &cofactor(0, 1)),
+        1.0
+    );
+
+    let mut solutions = Vec::new();
+
+    for z in real_roots(&determinant) {
+        let system = Mat3::from_rows(std::array::from_fn(|i| {
+            std::array::from_fn(|j| poly_eval(&reduced[i][j], z))
+        }));
+
+        let (_, _, v) = system.svd();
+        let solution = v.column(2);
+
+        if solution[2].abs() < 1e-12 {
+            continue;
+        }
+
+        let (x, y) = (solution[0] / solution[2], solution[1] / solution[2]);
+
+        let essential = Mat3::from_rows(std::array::from_fn(|i| {
+            std::array::from_fn(|j| {
+                x * basis[0][3 * i + j] + y * basis[1][3 * i + j] + z * basis[2][3 * i + j] + basis[3][3 * i + j]
+            })
+        }));
+
+        if essential.is_finite() {
+            solutions.push(essential * (1.0 / essential.norm()));
+        }
+    }
+
+    solutions
+}
+
+/// The four motions consistent with an essential matrix, mapping points
+/// from the first camera frame into the second
+pub fn decompose_essential(e: &Mat3) -> [SE3; 4] {
+    let (u, _, v) = e.svd();
+    let t = u.column(2).normalize();
+
+    let w = Mat3::from_rows([[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]);
+
+    let proper = |r: Mat3| if r.determinant() < 0.0 { -r } else { r };
+    let r1 = proper(u * w * v.transpose());
+    let r2 = proper(u * w.transpose() * v.transpose());
+
+    let (r1, r2) = (SO3::from_matrix(&r1), SO3::from_matrix(&r2));
+
+    [SE3::new(r1, t), SE3::new(r2, t), SE3::new(r1, -t), SE3::new(r2, -t)]
+}
+
+/// Depths along both bearing vectors of the point closest to the two rays
+pub fn ray_depths(pose: &SE3, bearing1: &Vec3, bearing2: &Vec3) -> Option<(f64, f64)> {
+    // Least squares solution of d1 R b1 + t = d2 b2
+    let a = pose.rotation * *bearing1;
+    let b = *bearing2;
+
+    let (aa, ab, bb) = (a.dot(&a), a.dot(&b), b.dot(&b));
+    let determinant = aa * bb - ab * ab;
+
+    if determinant < 1e-12 {
+        return None;
+    }
+
+    let (at, bt) = (a.dot(&pose.translation), b.dot(&pose.translation));
+    let d1 = (ab * bt - bb * at) / determinant;
+    let d2 = (aa * bt - ab * at) / determinant;
+
+    Some((d1, d2))
+}
+
+/// Chooses the decomposition of `e` that places the most points in front
+/// of both cameras. Returns the pose and that number of points.
+pub fn recover_pose(e: &Mat3, bearings1: &[Vec3], bearings2: &[Vec3], mask: &[bool]) -> (SE3, usize) {
+    decompose_essential(e)
+        .into_iter()
+        .map(|pose| {
+            let in_front = (0..bearings1.len())
+                .filter(|&i| mask[i])
+                .filter(|&i| ray_depths(&pose, &bearings1[i], &bearings2[i]).is_some_and(|(d1, d2)| d1 > 0.0 && d2 > 0.0))
+                .count();
+            (pose, in_front)
+        })
+        .max_by_key(|(_, in_front)| *in_front)
+        .unwrap()
+}
+
+/// Angle between a bearing vector and the epipolar plane it should lie on
+fn epipolar_angle(normal: &Vec3, bearing: &Vec3) -> f64 {
+    let norm = normal.norm();
+
+    if norm == 0.0 {
+        return f64::INFINITY;
+    }
+
+    (normal.dot(bearing) / (norm * bearing.norm())).abs().min(1.0).asin()
+}
+
+/// Five-point RANSAC problem over matched unit bearing vectors. Residuals
+/// are angles in radians between each bearing vector and its epipolar plane.
+pub struct EssentialEstimator<'a> {
+    pub bearings1: &'a [Vec3],
+    pub bearings2: &'a [Vec3]
+}
+
+impl Estimator for EssentialEstimator<'_> {
+    type Model = Mat3;
+
+    const SAMPLE_SIZE: usize = 5;
+
+    fn data_count(&self) -> usize {
+        self.bearings1.len()
+    }
+
+    fn fit(&self, sample: &[usize]) -> Vec<Mat3> {
+        let b1: Vec<Vec3> = sample.iter().map(|&i| self.bearings1[i]).collect();
+        let b2: Vec<Vec3> = sample.iter().map(|&i| self.bearings2[i]).collect();
+        five_point(&b1, &b2)
+    }
+
+    fn residual(&self, e: &Mat3, index: usize) -> f64 {
+        let b1 = &self.bearings1[index];
+        let b2 = &self.bearings2[index];
+
+        epipolar_angle(&(*e * *b1), b2).max(epipolar_angle(&(e.transpose() * *b2), b1))
+    }
+}
+
+/// Relative pose of two calibrated views, with unit translation
+pub struct RelativePose {
+    /// First camera frame to second camera frame
+    pub pose: SE3,
+    pub essential: Mat3,
+    pub inliers: Vec<bool>,
+    pub inlier_count: usize
+}
+
+/// Estimates the relative pose from matched unit bearing vectors with
+/// five-point RANSAC. Only inliers in front of both cameras are kept.
+pub fn estimate_relative_pose(
+    bearings1: &[Vec3],
+    bearings2: &[Vec3],
+    config: &RansacConfig,
+    rng: &mut Rng
+) -> Option<RelativePose> {
+    assert_eq!(bearings1.len(), bearings2.len());
+
+    let estimator = EssentialEstimator { bearings1, bearings2 };
+    let result = ransac(&estimator, config, rng)?;
+
+    let (pose, _) = recover_pose(&result.model, bearings1, bearings2, &result.inliers);
+
+    let inliers: Vec<bool> = (0..bearings1.len())
+        .map(|i| {
+            result.inliers[i] &&
+            ray_depths(&pose, &bearings1[i], &bearings2[i]).is_some_and(|(d1, d2)| d1 > 0.0 && d2 > 0.0)
+        })
+        .collect();
+    let inlier_count = inliers.iter().filter(|&&inlier| inlier).count();
+
+    Some(RelativePose { pose, essential: result.model, inliers, inlier_count })
+}
@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use crate::camera::CameraModel;
+use crate::geometry::SE3;
+use crate::imu::ImuState;
+use crate::linalg::{Vec2, Vec3};
+use crate::map::MapPointId;
+use crate::matcher::{match_windows, KeypointGrid, SearchWindow, WindowMatch};
+use crate::orb::{CornerData, CornerDescriptor};
+use crate::vocabulary::{BowVector, FeatureVector, Vocabulary};
+
+/// Scale of each octave of the image hierarchy relative to level 0,
+/// and the variance of keypoint positions detected at that octave.
+#[derive(Clone, Debug)]
+pub struct ScalePyramid {
+    pub scale_factor: f64,
+    pub scale_factors: Vec<f64>,
+    pub inv_scale_factors: Vec<f64>,
+    pub level_sigma2: Vec<f64>,
+    pub inv_level_sigma2: Vec<f64>
+}
+
+impl ScalePyramid {
+    pub fn new(levels: u32, scale_factor: f64) -> Self {
+        let scale_factors: Vec<f64> = (0..levels).map(|i| scale_factor.powi(i as i32)).collect();
+        let level_sigma2: Vec<f64> = scale_factors.iter().map(|s| s * s).collect();
+
+        Self {
+            scale_factor,
+            inv_scale_factors: scale_factors.iter().map(|s| 1.0 / s).collect(),
+            inv_level_sigma2: level_sigma2.iter().map(|s| 1.0 / s).collect(),
+            scale_factors,
+            level_sigma2
+        }
+    }
+
+    pub fn levels(&self) -> u32 {
+        self.scale_factors.len() as u32
+    }
+}
+
+/// Depth measured for the keypoints of a frame by a rectified stereo rig,
+/// or by a depth camera as if it were one.
+#[derive(Clone, Debug)]
+pub struct StereoDepth {
+    /// Distance between the optical centres of the two cameras, in metres
+    pub baseline: f64,
+    /// Keypoints closer than this are close points, whose depth is reliable
+    /// enough to create map points from a single frame
+    pub close_depth: f64,
+    /// Level-0 undistorted x coordinate of each keypoint in the right image
+    pub right: Vec<Option<f64>>,
+    /// Depth of each keypoint, in metres
+    pub depths: Vec<Option<f64>>
+}
+
+/// Features extracted from one image, with the camera that took it.
+#[derive(Clone)]
+pub struct Frame {
+    pub id: u64,
+    /// Seconds
+    pub timestamp: f64,
+    pub camera: Arc<dyn CameraModel>,
+    pub image_size: wgpu::Extent3d,
+    pub corners: Vec<CornerData>,
+    /// Level-0 pixel positions as detected
+    pub keypoints: Vec<Vec2>,
+    /// Keypoint positions in an ideal pinhole camera
+    pub undistorted: Vec<Vec2>,
+    pub descriptors: Vec<CornerDescriptor>,
+    pub scale: ScalePyramid,
+    /// World to camera transformation, once estimated
+    pub pose: Option<SE3>,
+    /// Map point observed by each keypoint
+    pub map_points: Vec<Option<MapPointId>>,
+    /// Map point associations rejected by pose optimisation
+    pub outliers: Vec<bool>,
+    /// Bag of words representation, once computed
+    pub bow: Option<BowVector>,
+    /// Keypoints grouped by vocabulary node, once computed
+    pub features: Option<FeatureVector>,
+    /// Keypoint depths of stereo and RGB-D frames
+    pub stereo: Option<StereoDepth>,
+    /// Velocity, biases and preintegration of visual-inertial frames
+    pub imu: Option<ImuState>,
+    grid: KeypointGrid
+}
+
+impl Frame {
+    pub fn new(
+        id: u64,
+        timestamp: f64,
+        camera: Arc<dyn CameraModel>,
+        image_size: wgpu::Extent3d,
+        scale: ScalePyramid,
+        corners: Vec<CornerData>,
+        descriptors: Vec<CornerDescriptor>
+    ) -> Self {
+        let keypoints: Vec<Vec2> = corners.iter()
+            .map(|c| {
+                let [x, y] = c.scaled_position();
+                Vec2::new(x as f64, y as f64)
+            })
+            .collect();
+
+        let undistorted: Vec<Vec2> = keypoints.iter().map(|p| camera.undistort(p)).collect();
+
+        let grid = KeypointGrid::new(
+            image_size,
+            undistorted.iter()
+                .zip(&corners)
+                .map(|(p, c)| ([p.x() as f32, p.y() as f32], c.octave))
+                .collect()
+        );
+
+        let count = corners.len();
+
+        Self {
+            id,
+            timestamp,
+            camera,
+            image_size,
+            corners,
+            keypoints,
+            undistorted,
+            descriptors,
+            scale,
+            pose: None,
+            map_points: vec![None; count],
+            outliers: vec![false; count],
+            bow: None,
+            features: None,
+            stereo: None,
+            imu: None,
+            grid
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.corners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.corners.is_empty()
+    }
+
+    pub fn octave(&self, i: usize) -> u32 {
+        self.corners[i].octave
+    }
+
+    /// Indices of keypoints whose undistorted position is within `radius`
+    /// of `(x, y)` and whose octave is in `min_octave..=max_octave`
+    pub fn features_in_area(&self, x: f64, y: f64, radius: f64, min_octave: u32, max_octave: u32) -> Vec<usize> {
+        let window = SearchWindow {
+            x: x as f32,
+            y: y as f32,
+            radius: radius as f32,
+            min_octave,
+            max_octave
+        };
+
+        self.grid.query(&window).map(|i| i as usize).collect()
+    }
+
+    /// CPU counterpart of `OrbProgram::match_windows` over the undistorted
+    /// keypoints of this frame
+    pub fn match_windows(&self, windows: &[SearchWindow], queries: &[CornerDescriptor], dst: &mut [WindowMatch]) {
+        match_windows(&self.grid, &self.descriptors, windows, queries, dst);
+    }
+
+    /// Computes `bow` and `features`, with features grouped at the vocabulary
+    /// node `levels_up` levels above the words
+    pub fn compute_bow(&mut self, vocabulary: &Vocabulary, levels_up: u32) {
+        if self.bow.is_none() {
+            let (bow, features) = vocabulary.transform(&self.descriptors, levels_up);
+            self.bow = Some(bow);
+            self.features = Some(features);
+        }
+    }
+
+    /// Sets the right image coordinates of the keypoints of a rectified
+    /// stereo frame, from which their depths follow. The camera must be the
+    /// rectified pinhole camera.
+    pub fn set_stereo(&mut self, baseline: f64, close_depth: f64, right: Vec<Option<f64>>) {
+        assert_eq!(right.len(), self.len());
+
+        let fx = self.camera.pinhole().fx;
+        let depths = right.iter()
+            .zip(&self.undistorted)
+            .map(|(right, left)| right.map(|right| fx * baseline / (left.x() - right)))
+            .collect();
+
+        self.stereo = Some(StereoDepth { baseline, close_depth, right, depths });
+    }
+
+    /// Sets the depths of the keypoints of an RGB-D frame, with right image
+    /// coordinates of a virtual stereo rig with the given baseline
+    pub fn set_depths(&mut self, baseline: f64, close_depth: f64, depths: Vec<Option<f64>>) {
+        assert_eq!(depths.len(), self.len());
+
+        let fx = self.camera.pinhole().fx;
+        let right = depths.iter()
+            .zip(&self.undistorted)
+            .map(|(depth, left)| depth.map(|depth| left.x() - fx * baseline / depth))
+            .collect();
+
+        self.stereo = Some(StereoDepth { baseline, close_depth, right, depths });
+    }
+
+    pub fn depth(&self, i: usize) -> Option<f64> {
+        self.stereo.as_ref()?.depths[i]
+    }
+
+    /// Whether keypoint `i` has a depth below the close point threshold
+    pub fn is_close(&self, i: usize) -> bool {
+        self.stereo.as_ref().is_some_and(|stereo| stereo.depths[i].is_some_and(|depth| depth < stereo.close_depth))
+    }
+
+    /// World position of keypoint `i` from its depth, once the frame has
+    /// a pose
+    pub fn unproject_stereo(&self, i: usize) -> Option<Vec3> {
+        let depth = self.depth(i)?;
+        let pose = self.pose?;
+
+        let normalized = self.camera.pinhole().normalize(&self.undistorted[i]);
+        let point = Vec3::new(normalized.x(), normalized.y(), 1.0) * depth;
+
+        Some(pose.inverse().transform(&point))
+    }
+
+    /// Close keypoints matching an inlier map point, and close keypoints
+    /// without one, for `TrackingStatistics`
+    pub fn close_point_counts(&self) -> (usize, usize) {
+        let mut counts = (0, 0);
+
+        for i in (0..self.len()).filter(|&i| self.is_close(i)) {
+            if self.map_points[i].is_some() && !self.outliers[i] {
+                counts.0 += 1;
+            } else {
+                counts.1 += 1;
+            }
+        }
+
+        counts
+    }
+
+    /// Whether a pixel lies inside the image bounds
+    pub fn is_in_image(&self, pixel: &Vec2) -> bool {
+        pixel.x() >= 0.0 && pixel.y() >= 0.0 &&
+        pixel.x() < self.image_size.width as f64 && pixel.y() < self.image_size.height as f64
+    }
+}
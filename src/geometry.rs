@@ -0,0 +1,545 @@
+use std::ops::Mul;
+
+use crate::linalg::{DMatrix, Mat3, Mat3x4, Mat6, Mat7, Matrix, Vec2, Vec3, Vec6, Vec7};
+
+/// Below this angle, closed forms are replaced by Taylor expansions
+const SMALL_ANGLE: f64 = 1e-6;
+
+/// Inverse of `Vec3::hat`
+fn vee(m: &Mat3) -> Vec3 {
+    Vec3::new(m[(2, 1)], m[(0, 2)], m[(1, 0)])
+}
+
+/// `sum_k ad^k / (k + 1)!`, the left Jacobian of any matrix Lie group in
+/// terms of the matrix of its adjoint action on the algebra
+fn jacobian_series<const N: usize>(ad: &Matrix<N, N>) -> Matrix<N, N> {
+    let mut result = Matrix::<N, N>::identity();
+    let mut term = Matrix::<N, N>::identity();
+
+    for k in 1..40 {
+        term = term * *ad * (1.0 / (k + 1) as f64);
+        result += term;
+
+        if term.norm() < 1e-17 {
+            break;
+        }
+    }
+
+    result
+}
+
+/// Rotation in three dimensions, with tangent vectors given as rotation
+/// vectors (axis times angle).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SO3 {
+    matrix: Mat3
+}
+
+impl SO3 {
+    pub fn identity() -> Self {
+        Self { matrix: Mat3::identity() }
+    }
+
+    /// Nearest rotation to a matrix in the Frobenius norm
+    pub fn from_matrix(matrix: &Mat3) -> Self {
+        let (u, _, v) = matrix.svd();
+        let sign = (u * v.transpose()).determinant().signum();
+
+        Self { matrix: u * Mat3::from_diagonal(&Vec3::new(1.0, 1.0, sign)) * v.transpose() }
+    }
+
+    /// Rotation of a quaternion `[w, x, y, z]`, which is normalised first
+    pub fn from_quaternion(quaternion: [f64; 4]) -> Self {
+        let norm = quaternion.iter().map(|q| q * q).sum::<f64>().sqrt();
+        let [w, x, y, z] = quaternion.map(|q| q / norm);
+
+        Self {
+            matrix: Mat3::from_rows([
+                [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y)],
+                [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x)],
+                [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y)]
+            ])
+        }
+    }
+
+    /// Unit quaternion `[w, x, y, z]` with `w >= 0`, by Shepperd's method
+    pub fn to_quaternion(&self) -> [f64; 4] {
+        let r = &self.matrix;
+        let trace = r.trace();
+
+        let q = if trace > r[(0, 0)].max(r[(1, 1)]).max(r[(2, 2)]) {
+            let w = (1.0 + trace).sqrt() * 0.5;
+            let k = 0.25 / w;
+            [w, (r[(2, 1)] - r[(1, 2)]) * k, (r[(0, 2)] - r[(2, 0)]) * k, (r[(1, 0)] - r[(0, 1)]) * k]
+        } else if r[(0, 0)] >= r[(1, 1)] && r[(0, 0)] >= r[(2, 2)] {
+            let x = (1.0 + 2.0 * r[(0, 0)] - trace).sqrt() * 0.5;
+            let k = 0.25 / x;
+            [(r[(2, 1)] - r[(1, 2)]) * k, x, (r[(0, 1)] + r[(1, 0)]) * k, (r[(0, 2)] + r[(2, 0)]) * k]
+        } else if r[(1, 1)] >= r[(2, 2)] {
+            let y = (1.0 + 2.0 * r[(1, 1)] - trace).sqrt() * 0.5;
+            let k = 0.25 / y;
+            [(r[(0, 2)] - r[(2, 0)]) * k, (r[(0, 1)] + r[(1, 0)]) * k, y, (r[(1, 2)] + r[(2, 1)]) * k]
+        } else {
+            let z = (1.0 + 2.0 * r[(2, 2)] - trace).sqrt() * 0.5;
+            let k = 0.25 / z;
+            [(r[(1, 0)] - r[(0, 1)]) * k, (r[(0, 2)] + r[(2, 0)]) * k, (r[(1, 2)] + r[(2, 1)]) * k, z]
+        };
+
+        if q[0] < 0.0 { q.map(|v| -v) } else { q }
+    }
+
+    pub fn matrix(&self) -> Mat3 {
+        self.matrix
+    }
+
+    /// Rodrigues' formula
+    pub fn exp(omega: &Vec3) -> Self {
+        let theta2 = omega.norm_squared();
+        let k = omega.hat();
+
+        // sin(theta) / theta and (1 - cos(theta)) / theta^2
+        let (a, b) = if theta2 < SMALL_ANGLE * SMALL_ANGLE {
+            (1.0 - theta2 / 6.0, 0.5 - theta2 / 24.0)
+        } else {
+            let theta = theta2.sqrt();
+            (theta.sin() / theta, (1.0 - theta.cos()) / theta2)
+        };
+
+        Self { matrix: Mat3::identity() + k * a + k * k * b }
+    }
+
+    /// Rotation vector with angle in [0, pi]
+    pub fn log(&self) -> Vec3 {
+        let r = &self.matrix;
+
+        // sin(theta) times the axis, and cos(theta)
+        let sin_axis = vee(&(*r - r.transpose())) * 0.5;
+        let cos = (r.trace() - 1.0) / 2.0;
+        let theta = sin_axis.norm().atan2(cos);
+
+        if theta < SMALL_ANGLE {
+            return sin_axis * (1.0 + theta * theta / 6.0);
+        }
+
+        if std::f64::consts::PI - theta < 1e-4 {
+            // The symmetric part is cos(theta) I + (1 - cos(theta)) a a^T, so
+            // its largest column gives the axis up to sign
+            let outer = (*r + r.transpose()) * 0.5 - Mat3::identity() * cos;
+            let k = (0..3).max_by(|&i, &j| outer[(i, i)].total_cmp(&outer[(j, j)])).unwrap();
+            let mut axis = outer.column(k).normalize();
+
+            if sin_axis.dot(&axis) < 0.0 {
+                axis = -axis;
+            }
+
+            return axis * theta;
+        }
+
+        sin_axis * (theta / theta.sin())
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self { matrix: self.matrix.transpose() }
+    }
+
+    /// `R exp(w) R^T = exp(Ad w)`
+    pub fn adjoint(&self) -> Mat3 {
+        self.matrix
+    }
+
+    /// Geodesic from `self` (t = 0) to `other` (t = 1)
+    pub fn interpolate(&self, other: &SO3, t: f64) -> SO3 {
+        *self * SO3::exp(&((self.inverse() * *other).log() * t))
+    }
+
+    /// `exp(w + d) ~ exp(J_l(w) d) exp(w)` for small `d`
+    pub fn left_jacobian(omega: &Vec3) -> Mat3 {
+        let theta2 = omega.norm_squared();
+        let k = omega.hat();
+
+        // (1 - cos(theta)) / theta^2 and (theta - sin(theta)) / theta^3
+        let (a, b) = if theta2 < SMALL_ANGLE * SMALL_ANGLE {
+            (0.5 - theta2 / 24.0, 1.0 / 6.0 - theta2 / 120.0)
+        } else {
+            let theta = theta2.sqrt();
+            ((1.0 - theta.cos()) / theta2, (theta - theta.sin()) / (theta2 * theta))
+        };
+
+        Mat3::identity() + k * a + k * k * b
+    }
+
+    pub fn left_jacobian_inverse(omega: &Vec3) -> Mat3 {
+        let theta2 = omega.norm_squared();
+        let k = omega.hat();
+
+        let b = if theta2 < SMALL_ANGLE * SMALL_ANGLE {
+            1.0 / 12.0 + theta2 / 720.0
+        } else {
+            let theta = theta2.sqrt();
+            1.0 / theta2 - (1.0 + theta.cos()) / (2.0 * theta * theta.sin())
+        };
+
+        Mat3::identity() - k * 0.5 + k * k * b
+    }
+
+    /// `exp(w + d) ~ exp(w) exp(J_r(w) d)` for small `d`
+    pub fn right_jacobian(omega: &Vec3) -> Mat3 {
+        Self::left_jacobian(&(-*omega))
+    }
+
+    pub fn right_jacobian_inverse(omega: &Vec3) -> Mat3 {
+        Self::left_jacobian_inverse(&(-*omega))
+    }
+}
+
+impl Mul for SO3 {
+    type Output = SO3;
+
+    fn mul(self, other: SO3) -> SO3 {
+        SO3 { matrix: self.matrix * other.matrix }
+    }
+}
+
+impl Mul<Vec3> for SO3 {
+    type Output = Vec3;
+
+    fn mul(self, point: Vec3) -> Vec3 {
+        self.matrix * point
+    }
+}
+
+/// Rigid body transformation `x' = R x + t`. Frame poses map world
+/// points into the camera frame. Tangent vectors are ordered as
+/// `[omega, v]`, rotation first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SE3 {
+    pub rotation: SO3,
+    pub translation: Vec3
+}
+
+impl SE3 {
+    pub fn new(rotation: SO3, translation: Vec3) -> Self {
+        Self { rotation, translation }
+    }
+
+    pub fn identity() -> Self {
+        Self { rotation: SO3::identity(), translation: Vec3::zeros() }
+    }
+
+    pub fn exp(xi: &Vec6) -> Self {
+        let omega: Vec3 = xi.segment(0);
+        let v: Vec3 = xi.segment(3);
+
+        Self {
+            rotation: SO3::exp(&omega),
+            translation: SO3::left_jacobian(&omega) * v
+        }
+    }
+
+    pub fn log(&self) -> Vec6 {
+        let omega = self.rotation.log();
+        let v = SO3::left_jacobian_inverse(&omega) * self.translation;
+
+        let mut xi = Vec6::zeros();
+        xi.set_segment(0, &omega);
+        xi.set_segment(3, &v);
+        xi
+    }
+
+    pub fn transform(&self, point: &Vec3) -> Vec3 {
+        self.rotation * *point + self.translation
+    }
+
+    pub fn inverse(&self) -> Self {
+        let rotation = self.rotation.inverse();
+        Self { rotation, translation: -(rotation * self.translation) }
+    }
+
+    /// Position of the camera in the world for a world-to-camera pose
+    pub fn camera_center(&self) -> Vec3 {
+        -(self.rotation.inverse() * self.translation)
+    }
+
+    /// Applies a small motion on the left, `exp(delta) * self`
+    pub fn retract(&self, delta: &Vec6) -> SE3 {
+        SE3::exp(delta) * *self
+    }
+
+    /// `T exp(xi) T^-1 = exp(Ad xi)`
+    pub fn adjoint(&self) -> Mat6 {
+        let r = self.rotation.matrix();
+
+        let mut result = Mat6::zeros();
+        result.set_block(0, 0, &r);
+        result.set_block(3, 0, &(self.translation.hat() * r));
+        result.set_block(3, 3, &r);
+        result
+    }
+
+    /// Geodesic from `self` (t = 0) to `other` (t = 1)
+    pub fn interpolate(&self, other: &SE3, t: f64) -> SE3 {
+        *self * SE3::exp(&((self.inverse() * *other).log() * t))
+    }
+
+    /// `exp(xi + d) ~ exp(J_l(xi) d) exp(xi)` for small `d`
+    pub fn left_jacobian(xi: &Vec6) -> Mat6 {
+        let omega: Vec3 = xi.segment(0);
+        let j = SO3::left_jacobian(&omega);
+
+        let mut result = Mat6::zeros();
+        result.set_block(0, 0, &j);
+        result.set_block(3, 0, &translation_jacobian(xi));
+        result.set_block(3, 3, &j);
+        result
+    }
+
+    pub fn left_jacobian_inverse(xi: &Vec6) -> Mat6 {
+        let omega: Vec3 = xi.segment(0);
+        let j_inverse = SO3::left_jacobian_inverse(&omega);
+
+        let mut result = Mat6::zeros();
+        result.set_block(0, 0, &j_inverse);
+        result.set_block(3, 0, &(-(j_inverse * translation_jacobian(xi) * j_inverse)));
+        result.set_block(3, 3, &j_inverse);
+        result
+    }
+
+    /// `exp(xi + d) ~ exp(xi) exp(J_r(xi) d)` for small `d`
+    pub fn right_jacobian(xi: &Vec6) -> Mat6 {
+        Self::left_jacobian(&(-*xi))
+    }
+
+    pub fn right_jacobian_inverse(xi: &Vec6) -> Mat6 {
+        Self::left_jacobian_inverse(&(-*xi))
+    }
+
+    /// `[R | t]` as a 3x4 matrix
+    pub fn matrix(&self) -> Mat3x4 {
+        let mut result = Mat3x4::zeros();
+        result.set_block(0, 0, &self.rotation.matrix());
+        result.set_block(0, 3, &self.translation);
+        result
+    }
+}
+
+/// Coupling block `Q` of the SE(3) left Jacobian (Barfoot, 2017, eq. 7.86)
+fn translation_jacobian(xi: &Vec6) -> Mat3 {
+    let omega: Vec3 = xi.segment(0);
+    let v: Vec3 = xi.segment(3);
+
+    let theta2 = omega.norm_squared();
+    let w = omega.hat();
+    let p = v.hat();
+
+    let (a, b, c) = if theta2 < SMALL_ANGLE * SMALL_ANGLE {
+        (1.0 / 6.0, 1.0 / 24.0, 1.0 / 120.0)
+    } else {
+        let theta = theta2.sqrt();
+        let (sin, cos) = theta.sin_cos();
+        (
+            (theta - sin) / (theta2 * theta),
+            (theta2 + 2.0 * cos - 2.0) / (2.0 * theta2 * theta2),
+            (2.0 * theta - 3.0 * sin + theta * cos) / (2.0 * theta2 * theta2 * theta)
+        )
+    };
+
+    p * 0.5 +
+        (w * p + p * w + w * p * w) * a +
+        (w * w * p + p * w * w - w * p * w * 3.0) * b +
+        (w * p * w * w + w * w * p * w) * c
+}
+
+impl Mul for SE3 {
+    type Output = SE3;
+
+    /// `(a * b).transform(x) == a.transform(&b.transform(x))`
+    fn mul(self, other: SE3) -> SE3 {
+        SE3 {
+            rotation: self.rotation * other.rotation,
+            translation: self.rotation * other.translation + self.translation
+        }
+    }
+}
+
+/// Similarity transformation `x' = s R x + t`, as estimated between the
+/// two ends of a monocular loop. Tangent vectors are ordered as
+/// `[omega, v, sigma]` with `s = exp(sigma)`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sim3 {
+    pub rotation: SO3,
+    pub translation: Vec3,
+    pub scale: f64
+}
+
+/// Coefficients of `W = a [w]x + b [w]x^2 + c I`, which maps the
+/// translational part of a Sim(3) tangent vector to the translation
+fn sim3_w(omega: &Vec3, sigma: f64) -> Mat3 {
+    let theta2 = omega.norm_squared();
+    let theta = theta2.sqrt();
+    let s = sigma.exp();
+
+    let (a, b, c) = if sigma.abs() < SMALL_ANGLE {
+        if theta < SMALL_ANGLE {
+            (0.5, 1.0 / 6.0, 1.0)
+        } else {
+            ((1.0 - theta.cos()) / theta2, (theta - theta.sin()) / (theta2 * theta), 1.0)
+        }
+    } else {
+        let c = (s - 1.0) / sigma;
+
+        if theta < SMALL_ANGLE {
+            let sigma2 = sigma * sigma;
+            (
+                ((sigma - 1.0) * s + 1.0) / sigma2,
+                ((0.5 * sigma2 - sigma + 1.0) * s - 1.0) / (sigma2 * sigma),
+                c
+            )
+        } else {
+            let a = s * theta.sin();
+            let b = s * theta.cos();
+            let d = theta2 + sigma * sigma;
+            (
+                (a * sigma + (1.0 - b) * theta) / (theta * d),
+                (c - ((b - 1.0) * sigma + a * theta) / d) / theta2,
+                c
+            )
+        }
+    };
+
+    let w = omega.hat();
+    w * a + w * w * b + Mat3::identity() * c
+}
+
+impl Sim3 {
+    pub fn new(rotation: SO3, translation: Vec3, scale: f64) -> Self {
+        Self { rotation, translation, scale }
+    }
+
+    pub fn identity() -> Self {
+        Self { rotation: SO3::identity(), translation: Vec3::zeros(), scale: 1.0 }
+    }
+
+    /// Rigid body transformation with unit scale
+    pub fn from_se3(pose: &SE3) -> Self {
+        Self { rotation: pose.rotation, translation: pose.translation, scale: 1.0 }
+    }
+
+    /// Rigid body part, with the translation divided by the scale as in
+    /// ORB-SLAM's correction of keyframe poses after a loop closure
+    pub fn to_se3(&self) -> SE3 {
+        SE3 { rotation: self.rotation, translation: self.translation * (1.0 / self.scale) }
+    }
+
+    pub fn exp(xi: &Vec7) -> Self {
+        let omega: Vec3 = xi.segment(0);
+        let v: Vec3 = xi.segment(3);
+        let sigma = xi[6];
+
+        Self {
+            rotation: SO3::exp(&omega),
+            translation: sim3_w(&omega, sigma) * v,
+            scale: sigma.exp()
+        }
+    }
+
+    pub fn log(&self) -> Vec7 {
+        let omega = self.rotation.log();
+        let sigma = self.scale.ln();
+        let v = sim3_w(&omega, sigma).try_inverse().unwrap_or(Mat3::identity()) * self.translation;
+
+        let mut xi = Vec7::zeros();
+        xi.set_segment(0, &omega);
+        xi.set_segment(3, &v);
+        xi[6] = sigma;
+        xi
+    }
+
+    pub fn transform(&self, point: &Vec3) -> Vec3 {
+        (self.rotation * *point) * self.scale + self.translation
+    }
+
+    pub fn inverse(&self) -> Self {
+        let rotation = self.rotation.inverse();
+        let scale = 1.0 / self.scale;
+
+        Self { rotation, translation: -(rotation * self.translation) * scale, scale }
+    }
+
+    /// `S exp(xi) S^-1 = exp(Ad xi)`
+    pub fn adjoint(&self) -> Mat7 {
+        let r = self.rotation.matrix();
+
+        let mut result = Mat7::zeros();
+        result.set_block(0, 0, &r);
+        result.set_block(3, 0, &(self.translation.hat() * r));
+        result.set_block(3, 3, &(r * self.scale));
+        result.set_block(3, 6, &(-self.translation));
+        result[(6, 6)] = 1.0;
+        result
+    }
+
+    /// Geodesic from `self` (t = 0) to `other` (t = 1)
+    pub fn interpolate(&self, other: &Sim3, t: f64) -> Sim3 {
+        *self * Sim3::exp(&((self.inverse() * *other).log() * t))
+    }
+
+    /// Matrix of the adjoint action of the algebra, `[xi, d] = ad(xi) d`
+    pub fn ad(xi: &Vec7) -> Mat7 {
+        let omega: Vec3 = xi.segment(0);
+        let v: Vec3 = xi.segment(3);
+        let w = omega.hat();
+
+        let mut result = Mat7::zeros();
+        result.set_block(0, 0, &w);
+        result.set_block(3, 0, &v.hat());
+        result.set_block(3, 3, &(w + Mat3::identity() * xi[6]));
+        result.set_block(3, 6, &(-v));
+        result
+    }
+
+    /// `exp(xi + d) ~ exp(J_l(xi) d) exp(xi)` for small `d`, evaluated as a
+    /// power series
+    pub fn left_jacobian(xi: &Vec7) -> Mat7 {
+        jacobian_series(&Self::ad(xi))
+    }
+
+    /// `exp(xi + d) ~ exp(xi) exp(J_r(xi) d)` for small `d`
+    pub fn right_jacobian(xi: &Vec7) -> Mat7 {
+        jacobian_series(&Self::ad(&(-*xi)))
+    }
+}
+
+impl Mul for Sim3 {
+    type Output = Sim3;
+
+    fn mul(self, other: Sim3) -> Sim3 {
+        Sim3 {
+            rotation: self.rotation * other.rotation,
+            translation: (self.rotation * other.translation) * self.scale + self.translation,
+            scale: self.scale * other.scale
+        }
+    }
+}
+
+/// Linear triangulation of a point seen at normalised image coordinates
+/// `x1` and `x2` by cameras with projection matrices `p1` and `p2`.
+/// Returns `None` for points at infinity.
+pub fn triangulate(p1: &Mat3x4, p2: &Mat3x4, x1: &Vec2, x2: &Vec2) -> Option<Vec3> {
+    let mut a = DMatrix::zeros(4, 4);
+
+    for j in 0..4 {
+        a[(0, j)] = x1.x() * p1[(2, j)] - p1[(0, j)];
+        a[(1, j)] = x1.y() * p1[(2, j)] - p1[(1, j)];
+        a[(2, j)] = x2.x() * p2[(2, j)] - p2[(0, j)];
+        a[(3, j)] = x2.y() * p2[(2, j)] - p2[(1, j)];
+    }
+
+    let x = a.null_vector();
+
+    if x[3].abs() < 1e-12 {
+        return None;
+    }
+
+    let point = Vec3::new(x[0] / x[3], x[1] / x[3], x[2] / x[3]);
+    point.is_finite().then_some(point)
+}
@@ -0,0 +1,476 @@
+use std::io;
+use std::path::Path;
+
+/// Interleaved image samples, row-major without padding.
+#[derive(Clone)]
+pub struct Image<T> {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u32,
+    pub data: Vec<T>
+}
+
+pub enum DecodedImage {
+    U8(Image<u8>),
+    U16(Image<u16>)
+}
+
+impl DecodedImage {
+    pub fn width(&self) -> u32 {
+        match self {
+            DecodedImage::U8(image) => image.width,
+            DecodedImage::U16(image) => image.width
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        match self {
+            DecodedImage::U8(image) => image.height,
+            DecodedImage::U16(image) => image.height
+        }
+    }
+
+    /// Converts to the layout expected by `OrbProgram::write_input_image`.
+    /// 16 bit samples keep their most significant byte.
+    pub fn to_rgba8(&self) -> Image<u8> {
+        let image = match self {
+            DecodedImage::U8(image) => image.clone(),
+            DecodedImage::U16(image) => Image {
+                width: image.width,
+                height: image.height,
+                channels: image.channels,
+                data: image.data.iter().map(|&v| (v >> 8) as u8).collect()
+            }
+        };
+
+        let mut data = Vec::with_capacity(image.data.len() / image.channels as usize * 4);
+
+        for pixel in image.data.chunks_exact(image.channels as usize) {
+            match *pixel {
+                [g] => data.extend_from_slice(&[g, g, g, 255]),
+                [g, a] => data.extend_from_slice(&[g, g, g, a]),
+                [r, g, b] => data.extend_from_slice(&[r, g, b, 255]),
+                [r, g, b, a] => data.extend_from_slice(&[r, g, b, a]),
+                _ => unreachable!()
+            }
+        }
+
+        Image { width: image.width, height: image.height, channels: 4, data }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Loads a PNG or binary PNM (PGM / PPM) image, chosen by file extension.
+pub fn load(path: impl AsRef<Path>) -> io::Result<DecodedImage> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("png") => decode_png(&bytes),
+        Some("pgm" | "ppm" | "pnm") => decode_pnm(&bytes),
+        _ => Err(io::Error::new(io::ErrorKind::Unsupported, "unsupported image format"))
+    }
+}
+
+pub fn is_supported(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref(),
+        Some("png" | "pgm" | "ppm" | "pnm")
+    )
+}
+
+/// Decodes binary greyscale (P5) and colour (P6) portable anymaps.
+pub fn decode_pnm(bytes: &[u8]) -> io::Result<DecodedImage> {
+    let mut pos = 0;
+    let mut header = Vec::new();
+
+    // Magic number, width, height and maximum value separated by whitespace and comments
+    while header.len() < 4 {
+        while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'#') {
+            if bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            pos += 1;
+        }
+
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+
+        if start == pos {
+            return Err(invalid("truncated PNM header"));
+        }
+
+        header.push(std::str::from_utf8(&bytes[start..pos]).map_err(|_| invalid("invalid PNM header"))?);
+    }
+
+    // Exactly one whitespace byte separates the header from the samples
+    pos += 1;
+
+    let channels = match header[0] {
+        "P5" => 1,
+        "P6" => 3,
+        _ => return Err(invalid("only binary PGM and PPM are supported"))
+    };
+
+    let parse = |s: &str| s.parse::<u32>().map_err(|_| invalid("invalid PNM header"));
+    let width = parse(header[1])?;
+    let height = parse(header[2])?;
+    let max_value = parse(header[3])?;
+
+    let bytes_per_sample = if max_value < 256 { 1 } else { 2 };
+    let sample_count = (width as usize).checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(channels as usize))
+        .filter(|count| count.checked_mul(bytes_per_sample).is_some())
+        .ok_or_else(|| invalid("PNM image too large"))?;
+    let samples = bytes.get(pos..).unwrap_or_default();
+
+    if bytes_per_sample == 1 {
+        let data = samples.get(..sample_count).ok_or_else(|| invalid("truncated PNM data"))?.to_vec();
+        Ok(DecodedImage::U8(Image { width, height, channels, data }))
+    } else {
+        let data = samples.get(..sample_count * 2).ok_or_else(|| invalid("truncated PNM data"))?
+            .chunks_exact(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .collect();
+        Ok(DecodedImage::U16(Image { width, height, channels, data }))
+    }
+}
+
+/// Decodes non-interlaced PNG images with a bit depth of 8 or 16.
+/// Palette images are expanded to RGB.
+pub fn decode_png(bytes: &[u8]) -> io::Result<DecodedImage> {
+    const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+    if bytes.get(..8) != Some(&SIGNATURE) {
+        return Err(invalid("missing PNG signature"));
+    }
+
+    let mut pos = 8;
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut compressed = Vec::new();
+
+    while pos + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let kind = &bytes[pos + 4..pos + 8];
+        let data = bytes.get(pos + 8..pos + 8 + length).ok_or_else(|| invalid("truncated PNG chunk"))?;
+
+        match kind {
+            b"IHDR" => header = Some(data),
+            b"PLTE" => palette = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+
+        // Skip the chunk data and CRC
+        pos += 12 + length;
+    }
+
+    let header = header.filter(|h| h.len() == 13).ok_or_else(|| invalid("missing PNG header"))?;
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap());
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    let bit_depth = header[8] as u32;
+    let color_type = header[9];
+
+    if header[12] != 0 {
+        return Err(invalid("interlaced PNG images are not supported"));
+    }
+
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(invalid("invalid PNG color type"))
+    };
+
+    if bit_depth != 8 && bit_depth != 16 {
+        return Err(invalid("only 8 and 16 bit PNG images are supported"));
+    }
+
+    if color_type == 3 && bit_depth != 8 {
+        return Err(invalid("invalid PNG palette bit depth"));
+    }
+
+    let raw = zlib_decompress(&compressed)?;
+
+    let bytes_per_pixel = (channels * bit_depth / 8) as usize;
+    let stride = width as usize * bytes_per_pixel;
+
+    if raw.len() < (stride + 1) * height as usize {
+        return Err(invalid("truncated PNG data"));
+    }
+
+    let mut pixels = vec![0u8; stride * height as usize];
+
+    for y in 0..height as usize {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+
+        let (previous, current) = pixels.split_at_mut(y * stride);
+        let previous = if y > 0 { &previous[(y - 1) * stride..] } else { &[][..] };
+        let current = &mut current[..stride];
+
+        for x in 0..stride {
+            let a = if x >= bytes_per_pixel { current[x - bytes_per_pixel] } else { 0 };
+            let b = if y > 0 { previous[x] } else { 0 };
+            let c = if y > 0 && x >= bytes_per_pixel { previous[x - bytes_per_pixel] } else { 0 };
+
+            current[x] = match filter {
+                0 => line[x],
+                1 => line[x].wrapping_add(a),
+                2 => line[x].wrapping_add(b),
+                3 => line[x].wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                4 => line[x].wrapping_add(paeth(a, b, c)),
+                _ => return Err(invalid("invalid PNG filter type"))
+            };
+        }
+    }
+
+    if color_type == 3 {
+        let data = pixels.iter()
+            .map(|&i| palette.get(i as usize * 3..i as usize * 3 + 3).ok_or_else(|| invalid("invalid PNG palette index")))
+            .collect::<io::Result<Vec<_>>>()?
+            .concat();
+
+        return Ok(DecodedImage::U8(Image { width, height, channels: 3, data }));
+    }
+
+    if bit_depth == 16 {
+        let data = pixels.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect();
+        Ok(DecodedImage::U16(Image { width, height, channels, data }))
+    } else {
+        Ok(DecodedImage::U8(Image { width, height, channels, data: pixels }))
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn zlib_decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0f != 8 || data[1] & 0x20 != 0 || !(data[0] as u16 * 256 + data[1] as u16).is_multiple_of(31) {
+        return Err(invalid("invalid zlib header"));
+    }
+
+    inflate(&data[2..])
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or_else(|| invalid("truncated deflate stream"))?;
+            self.buffer |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+
+        let value = self.buffer & ((1u64 << n) - 1) as u32;
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code stored as code counts per length and symbols
+/// ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for i in 1..15 {
+            offsets[i + 1] = offsets[i] + counts[i];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> io::Result<u16> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Decompresses a raw DEFLATE stream (RFC 1951).
+fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut reader = BitReader { data, pos: 0, buffer: 0, count: 0 };
+    let mut output = Vec::new();
+
+    loop {
+        let last = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let header = data.get(reader.pos..reader.pos + 4).ok_or_else(|| invalid("truncated stored block"))?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let start = reader.pos + 4;
+                output.extend_from_slice(data.get(start..start + length).ok_or_else(|| invalid("truncated stored block"))?);
+                reader.pos = start + length;
+            },
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            },
+            2 => {
+                let literal_count = reader.bits(5)? as usize + 257;
+                let distance_count = reader.bits(5)? as usize + 1;
+                let code_count = reader.bits(4)? as usize + 4;
+
+                let mut code_lengths = [0u8; 19];
+                for &i in CODE_LENGTH_ORDER.iter().take(code_count) {
+                    code_lengths[i] = reader.bits(3)? as u8;
+                }
+                let code_lengths = Huffman::new(&code_lengths);
+
+                let mut lengths = vec![0u8; literal_count + distance_count];
+                let mut i = 0;
+
+                while i < lengths.len() {
+                    let symbol = code_lengths.decode(&mut reader)?;
+
+                    let (value, repeat) = match symbol {
+                        0..=15 => (symbol as u8, 1),
+                        16 => {
+                            let previous = *lengths[..i].last().ok_or_else(|| invalid("invalid code length repeat"))?;
+                            (previous, 3 + reader.bits(2)? as usize)
+                        },
+                        17 => (0, 3 + reader.bits(3)? as usize),
+                        _ => (0, 11 + reader.bits(7)? as usize)
+                    };
+
+                    if i + repeat > lengths.len() {
+                        return Err(invalid("too many code lengths"));
+                    }
+
+                    lengths[i..i + repeat].fill(value);
+                    i += repeat;
+                }
+
+                let literals = Huffman::new(&lengths[..literal_count]);
+                let distances = Huffman::new(&lengths[literal_count..]);
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            },
+            _ => return Err(invalid("invalid deflate block type"))
+        }
+
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+fn inflate_block(reader: &mut BitReader, output: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> io::Result<()> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(invalid("invalid length symbol"));
+        }
+        let length = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+        let symbol = distances.decode(reader)? as usize;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(invalid("invalid distance symbol"));
+        }
+        let distance = DISTANCE_BASE[symbol] as usize + reader.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+
+        if distance > output.len() {
+            return Err(invalid("distance too far back"));
+        }
+
+        // Copies may overlap the bytes they produce
+        let start = output.len() - distance;
+        for i in 0..length {
+            output.push(output[start + i]);
+        }
+    }
+}
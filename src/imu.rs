@@ -0,0 +1,684 @@
+use crate::geometry::{SE3, SO3};
+use crate::linalg::{DMatrix, Mat3, Mat6, Matrix, Vec3, Vector};
+use crate::map::{KeyFrameId, Map};
+
+/// Standard gravity, in m/s²
+pub const GRAVITY: f64 = 9.81;
+
+/// Gyroscope and accelerometer sample, in body coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImuMeasurement {
+    /// Seconds, on the clock of the frames
+    pub timestamp: f64,
+    /// Specific force, in m/s²
+    pub acceleration: Vec3,
+    /// Radians per second
+    pub angular_velocity: Vec3
+}
+
+/// Biases subtracted from the gyroscope and accelerometer measurements
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImuBias {
+    pub gyro: Vec3,
+    pub accel: Vec3
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImuCalibration {
+    /// Transformation from IMU body to camera coordinates
+    pub camera_from_body: SE3,
+    /// Gyroscope noise density, in rad/s/√Hz
+    pub gyro_noise: f64,
+    /// Accelerometer noise density, in m/s²/√Hz
+    pub accel_noise: f64,
+    /// Gyroscope bias random walk, in rad/s²/√Hz
+    pub gyro_walk: f64,
+    /// Accelerometer bias random walk, in m/s³/√Hz
+    pub accel_walk: f64
+}
+
+impl Default for ImuCalibration {
+    fn default() -> Self {
+        // Noise values used by ORB-SLAM3 for the EuRoC dataset, with the
+        // camera at the IMU
+        Self {
+            camera_from_body: SE3::identity(),
+            gyro_noise: 1.7e-4,
+            accel_noise: 2.0e-3,
+            gyro_walk: 1.9393e-5,
+            accel_walk: 3.0e-3
+        }
+    }
+}
+
+/// Inertial state a frame was tracked with. Keyframes are chained by the
+/// preintegration from the previous keyframe with an inertial state.
+#[derive(Clone, Debug)]
+pub struct ImuState {
+    /// Velocity of the body in world coordinates, once estimated
+    pub velocity: Vec3,
+    pub bias: ImuBias,
+    /// Measurements since the previous keyframe, `None` at the start of
+    /// the chain
+    pub preintegration: Option<Preintegrated>
+}
+
+/// Orientation, position and velocity of the IMU body in the world
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NavState {
+    /// Body to world rotation
+    pub rotation: SO3,
+    pub position: Vec3,
+    pub velocity: Vec3
+}
+
+impl NavState {
+    /// State of the body of a camera with world to camera `pose`
+    pub fn from_camera_pose(pose: &SE3, camera_from_body: &SE3, velocity: Vec3) -> Self {
+        let body_to_world = pose.inverse() * *camera_from_body;
+        Self { rotation: body_to_world.rotation, position: body_to_world.translation, velocity }
+    }
+
+    /// World to camera pose of the camera on the body
+    pub fn camera_pose(&self, camera_from_body: &SE3) -> SE3 {
+        *camera_from_body * SE3::new(self.rotation, self.position).inverse()
+    }
+}
+
+/// On-manifold preintegration of IMU measurements between two frames, as
+/// in Forster et al. and ORB-SLAM3. The deltas are integrated with `bias`
+/// and corrected to first order for other biases with their Jacobians.
+/// Residuals and covariances are ordered as `[rotation, velocity,
+/// position]`, bias Jacobians and walks as `[gyro, accel]`.
+#[derive(Clone, Debug)]
+pub struct Preintegrated {
+    pub calibration: ImuCalibration,
+    /// Bias the measurements were integrated with
+    pub bias: ImuBias,
+    /// Integrated time, in seconds
+    pub dt: f64,
+    pub delta_rotation: SO3,
+    pub delta_velocity: Vec3,
+    pub delta_position: Vec3,
+    pub rotation_gyro: Mat3,
+    pub velocity_gyro: Mat3,
+    pub velocity_accel: Mat3,
+    pub position_gyro: Mat3,
+    pub position_accel: Mat3,
+    /// Covariance of the deltas
+    pub covariance: Matrix<9, 9>,
+    /// Covariance of the bias change over the integrated time
+    pub walk_covariance: Mat6,
+    /// Acceleration, angular velocity and duration of each integrated
+    /// measurement, kept for reintegration
+    measurements: Vec<(Vec3, Vec3, f64)>
+}
+
+impl Preintegrated {
+    pub fn new(calibration: ImuCalibration, bias: ImuBias) -> Self {
+        Self {
+            calibration,
+            bias,
+            dt: 0.0,
+            delta_rotation: SO3::identity(),
+            delta_velocity: Vec3::zeros(),
+            delta_position: Vec3::zeros(),
+            rotation_gyro: Mat3::zeros(),
+            velocity_gyro: Mat3::zeros(),
+            velocity_accel: Mat3::zeros(),
+            position_gyro: Mat3::zeros(),
+            position_accel: Mat3::zeros(),
+            covariance: Matrix::zeros(),
+            walk_covariance: Mat6::zeros(),
+            measurements: Vec::new()
+        }
+    }
+
+    /// Integrates a measurement held for `dt` seconds, propagating the
+    /// bias Jacobians and the covariance of the deltas
+    pub fn integrate(&mut self, acceleration: &Vec3, angular_velocity: &Vec3, dt: f64) {
+        if dt <= 0.0 {
+            return;
+        }
+
+        self.measurements.push((*acceleration, *angular_velocity, dt));
+
+        let acc = *acceleration - self.bias.accel;
+        let omega = (*angular_velocity - self.bias.gyro) * dt;
+        let rotation = self.delta_rotation.matrix();
+        let acc_hat = acc.hat();
+        let dt2 = dt * dt;
+
+        // Position and velocity first, as they use the previous rotation
+        self.delta_position += self.delta_velocity * dt + rotation * acc * (0.5 * dt2);
+        self.delta_velocity += rotation * acc * dt;
+
+        let mut a = Matrix::<9, 9>::identity();
+        let mut b = Matrix::<9, 6>::zeros();
+
+        a.set_block(3, 0, &(rotation * acc_hat * -dt));
+        a.set_block(6, 0, &(rotation * acc_hat * (-0.5 * dt2)));
+        a.set_block(6, 3, &(Mat3::identity() * dt));
+        b.set_block(3, 3, &(rotation * dt));
+        b.set_block(6, 3, &(rotation * (0.5 * dt2)));
+
+        self.position_accel += self.velocity_accel * dt - rotation * (0.5 * dt2);
+        self.position_gyro += self.velocity_gyro * dt - rotation * acc_hat * self.rotation_gyro * (0.5 * dt2);
+        self.velocity_accel -= rotation * dt;
+        self.velocity_gyro -= rotation * acc_hat * self.rotation_gyro * dt;
+
+        let increment = SO3::exp(&omega);
+        let right_jacobian = SO3::right_jacobian(&omega);
+
+        a.set_block(0, 0, &increment.matrix().transpose());
+        b.set_block(0, 0, &(right_jacobian * dt));
+
+        // Continuous noise densities to the variance of a sample held for dt
+        let calibration = &self.calibration;
+        let gyro_variance = calibration.gyro_noise * calibration.gyro_noise / dt;
+        let accel_variance = calibration.accel_noise * calibration.accel_noise / dt;
+        let noise = Mat6::from_diagonal(&Vector::from_array([
+            gyro_variance, gyro_variance, gyro_variance, accel_variance, accel_variance, accel_variance
+        ]));
+
+        self.covariance = a * self.covariance * a.transpose() + b * noise * b.transpose();
+
+        let gyro_walk = calibration.gyro_walk * calibration.gyro_walk * dt;
+        let accel_walk = calibration.accel_walk * calibration.accel_walk * dt;
+        self.walk_covariance += Mat6::from_diagonal(&Vector::from_array([
+            gyro_walk, gyro_walk, gyro_walk, accel_walk, accel_walk, accel_walk
+        ]));
+
+        self.rotation_gyro = increment.matrix().transpose() * self.rotation_gyro - right_jacobian * dt;
+        self.delta_rotation = SO3::from_matrix(&(rotation * increment.matrix()));
+        self.dt += dt;
+    }
+
+    /// Integrates the measurements covering `[start, end]`, each holding
+    /// until the next. The last measurement before `start` covers the
+    /// beginning of the interval.
+    pub fn integrate_interval(&mut self, measurements: &[ImuMeasurement], start: f64, end: f64) {
+        for (k, measurement) in measurements.iter().enumerate() {
+            let until = measurements.get(k + 1).map_or(end, |next| next.timestamp.min(end));
+            let from = measurement.timestamp.max(start);
+
+            if until > from {
+                self.integrate(&measurement.acceleration, &measurement.angular_velocity, until - from);
+            }
+        }
+    }
+
+    /// Integrates the same measurements again with another bias
+    pub fn reintegrate(&mut self, bias: ImuBias) {
+        let measurements = std::mem::take(&mut self.measurements);
+        *self = Self::new(self.calibration, bias);
+
+        for (acceleration, angular_velocity, dt) in measurements {
+            self.integrate(&acceleration, &angular_velocity, dt);
+        }
+    }
+
+    /// Continues with the measurements of `later`, which starts where this
+    /// preintegration ends, as when the keyframe between them is erased
+    pub fn append(&mut self, later: &Preintegrated) {
+        for (acceleration, angular_velocity, dt) in &later.measurements {
+            self.integrate(acceleration, angular_velocity, *dt);
+        }
+    }
+
+    pub fn rotation(&self, bias: &ImuBias) -> SO3 {
+        self.delta_rotation * SO3::exp(&(self.rotation_gyro * (bias.gyro - self.bias.gyro)))
+    }
+
+    pub fn velocity(&self, bias: &ImuBias) -> Vec3 {
+        self.delta_velocity + self.velocity_gyro * (bias.gyro - self.bias.gyro) + self.velocity_accel * (bias.accel - self.bias.accel)
+    }
+
+    pub fn position(&self, bias: &ImuBias) -> Vec3 {
+        self.delta_position + self.position_gyro * (bias.gyro - self.bias.gyro) + self.position_accel * (bias.accel - self.bias.accel)
+    }
+
+    /// Inverse covariance of the deltas, zero before any measurement
+    pub fn information(&self) -> Matrix<9, 9> {
+        self.covariance.try_inverse().filter(|_| self.dt > 0.0).unwrap_or_default()
+    }
+
+    /// Inverse covariance of the bias change
+    pub fn walk_information(&self) -> Mat6 {
+        self.walk_covariance.try_inverse().filter(|_| self.dt > 0.0).unwrap_or_default()
+    }
+
+    /// State at the end of the interval from the state at its start
+    pub fn predict(&self, state: &NavState, bias: &ImuBias, gravity: &Vec3) -> NavState {
+        let dt = self.dt;
+
+        NavState {
+            rotation: state.rotation * self.rotation(bias),
+            position: state.position + state.velocity * dt + *gravity * (0.5 * dt * dt) + state.rotation * self.position(bias),
+            velocity: state.velocity + *gravity * dt + state.rotation * self.velocity(bias)
+        }
+    }
+
+    /// Body velocity at the end of the interval, given the world to camera
+    /// poses at both ends: the preintegrated position fixes the velocity at
+    /// the start, which the preintegrated velocity carries to the end.
+    /// `None` before any measurement.
+    pub fn end_velocity(&self, pose_i: &SE3, pose_j: &SE3, bias: &ImuBias, gravity: &Vec3) -> Option<Vec3> {
+        if self.dt <= 0.0 {
+            return None;
+        }
+
+        let camera_from_body = &self.calibration.camera_from_body;
+        let (i, j) = (NavState::from_camera_pose(pose_i, camera_from_body, Vec3::zeros()), NavState::from_camera_pose(pose_j, camera_from_body, Vec3::zeros()));
+        let dt = self.dt;
+
+        let start = (j.position - i.position - *gravity * (0.5 * dt * dt) - i.rotation * self.position(bias)) * (1.0 / dt);
+        Some(start + *gravity * dt + i.rotation * self.velocity(bias))
+    }
+
+    /// Error between the motion of the bodies of two cameras, with world
+    /// to camera poses and body velocities, and the preintegrated motion
+    /// corrected for `bias`
+    pub fn residual(&self, pose_i: &SE3, velocity_i: &Vec3, pose_j: &SE3, velocity_j: &Vec3, bias: &ImuBias, gravity: &Vec3) -> Vector<9> {
+        let camera_from_body = &self.calibration.camera_from_body;
+        let (i, j) = (NavState::from_camera_pose(pose_i, camera_from_body, *velocity_i), NavState::from_camera_pose(pose_j, camera_from_body, *velocity_j));
+        let inverse_i = i.rotation.inverse();
+        let dt = self.dt;
+
+        let mut residual = Vector::<9>::zeros();
+        residual.set_segment(0, &(self.rotation(bias).inverse() * inverse_i * j.rotation).log());
+        residual.set_segment(3, &(inverse_i * (j.velocity - i.velocity - *gravity * dt) - self.velocity(bias)));
+        residual.set_segment(6, &(inverse_i * (j.position - i.position - i.velocity * dt - *gravity * (0.5 * dt * dt)) - self.position(bias)));
+        residual
+    }
+
+    /// Whitened squared residual
+    pub fn chi2(&self, pose_i: &SE3, velocity_i: &Vec3, pose_j: &SE3, velocity_j: &Vec3, bias: &ImuBias, gravity: &Vec3) -> f64 {
+        let residual = self.residual(pose_i, velocity_i, pose_j, velocity_j, bias, gravity);
+        (residual.transpose() * self.information() * residual)[(0, 0)]
+    }
+
+    /// Residual and its derivatives with respect to the left perturbations
+    /// `[omega, v]` of the camera poses, the world velocities and the bias,
+    /// following ORB-SLAM3's inertial edge
+    pub(crate) fn linearize(&self, pose_i: &SE3, velocity_i: &Vec3, pose_j: &SE3, velocity_j: &Vec3, bias: &ImuBias, gravity: &Vec3) -> InertialLinearization {
+        let camera_from_body = &self.calibration.camera_from_body;
+        let (i, j) = (NavState::from_camera_pose(pose_i, camera_from_body, *velocity_i), NavState::from_camera_pose(pose_j, camera_from_body, *velocity_j));
+        let residual = self.residual(pose_i, velocity_i, pose_j, velocity_j, bias, gravity);
+
+        let (rotation_i, rotation_j) = (i.rotation.matrix(), j.rotation.matrix());
+        let inverse_i = rotation_i.transpose();
+        let dt = self.dt;
+
+        let rotation_error = residual.segment::<3>(0);
+        let inverse_jacobian = SO3::right_jacobian_inverse(&rotation_error);
+        let gyro_change = self.rotation_gyro * (bias.gyro - self.bias.gyro);
+
+        // Derivatives with respect to the body perturbation `[phi, dp]`,
+        // where `R <- R exp(phi)` and `p <- p + R dp`
+        let mut body_i = Matrix::<9, 6>::zeros();
+        body_i.set_block(0, 0, &(inverse_jacobian * -1.0 * rotation_j.transpose() * rotation_i));
+        body_i.set_block(3, 0, &(inverse_i * (j.velocity - i.velocity - *gravity * dt)).hat());
+        body_i.set_block(6, 0, &(inverse_i * (j.position - i.position - i.velocity * dt - *gravity * (0.5 * dt * dt))).hat());
+        body_i.set_block(6, 3, &(-Mat3::identity()));
+
+        let mut body_j = Matrix::<9, 6>::zeros();
+        body_j.set_block(0, 0, &inverse_jacobian);
+        body_j.set_block(6, 3, &(inverse_i * rotation_j));
+
+        let mut velocity_i = Matrix::<9, 3>::zeros();
+        velocity_i.set_block(3, 0, &(-inverse_i));
+        velocity_i.set_block(6, 0, &(inverse_i * -dt));
+
+        let mut velocity_j = Matrix::<9, 3>::zeros();
+        velocity_j.set_block(3, 0, &inverse_i);
+
+        let error_rotation = SO3::exp(&rotation_error).matrix();
+        let mut bias_jacobian = Matrix::<9, 6>::zeros();
+        bias_jacobian.set_block(0, 0, &(inverse_jacobian * -1.0 * error_rotation.transpose() * SO3::right_jacobian(&gyro_change) * self.rotation_gyro));
+        bias_jacobian.set_block(3, 0, &(-self.velocity_gyro));
+        bias_jacobian.set_block(3, 3, &(-self.velocity_accel));
+        bias_jacobian.set_block(6, 0, &(-self.position_gyro));
+        bias_jacobian.set_block(6, 3, &(-self.position_accel));
+
+        InertialLinearization {
+            residual,
+            pose_i: body_i * camera_to_body_perturbation(camera_from_body),
+            velocity_i,
+            bias: bias_jacobian,
+            pose_j: body_j * camera_to_body_perturbation(camera_from_body),
+            velocity_j
+        }
+    }
+}
+
+/// Error of an inertial edge and its derivatives, see `Preintegrated::linearize`
+pub(crate) struct InertialLinearization {
+    pub residual: Vector<9>,
+    pub pose_i: Matrix<9, 6>,
+    pub velocity_i: Matrix<9, 3>,
+    pub bias: Matrix<9, 6>,
+    pub pose_j: Matrix<9, 6>,
+    pub velocity_j: Matrix<9, 3>
+}
+
+/// Body perturbation `[phi, dp]` caused by the left perturbation
+/// `[omega, v]` of a world to camera pose. With `T_wb = T_cw^-1 T_cb`,
+/// `phi = -R_bc omega` and `dp = R_bc (t_cb^ omega - v)`.
+fn camera_to_body_perturbation(camera_from_body: &SE3) -> Mat6 {
+    let body_from_camera = camera_from_body.rotation.matrix().transpose();
+
+    let mut jacobian = Mat6::zeros();
+    jacobian.set_block(0, 0, &(-body_from_camera));
+    jacobian.set_block(3, 0, &(body_from_camera * camera_from_body.translation.hat()));
+    jacobian.set_block(3, 3, &(-body_from_camera));
+    jacobian
+}
+
+pub struct InertialInitConfig {
+    /// Keyframes with an inertial state needed to initialise...
+    pub min_keyframes: usize,
+    /// ...and the time they must span, in seconds
+    pub min_time: f64,
+    /// Inverse variance of the prior keeping the accelerometer bias small
+    pub accel_bias_prior: f64,
+    /// Gauss-Newton iterations for the gyroscope bias and for the gravity
+    /// direction
+    pub iterations: usize
+}
+
+impl Default for InertialInitConfig {
+    fn default() -> Self {
+        // Values used by ORB-SLAM3's inertial initialisation
+        Self {
+            min_keyframes: 10,
+            min_time: 2.0,
+            accel_bias_prior: 1e5,
+            iterations: 10
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InertialInitialization {
+    /// Factor the positions of a monocular map are multiplied by to be in
+    /// metres, 1 when the map already is
+    pub scale: f64,
+    /// Gravity in world coordinates, with magnitude `GRAVITY`
+    pub gravity: Vec3,
+    pub bias: ImuBias,
+    /// Body velocity at each keyframe, in world coordinates after scaling
+    pub velocities: Vec<Vec3>
+}
+
+/// Inertial initialisation from the world to camera poses of consecutive
+/// keyframes and the preintegrations between them, where
+/// `preintegrations[k]` goes from keyframe `k` to `k + 1`. The gyroscope
+/// bias is estimated first by aligning the preintegrated rotations with
+/// the visual ones, as in Visual-Inertial ORB-SLAM. Velocities, gravity,
+/// the accelerometer bias and, for monocular maps, the scale then follow
+/// from the preintegrated velocities and positions, which are linear in
+/// them: they are solved for without constraints, then again with the
+/// gravity magnitude fixed and its direction refined by Gauss-Newton.
+pub fn initialize_inertial(
+    config: &InertialInitConfig,
+    poses: &[SE3],
+    preintegrations: &[Preintegrated],
+    estimate_scale: bool
+) -> Option<InertialInitialization> {
+    assert_eq!(poses.len(), preintegrations.len() + 1);
+
+    if preintegrations.len() < 2 || preintegrations.iter().any(|p| p.dt <= 0.0) {
+        return None;
+    }
+
+    let camera_from_body = preintegrations[0].calibration.camera_from_body;
+    let rotations: Vec<SO3> = poses.iter().map(|pose| pose.rotation.inverse() * camera_from_body.rotation).collect();
+
+    // Gyroscope bias
+    let mut gyro = preintegrations[0].bias.gyro;
+
+    for _ in 0..config.iterations {
+        let mut hessian = Mat3::zeros();
+        let mut gradient = Vec3::zeros();
+
+        for (k, preintegrated) in preintegrations.iter().enumerate() {
+            let bias = ImuBias { gyro, accel: preintegrated.bias.accel };
+            let error_rotation = preintegrated.rotation(&bias).inverse() * rotations[k].inverse() * rotations[k + 1];
+            let error = error_rotation.log();
+
+            let change = preintegrated.rotation_gyro * (gyro - preintegrated.bias.gyro);
+            let jacobian = SO3::right_jacobian_inverse(&error) * -1.0 * error_rotation.matrix().transpose() *
+                SO3::right_jacobian(&change) * preintegrated.rotation_gyro;
+
+            hessian += jacobian.transpose() * jacobian;
+            gradient += jacobian.transpose() * error;
+        }
+
+        let step = hessian.cholesky_solve(&(-gradient))?;
+        gyro += step;
+
+        if step.norm() < 1e-10 {
+            break;
+        }
+    }
+
+    // Unconstrained gravity, then its direction with a fixed magnitude
+    let solution = solve_inertial_system(config, poses, &rotations, preintegrations, gyro, estimate_scale, None);
+    let linear_gravity = Vec3::new(solution[0], solution[1], solution[2]);
+
+    let down = Vec3::new(0.0, 0.0, -1.0);
+    let axis = down.cross(&linear_gravity);
+    let angle = axis.norm().atan2(down.dot(&linear_gravity));
+    let mut gravity_rotation = if axis.norm() < 1e-12 { SO3::identity() } else { SO3::exp(&(axis.normalize() * angle)) };
+
+    let mut solution = Vec::new();
+
+    for _ in 0..config.iterations.max(1) {
+        solution = solve_inertial_system(config, poses, &rotations, preintegrations, gyro, estimate_scale, Some(&gravity_rotation));
+        let step = Vec3::new(solution[0], solution[1], 0.0);
+
+        gravity_rotation = gravity_rotation * SO3::exp(&step);
+
+        if step.norm() < 1e-10 {
+            break;
+        }
+    }
+
+    let gravity = gravity_rotation * (down * GRAVITY);
+    let layout = InertialColumns::new(2, estimate_scale);
+    let scale = if estimate_scale { solution[layout.scale] } else { 1.0 };
+
+    if scale <= 0.0 || !scale.is_finite() || !gravity.is_finite() {
+        return None;
+    }
+
+    let accel = Vec3::new(solution[layout.accel], solution[layout.accel + 1], solution[layout.accel + 2]);
+    let velocities = (0..poses.len())
+        .map(|k| {
+            let v = layout.velocities + 3 * k;
+            Vec3::new(solution[v], solution[v + 1], solution[v + 2])
+        })
+        .collect();
+
+    Some(InertialInitialization { scale, gravity, bias: ImuBias { gyro, accel }, velocities })
+}
+
+/// Column of each unknown of the inertial initialisation system
+struct InertialColumns {
+    accel: usize,
+    scale: usize,
+    velocities: usize
+}
+
+impl InertialColumns {
+    /// Gravity comes first, with `gravity` columns
+    fn new(gravity: usize, estimate_scale: bool) -> Self {
+        Self { accel: gravity, scale: gravity + 3, velocities: gravity + 3 + estimate_scale as usize }
+    }
+}
+
+/// Least squares solution of the preintegrated velocity and position
+/// constraints for gravity, or a two entry update of the direction of
+/// `gravity_rotation`, the accelerometer bias, the scale if estimated and
+/// the velocities, laid out as in `InertialColumns`
+fn solve_inertial_system(
+    config: &InertialInitConfig,
+    poses: &[SE3],
+    rotations: &[SO3],
+    preintegrations: &[Preintegrated],
+    gyro: Vec3,
+    estimate_scale: bool,
+    gravity_rotation: Option<&SO3>
+) -> Vec<f64> {
+    let gravity_columns = if gravity_rotation.is_some() { 2 } else { 3 };
+    let layout = InertialColumns::new(gravity_columns, estimate_scale);
+
+    let rows = 6 * preintegrations.len() + 3;
+    let cols = layout.velocities + 3 * poses.len();
+
+    let mut a = DMatrix::zeros(rows, cols);
+    let mut b = vec![0.0; rows];
+
+    // Gravity as an affine function of the gravity unknowns
+    let (gravity_offset, gravity_jacobian) = match gravity_rotation {
+        Some(rotation) => {
+            let nominal = Vec3::new(0.0, 0.0, -GRAVITY);
+            (*rotation * nominal, rotation.matrix() * nominal.hat() * -1.0)
+        },
+        None => (Vec3::zeros(), Mat3::identity())
+    };
+
+    let camera_from_body = &preintegrations[0].calibration.camera_from_body;
+    let centres: Vec<Vec3> = poses.iter().map(|pose| pose.camera_center()).collect();
+    let levers: Vec<Vec3> = poses.iter().map(|pose| pose.rotation.inverse() * camera_from_body.translation).collect();
+
+    let set = |a: &mut DMatrix, row: usize, col: usize, block: &Mat3, columns: usize| {
+        for r in 0..3 {
+            for c in 0..columns {
+                a[(row + r, col + c)] = block[(r, c)];
+            }
+        }
+    };
+
+    for (k, preintegrated) in preintegrations.iter().enumerate() {
+        let dt = preintegrated.dt;
+        let inverse_i = rotations[k].inverse().matrix();
+        let bias = ImuBias { gyro, accel: preintegrated.bias.accel };
+        let (row_v, row_p) = (6 * k, 6 * k + 3);
+
+        // R_i^T (v_j - v_i - g dt) = dV + J_va (b_a - b_a0)
+        let gravity_v = inverse_i * -dt;
+        set(&mut a, row_v, 0, &(gravity_v * gravity_jacobian), gravity_columns);
+        set(&mut a, row_v, layout.accel, &(-preintegrated.velocity_accel), 3);
+        set(&mut a, row_v, layout.velocities + 3 * k, &(-inverse_i), 3);
+        set(&mut a, row_v, layout.velocities + 3 * (k + 1), &inverse_i, 3);
+
+        let rhs_v = preintegrated.velocity(&bias) - preintegrated.velocity_accel * preintegrated.bias.accel - gravity_v * gravity_offset;
+
+        // The body is at s c + R_wc t_cb for camera centre c, so
+        // R_i^T (s (c_j - c_i) + R_wc_j t_cb - R_wc_i t_cb - v_i dt - g dt^2 / 2)
+        //     = dP + J_pa (b_a - b_a0)
+        let gravity_p = inverse_i * (-0.5 * dt * dt);
+        set(&mut a, row_p, 0, &(gravity_p * gravity_jacobian), gravity_columns);
+        set(&mut a, row_p, layout.accel, &(-preintegrated.position_accel), 3);
+        set(&mut a, row_p, layout.velocities + 3 * k, &(inverse_i * -dt), 3);
+
+        let displacement = inverse_i * (centres[k + 1] - centres[k]);
+        let mut rhs_p = preintegrated.position(&bias) - preintegrated.position_accel * preintegrated.bias.accel -
+            gravity_p * gravity_offset - inverse_i * (levers[k + 1] - levers[k]);
+
+        if estimate_scale {
+            for r in 0..3 {
+                a[(row_p + r, layout.scale)] = displacement[r];
+            }
+        } else {
+            rhs_p -= displacement;
+        }
+
+        for r in 0..3 {
+            b[row_v + r] = rhs_v[r];
+            b[row_p + r] = rhs_p[r];
+        }
+    }
+
+    let prior = config.accel_bias_prior.sqrt();
+
+    for r in 0..3 {
+        a[(rows - 3 + r, layout.accel + r)] = prior;
+    }
+
+    a.solve_least_squares(&b)
+}
+
+/// Keyframes with an inertial state, in insertion order
+pub fn inertial_keyframes(map: &Map) -> Vec<KeyFrameId> {
+    map.keyframes().filter(|kf| kf.frame.imu.is_some()).map(|kf| kf.id).collect()
+}
+
+/// Initialises the inertial state of a map from its chain of keyframes
+/// with an inertial state, once there are enough spanning enough time.
+/// Monocular maps are scaled to metres. The keyframes get their
+/// velocities and the common bias, which their preintegrations are
+/// integrated again with, and the map its gravity.
+pub fn initialize_inertial_map(config: &InertialInitConfig, map: &mut Map) -> Option<InertialInitialization> {
+    // The chain starts at the last keyframe without a preintegration
+    let chain = inertial_keyframes(map);
+    let start = chain.iter()
+        .rposition(|&id| map.keyframe(id).unwrap().frame.imu.as_ref().unwrap().preintegration.is_none())
+        .unwrap_or(0);
+    let chain = &chain[start..];
+
+    if chain.len() < config.min_keyframes.max(3) {
+        return None;
+    }
+
+    let frame = |id: KeyFrameId| &map.keyframe(id).unwrap().frame;
+
+    if frame(*chain.last().unwrap()).timestamp - frame(chain[0]).timestamp < config.min_time {
+        return None;
+    }
+
+    let poses: Vec<SE3> = chain.iter().map(|&id| frame(id).pose.unwrap()).collect();
+    let preintegrations: Vec<Preintegrated> = chain[1..].iter()
+        .map(|&id| frame(id).imu.as_ref().unwrap().preintegration.clone().unwrap())
+        .collect();
+
+    let estimate_scale = frame(chain[0]).stereo.is_none();
+    let result = initialize_inertial(config, &poses, &preintegrations, estimate_scale)?;
+
+    if estimate_scale {
+        scale_map(map, result.scale);
+    }
+
+    for (&id, velocity) in chain.iter().zip(&result.velocities) {
+        let imu = map.keyframe_mut(id).unwrap().frame.imu.as_mut().unwrap();
+        imu.velocity = *velocity;
+        imu.bias = result.bias;
+
+        if let Some(preintegration) = &mut imu.preintegration {
+            preintegration.reintegrate(result.bias);
+        }
+    }
+
+    map.set_gravity(result.gravity);
+    Some(result)
+}
+
+/// Multiplies every position in the map by `scale`
+fn scale_map(map: &mut Map, scale: f64) {
+    let keyframes: Vec<KeyFrameId> = map.keyframes().map(|kf| kf.id).collect();
+
+    for id in keyframes {
+        let pose = map.keyframe_mut(id).unwrap().frame.pose.as_mut().unwrap();
+        pose.translation = pose.translation * scale;
+    }
+
+    let points: Vec<_> = map.points().map(|point| point.id).collect();
+
+    for id in points {
+        let point = map.point_mut(id).unwrap();
+        point.position = point.position * scale;
+        map.update_normal_and_depth(id);
+    }
+}
@@ -0,0 +1,611 @@
+use crate::camera::{CameraModel, Pinhole};
+use crate::essential::decompose_essential;
+use crate::frame::Frame;
+use crate::geometry::{triangulate, SE3, SO3};
+use crate::linalg::{DMatrix, Mat3, Mat3x4, Vec2, Vec3};
+use crate::matcher::{filter_by_rotation, hamming_distance, Match, NO_MATCH};
+use crate::optimizer::{CHI2_EPIPOLAR, CHI2_MONO};
+use crate::random::Rng;
+
+pub struct InitializerConfig {
+    /// Standard deviation of keypoint positions at octave 0, in pixels
+    pub sigma: f64,
+    /// Number of RANSAC hypotheses for each model
+    pub iterations: usize,
+    /// Radius of the search for matches around each reference keypoint
+    pub search_radius: f64,
+    /// Matches with a larger Hamming distance are rejected
+    pub max_distance: u32,
+    /// Best distance must be below `ratio` times the second best distance
+    pub ratio: f32,
+    /// Fewer matches than this and initialisation is not attempted
+    pub min_matches: usize,
+    /// Fewer triangulated points than this and the reconstruction is rejected
+    pub min_triangulated: usize,
+    /// Degrees
+    pub min_parallax: f64,
+    /// The homography is chosen when `SH / (SH + SF)` exceeds this
+    pub homography_ratio: f64
+}
+
+impl Default for InitializerConfig {
+    fn default() -> Self {
+        // Values used by ORB-SLAM's monocular initialisation
+        Self {
+            sigma: 1.0,
+            iterations: 200,
+            search_radius: 100.0,
+            max_distance: 50,
+            ratio: 0.9,
+            min_matches: 100,
+            min_triangulated: 50,
+            min_parallax: 1.0,
+            homography_ratio: 0.40
+        }
+    }
+}
+
+/// Two-view model the reconstruction was recovered from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TwoViewModel {
+    Homography,
+    Fundamental
+}
+
+/// Relative pose and structure of two views. The reference camera is the
+/// world frame and the baseline has unit length.
+pub struct Initialization {
+    pub model: TwoViewModel,
+    /// Reference camera to current camera
+    pub pose: SE3,
+    /// Query indexes the reference frame, train the current frame
+    pub matches: Vec<Match>,
+    /// Triangulated point for each match, in the reference camera frame
+    pub points: Vec<Option<Vec3>>,
+    /// Median parallax of the triangulated points, in degrees
+    pub parallax: f64
+}
+
+impl Initialization {
+    pub fn triangulated_count(&self) -> usize {
+        self.points.iter().filter(|p| p.is_some()).count()
+    }
+}
+
+/// Bootstraps a monocular map from a reference frame and a later frame
+/// that sees the same scene from a sufficiently different position.
+pub struct MonocularInitializer {
+    pub config: InitializerConfig,
+    reference: Frame,
+    rng: Rng
+}
+
+impl MonocularInitializer {
+    pub fn new(config: InitializerConfig, reference: Frame, seed: u64) -> Self {
+        Self { config, reference, rng: Rng::new(seed) }
+    }
+
+    pub fn reference(&self) -> &Frame {
+        &self.reference
+    }
+
+    /// Replaces the reference frame, e.g. after initialisation failed
+    /// because the two frames shared too few matches.
+    pub fn reset(&mut self, reference: Frame) {
+        self.reference = reference;
+    }
+
+    pub fn initialize(&mut self, current: &Frame) -> Option<Initialization> {
+        let matches = search_for_initialization(&self.config, &self.reference, current);
+
+        if matches.len() < self.config.min_matches {
+            return None;
+        }
+
+        let pinhole = self.reference.camera.pinhole();
+        let reference_points: Vec<Vec2> = matches.iter().map(|m| self.reference.undistorted[m.query as usize]).collect();
+        let current_points: Vec<Vec2> = matches.iter().map(|m| current.undistorted[m.train as usize]).collect();
+
+        let mut reconstruction = reconstruct(
+            &self.config,
+            &pinhole,
+            &reference_points,
+            &current_points,
+            &mut self.rng
+        )?;
+
+        reconstruction.matches = matches;
+        Some(reconstruction)
+    }
+}
+
+/// Matches octave 0 keypoints of the reference frame to octave 0 keypoints
+/// of the current frame near the same position, as ORB-SLAM does before
+/// initialisation. Each current keypoint is matched at most once.
+pub fn search_for_initialization(config: &InitializerConfig, reference: &Frame, current: &Frame) -> Vec<Match> {
+    let mut best_for_train = vec![(NO_MATCH, NO_MATCH); current.len()];
+
+    for (query, point) in reference.undistorted.iter().enumerate() {
+        if reference.octave(query) > 0 {
+            continue;
+        }
+
+        let mut best = (NO_MATCH, NO_MATCH);
+        let mut second_distance = NO_MATCH;
+
+        for train in current.features_in_area(point.x(), point.y(), config.search_radius, 0, 0) {
+            let distance = hamming_distance(&reference.descriptors[query], &current.descriptors[train]);
+
+            // Keep the previous match of this train keypoint if it is better
+            if best_for_train[train].1 <= distance {
+                continue;
+            }
+
+            if distance < best.1 {
+                second_distance = best.1;
+                best = (train as u32, distance);
+            } else if distance < second_distance {
+                second_distance = distance;
+            }
+        }
+
+        let (train, distance) = best;
+
+        if train == NO_MATCH || distance > config.max_distance {
+            continue;
+        }
+
+        if second_distance != NO_MATCH && distance as f32 >= config.ratio * second_distance as f32 {
+            continue;
+        }
+
+        best_for_train[train as usize] = (query as u32, distance);
+    }
+
+    let matches: Vec<Match> = best_for_train.iter()
+        .enumerate()
+        .filter(|(_, (query, _))| *query != NO_MATCH)
+        .map(|(train, &(query, distance))| Match { query, train: train as u32, distance })
+        .collect();
+
+    filter_by_rotation(&matches, &reference.corners, &current.corners)
+}
+
+/// Estimates a homography and a fundamental matrix from undistorted pixel
+/// correspondences in parallel, chooses between them with ORB-SLAM's score
+/// ratio and recovers the relative pose and structure. `matches` of the
+/// result is left empty.
+pub fn reconstruct(
+    config: &InitializerConfig,
+    pinhole: &Pinhole,
+    reference: &[Vec2],
+    current: &[Vec2],
+    rng: &mut Rng
+) -> Option<Initialization> {
+    assert_eq!(reference.len(), current.len());
+
+    if reference.len() < 8 {
+        return None;
+    }
+
+    // Both models are scored on the same minimal sets
+    let sets: Vec<Vec<usize>> = (0..config.iterations)
+        .map(|_| rng.sample_indices(reference.len(), 8))
+        .collect();
+
+    let ((homography, homography_score, homography_inliers), (fundamental, fundamental_score, fundamental_inliers)) =
+        std::thread::scope(|scope| {
+            let homography = scope.spawn(|| find_homography(reference, current, &sets, config.sigma));
+            let fundamental = find_fundamental(reference, current, &sets, config.sigma);
+            (homography.join().unwrap(), fundamental)
+        });
+
+    if homography_score + fundamental_score <= 0.0 {
+        return None;
+    }
+
+    let ratio = homography_score / (homography_score + fundamental_score);
+
+    if ratio > config.homography_ratio {
+        reconstruct_homography(config, pinhole, reference, current, &homography, &homography_inliers)
+    } else {
+        reconstruct_fundamental(config, pinhole, reference, current, &fundamental, &fundamental_inliers)
+    }
+}
+
+/// Similarity transformation giving points zero mean and unit mean absolute
+/// deviation along each axis
+fn normalize(points: &[Vec2]) -> (Vec<Vec2>, Mat3) {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.x()).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.y()).sum::<f64>() / n;
+
+    let deviation_x = points.iter().map(|p| (p.x() - mean_x).abs()).sum::<f64>() / n;
+    let deviation_y = points.iter().map(|p| (p.y() - mean_y).abs()).sum::<f64>() / n;
+
+    let scale_x = if deviation_x > 0.0 { 1.0 / deviation_x } else { 1.0 };
+    let scale_y = if deviation_y > 0.0 { 1.0 / deviation_y } else { 1.0 };
+
+    let normalized = points.iter()
+        .map(|p| Vec2::new((p.x() - mean_x) * scale_x, (p.y() - mean_y) * scale_y))
+        .collect();
+
+    let transform = Mat3::from_rows([
+        [scale_x, 0.0, -mean_x * scale_x],
+        [0.0, scale_y, -mean_y * scale_y],
+        [0.0, 0.0, 1.0]
+    ]);
+
+    (normalized, transform)
+}
+
+fn homogeneous(p: &Vec2) -> Vec3 {
+    Vec3::new(p.x(), p.y(), 1.0)
+}
+
+/// Direct linear transform for `x2 = H x1`
+fn compute_homography(p1: &[Vec2], p2: &[Vec2]) -> Mat3 {
+    let mut a = DMatrix::zeros(2 * p1.len(), 9);
+
+    for (i, (a1, a2)) in p1.iter().zip(p2).enumerate() {
+        let (u1, v1, u2, v2) = (a1.x(), a1.y(), a2.x(), a2.y());
+
+        let rows = [
+            [0.0, 0.0, 0.0, -u1, -v1, -1.0, v2 * u1, v2 * v1, v2],
+            [u1, v1, 1.0, 0.0, 0.0, 0.0, -u2 * u1, -u2 * v1, -u2]
+        ];
+
+        for (r, row) in rows.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                a[(2 * i + r, j)] = *value;
+            }
+        }
+    }
+
+    let h = a.null_vector();
+    Mat3::from_rows([[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], h[8]]])
+}
+
+/// Eight point algorithm for `x2^T F x1 = 0`, with rank 2 enforced
+fn compute_fundamental(p1: &[Vec2], p2: &[Vec2]) -> Mat3 {
+    let mut a = DMatrix::zeros(p1.len(), 9);
+
+    for (i, (a1, a2)) in p1.iter().zip(p2).enumerate() {
+        let (u1, v1, u2, v2) = (a1.x(), a1.y(), a2.x(), a2.y());
+        let row = [u2 * u1, u2 * v1, u2, v2 * u1, v2 * v1, v2, u1, v1, 1.0];
+
+        for (j, value) in row.iter().enumerate() {
+            a[(i, j)] = *value;
+        }
+    }
+
+    let f = a.null_vector();
+    let f = Mat3::from_rows([[f[0], f[1], f[2]], [f[3], f[4], f[5]], [f[6], f[7], f[8]]]);
+
+    let (u, s, v) = f.svd();
+    u * Mat3::from_diagonal(&Vec3::new(s[0], s[1], 0.0)) * v.transpose()
+}
+
+fn find_homography(reference: &[Vec2], current: &[Vec2], sets: &[Vec<usize>], sigma: f64) -> (Mat3, f64, Vec<bool>) {
+    let (n1, t1) = normalize(reference);
+    let (n2, t2) = normalize(current);
+    let t2_inverse = t2.try_inverse().unwrap();
+
+    let mut best = (Mat3::zeros(), 0.0, vec![false; reference.len()]);
+
+    for set in sets {
+        let p1: Vec<Vec2> = set.iter().map(|&i| n1[i]).collect();
+        let p2: Vec<Vec2> = set.iter().map(|&i| n2[i]).collect();
+
+        let h21 = t2_inverse * compute_homography(&p1, &p2) * t1;
+        let Some(h12) = h21.try_inverse() else {
+            continue;
+        };
+
+        let (score, inliers) = check_homography(reference, current, &h21, &h12, sigma);
+
+        if score > best.1 {
+            best = (h21, score, inliers);
+        }
+    }
+
+    best
+}
+
+/// Symmetric transfer score of a homography
+fn check_homography(reference: &[Vec2], current: &[Vec2], h21: &Mat3, h12: &Mat3, sigma: f64) -> (f64, Vec<bool>) {
+    let inv_sigma2 = 1.0 / (sigma * sigma);
+    let mut score = 0.0;
+    let mut inliers = vec![false; reference.len()];
+
+    for (i, (p1, p2)) in reference.iter().zip(current).enumerate() {
+        let p2_in_1 = (*h12 * homogeneous(p2)).hnormalize();
+        let chi2_1 = (*p1 - p2_in_1).norm_squared() * inv_sigma2;
+
+        let p1_in_2 = (*h21 * homogeneous(p1)).hnormalize();
+        let chi2_2 = (*p2 - p1_in_2).norm_squared() * inv_sigma2;
+
+        // Each direction scores on its own, as in ORB-SLAM, even when
+        // the other direction makes the correspondence an outlier
+        let mut inlier = true;
+
+        for chi2 in [chi2_1, chi2_2] {
+            if chi2.is_finite() && chi2 <= CHI2_MONO {
+                score += CHI2_MONO - chi2;
+            } else {
+                inlier = false;
+            }
+        }
+
+        inliers[i] = inlier;
+    }
+
+    (score, inliers)
+}
+
+fn find_fundamental(reference: &[Vec2], current: &[Vec2], sets: &[Vec<usize>], sigma: f64) -> (Mat3, f64, Vec<bool>) {
+    let (n1, t1) = normalize(reference);
+    let (n2, t2) = normalize(current);
+
+    let mut best = (Mat3::zeros(), 0.0, vec![false; reference.len()]);
+
+    for set in sets {
+        let p1: Vec<Vec2> = set.iter().map(|&i| n1[i]).collect();
+        let p2: Vec<Vec2> = set.iter().map(|&i| n2[i]).collect();
+
+        let f21 = t2.transpose() * compute_fundamental(&p1, &p2) * t1;
+        let (score, inliers) = check_fundamental(reference, current, &f21, sigma);
+
+        if score > best.1 {
+            best = (f21, score, inliers);
+        }
+    }
+
+    best
+}
+
+/// Symmetric epipolar distance score of a fundamental matrix. Each direction
+/// is gated with the one dimensional threshold but scored like a homography
+/// direction so that the two scores are comparable.
+fn check_fundamental(reference: &[Vec2], current: &[Vec2], f21: &Mat3, sigma: f64) -> (f64, Vec<bool>) {
+    let inv_sigma2 = 1.0 / (sigma * sigma);
+    let mut score = 0.0;
+    let mut inliers = vec![false; reference.len()];
+
+    for (i, (p1, p2)) in reference.iter().zip(current).enumerate() {
+        let x1 = homogeneous(p1);
+        let x2 = homogeneous(p2);
+
+        // Epipolar line of x1 in the current image
+        let l2 = *f21 * x1;
+        let chi2_1 = l2.dot(&x2).powi(2) / (l2.x() * l2.x() + l2.y() * l2.y()) * inv_sigma2;
+
+        // Epipolar line of x2 in the reference image
+        let l1 = f21.transpose() * x2;
+        let chi2_2 = l1.dot(&x1).powi(2) / (l1.x() * l1.x() + l1.y() * l1.y()) * inv_sigma2;
+
+        let mut inlier = true;
+
+        for chi2 in [chi2_1, chi2_2] {
+            if chi2.is_finite() && chi2 <= CHI2_EPIPOLAR {
+                score += CHI2_MONO - chi2;
+            } else {
+                inlier = false;
+            }
+        }
+
+        inliers[i] = inlier;
+    }
+
+    (score, inliers)
+}
+
+/// Result of triangulating the inliers under one motion hypothesis
+struct Hypothesis {
+    pose: SE3,
+    points: Vec<Option<Vec3>>,
+    good: usize,
+    parallax: f64
+}
+
+/// Triangulates inliers with the reference camera at the origin, keeping
+/// points in front of both cameras with small reprojection error. Points
+/// with too little parallax are counted but not kept.
+fn check_pose(
+    pose: SE3,
+    pinhole: &Pinhole,
+    reference: &[Vec2],
+    current: &[Vec2],
+    inliers: &[bool],
+    max_error2: f64
+) -> Hypothesis {
+    let p1 = SE3::identity().matrix();
+    let p2: Mat3x4 = pose.matrix();
+    let center2 = pose.camera_center();
+
+    let mut points = vec![None; reference.len()];
+    let mut cos_parallaxes = Vec::new();
+    let mut good = 0;
+
+    for i in (0..reference.len()).filter(|&i| inliers[i]) {
+        let x1 = pinhole.normalize(&reference[i]);
+        let x2 = pinhole.normalize(&current[i]);
+
+        let Some(point) = triangulate(&p1, &p2, &x1, &x2) else {
+            continue;
+        };
+
+        let ray1 = point;
+        let ray2 = point - center2;
+        let cos_parallax = ray1.dot(&ray2) / (ray1.norm() * ray2.norm());
+
+        // Points with negligible parallax may be triangulated behind a camera
+        let point2 = pose.transform(&point);
+        if (point.z() <= 0.0 || point2.z() <= 0.0) && cos_parallax < 0.99998 {
+            continue;
+        }
+
+        if (pinhole.project(&point) - reference[i]).norm_squared() > max_error2 {
+            continue;
+        }
+
+        if (pinhole.project(&point2) - current[i]).norm_squared() > max_error2 {
+            continue;
+        }
+
+        cos_parallaxes.push(cos_parallax);
+        good += 1;
+
+        if cos_parallax < 0.99998 {
+            points[i] = Some(point);
+        }
+    }
+
+    // The 51st largest parallax, to be robust to a few points triangulated
+    // with spuriously large parallax
+    let parallax = if cos_parallaxes.is_empty() {
+        0.0
+    } else {
+        cos_parallaxes.sort_by(|a, b| a.total_cmp(b));
+        let index = 50.min(cos_parallaxes.len() - 1);
+        cos_parallaxes[index].clamp(-1.0, 1.0).acos().to_degrees()
+    };
+
+    Hypothesis { pose, points, good, parallax }
+}
+
+fn reconstruct_fundamental(
+    config: &InitializerConfig,
+    pinhole: &Pinhole,
+    reference: &[Vec2],
+    current: &[Vec2],
+    f21: &Mat3,
+    inliers: &[bool]
+) -> Option<Initialization> {
+    let inlier_count = inliers.iter().filter(|&&i| i).count();
+    let k = pinhole.matrix();
+    let e21 = k.transpose() * *f21 * k;
+
+    let max_error2 = 4.0 * config.sigma * config.sigma;
+    let hypotheses: Vec<Hypothesis> = decompose_essential(&e21)
+        .into_iter()
+        .map(|pose| check_pose(pose, pinhole, reference, current, inliers, max_error2))
+        .collect();
+
+    let max_good = hypotheses.iter().map(|h| h.good).max().unwrap_or(0);
+    let min_good = ((0.9 * inlier_count as f64) as usize).max(config.min_triangulated);
+
+    // Reject if no hypothesis stands out clearly
+    let similar = hypotheses.iter().filter(|h| h.good as f64 > 0.7 * max_good as f64).count();
+
+    if max_good < min_good || similar > 1 {
+        return None;
+    }
+
+    let best = hypotheses.into_iter().find(|h| h.good == max_good)?;
+
+    if best.parallax <= config.min_parallax {
+        return None;
+    }
+
+    Some(Initialization {
+        model: TwoViewModel::Fundamental,
+        pose: best.pose,
+        matches: Vec::new(),
+        points: best.points,
+        parallax: best.parallax
+    })
+}
+
+/// The eight motion and plane hypotheses of Faugeras' homography
+/// decomposition. The plane normals are not used further.
+fn decompose_homography(pinhole: &Pinhole, h21: &Mat3) -> Option<Vec<SE3>> {
+    let k = pinhole.matrix();
+    let a = k.try_inverse()? * *h21 * k;
+
+    let (u, w, v) = a.svd();
+    let s = u.determinant() * v.determinant();
+    let (d1, d2, d3) = (w[0], w[1], w[2]);
+
+    if d1 / d2 < 1.00001 || d2 / d3 < 1.00001 {
+        return None;
+    }
+
+    let aux1 = ((d1 * d1 - d2 * d2) / (d1 * d1 - d3 * d3)).sqrt();
+    let aux3 = ((d2 * d2 - d3 * d3) / (d1 * d1 - d3 * d3)).sqrt();
+    let x1 = [aux1, aux1, -aux1, -aux1];
+    let x3 = [aux3, -aux3, aux3, -aux3];
+
+    let mut poses = Vec::with_capacity(8);
+
+    // d' = d2
+    let aux_sin_theta = ((d1 * d1 - d2 * d2) * (d2 * d2 - d3 * d3)).sqrt() / ((d1 + d3) * d2);
+    let cos_theta = (d2 * d2 + d1 * d3) / ((d1 + d3) * d2);
+    let sin_theta = [aux_sin_theta, -aux_sin_theta, -aux_sin_theta, aux_sin_theta];
+
+    for i in 0..4 {
+        let rp = Mat3::from_rows([
+            [cos_theta, 0.0, -sin_theta[i]],
+            [0.0, 1.0, 0.0],
+            [sin_theta[i], 0.0, cos_theta]
+        ]);
+
+        let rotation = u * rp * v.transpose() * s;
+        let translation = (u * (Vec3::new(x1[i], 0.0, -x3[i]) * (d1 - d3))).normalize();
+        poses.push(SE3::new(SO3::from_matrix(&rotation), translation));
+    }
+
+    // d' = -d2
+    let aux_sin_phi = ((d1 * d1 - d2 * d2) * (d2 * d2 - d3 * d3)).sqrt() / ((d1 - d3) * d2);
+    let cos_phi = (d1 * d3 - d2 * d2) / ((d1 - d3) * d2);
+    let sin_phi = [aux_sin_phi, -aux_sin_phi, -aux_sin_phi, aux_sin_phi];
+
+    for i in 0..4 {
+        let rp = Mat3::from_rows([
+            [cos_phi, 0.0, sin_phi[i]],
+            [0.0, -1.0, 0.0],
+            [sin_phi[i], 0.0, -cos_phi]
+        ]);
+
+        let rotation = u * rp * v.transpose() * s;
+        let translation = (u * (Vec3::new(x1[i], 0.0, x3[i]) * (d1 + d3))).normalize();
+        poses.push(SE3::new(SO3::from_matrix(&rotation), translation));
+    }
+
+    Some(poses)
+}
+
+fn reconstruct_homography(
+    config: &InitializerConfig,
+    pinhole: &Pinhole,
+    reference: &[Vec2],
+    current: &[Vec2],
+    h21: &Mat3,
+    inliers: &[bool]
+) -> Option<Initialization> {
+    let inlier_count = inliers.iter().filter(|&&i| i).count();
+    let max_error2 = 4.0 * config.sigma * config.sigma;
+
+    let mut hypotheses: Vec<Hypothesis> = decompose_homography(pinhole, h21)?
+        .into_iter()
+        .map(|pose| check_pose(pose, pinhole, reference, current, inliers, max_error2))
+        .collect();
+
+    hypotheses.sort_by_key(|h| std::cmp::Reverse(h.good));
+
+    let best = hypotheses.swap_remove(0);
+    let second_good = hypotheses.iter().map(|h| h.good).max().unwrap_or(0);
+
+    let accepted = (second_good as f64) < 0.75 * best.good as f64 &&
+        best.parallax >= config.min_parallax &&
+        best.good > config.min_triangulated &&
+        best.good as f64 > 0.9 * inlier_count as f64;
+
+    accepted.then_some(Initialization {
+        model: TwoViewModel::Homography,
+        pose: best.pose,
+        matches: Vec::new(),
+        points: best.points,
+        parallax: best.parallax
+    })
+}
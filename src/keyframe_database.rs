@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::map::{KeyFrameId, Map};
+use crate::vocabulary::{BowVector, Vocabulary, WordId};
+
+/// Number of best covisible keyframes whose scores are accumulated with a
+/// candidate's, as in ORB-SLAM
+const SCORE_NEIGHBOURS: usize = 10;
+
+/// Inverted index from vocabulary words to the keyframes whose BoW vector
+/// contains them, queried for loop closure and relocalisation candidates.
+#[derive(Clone, Debug, Default)]
+pub struct KeyFrameDatabase {
+    words: BTreeMap<WordId, BTreeSet<KeyFrameId>>
+}
+
+impl KeyFrameDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, keyframe: KeyFrameId, bow: &BowVector) {
+        for &word in bow.0.keys() {
+            self.words.entry(word).or_default().insert(keyframe);
+        }
+    }
+
+    /// Removes a keyframe indexed with `bow`
+    pub fn erase(&mut self, keyframe: KeyFrameId, bow: &BowVector) {
+        for word in bow.0.keys() {
+            if let Some(keyframes) = self.words.get_mut(word) {
+                keyframes.remove(&keyframe);
+
+                if keyframes.is_empty() {
+                    self.words.remove(word);
+                }
+            }
+        }
+    }
+
+    /// Number of words each indexed keyframe shares with `bow`, for the
+    /// keyframes sharing at least one
+    pub fn common_words(&self, bow: &BowVector) -> BTreeMap<KeyFrameId, usize> {
+        let mut counts = BTreeMap::new();
+
+        for keyframes in bow.0.keys().filter_map(|word| self.words.get(word)) {
+            for &keyframe in keyframes {
+                *counts.entry(keyframe).or_default() += 1;
+            }
+        }
+
+        counts
+    }
+}
+
+/// Keyframes that may close a loop with `keyframe`, following ORB-SLAM's
+/// DetectLoopCandidates. Keyframes not connected to it that share enough
+/// words and score at least `min_score` are grouped with their best
+/// covisible keyframes. The best keyframe of each group whose accumulated
+/// score is close to the best group's is returned.
+pub fn loop_candidates(map: &Map, vocabulary: &Vocabulary, keyframe: KeyFrameId, min_score: f64) -> Vec<KeyFrameId> {
+    let Some(current) = map.keyframe(keyframe) else {
+        return Vec::new();
+    };
+
+    let Some(bow) = &current.frame.bow else {
+        return Vec::new();
+    };
+
+    let connected: BTreeSet<KeyFrameId> = std::iter::once(keyframe)
+        .chain(current.covisibles().iter().map(|&(id, _)| id))
+        .collect();
+
+    let mut common = map.database().common_words(bow);
+    common.retain(|id, _| !connected.contains(id));
+
+    let scores = similar_keyframes(map, vocabulary, bow, &common, min_score);
+    best_of_groups(map, &scores)
+}
+
+/// Keyframes that may show the place seen in a frame whose tracking was
+/// lost, following ORB-SLAM's DetectRelocalizationCandidates. Like
+/// `loop_candidates`, but over every keyframe and without a minimum score.
+pub fn relocalisation_candidates(map: &Map, vocabulary: &Vocabulary, bow: &BowVector) -> Vec<KeyFrameId> {
+    let common = map.database().common_words(bow);
+    let scores = similar_keyframes(map, vocabulary, bow, &common, 0.0);
+    best_of_groups(map, &scores)
+}
+
+/// Scores against `bow` of the keyframes sharing more than 80% of the
+/// largest number of common words, keeping those of at least `min_score`
+fn similar_keyframes(
+    map: &Map,
+    vocabulary: &Vocabulary,
+    bow: &BowVector,
+    common: &BTreeMap<KeyFrameId, usize>,
+    min_score: f64
+) -> BTreeMap<KeyFrameId, f64> {
+    let Some(&max_common) = common.values().max() else {
+        return BTreeMap::new();
+    };
+
+    let min_common = 0.8 * max_common as f64;
+
+    common.iter()
+        .filter(|&(_, &count)| count as f64 > min_common)
+        .filter_map(|(&id, _)| {
+            let other = map.keyframe(id)?.frame.bow.as_ref()?;
+            let score = vocabulary.score(bow, other);
+            (score >= min_score).then_some((id, score))
+        })
+        .collect()
+}
+
+/// Accumulates the score of each keyframe with those of its best covisible
+/// keyframes, and returns the best keyframe of each group scoring more than
+/// 75% of the best accumulated score
+fn best_of_groups(map: &Map, scores: &BTreeMap<KeyFrameId, f64>) -> Vec<KeyFrameId> {
+    let groups: Vec<(f64, KeyFrameId)> = scores.iter()
+        .map(|(&id, &score)| {
+            let mut accumulated = score;
+            let mut best = (score, id);
+
+            for neighbour in map.keyframe(id).into_iter().flat_map(|kf| kf.best_covisibles(SCORE_NEIGHBOURS)) {
+                if let Some(&neighbour_score) = scores.get(&neighbour) {
+                    accumulated += neighbour_score;
+
+                    if neighbour_score > best.0 {
+                        best = (neighbour_score, neighbour);
+                    }
+                }
+            }
+
+            (accumulated, best.1)
+        })
+        .collect();
+
+    let best_accumulated = groups.iter().map(|&(score, _)| score).fold(0.0, f64::max);
+    let min_accumulated = 0.75 * best_accumulated;
+
+    let mut candidates = Vec::new();
+
+    for (score, id) in groups {
+        if score > min_accumulated && !candidates.contains(&id) {
+            candidates.push(id);
+        }
+    }
+
+    candidates
+}
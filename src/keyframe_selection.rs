@@ -0,0 +1,173 @@
+/// Kind of camera rig, which decides whether close points can be
+/// triangulated from a single frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sensor {
+    Monocular,
+    Stereo,
+    Rgbd
+}
+
+pub struct KeyFrameSelectionConfig {
+    /// Frames that must pass after a keyframe before local mapping being
+    /// idle is enough to insert another one
+    pub min_frames: u64,
+    /// Frames after which a keyframe is due, usually the frame rate. Also the
+    /// number of frames after relocalisation during which none are inserted.
+    pub max_frames: u64,
+    /// A keyframe is needed when fewer than this fraction of the points
+    /// tracked by the reference keyframe are tracked by the current frame
+    pub reference_ratio: f64,
+    /// `reference_ratio` for monocular rigs
+    pub monocular_reference_ratio: f64,
+    /// `reference_ratio` while the map has a single keyframe
+    pub initial_reference_ratio: f64,
+    /// Stereo and RGB-D rigs insert a keyframe regardless of elapsed frames
+    /// when tracking falls below this fraction of the reference keyframe
+    pub weak_tracking_ratio: f64,
+    /// Frames tracking this many inliers or fewer never become keyframes
+    pub min_inliers: usize,
+    /// Map points observed by fewer keyframes are not counted as tracked by
+    /// the reference keyframe, unless the map has `min_observations`
+    /// keyframes or fewer
+    pub min_observations: usize,
+    /// Stereo and RGB-D rigs need a keyframe when fewer close points than
+    /// this are tracked...
+    pub max_tracked_close: usize,
+    /// ...and more close points than this could be created
+    pub min_untracked_close: usize,
+    /// Stereo and RGB-D rigs still insert while local mapping is busy if
+    /// fewer keyframes than this are waiting
+    pub max_queued: usize
+}
+
+impl Default for KeyFrameSelectionConfig {
+    fn default() -> Self {
+        // Values used by ORB-SLAM for a 30 fps camera
+        Self {
+            min_frames: 0,
+            max_frames: 30,
+            reference_ratio: 0.75,
+            monocular_reference_ratio: 0.9,
+            initial_reference_ratio: 0.4,
+            weak_tracking_ratio: 0.25,
+            min_inliers: 15,
+            min_observations: 3,
+            max_tracked_close: 100,
+            min_untracked_close: 70,
+            max_queued: 3
+        }
+    }
+}
+
+/// State of tracking and local mapping when a frame has been tracked
+#[derive(Clone, Debug, Default)]
+pub struct TrackingStatistics {
+    pub frame_id: u64,
+    /// Id of the frame the last keyframe was created from
+    pub last_keyframe_frame_id: u64,
+    /// Id of the last frame that was relocalised, if any
+    pub last_relocalisation_frame_id: Option<u64>,
+    pub keyframes_in_map: usize,
+    /// Map points matched by the current frame after pose optimisation
+    pub tracked_inliers: usize,
+    /// Map points of the reference keyframe with enough observations, see
+    /// `Map::tracked_points`
+    pub reference_tracked_points: usize,
+    /// Local mapping has processed every queued keyframe
+    pub local_mapping_idle: bool,
+    /// Local mapping was stopped, e.g. while a loop is being closed
+    pub local_mapping_stopped: bool,
+    /// Keyframes waiting to be processed by local mapping
+    pub queued_keyframes: usize,
+    /// Keypoints with a close depth that match a map point
+    pub tracked_close: usize,
+    /// Keypoints with a close depth and no map point, see
+    /// `Frame::close_point_counts`
+    pub untracked_close: usize
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyFrameDecision {
+    pub insert: bool,
+    /// Local mapping should abort its bundle adjustment so it can accept
+    /// keyframes sooner
+    pub interrupt_mapping: bool
+}
+
+/// Decides when tracking inserts a keyframe, following ORB-SLAM: often
+/// enough that tracking stays robust, but only while the current frame
+/// still tracks enough points and local mapping can keep up.
+pub struct KeyFrameSelection {
+    pub config: KeyFrameSelectionConfig,
+    pub sensor: Sensor
+}
+
+impl KeyFrameSelection {
+    pub fn new(config: KeyFrameSelectionConfig, sensor: Sensor) -> Self {
+        Self { config, sensor }
+    }
+
+    /// Minimum observations for a reference keyframe point to count as
+    /// tracked, see `TrackingStatistics::reference_tracked_points`
+    pub fn min_observations(&self, keyframes_in_map: usize) -> usize {
+        if keyframes_in_map <= 2 {
+            2
+        } else {
+            self.config.min_observations
+        }
+    }
+
+    pub fn decide(&self, stats: &TrackingStatistics) -> KeyFrameDecision {
+        let config = &self.config;
+        let skip = KeyFrameDecision::default();
+
+        if stats.local_mapping_stopped {
+            return skip;
+        }
+
+        let recently_relocalised = stats.last_relocalisation_frame_id
+            .is_some_and(|id| stats.frame_id < id + config.max_frames);
+
+        if recently_relocalised && stats.keyframes_in_map as u64 > config.max_frames {
+            return skip;
+        }
+
+        let monocular = self.sensor == Sensor::Monocular;
+
+        let need_close = !monocular &&
+            stats.tracked_close < config.max_tracked_close &&
+            stats.untracked_close > config.min_untracked_close;
+
+        let reference_ratio = if stats.keyframes_in_map < 2 {
+            config.initial_reference_ratio
+        } else if monocular {
+            config.monocular_reference_ratio
+        } else {
+            config.reference_ratio
+        };
+
+        let tracked = stats.tracked_inliers as f64;
+        let reference = stats.reference_tracked_points as f64;
+
+        let max_frames_passed = stats.frame_id >= stats.last_keyframe_frame_id + config.max_frames;
+        let min_frames_passed = stats.frame_id >= stats.last_keyframe_frame_id + config.min_frames &&
+            stats.local_mapping_idle;
+        let tracking_weak = !monocular && (tracked < reference * config.weak_tracking_ratio || need_close);
+
+        let tracking_dropped = (tracked < reference * reference_ratio || need_close) &&
+            stats.tracked_inliers > config.min_inliers;
+
+        if !((max_frames_passed || min_frames_passed || tracking_weak) && tracking_dropped) {
+            return skip;
+        }
+
+        if stats.local_mapping_idle {
+            return KeyFrameDecision { insert: true, interrupt_mapping: false };
+        }
+
+        KeyFrameDecision {
+            insert: !monocular && stats.queued_keyframes < config.max_queued,
+            interrupt_mapping: true
+        }
+    }
+}
@@ -1 +1,28 @@
-pub mod orb;
\ No newline at end of file
+pub mod orb;
+pub mod matcher;
+pub mod vocabulary;
+pub mod image;
+pub mod random;
+pub mod linalg;
+pub mod camera;
+pub mod geometry;
+pub mod frame;
+pub mod initializer;
+pub mod ransac;
+pub mod essential;
+pub mod pnp;
+pub mod optimizer;
+pub mod map;
+pub mod tracking;
+pub mod keyframe_selection;
+pub mod local_mapping;
+pub mod bundle_adjustment;
+pub mod bundle_program;
+pub mod keyframe_database;
+pub mod sim3;
+pub mod pose_graph;
+pub mod loop_closing;
+pub mod stereo;
+pub mod rgbd;
+pub mod imu;
+pub mod dataset;
\ No newline at end of file
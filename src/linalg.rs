@@ -0,0 +1,809 @@
+use std::ops::{Add, AddAssign, Index, IndexMut, Mul, Neg, Sub, SubAssign};
+
+/// Fixed size, row-major matrix of `f64`. Vectors are single column matrices.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Matrix<const R: usize, const C: usize> {
+    pub data: [[f64; C]; R]
+}
+
+pub type Vector<const N: usize> = Matrix<N, 1>;
+
+pub type Vec2 = Vector<2>;
+pub type Vec3 = Vector<3>;
+pub type Vec4 = Vector<4>;
+pub type Vec6 = Vector<6>;
+pub type Vec7 = Vector<7>;
+pub type Mat2 = Matrix<2, 2>;
+pub type Mat3 = Matrix<3, 3>;
+pub type Mat4 = Matrix<4, 4>;
+pub type Mat6 = Matrix<6, 6>;
+pub type Mat7 = Matrix<7, 7>;
+pub type Mat2x3 = Matrix<2, 3>;
+pub type Mat3x2 = Matrix<3, 2>;
+pub type Mat3x4 = Matrix<3, 4>;
+
+impl<const R: usize, const C: usize> Default for Matrix<R, C> {
+    fn default() -> Self {
+        Self::zeros()
+    }
+}
+
+impl<const R: usize, const C: usize> Matrix<R, C> {
+    pub const fn from_rows(data: [[f64; C]; R]) -> Self {
+        Self { data }
+    }
+
+    pub const fn zeros() -> Self {
+        Self { data: [[0.0; C]; R] }
+    }
+
+    pub fn transpose(&self) -> Matrix<C, R> {
+        let mut result = Matrix::<C, R>::zeros();
+        for i in 0..R {
+            for j in 0..C {
+                result.data[j][i] = self.data[i][j];
+            }
+        }
+        result
+    }
+
+    pub fn map(&self, f: impl Fn(f64) -> f64) -> Self {
+        let mut result = *self;
+        result.data.iter_mut().flatten().for_each(|v| *v = f(*v));
+        result
+    }
+
+    /// Frobenius norm
+    pub fn norm(&self) -> f64 {
+        self.data.iter().flatten().map(|v| v * v).sum::<f64>().sqrt()
+    }
+
+    pub fn row(&self, i: usize) -> Matrix<1, C> {
+        Matrix { data: [self.data[i]] }
+    }
+
+    pub fn column(&self, j: usize) -> Vector<R> {
+        let mut result = Vector::<R>::zeros();
+        for i in 0..R {
+            result.data[i][0] = self.data[i][j];
+        }
+        result
+    }
+
+    pub fn set_column(&mut self, j: usize, column: &Vector<R>) {
+        for i in 0..R {
+            self.data[i][j] = column.data[i][0];
+        }
+    }
+
+    /// Copies the block of size `BR x BC` starting at `(i, j)`
+    pub fn block<const BR: usize, const BC: usize>(&self, i: usize, j: usize) -> Matrix<BR, BC> {
+        let mut result = Matrix::<BR, BC>::zeros();
+        for r in 0..BR {
+            for c in 0..BC {
+                result.data[r][c] = self.data[i + r][j + c];
+            }
+        }
+        result
+    }
+
+    pub fn set_block<const BR: usize, const BC: usize>(&mut self, i: usize, j: usize, block: &Matrix<BR, BC>) {
+        for r in 0..BR {
+            for c in 0..BC {
+                self.data[i + r][j + c] = block.data[r][c];
+            }
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.data.iter().flatten().all(|v| v.is_finite())
+    }
+}
+
+impl<const N: usize> Matrix<N, N> {
+    pub fn identity() -> Self {
+        let mut result = Self::zeros();
+        for i in 0..N {
+            result.data[i][i] = 1.0;
+        }
+        result
+    }
+
+    pub fn from_diagonal(diagonal: &Vector<N>) -> Self {
+        let mut result = Self::zeros();
+        for i in 0..N {
+            result.data[i][i] = diagonal.data[i][0];
+        }
+        result
+    }
+
+    pub fn trace(&self) -> f64 {
+        (0..N).map(|i| self.data[i][i]).sum()
+    }
+
+    /// Inverse by Gauss-Jordan elimination with partial pivoting
+    pub fn try_inverse(&self) -> Option<Self> {
+        let mut a = *self;
+        let mut inverse = Self::identity();
+
+        for col in 0..N {
+            let pivot = (col..N)
+                .max_by(|&i, &j| a.data[i][col].abs().total_cmp(&a.data[j][col].abs()))
+                .unwrap();
+
+            if a.data[pivot][col].abs() < 1e-300 {
+                return None;
+            }
+
+            a.data.swap(col, pivot);
+            inverse.data.swap(col, pivot);
+
+            let scale = 1.0 / a.data[col][col];
+            for j in 0..N {
+                a.data[col][j] *= scale;
+                inverse.data[col][j] *= scale;
+            }
+
+            for i in 0..N {
+                if i != col {
+                    let factor = a.data[i][col];
+                    if factor != 0.0 {
+                        for j in 0..N {
+                            a.data[i][j] -= factor * a.data[col][j];
+                            inverse.data[i][j] -= factor * inverse.data[col][j];
+                        }
+                    }
+                }
+            }
+        }
+
+        Some(inverse)
+    }
+
+    /// Solves `self * x = b` for a symmetric positive definite `self`
+    pub fn cholesky_solve(&self, b: &Vector<N>) -> Option<Vector<N>> {
+        let mut l = Self::zeros();
+
+        for i in 0..N {
+            for j in 0..=i {
+                let sum: f64 = (0..j).map(|k| l.data[i][k] * l.data[j][k]).sum();
+
+                if i == j {
+                    let diagonal = self.data[i][i] - sum;
+                    if diagonal <= 0.0 {
+                        return None;
+                    }
+                    l.data[i][i] = diagonal.sqrt();
+                } else {
+                    l.data[i][j] = (self.data[i][j] - sum) / l.data[j][j];
+                }
+            }
+        }
+
+        let mut y = Vector::<N>::zeros();
+        for i in 0..N {
+            let sum: f64 = (0..i).map(|k| l.data[i][k] * y.data[k][0]).sum();
+            y.data[i][0] = (b.data[i][0] - sum) / l.data[i][i];
+        }
+
+        let mut x = Vector::<N>::zeros();
+        for i in (0..N).rev() {
+            let sum: f64 = (i + 1..N).map(|k| l.data[k][i] * x.data[k][0]).sum();
+            x.data[i][0] = (y.data[i][0] - sum) / l.data[i][i];
+        }
+
+        Some(x)
+    }
+}
+
+impl Mat2 {
+    pub fn determinant(&self) -> f64 {
+        self.data[0][0] * self.data[1][1] - self.data[0][1] * self.data[1][0]
+    }
+}
+
+impl Mat3 {
+    pub fn determinant(&self) -> f64 {
+        let m = &self.data;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) -
+        m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0]) +
+        m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn from_columns(a: &Vec3, b: &Vec3, c: &Vec3) -> Self {
+        Matrix::from_rows([
+            [a.x(), b.x(), c.x()],
+            [a.y(), b.y(), c.y()],
+            [a.z(), b.z(), c.z()]
+        ])
+    }
+}
+
+impl<const N: usize> Vector<N> {
+    pub fn from_array(values: [f64; N]) -> Self {
+        let mut result = Self::zeros();
+        for (row, value) in result.data.iter_mut().zip(values) {
+            row[0] = value;
+        }
+        result
+    }
+
+    pub fn to_array(&self) -> [f64; N] {
+        let mut result = [0.0; N];
+        for (value, row) in result.iter_mut().zip(&self.data) {
+            *value = row[0];
+        }
+        result
+    }
+
+    pub fn dot(&self, other: &Self) -> f64 {
+        (0..N).map(|i| self.data[i][0] * other.data[i][0]).sum()
+    }
+
+    pub fn norm_squared(&self) -> f64 {
+        self.dot(self)
+    }
+
+    pub fn normalize(&self) -> Self {
+        *self * (1.0 / self.norm())
+    }
+
+    /// Copies `M` entries starting at index `i`
+    pub fn segment<const M: usize>(&self, i: usize) -> Vector<M> {
+        self.block::<M, 1>(i, 0)
+    }
+
+    pub fn set_segment<const M: usize>(&mut self, i: usize, segment: &Vector<M>) {
+        self.set_block(i, 0, segment);
+    }
+}
+
+impl Vec2 {
+    pub const fn new(x: f64, y: f64) -> Self {
+        Matrix { data: [[x], [y]] }
+    }
+
+    pub fn x(&self) -> f64 {
+        self.data[0][0]
+    }
+
+    pub fn y(&self) -> f64 {
+        self.data[1][0]
+    }
+}
+
+impl Vec3 {
+    pub const fn new(x: f64, y: f64, z: f64) -> Self {
+        Matrix { data: [[x], [y], [z]] }
+    }
+
+    pub fn x(&self) -> f64 {
+        self.data[0][0]
+    }
+
+    pub fn y(&self) -> f64 {
+        self.data[1][0]
+    }
+
+    pub fn z(&self) -> f64 {
+        self.data[2][0]
+    }
+
+    pub fn cross(&self, other: &Self) -> Self {
+        Vec3::new(
+            self.y() * other.z() - self.z() * other.y(),
+            self.z() * other.x() - self.x() * other.z(),
+            self.x() * other.y() - self.y() * other.x()
+        )
+    }
+
+    /// Skew-symmetric matrix such that `hat(a) * b == a.cross(b)`
+    pub fn hat(&self) -> Mat3 {
+        Matrix::from_rows([
+            [0.0, -self.z(), self.y()],
+            [self.z(), 0.0, -self.x()],
+            [-self.y(), self.x(), 0.0]
+        ])
+    }
+
+    /// Homogeneous coordinates to inhomogeneous
+    pub fn hnormalize(&self) -> Vec2 {
+        Vec2::new(self.x() / self.z(), self.y() / self.z())
+    }
+}
+
+impl<const N: usize> Index<usize> for Vector<N> {
+    type Output = f64;
+
+    fn index(&self, i: usize) -> &f64 {
+        &self.data[i][0]
+    }
+}
+
+impl<const N: usize> IndexMut<usize> for Vector<N> {
+    fn index_mut(&mut self, i: usize) -> &mut f64 {
+        &mut self.data[i][0]
+    }
+}
+
+impl<const R: usize, const C: usize> Index<(usize, usize)> for Matrix<R, C> {
+    type Output = f64;
+
+    fn index(&self, (i, j): (usize, usize)) -> &f64 {
+        &self.data[i][j]
+    }
+}
+
+impl<const R: usize, const C: usize> IndexMut<(usize, usize)> for Matrix<R, C> {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut f64 {
+        &mut self.data[i][j]
+    }
+}
+
+impl<const R: usize, const C: usize> Add for Matrix<R, C> {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
+
+impl<const R: usize, const C: usize> AddAssign for Matrix<R, C> {
+    fn add_assign(&mut self, other: Self) {
+        for i in 0..R {
+            for j in 0..C {
+                self.data[i][j] += other.data[i][j];
+            }
+        }
+    }
+}
+
+impl<const R: usize, const C: usize> Sub for Matrix<R, C> {
+    type Output = Self;
+
+    fn sub(mut self, other: Self) -> Self {
+        self -= other;
+        self
+    }
+}
+
+impl<const R: usize, const C: usize> SubAssign for Matrix<R, C> {
+    fn sub_assign(&mut self, other: Self) {
+        for i in 0..R {
+            for j in 0..C {
+                self.data[i][j] -= other.data[i][j];
+            }
+        }
+    }
+}
+
+impl<const R: usize, const C: usize> Neg for Matrix<R, C> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        self.map(|v| -v)
+    }
+}
+
+impl<const R: usize, const C: usize> Mul<f64> for Matrix<R, C> {
+    type Output = Self;
+
+    fn mul(self, scale: f64) -> Self {
+        self.map(|v| v * scale)
+    }
+}
+
+impl<const R: usize, const C: usize> Mul<Matrix<R, C>> for f64 {
+    type Output = Matrix<R, C>;
+
+    fn mul(self, matrix: Matrix<R, C>) -> Matrix<R, C> {
+        matrix * self
+    }
+}
+
+impl<const R: usize, const C: usize, const K: usize> Mul<Matrix<C, K>> for Matrix<R, C> {
+    type Output = Matrix<R, K>;
+
+    fn mul(self, other: Matrix<C, K>) -> Matrix<R, K> {
+        let mut result = Matrix::<R, K>::zeros();
+        for i in 0..R {
+            for k in 0..C {
+                let a = self.data[i][k];
+                if a != 0.0 {
+                    for j in 0..K {
+                        result.data[i][j] += a * other.data[k][j];
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+impl<const R: usize, const C: usize, const K: usize> Mul<&Matrix<C, K>> for &Matrix<R, C> {
+    type Output = Matrix<R, K>;
+
+    fn mul(self, other: &Matrix<C, K>) -> Matrix<R, K> {
+        *self * *other
+    }
+}
+
+/// Heap allocated, row-major matrix for problems whose size is only known
+/// at runtime, such as DLT systems with one row per correspondence.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DMatrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<f64>
+}
+
+/// Thin singular value decomposition `A = U * diag(S) * V^T`, with singular
+/// values in decreasing order.
+pub struct Svd {
+    pub u: DMatrix,
+    pub singular_values: Vec<f64>,
+    pub v: DMatrix
+}
+
+impl DMatrix {
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Self { rows, cols, data: vec![0.0; rows * cols] }
+    }
+
+    pub fn identity(n: usize) -> Self {
+        let mut result = Self::zeros(n, n);
+        for i in 0..n {
+            result[(i, i)] = 1.0;
+        }
+        result
+    }
+
+    pub fn from_fixed<const R: usize, const C: usize>(matrix: &Matrix<R, C>) -> Self {
+        Self { rows: R, cols: C, data: matrix.data.iter().flatten().copied().collect() }
+    }
+
+    pub fn to_fixed<const R: usize, const C: usize>(&self) -> Matrix<R, C> {
+        assert!(self.rows == R && self.cols == C);
+
+        let mut result = Matrix::<R, C>::zeros();
+        for i in 0..R {
+            for j in 0..C {
+                result.data[i][j] = self[(i, j)];
+            }
+        }
+        result
+    }
+
+    pub fn column(&self, j: usize) -> Vec<f64> {
+        (0..self.rows).map(|i| self[(i, j)]).collect()
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut result = Self::zeros(self.cols, self.rows);
+        for i in 0..self.rows {
+            for j in 0..self.cols {
+                result[(j, i)] = self[(i, j)];
+            }
+        }
+        result
+    }
+
+    /// One-sided Jacobi SVD. Matrices with fewer rows than columns are
+    /// padded with zero rows, in which case only `V` and `S` are complete.
+    pub fn svd(&self) -> Svd {
+        let m = self.rows.max(self.cols);
+        let n = self.cols;
+
+        let mut a = Self::zeros(m, n);
+        a.data[..self.data.len()].copy_from_slice(&self.data);
+        let mut v = Self::identity(n);
+
+        for _sweep in 0..100 {
+            let mut rotated = false;
+
+            for p in 0..n {
+                for q in p + 1..n {
+                    let mut alpha = 0.0;
+                    let mut beta = 0.0;
+                    let mut gamma = 0.0;
+
+                    for i in 0..m {
+                        alpha += a[(i, p)] * a[(i, p)];
+                        beta += a[(i, q)] * a[(i, q)];
+                        gamma += a[(i, p)] * a[(i, q)];
+                    }
+
+                    if gamma.abs() <= 1e-15 * (alpha * beta).sqrt() || gamma == 0.0 {
+                        continue;
+                    }
+
+                    rotated = true;
+
+                    let zeta = (beta - alpha) / (2.0 * gamma);
+                    let t = zeta.signum() / (zeta.abs() + (1.0 + zeta * zeta).sqrt());
+                    let c = 1.0 / (1.0 + t * t).sqrt();
+                    let s = c * t;
+
+                    for i in 0..m {
+                        let (ap, aq) = (a[(i, p)], a[(i, q)]);
+                        a[(i, p)] = c * ap - s * aq;
+                        a[(i, q)] = s * ap + c * aq;
+                    }
+
+                    for i in 0..n {
+                        let (vp, vq) = (v[(i, p)], v[(i, q)]);
+                        v[(i, p)] = c * vp - s * vq;
+                        v[(i, q)] = s * vp + c * vq;
+                    }
+                }
+            }
+
+            if !rotated {
+                break;
+            }
+        }
+
+        let norms: Vec<f64> = (0..n)
+            .map(|j| (0..m).map(|i| a[(i, j)] * a[(i, j)]).sum::<f64>().sqrt())
+            .collect();
+
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&i, &j| norms[j].total_cmp(&norms[i]));
+
+        let largest = norms[order[0]];
+        let mut u = Self::zeros(m, n);
+        let mut sorted_v = Self::zeros(n, n);
+        let mut singular_values = Vec::with_capacity(n);
+        let mut valid = vec![false; n];
+
+        for (k, &j) in order.iter().enumerate() {
+            singular_values.push(norms[j]);
+
+            for i in 0..n {
+                sorted_v[(i, k)] = v[(i, j)];
+            }
+
+            if norms[j] > 1e-12 * largest && norms[j] > 0.0 {
+                valid[k] = true;
+                for i in 0..m {
+                    u[(i, k)] = a[(i, j)] / norms[j];
+                }
+            }
+        }
+
+        // Complete U with an orthonormal basis where singular values vanish
+        let mut basis = 0;
+        for k in 0..n {
+            while !valid[k] && basis < m {
+                let mut candidate = vec![0.0; m];
+                candidate[basis] = 1.0;
+                basis += 1;
+
+                for other in (0..n).filter(|&o| valid[o]) {
+                    let projection: f64 = (0..m).map(|i| candidate[i] * u[(i, other)]).sum();
+                    for (i, value) in candidate.iter_mut().enumerate() {
+                        *value -= projection * u[(i, other)];
+                    }
+                }
+
+                let norm = candidate.iter().map(|c| c * c).sum::<f64>().sqrt();
+                if norm > 0.1 {
+                    for (i, value) in candidate.iter().enumerate() {
+                        u[(i, k)] = value / norm;
+                    }
+                    valid[k] = true;
+                }
+            }
+        }
+
+        u.rows = self.rows;
+        u.data.truncate(self.rows * n);
+
+        Svd { u, singular_values, v: sorted_v }
+    }
+
+    /// Unit vector minimising `|A x|`, the right singular vector of the
+    /// smallest singular value
+    pub fn null_vector(&self) -> Vec<f64> {
+        let svd = self.svd();
+        svd.v.column(self.cols - 1)
+    }
+
+    /// Minimum norm least squares solution of `A x = b`, for matrices with
+    /// at least as many rows as columns
+    pub fn solve_least_squares(&self, b: &[f64]) -> Vec<f64> {
+        assert!(self.rows >= self.cols && b.len() == self.rows);
+
+        let svd = self.svd();
+        let largest = svd.singular_values[0];
+        let mut x = vec![0.0; self.cols];
+
+        for (k, &sigma) in svd.singular_values.iter().enumerate() {
+            if sigma <= 1e-12 * largest || sigma == 0.0 {
+                continue;
+            }
+
+            let projection: f64 = (0..self.rows).map(|i| svd.u[(i, k)] * b[i]).sum::<f64>() / sigma;
+            for (j, value) in x.iter_mut().enumerate() {
+                *value += projection * svd.v[(j, k)];
+            }
+        }
+
+        x
+    }
+
+    /// Solves `A x = b` for a symmetric positive definite `A`
+    pub fn cholesky_solve(&self, b: &[f64]) -> Option<Vec<f64>> {
+        assert!(self.rows == self.cols && b.len() == self.rows);
+
+        let n = self.rows;
+        let mut l = Self::zeros(n, n);
+
+        for i in 0..n {
+            for j in 0..=i {
+                let sum: f64 = (0..j).map(|k| l[(i, k)] * l[(j, k)]).sum();
+
+                if i == j {
+                    let diagonal = self[(i, i)] - sum;
+                    if diagonal <= 0.0 {
+                        return None;
+                    }
+                    l[(i, i)] = diagonal.sqrt();
+                } else {
+                    l[(i, j)] = (self[(i, j)] - sum) / l[(j, j)];
+                }
+            }
+        }
+
+        let mut y = vec![0.0; n];
+        for i in 0..n {
+            let sum: f64 = (0..i).map(|k| l[(i, k)] * y[k]).sum();
+            y[i] = (b[i] - sum) / l[(i, i)];
+        }
+
+        let mut x = vec![0.0; n];
+        for i in (0..n).rev() {
+            let sum: f64 = (i + 1..n).map(|k| l[(k, i)] * x[k]).sum();
+            x[i] = (y[i] - sum) / l[(i, i)];
+        }
+
+        Some(x)
+    }
+}
+
+impl Index<(usize, usize)> for DMatrix {
+    type Output = f64;
+
+    fn index(&self, (i, j): (usize, usize)) -> &f64 {
+        &self.data[i * self.cols + j]
+    }
+}
+
+impl IndexMut<(usize, usize)> for DMatrix {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut f64 {
+        &mut self.data[i * self.cols + j]
+    }
+}
+
+impl Mul for &DMatrix {
+    type Output = DMatrix;
+
+    fn mul(self, other: &DMatrix) -> DMatrix {
+        assert_eq!(self.cols, other.rows);
+
+        let mut result = DMatrix::zeros(self.rows, other.cols);
+        for i in 0..self.rows {
+            for k in 0..self.cols {
+                let a = self[(i, k)];
+                if a != 0.0 {
+                    for j in 0..other.cols {
+                        result[(i, j)] += a * other[(k, j)];
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Symmetric matrix stored as the lower triangle of each row from its first
+/// non-zero column. Cholesky factors keep this envelope, so systems whose
+/// non-zeros stay near the diagonal, such as pose graphs ordered along the
+/// trajectory, are solved without filling in the whole matrix.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvelopeMatrix {
+    first: Vec<usize>,
+    rows: Vec<Vec<f64>>
+}
+
+impl EnvelopeMatrix {
+    /// Zero matrix whose row `i` may be non-zero from column `first[i]`
+    pub fn new(first: Vec<usize>) -> Self {
+        let rows = first.iter()
+            .enumerate()
+            .map(|(i, &first)| {
+                assert!(first <= i);
+                vec![0.0; i + 1 - first]
+            })
+            .collect();
+
+        Self { first, rows }
+    }
+
+    pub fn size(&self) -> usize {
+        self.first.len()
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        let (i, j) = (i.max(j), i.min(j));
+
+        if j < self.first[i] {
+            0.0
+        } else {
+            self.rows[i][j - self.first[i]]
+        }
+    }
+
+    /// Adds `value` to entries `(i, j)` and `(j, i)`, which must be inside
+    /// the envelope
+    pub fn add(&mut self, i: usize, j: usize, value: f64) {
+        let (i, j) = (i.max(j), i.min(j));
+        assert!(j >= self.first[i], "entry outside the envelope");
+
+        self.rows[i][j - self.first[i]] += value;
+    }
+
+    /// Solves `A x = b` for a symmetric positive definite `A`
+    pub fn cholesky_solve(&self, b: &[f64]) -> Option<Vec<f64>> {
+        let n = self.size();
+        assert_eq!(b.len(), n);
+
+        let mut l = self.clone();
+
+        for i in 0..n {
+            for j in self.first[i]..=i {
+                let start = self.first[i].max(self.first[j]);
+                let sum: f64 = (start..j).map(|k| l.get(i, k) * l.get(j, k)).sum();
+                let value = self.get(i, j) - sum;
+
+                let entry = if i == j {
+                    if value <= 0.0 {
+                        return None;
+                    }
+                    value.sqrt()
+                } else {
+                    value / l.get(j, j)
+                };
+
+                l.rows[i][j - self.first[i]] = entry;
+            }
+        }
+
+        let mut y = vec![0.0; n];
+        for i in 0..n {
+            let sum: f64 = (self.first[i]..i).map(|k| l.get(i, k) * y[k]).sum();
+            y[i] = (b[i] - sum) / l.get(i, i);
+        }
+
+        // Back substitution with L^T, a column of the transpose per row of L
+        let mut x = y;
+        for i in (0..n).rev() {
+            x[i] /= l.get(i, i);
+
+            for k in self.first[i]..i {
+                x[k] -= l.get(i, k) * x[i];
+            }
+        }
+
+        Some(x)
+    }
+}
+
+impl Mat3 {
+    /// Singular value decomposition `U * diag(S) * V^T`
+    pub fn svd(&self) -> (Mat3, Vec3, Mat3) {
+        let svd = DMatrix::from_fixed(self).svd();
+        (svd.u.to_fixed(), Vec3::from_array([svd.singular_values[0], svd.singular_values[1], svd.singular_values[2]]), svd.v.to_fixed())
+    }
+}
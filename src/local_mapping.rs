@@ -0,0 +1,714 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use crate::bundle_adjustment::{local_bundle_adjustment, BundleAdjustmentConfig, CpuLinearizer, Linearizer};
+use crate::camera::CameraModel;
+use crate::frame::Frame;
+use crate::geometry::triangulate;
+use crate::imu::{initialize_inertial_map, InertialInitConfig};
+use crate::linalg::Vec3;
+use crate::map::{KeyFrame, KeyFrameId, Map, MapPointId, SharedMap};
+use crate::matcher::{filter_by_rotation, hamming_distance, Match, NO_MATCH};
+use crate::optimizer::{CHI2_EPIPOLAR, CHI2_MONO};
+use crate::vocabulary::Vocabulary;
+
+pub struct LocalMappingConfig {
+    /// Number of best covisible keyframes new points are triangulated with
+    pub triangulation_neighbours: usize,
+    /// Matches for triangulation with a larger Hamming distance are rejected
+    pub triangulation_max_distance: u32,
+    /// Rays with a larger cosine of their parallax are not triangulated
+    pub max_parallax_cos: f64,
+    /// Keyframe pairs with a baseline below this fraction of the median
+    /// depth of the neighbour are not used for triangulation. Stereo and
+    /// RGB-D neighbours are used once the baseline exceeds that of their rig.
+    pub min_baseline_ratio: f64,
+    /// Number of best covisible keyframes duplicate points are fused with...
+    pub fuse_neighbours: usize,
+    /// ...together with this many of the best covisible keyframes of each
+    pub fuse_second_neighbours: usize,
+    /// Search radius around projected points when fusing, in pixels at
+    /// octave 0
+    pub fuse_radius: f64,
+    /// Fused matches with a larger Hamming distance are rejected
+    pub fuse_max_distance: u32,
+    /// New points found in fewer than this fraction of the frames they
+    /// were predicted to be visible in are culled...
+    pub min_found_ratio: f64,
+    /// ...as are those observed by this many keyframes or fewer two
+    /// keyframes after their creation
+    pub min_observations: usize,
+    /// Like `min_observations` in stereo and RGB-D maps, whose points are
+    /// created more easily
+    pub stereo_min_observations: usize,
+    /// Keyframes whose map points are this redundant are culled, counting
+    /// only the close points of stereo and RGB-D keyframes...
+    pub redundancy_ratio: f64,
+    /// ...where a point is redundant when this many other keyframes
+    /// observe it at the same or a finer scale
+    pub redundant_observers: usize,
+    /// Stereo and RGB-D keyframes create points from their unmatched
+    /// keypoints by increasing depth, from every close keypoint and further
+    /// ones until this many keypoints have a point
+    pub stereo_points: usize,
+    /// Level of the vocabulary at which features are grouped, counted up
+    /// from the words
+    pub levels_up: u32,
+    pub bundle_adjustment: BundleAdjustmentConfig,
+    /// Initialisation of visual-inertial maps
+    pub inertial: InertialInitConfig
+}
+
+impl Default for LocalMappingConfig {
+    fn default() -> Self {
+        // Values used by ORB-SLAM's local mapping
+        Self {
+            triangulation_neighbours: 20,
+            triangulation_max_distance: 50,
+            max_parallax_cos: 0.9998,
+            min_baseline_ratio: 0.01,
+            fuse_neighbours: 20,
+            fuse_second_neighbours: 5,
+            fuse_radius: 3.0,
+            fuse_max_distance: 50,
+            min_found_ratio: 0.25,
+            min_observations: 2,
+            stereo_min_observations: 3,
+            redundancy_ratio: 0.9,
+            redundant_observers: 3,
+            stereo_points: 100,
+            levels_up: 4,
+            bundle_adjustment: BundleAdjustmentConfig::default(),
+            inertial: InertialInitConfig::default()
+        }
+    }
+}
+
+/// Inserts keyframes from tracking into the map and maintains the map
+/// around them: recently created points are culled, new points are
+/// triangulated with covisible keyframes, duplicate points are fused, the
+/// neighbourhood is refined by local bundle adjustment and redundant
+/// keyframes are culled. Visual-inertial maps are initialised once their
+/// keyframes span enough time.
+pub struct LocalMapper {
+    pub config: LocalMappingConfig,
+    map: SharedMap,
+    vocabulary: Arc<Vocabulary>,
+    /// Set to stop a running local bundle adjustment early
+    abort: Arc<AtomicBool>,
+    /// Evaluates the normal equations of local bundle adjustment
+    linearizer: Arc<dyn Linearizer + Send + Sync>,
+    /// Points created by local mapping and not yet validated, with the
+    /// keyframe that created them
+    recent_points: Vec<(MapPointId, KeyFrameId)>
+}
+
+impl LocalMapper {
+    pub fn new(config: LocalMappingConfig, map: SharedMap, vocabulary: Arc<Vocabulary>) -> Self {
+        Self {
+            config,
+            map,
+            vocabulary,
+            abort: Arc::new(AtomicBool::new(false)),
+            linearizer: Arc::new(CpuLinearizer),
+            recent_points: Vec::new()
+        }
+    }
+
+    /// Runs local bundle adjustment with `linearizer`, such as a
+    /// `BundleProgram` sharing the device of feature extraction
+    pub fn with_linearizer(mut self, linearizer: Arc<dyn Linearizer + Send + Sync>) -> Self {
+        self.linearizer = linearizer;
+        self
+    }
+
+    pub fn map(&self) -> &SharedMap {
+        &self.map
+    }
+
+    /// Flag that stops a running local bundle adjustment when set. It is
+    /// not cleared by `process_keyframe`; `LocalMappingThread` clears it
+    /// as it takes each keyframe from its queue.
+    pub fn abort_flag(&self) -> Arc<AtomicBool> {
+        self.abort.clone()
+    }
+
+    /// Inserts a tracked frame as a keyframe. Its `map_points` become
+    /// observations. Fusing, bundle adjustment and keyframe culling are
+    /// skipped unless `queue_empty`, so local mapping catches up when
+    /// tracking inserts keyframes faster than they are processed. So is the
+    /// inertial initialisation, which may rescale the map and which the
+    /// tracker anchors on the newest keyframe.
+    pub fn process_keyframe(&mut self, frame: Frame, queue_empty: bool) -> KeyFrameId {
+        let map = self.map.clone();
+
+        let id = {
+            let mut map = map.write().unwrap();
+
+            let id = self.insert_keyframe(&mut map, frame);
+            self.create_stereo_points(&mut map, id);
+            self.cull_points(&mut map, id);
+            self.create_points(&mut map, id);
+
+            if queue_empty {
+                self.fuse_neighbours(&mut map, id);
+            }
+
+            id
+        };
+
+        if queue_empty {
+            if map.read().unwrap().keyframe_count() > 2 {
+                local_bundle_adjustment(&self.config.bundle_adjustment, self.linearizer.as_ref(), &map, id, &self.abort);
+            }
+
+            let mut map = map.write().unwrap();
+
+            if map.gravity().is_none() && map.keyframe(id).is_some_and(|kf| kf.frame.imu.is_some()) {
+                initialize_inertial_map(&self.config.inertial, &mut map);
+            }
+
+            self.cull_keyframes(&mut map, id);
+        }
+
+        id
+    }
+
+    fn insert_keyframe(&self, map: &mut Map, mut frame: Frame) -> KeyFrameId {
+        frame.compute_bow(&self.vocabulary, self.config.levels_up);
+
+        let links: Vec<(usize, MapPointId)> = frame.map_points.iter()
+            .enumerate()
+            .filter_map(|(i, id)| id.map(|id| (i, id)))
+            .collect();
+
+        frame.map_points.fill(None);
+        let id = map.add_keyframe(frame);
+
+        for (i, point) in links {
+            let Some(map_point) = map.point(point) else {
+                continue;
+            };
+
+            // Two keypoints may have been matched to the same point
+            if map_point.observations.contains_key(&id) {
+                continue;
+            }
+
+            map.add_observation(id, i, point);
+            map.update_normal_and_depth(point);
+            map.compute_distinctive_descriptor(point);
+        }
+
+        map.update_connections(id);
+        id
+    }
+
+    /// Creates points from the depths of a stereo or RGB-D keyframe, as
+    /// ORB-SLAM does when such a keyframe is created
+    fn create_stereo_points(&self, map: &mut Map, id: KeyFrameId) {
+        let frame = &map.keyframe(id).unwrap().frame;
+
+        let Some(stereo) = &frame.stereo else {
+            return;
+        };
+
+        let close_depth = stereo.close_depth;
+
+        let mut by_depth: Vec<(f64, usize)> = stereo.depths.iter()
+            .enumerate()
+            .filter_map(|(i, depth)| depth.map(|depth| (depth, i)))
+            .collect();
+
+        by_depth.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        for (with_point, (depth, i)) in by_depth.into_iter().enumerate() {
+            if depth > close_depth && with_point > self.config.stereo_points {
+                break;
+            }
+
+            let frame = &map.keyframe(id).unwrap().frame;
+
+            if frame.map_points[i].is_some() {
+                continue;
+            }
+
+            let position = frame.unproject_stereo(i).unwrap();
+            let point = map.add_point(position, id, i);
+            map.compute_distinctive_descriptor(point);
+        }
+    }
+
+    fn cull_points(&mut self, map: &mut Map, current: KeyFrameId) {
+        let config = &self.config;
+
+        let stereo = map.keyframe(current).is_some_and(|keyframe| keyframe.frame.stereo.is_some());
+        let min_observations = if stereo { config.stereo_min_observations } else { config.min_observations };
+
+        self.recent_points.retain(|&(point, created)| {
+            let Some(map_point) = map.point(point) else {
+                return false;
+            };
+
+            let age = current - created;
+            let unreliable = map_point.found_ratio() < config.min_found_ratio ||
+                (age >= 2 && map_point.observations.len() <= min_observations);
+
+            if unreliable {
+                map.erase_point(point);
+                return false;
+            }
+
+            age < 3
+        });
+    }
+
+    /// Triangulates unmatched keypoints of a keyframe with those of its
+    /// best covisible keyframes
+    fn create_points(&mut self, map: &mut Map, id: KeyFrameId) {
+        let keyframe = map.keyframe(id).unwrap();
+        let neighbours: Vec<KeyFrameId> = keyframe.best_covisibles(self.config.triangulation_neighbours).collect();
+        let center = keyframe.frame.pose.unwrap().camera_center();
+
+        for neighbour in neighbours {
+            let keyframe = map.keyframe(id).unwrap();
+            let Some(other) = map.keyframe(neighbour) else {
+                continue;
+            };
+
+            let baseline = (other.frame.pose.unwrap().camera_center() - center).norm();
+
+            let too_close = match &other.frame.stereo {
+                Some(stereo) => baseline < stereo.baseline,
+                None => median_depth(map, other).is_none_or(|depth| baseline / depth < self.config.min_baseline_ratio)
+            };
+
+            if too_close {
+                continue;
+            }
+
+            let matches = search_for_triangulation(self.config.triangulation_max_distance, keyframe, other);
+
+            let points: Vec<(Vec3, Match)> = matches.into_iter()
+                .filter_map(|m| {
+                    let point = self.triangulate_match(keyframe, other, &m)?;
+                    Some((point, m))
+                })
+                .collect();
+
+            for (position, m) in points {
+                let point = map.add_point(position, id, m.query as usize);
+                map.add_observation(neighbour, m.train as usize, point);
+                map.compute_distinctive_descriptor(point);
+                map.update_normal_and_depth(point);
+
+                self.recent_points.push((point, id));
+            }
+        }
+    }
+
+    /// Triangulated point of a match between two keyframes, if it has enough
+    /// parallax, lies in front of both cameras, reprojects close to both
+    /// keypoints and its distances to the cameras agree with their octaves
+    fn triangulate_match(&self, a: &KeyFrame, b: &KeyFrame, m: &Match) -> Option<Vec3> {
+        let (i, j) = (m.query as usize, m.train as usize);
+        let (pose_a, pose_b) = (a.frame.pose.unwrap(), b.frame.pose.unwrap());
+        let (pinhole_a, pinhole_b) = (a.frame.camera.pinhole(), b.frame.camera.pinhole());
+
+        let x_a = pinhole_a.normalize(&a.frame.undistorted[i]);
+        let x_b = pinhole_b.normalize(&b.frame.undistorted[j]);
+
+        let ray_a = pose_a.rotation.inverse() * Vec3::new(x_a.x(), x_a.y(), 1.0);
+        let ray_b = pose_b.rotation.inverse() * Vec3::new(x_b.x(), x_b.y(), 1.0);
+        let parallax_cos = ray_a.dot(&ray_b) / (ray_a.norm() * ray_b.norm());
+
+        if parallax_cos <= 0.0 || parallax_cos >= self.config.max_parallax_cos {
+            return None;
+        }
+
+        let point = triangulate(&pose_a.matrix(), &pose_b.matrix(), &x_a, &x_b)?;
+
+        for (keyframe, pose, pinhole, index) in [(a, pose_a, pinhole_a, i), (b, pose_b, pinhole_b, j)] {
+            let camera_point = pose.transform(&point);
+
+            if camera_point.z() <= 0.0 {
+                return None;
+            }
+
+            let error = pinhole.project(&camera_point) - keyframe.frame.undistorted[index];
+            let sigma2 = keyframe.frame.scale.level_sigma2[keyframe.frame.octave(index) as usize];
+
+            if error.norm_squared() > CHI2_MONO * sigma2 {
+                return None;
+            }
+        }
+
+        let distance_a = (point - pose_a.camera_center()).norm();
+        let distance_b = (point - pose_b.camera_center()).norm();
+
+        if distance_a == 0.0 || distance_b == 0.0 {
+            return None;
+        }
+
+        let scale = &a.frame.scale;
+        let distance_ratio = distance_b / distance_a;
+        let octave_ratio = scale.scale_factors[a.frame.octave(i) as usize] / scale.scale_factors[b.frame.octave(j) as usize];
+        let tolerance = 1.5 * scale.scale_factor;
+
+        if distance_ratio * tolerance < octave_ratio || distance_ratio > octave_ratio * tolerance {
+            return None;
+        }
+
+        Some(point)
+    }
+
+    /// Fuses the points of a keyframe with those of its neighbours in the
+    /// covisibility graph, then refreshes its points and connections
+    fn fuse_neighbours(&self, map: &mut Map, id: KeyFrameId) {
+        let keyframe = map.keyframe(id).unwrap();
+        let mut targets: Vec<KeyFrameId> = keyframe.best_covisibles(self.config.fuse_neighbours).collect();
+
+        for neighbour in targets.clone() {
+            for second in map.keyframe(neighbour).unwrap().best_covisibles(self.config.fuse_second_neighbours) {
+                if second != id && !targets.contains(&second) {
+                    targets.push(second);
+                }
+            }
+        }
+
+        let points: Vec<MapPointId> = keyframe.frame.map_points.iter().flatten().copied().collect();
+
+        for &target in &targets {
+            self.fuse(map, target, &points);
+        }
+
+        let candidates: BTreeSet<MapPointId> = targets.iter()
+            .filter_map(|&target| map.keyframe(target))
+            .flat_map(|target| target.frame.map_points.iter().flatten().copied())
+            .collect();
+
+        let candidates: Vec<MapPointId> = candidates.into_iter().collect();
+        self.fuse(map, id, &candidates);
+
+        let points: Vec<MapPointId> = map.keyframe(id).unwrap().frame.map_points.iter().flatten().copied().collect();
+
+        for point in points {
+            map.compute_distinctive_descriptor(point);
+            map.update_normal_and_depth(point);
+        }
+
+        map.update_connections(id);
+    }
+
+    /// Projects map points into a keyframe and matches them to nearby
+    /// keypoints. Keypoints already observing another point keep whichever
+    /// point has more observations.
+    fn fuse(&self, map: &mut Map, id: KeyFrameId, points: &[MapPointId]) {
+        for &point in points {
+            let Some(keyframe) = map.keyframe(id) else {
+                return;
+            };
+
+            let Some(map_point) = map.point(point) else {
+                continue;
+            };
+
+            if map_point.observations.contains_key(&id) {
+                continue;
+            }
+
+            let frame = &keyframe.frame;
+            let pose = frame.pose.unwrap();
+            let camera_point = pose.transform(&map_point.position);
+
+            if camera_point.z() <= 0.0 {
+                continue;
+            }
+
+            let pixel = frame.camera.pinhole().project(&camera_point);
+
+            if !frame.is_in_image(&pixel) {
+                continue;
+            }
+
+            let view = map_point.position - pose.camera_center();
+            let distance = view.norm();
+
+            if distance < 0.8 * map_point.min_distance || distance > 1.2 * map_point.max_distance {
+                continue;
+            }
+
+            // Points are only detectable from roughly the directions they were seen from
+            if view.dot(&map_point.normal) < 0.5 * distance {
+                continue;
+            }
+
+            let octave = map_point.predict_octave(distance, &frame.scale);
+            let radius = self.config.fuse_radius * frame.scale.scale_factors[octave as usize];
+
+            let mut best = (NO_MATCH, NO_MATCH as usize);
+
+            for index in frame.features_in_area(pixel.x(), pixel.y(), radius, octave.saturating_sub(1), octave) {
+                let error = pixel - frame.undistorted[index];
+
+                if error.norm_squared() * frame.scale.inv_level_sigma2[frame.octave(index) as usize] > CHI2_MONO {
+                    continue;
+                }
+
+                let distance = hamming_distance(&map_point.descriptor, &frame.descriptors[index]);
+
+                if distance < best.0 {
+                    best = (distance, index);
+                }
+            }
+
+            if best.0 > self.config.fuse_max_distance {
+                continue;
+            }
+
+            let index = best.1;
+
+            match frame.map_points[index] {
+                Some(existing) => {
+                    let existing_observations = map.point(existing).map_or(0, |p| p.observations.len());
+
+                    if existing_observations > map_point.observations.len() {
+                        map.replace_point(point, existing);
+                    } else {
+                        map.replace_point(existing, point);
+                    }
+                },
+                None => map.add_observation(id, index, point)
+            }
+        }
+    }
+
+    /// Erases covisible keyframes most of whose points are seen by enough
+    /// other keyframes at the same or a finer scale
+    fn cull_keyframes(&self, map: &mut Map, id: KeyFrameId) {
+        let neighbours: Vec<KeyFrameId> = map.keyframe(id).unwrap().covisibles().iter().map(|&(id, _)| id).collect();
+
+        for neighbour in neighbours {
+            if map.origin() == Some(neighbour) {
+                continue;
+            }
+
+            let Some(keyframe) = map.keyframe(neighbour) else {
+                continue;
+            };
+
+            let mut point_count = 0;
+            let mut redundant = 0;
+
+            for (i, point) in keyframe.frame.map_points.iter().enumerate() {
+                let Some(map_point) = point.and_then(|point| map.point(point)) else {
+                    continue;
+                };
+
+                // Far stereo points are no more reliable than monocular ones
+                if keyframe.frame.stereo.is_some() && !keyframe.frame.is_close(i) {
+                    continue;
+                }
+
+                point_count += 1;
+
+                if map_point.observations.len() <= self.config.redundant_observers {
+                    continue;
+                }
+
+                let octave = keyframe.frame.octave(i);
+
+                let observers = map_point.observations.iter()
+                    .filter(|&(&other, _)| other != neighbour)
+                    .filter(|&(other, &index)| map.keyframe(*other).is_some_and(|kf| kf.frame.octave(index) <= octave + 1))
+                    .count();
+
+                if observers >= self.config.redundant_observers {
+                    redundant += 1;
+                }
+            }
+
+            if redundant as f64 > self.config.redundancy_ratio * point_count as f64 {
+                map.erase_keyframe(neighbour);
+            }
+        }
+    }
+}
+
+/// Median depth of the points observed by a keyframe
+fn median_depth(map: &Map, keyframe: &KeyFrame) -> Option<f64> {
+    let pose = keyframe.frame.pose.unwrap();
+
+    let mut depths: Vec<f64> = keyframe.frame.map_points.iter()
+        .filter_map(|id| id.and_then(|id| map.point(id)))
+        .map(|point| pose.transform(&point.position).z())
+        .collect();
+
+    if depths.is_empty() {
+        return None;
+    }
+
+    depths.sort_by(f64::total_cmp);
+    Some(depths[(depths.len() - 1) / 2])
+}
+
+/// Matches keypoints without a map point in two keyframes through the
+/// vocabulary, keeping those consistent with the epipolar geometry of the
+/// keyframe poses. Query indexes `a`, train indexes `b`.
+fn search_for_triangulation(max_distance: u32, a: &KeyFrame, b: &KeyFrame) -> Vec<Match> {
+    let (Some(features_a), Some(features_b)) = (&a.frame.features, &b.frame.features) else {
+        return Vec::new();
+    };
+
+    let (pose_a, pose_b) = (a.frame.pose.unwrap(), b.frame.pose.unwrap());
+    let relative = pose_b * pose_a.inverse();
+
+    // Fundamental matrix from undistorted pixels of `a` to lines in `b`
+    let k_a = a.frame.camera.pinhole().matrix().try_inverse().unwrap();
+    let k_b = b.frame.camera.pinhole().matrix().try_inverse().unwrap();
+    let fundamental = k_b.transpose() * relative.translation.hat() * relative.rotation.matrix() * k_a;
+
+    let pinhole_b = b.frame.camera.pinhole();
+    let epipole_camera = pose_b.transform(&pose_a.camera_center());
+    let epipole = (epipole_camera.z() > 0.0).then(|| pinhole_b.project(&epipole_camera));
+
+    let mut matched_b = vec![false; b.frame.len()];
+    let mut matches = Vec::new();
+
+    for (node, indices_a) in &features_a.0 {
+        let Some(indices_b) = features_b.0.get(node) else {
+            continue;
+        };
+
+        for &i in indices_a {
+            if a.frame.map_points[i as usize].is_some() {
+                continue;
+            }
+
+            let p = a.frame.undistorted[i as usize];
+            let line = fundamental * Vec3::new(p.x(), p.y(), 1.0);
+
+            let mut best = (max_distance + 1, NO_MATCH);
+
+            for &j in indices_b {
+                if matched_b[j as usize] || b.frame.map_points[j as usize].is_some() {
+                    continue;
+                }
+
+                let distance = hamming_distance(&a.frame.descriptors[i as usize], &b.frame.descriptors[j as usize]);
+
+                if distance >= best.0 {
+                    continue;
+                }
+
+                let q = b.frame.undistorted[j as usize];
+                let octave = b.frame.octave(j as usize) as usize;
+
+                // Points next to the epipole are too close to the first camera
+                if let Some(epipole) = epipole {
+                    if (q - epipole).norm_squared() < 100.0 * b.frame.scale.scale_factors[octave] {
+                        continue;
+                    }
+                }
+
+                let residual = line.dot(&Vec3::new(q.x(), q.y(), 1.0));
+                let line_norm2 = line.x() * line.x() + line.y() * line.y();
+
+                if line_norm2 == 0.0 || residual * residual / line_norm2 > CHI2_EPIPOLAR * b.frame.scale.level_sigma2[octave] {
+                    continue;
+                }
+
+                best = (distance, j);
+            }
+
+            if best.1 != NO_MATCH {
+                matched_b[best.1 as usize] = true;
+                matches.push(Match { query: i, train: best.1, distance: best.0 });
+            }
+        }
+    }
+
+    filter_by_rotation(&matches, &a.frame.corners, &b.frame.corners)
+}
+
+/// Local mapping running on its own thread. Keyframes are sent to it over a
+/// channel and the ids of processed keyframes come back on another, for
+/// loop closing.
+pub struct LocalMappingThread {
+    keyframes: flume::Sender<Frame>,
+    processed: flume::Receiver<KeyFrameId>,
+    /// Keyframes inserted and not yet processed, including the one being
+    /// processed
+    pending: Arc<AtomicUsize>,
+    abort: Arc<AtomicBool>,
+    thread: JoinHandle<LocalMapper>
+}
+
+impl LocalMappingThread {
+    pub fn spawn(mut mapper: LocalMapper) -> Self {
+        let (keyframes, keyframe_receiver) = flume::unbounded::<Frame>();
+        let (processed_sender, processed) = flume::unbounded();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let abort = mapper.abort_flag();
+
+        let thread = std::thread::spawn({
+            let pending = pending.clone();
+            let abort = abort.clone();
+
+            move || {
+                while let Ok(frame) = keyframe_receiver.recv() {
+                    // Cleared before the queue is checked, so a keyframe
+                    // inserted from here on either skips the bundle
+                    // adjustment or interrupts it
+                    abort.store(false, Ordering::SeqCst);
+
+                    let id = mapper.process_keyframe(frame, keyframe_receiver.is_empty());
+                    let _ = processed_sender.send(id);
+
+                    pending.fetch_sub(1, Ordering::SeqCst);
+                }
+
+                mapper
+            }
+        });
+
+        Self { keyframes, processed, pending, abort, thread }
+    }
+
+    /// Queues a tracked frame to become a keyframe. A running bundle
+    /// adjustment is interrupted so the keyframe is processed sooner.
+    pub fn insert_keyframe(&self, frame: Frame) {
+        // Counted before it is queued, so the thread never sees it uncounted
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.abort.store(true, Ordering::SeqCst);
+        self.keyframes.send(frame).expect("local mapping thread stopped");
+    }
+
+    /// Stops a running bundle adjustment, see `KeyFrameDecision::interrupt_mapping`
+    pub fn interrupt_bundle_adjustment(&self) {
+        self.abort.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether every queued keyframe has been processed
+    pub fn is_idle(&self) -> bool {
+        self.pending.load(Ordering::SeqCst) == 0
+    }
+
+    /// Number of keyframes waiting to be processed
+    pub fn queued(&self) -> usize {
+        self.keyframes.len()
+    }
+
+    /// Ids of processed keyframes, in order
+    pub fn processed(&self) -> &flume::Receiver<KeyFrameId> {
+        &self.processed
+    }
+
+    /// Processes the remaining keyframes and stops the thread
+    pub fn shutdown(self) -> LocalMapper {
+        drop(self.keyframes);
+        self.thread.join().expect("local mapping thread panicked")
+    }
+}
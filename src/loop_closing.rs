@@ -0,0 +1,680 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use crate::bundle_adjustment::{global_bundle_adjustment, BundleAdjustmentConfig, BundleSummary, CpuLinearizer, Linearizer};
+use crate::camera::CameraModel;
+use crate::frame::Frame;
+use crate::geometry::Sim3;
+use crate::keyframe_database::loop_candidates;
+use crate::linalg::Mat7;
+use crate::map::{KeyFrameId, Map, MapPoint, MapPointId, SharedMap, ESSENTIAL_GRAPH_MIN_WEIGHT};
+use crate::matcher::{filter_by_rotation, hamming_distance, match_by_nodes, Match, MatcherConfig, NO_MATCH};
+use crate::optimizer::{Huber, CHI2_MONO};
+use crate::pose_graph::{optimize_pose_graph, PoseGraph, PoseGraphSummary};
+use crate::random::Rng;
+use crate::sim3::{solve_sim3, Sim3Config, Sim3Match};
+use crate::tracking::mapped_features;
+use crate::vocabulary::Vocabulary;
+
+pub struct LoopClosingConfig {
+    /// Loops are only detected in maps with more keyframes than this, and
+    /// this many keyframes after the last loop
+    pub min_keyframes: usize,
+    /// Number of consecutive keyframes whose candidates must belong to
+    /// connected groups of keyframes before a loop is accepted
+    pub consistency_threshold: usize,
+    /// Matching of mapped keypoints between the keyframe and a candidate
+    pub bow_matcher: MatcherConfig,
+    /// Candidates with fewer matched map points are discarded
+    pub min_bow_matches: usize,
+    pub sim3: Sim3Config,
+    /// Search radius around projected points, in pixels at octave 0, when
+    /// matching the points around the loop keyframe...
+    pub search_radius: f64,
+    /// ...and when fusing them with the corrected points
+    pub fuse_radius: f64,
+    /// Projected matches with a larger Hamming distance are rejected
+    pub projection_max_distance: u32,
+    /// Loops are accepted with at least this many matched keypoints
+    pub min_loop_matches: usize,
+    /// Covisibility edges with at least this weight join the essential graph
+    pub essential_graph_min_weight: usize,
+    pub pose_graph_iterations: usize,
+    /// Whether to refine the whole map after correcting a loop, with the
+    /// robust iterations and kernel of `bundle_adjustment`, on a thread of
+    /// its own
+    pub global_bundle_adjustment: bool,
+    pub bundle_adjustment: BundleAdjustmentConfig
+}
+
+impl Default for LoopClosingConfig {
+    fn default() -> Self {
+        // Values used by ORB-SLAM's monocular loop closing
+        Self {
+            min_keyframes: 10,
+            consistency_threshold: 3,
+            bow_matcher: MatcherConfig { max_distance: 50, ratio: 0.75 },
+            min_bow_matches: 20,
+            sim3: Sim3Config::default(),
+            search_radius: 10.0,
+            fuse_radius: 4.0,
+            projection_max_distance: 50,
+            min_loop_matches: 40,
+            essential_graph_min_weight: ESSENTIAL_GRAPH_MIN_WEIGHT,
+            pose_graph_iterations: 20,
+            global_bundle_adjustment: true,
+            bundle_adjustment: BundleAdjustmentConfig {
+                robust_iterations: 10,
+                iterations: 0,
+                chi2_threshold: CHI2_MONO,
+                kernel: Huber { delta: CHI2_MONO.sqrt() }
+            }
+        }
+    }
+}
+
+/// Loop closed by `LoopCloser`
+#[derive(Clone, Debug)]
+pub struct LoopClosure {
+    pub keyframe: KeyFrameId,
+    pub loop_keyframe: KeyFrameId,
+    /// World to camera similarity of `keyframe` that aligns it with the loop
+    /// keyframe
+    pub correction: Sim3,
+    /// Keypoints of `keyframe` matched to map points around the loop keyframe
+    pub matches: usize,
+    pub pose_graph: PoseGraphSummary
+}
+
+/// Loop candidate that passed the geometric verification
+struct LoopMatch {
+    keyframe: KeyFrameId,
+    /// Corrected world to camera similarity of the current keyframe
+    scw: Sim3,
+    /// Point around the loop keyframe matched to each keypoint of the
+    /// current keyframe
+    points: Vec<Option<MapPointId>>
+}
+
+/// Detects loops between keyframes processed by local mapping and earlier
+/// parts of the map, and corrects the accumulated drift. Candidates come
+/// from the keyframe database and must be consistent over several
+/// keyframes. A similarity to the loop keyframe is estimated from matched
+/// map points, the points around the loop keyframe are fused with those
+/// around the current one, and the correction is spread over the map by
+/// optimising the essential graph, optionally followed by global bundle
+/// adjustment in the background, which the next loop aborts.
+pub struct LoopCloser {
+    pub config: LoopClosingConfig,
+    map: SharedMap,
+    vocabulary: Arc<Vocabulary>,
+    /// Abort flag of local mapping's bundle adjustment, set when the map is
+    /// corrected so results computed before are discarded
+    mapping_abort: Arc<AtomicBool>,
+    /// Evaluates the normal equations of global bundle adjustment
+    linearizer: Arc<dyn Linearizer + Send + Sync>,
+    /// Global bundle adjustment started after the last loop
+    global_bundle_adjustment: Option<JoinHandle<Option<BundleSummary>>>,
+    /// Set to stop the running global bundle adjustment, which a new loop
+    /// makes outdated
+    global_abort: Arc<AtomicBool>,
+    /// Groups of connected keyframes around the candidates of previous
+    /// keyframes, with the number of consecutive keyframes they were found in
+    consistent_groups: Vec<(BTreeSet<KeyFrameId>, usize)>,
+    last_loop: Option<KeyFrameId>,
+    rng: Rng
+}
+
+impl LoopCloser {
+    pub fn new(config: LoopClosingConfig, map: SharedMap, vocabulary: Arc<Vocabulary>, mapping_abort: Arc<AtomicBool>) -> Self {
+        Self {
+            config,
+            map,
+            vocabulary,
+            mapping_abort,
+            linearizer: Arc::new(CpuLinearizer),
+            global_bundle_adjustment: None,
+            global_abort: Arc::new(AtomicBool::new(false)),
+            consistent_groups: Vec::new(),
+            last_loop: None,
+            rng: Rng::new(0)
+        }
+    }
+
+    /// Runs global bundle adjustment with `linearizer`
+    pub fn with_linearizer(mut self, linearizer: Arc<dyn Linearizer + Send + Sync>) -> Self {
+        self.linearizer = linearizer;
+        self
+    }
+
+    pub fn map(&self) -> &SharedMap {
+        &self.map
+    }
+
+    /// Flag stopping a running global bundle adjustment, set when a new loop
+    /// is detected
+    pub fn global_abort_flag(&self) -> Arc<AtomicBool> {
+        self.global_abort.clone()
+    }
+
+    pub fn is_running_global_bundle_adjustment(&self) -> bool {
+        self.global_bundle_adjustment.as_ref().is_some_and(|thread| !thread.is_finished())
+    }
+
+    /// Waits for the global bundle adjustment started after the last loop.
+    /// Returns its summary, or `None` if none was started or it was aborted.
+    pub fn wait_for_global_bundle_adjustment(&mut self) -> Option<BundleSummary> {
+        self.global_bundle_adjustment.take()?.join().expect("global bundle adjustment panicked")
+    }
+
+    /// Looks for a loop closed by a keyframe inserted by local mapping, and
+    /// corrects the map if one is found
+    pub fn process_keyframe(&mut self, id: KeyFrameId) -> Option<LoopClosure> {
+        let loop_match = {
+            let map = self.map.clone();
+            let map = map.read().unwrap();
+            let candidates = self.detect_loop(&map, id);
+            self.compute_sim3(&map, id, &candidates)?
+        };
+
+        // The map is about to change under the running global bundle
+        // adjustment, whose results would undo the correction. Waiting for
+        // it to stop keeps it from writing back afterwards.
+        self.global_abort.store(true, Ordering::SeqCst);
+        self.wait_for_global_bundle_adjustment();
+
+        let (matches, pose_graph) = self.correct_loop(id, &loop_match)?;
+
+        if self.config.global_bundle_adjustment {
+            self.global_abort.store(false, Ordering::SeqCst);
+
+            let config = self.config.bundle_adjustment.clone();
+            let linearizer = self.linearizer.clone();
+            let map = self.map.clone();
+            let abort = self.global_abort.clone();
+
+            self.global_bundle_adjustment = Some(std::thread::spawn(move || {
+                global_bundle_adjustment(&config, linearizer.as_ref(), &map, &abort)
+            }));
+        }
+
+        Some(LoopClosure {
+            keyframe: id,
+            loop_keyframe: loop_match.keyframe,
+            correction: loop_match.scw,
+            matches,
+            pose_graph
+        })
+    }
+
+    /// Candidates that belong to groups of connected keyframes in which the
+    /// candidates of `consistency_threshold` consecutive keyframes were found
+    fn detect_loop(&mut self, map: &Map, id: KeyFrameId) -> Vec<KeyFrameId> {
+        let too_soon = self.last_loop.is_some_and(|last| id < last + self.config.min_keyframes as u64);
+
+        if map.keyframe_count() <= self.config.min_keyframes || too_soon {
+            return Vec::new();
+        }
+
+        let Some(keyframe) = map.keyframe(id) else {
+            return Vec::new();
+        };
+
+        let Some(bow) = &keyframe.frame.bow else {
+            return Vec::new();
+        };
+
+        // Loops must look at least as similar as the covisible keyframes
+        let min_score = keyframe.covisibles().iter()
+            .filter_map(|&(other, _)| map.keyframe(other)?.frame.bow.as_ref())
+            .map(|other| self.vocabulary.score(bow, other))
+            .fold(1.0, f64::min);
+
+        let candidates = loop_candidates(map, &self.vocabulary, id, min_score);
+
+        if candidates.is_empty() {
+            self.consistent_groups.clear();
+            return Vec::new();
+        }
+
+        let mut groups = Vec::new();
+        let mut extended = vec![false; self.consistent_groups.len()];
+        let mut consistent = Vec::new();
+
+        for candidate in candidates {
+            let group: BTreeSet<KeyFrameId> = std::iter::once(candidate)
+                .chain(map.keyframe(candidate).into_iter().flat_map(|kf| kf.covisibles().iter().map(|&(id, _)| id)))
+                .collect();
+
+            let mut found = false;
+
+            for (i, (previous, count)) in self.consistent_groups.iter().enumerate() {
+                if group.is_disjoint(previous) {
+                    continue;
+                }
+
+                found = true;
+
+                if !extended[i] {
+                    groups.push((group.clone(), count + 1));
+                    extended[i] = true;
+                }
+
+                if count + 1 >= self.config.consistency_threshold && !consistent.contains(&candidate) {
+                    consistent.push(candidate);
+                }
+            }
+
+            if !found {
+                groups.push((group, 0));
+            }
+        }
+
+        self.consistent_groups = groups;
+        consistent
+    }
+
+    /// Estimates the similarity to the first candidate with enough matched
+    /// map points and Sim3 inliers, then matches the points around it by
+    /// projection
+    fn compute_sim3(&mut self, map: &Map, id: KeyFrameId, candidates: &[KeyFrameId]) -> Option<LoopMatch> {
+        let current = &map.keyframe(id)?.frame;
+        let current_features = mapped_features(current)?;
+
+        for &candidate in candidates {
+            let Some(loop_frame) = map.keyframe(candidate).map(|kf| &kf.frame) else {
+                continue;
+            };
+
+            let Some(loop_features) = mapped_features(loop_frame) else {
+                continue;
+            };
+
+            let matches = match_by_nodes(&self.config.bow_matcher, &current_features, &current.descriptors, &loop_features, &loop_frame.descriptors);
+            let matches = filter_by_rotation(&matches, &current.corners, &loop_frame.corners);
+
+            let pairs: Vec<(Match, MapPointId, &MapPoint, &MapPoint)> = matches.into_iter()
+                .filter_map(|m| {
+                    let loop_id = loop_frame.map_points[m.train as usize]?;
+                    Some((m, loop_id, map.point(current.map_points[m.query as usize]?)?, map.point(loop_id)?))
+                })
+                .collect();
+
+            if pairs.len() < self.config.min_bow_matches {
+                continue;
+            }
+
+            let (current_pose, loop_pose) = (current.pose.unwrap(), loop_frame.pose.unwrap());
+
+            let sim3_matches: Vec<Sim3Match> = pairs.iter()
+                .map(|&(m, _, current_point, loop_point)| Sim3Match {
+                    points: [current_pose.transform(&current_point.position), loop_pose.transform(&loop_point.position)],
+                    pixels: [current.keypoints[m.query as usize], loop_frame.keypoints[m.train as usize]],
+                    sigma2: [
+                        current.scale.level_sigma2[current.octave(m.query as usize) as usize],
+                        loop_frame.scale.level_sigma2[loop_frame.octave(m.train as usize) as usize]
+                    ]
+                })
+                .collect();
+
+            let cameras = [current.camera.as_ref(), loop_frame.camera.as_ref()];
+
+            // Depths of stereo and RGB-D keyframes fix the scale of the map
+            let config = Sim3Config { fixed_scale: self.config.sim3.fixed_scale || current.stereo.is_some(), ..self.config.sim3.clone() };
+
+            let Some(result) = solve_sim3(cameras, &sim3_matches, &config, &mut self.rng) else {
+                continue;
+            };
+
+            // Maps the world to the current camera frame as seen from the loop keyframe
+            let scw = result.sim3 * Sim3::from_se3(&loop_pose);
+
+            let mut points = vec![None; current.len()];
+
+            for (&(m, loop_id, _, _), _) in pairs.iter().zip(&result.inliers).filter(|(_, &inlier)| inlier) {
+                points[m.query as usize] = Some(loop_id);
+            }
+
+            // Points around the loop keyframe
+            let neighbourhood: BTreeSet<MapPointId> = std::iter::once(candidate)
+                .chain(map.keyframe(candidate).unwrap().covisibles().iter().map(|&(id, _)| id))
+                .filter_map(|id| map.keyframe(id))
+                .flat_map(|kf| kf.frame.map_points.iter().flatten().copied())
+                .collect();
+
+            let already_matched: BTreeSet<MapPointId> = points.iter().flatten().copied().collect();
+
+            for point_id in neighbourhood.into_iter().filter(|id| !already_matched.contains(id)) {
+                let point = map.point(point_id).unwrap();
+                let matched = project_and_match(current, &scw, point, self.config.search_radius, self.config.projection_max_distance, |index| points[index].is_some());
+
+                if let Some(index) = matched {
+                    points[index] = Some(point_id);
+                }
+            }
+
+            if points.iter().flatten().count() >= self.config.min_loop_matches {
+                return Some(LoopMatch { keyframe: candidate, scw, points });
+            }
+        }
+
+        None
+    }
+
+    /// Corrects the keyframes connected to the current one and their points
+    /// with the loop similarity, fuses the matched points, and spreads the
+    /// correction over the essential graph. Returns the number of fused
+    /// matches and the pose graph summary, or `None` if the keyframes
+    /// involved were erased in the meantime.
+    fn correct_loop(&mut self, id: KeyFrameId, loop_match: &LoopMatch) -> Option<(usize, PoseGraphSummary)> {
+        let map = self.map.clone();
+        let mut map = map.write().unwrap();
+
+        if map.keyframe(id).is_none() || map.keyframe(loop_match.keyframe).is_none() {
+            return None;
+        }
+
+        // Results of a running local bundle adjustment predate the correction
+        self.mapping_abort.store(true, Ordering::SeqCst);
+
+        map.update_connections(id);
+
+        let current = map.keyframe(id).unwrap();
+        let current_inverse = current.frame.pose.unwrap().inverse();
+        let connected: Vec<KeyFrameId> = std::iter::once(id)
+            .chain(current.covisibles().iter().map(|&(other, _)| other))
+            .collect();
+
+        // Corrected and original world to camera similarities
+        let mut corrected: BTreeMap<KeyFrameId, Sim3> = BTreeMap::new();
+        let mut original: BTreeMap<KeyFrameId, Sim3> = BTreeMap::new();
+
+        for &other in &connected {
+            let pose = map.keyframe(other).unwrap().frame.pose.unwrap();
+            let relative = Sim3::from_se3(&(pose * current_inverse));
+
+            corrected.insert(other, relative * loop_match.scw);
+            original.insert(other, Sim3::from_se3(&pose));
+        }
+
+        // Move the points of the connected keyframes with them
+        let mut corrected_by: BTreeMap<MapPointId, KeyFrameId> = BTreeMap::new();
+
+        for &other in &connected {
+            let points: Vec<MapPointId> = map.keyframe(other).unwrap().frame.map_points.iter().flatten().copied().collect();
+            let correction = corrected[&other].inverse() * original[&other];
+
+            for point in points {
+                if corrected_by.contains_key(&point) {
+                    continue;
+                }
+
+                let map_point = map.point_mut(point).unwrap();
+                map_point.position = correction.transform(&map_point.position);
+                corrected_by.insert(point, other);
+            }
+        }
+
+        for &other in &connected {
+            map.keyframe_mut(other).unwrap().frame.pose = Some(corrected[&other].to_se3());
+        }
+
+        for &point in corrected_by.keys() {
+            map.update_normal_and_depth(point);
+        }
+
+        // Loop points replace the duplicates they were matched to
+        let mut matches = 0;
+
+        for (index, &loop_point) in loop_match.points.iter().enumerate() {
+            let Some(loop_point) = loop_point.filter(|&point| map.point(point).is_some()) else {
+                continue;
+            };
+
+            matches += 1;
+
+            match map.keyframe(id).unwrap().frame.map_points[index] {
+                Some(existing) => map.replace_point(existing, loop_point),
+                None => {
+                    if !map.point(loop_point).unwrap().observations.contains_key(&id) {
+                        map.add_observation(id, index, loop_point);
+                        map.compute_distinctive_descriptor(loop_point);
+                    }
+                }
+            }
+        }
+
+        let loop_points: Vec<MapPointId> = std::iter::once(loop_match.keyframe)
+            .chain(map.keyframe(loop_match.keyframe).unwrap().covisibles().iter().map(|&(other, _)| other))
+            .filter_map(|other| map.keyframe(other))
+            .flat_map(|kf| kf.frame.map_points.iter().flatten().copied())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        for &other in &connected {
+            self.fuse(&mut map, other, &loop_points);
+        }
+
+        // Connections created by the fusion close the loop in the essential graph
+        let mut loop_connections = BTreeSet::new();
+
+        for &other in &connected {
+            let Some(keyframe) = map.keyframe(other) else {
+                continue;
+            };
+
+            let previous: BTreeSet<KeyFrameId> = keyframe.covisibles().iter().map(|&(k, _)| k).collect();
+            map.update_connections(other);
+
+            for &(k, _) in map.keyframe(other).unwrap().covisibles() {
+                if !previous.contains(&k) && !connected.contains(&k) {
+                    loop_connections.insert((other.min(k), other.max(k)));
+                }
+            }
+        }
+
+        let summary = self.optimize_essential_graph(&mut map, loop_match.keyframe, &corrected, &original, &corrected_by, &loop_connections);
+
+        map.add_loop_edge(id, loop_match.keyframe);
+
+        self.last_loop = Some(id);
+        Some((matches, summary))
+    }
+
+    /// Pose graph optimisation of the essential graph and the new loop
+    /// connections with the loop keyframe fixed, as in ORB-SLAM. Edges
+    /// measure the relative poses before the correction, except loop
+    /// connections which measure the corrected ones. The scale of stereo
+    /// and RGB-D keyframes is kept. Points follow the keyframe that
+    /// corrected them, or their reference keyframe.
+    fn optimize_essential_graph(
+        &self,
+        map: &mut Map,
+        loop_keyframe: KeyFrameId,
+        corrected: &BTreeMap<KeyFrameId, Sim3>,
+        original: &BTreeMap<KeyFrameId, Sim3>,
+        corrected_by: &BTreeMap<MapPointId, KeyFrameId>,
+        loop_connections: &BTreeSet<(KeyFrameId, KeyFrameId)>
+    ) -> PoseGraphSummary {
+        let mut graph = PoseGraph::new();
+        let mut vertices: BTreeMap<KeyFrameId, usize> = BTreeMap::new();
+        let mut before: BTreeMap<KeyFrameId, Sim3> = BTreeMap::new();
+
+        for keyframe in map.keyframes() {
+            let pose = corrected.get(&keyframe.id).copied().unwrap_or_else(|| Sim3::from_se3(&keyframe.frame.pose.unwrap()));
+            let fixed = keyframe.id == loop_keyframe;
+
+            let vertex = match keyframe.frame.stereo {
+                Some(_) => graph.add_se3_vertex(pose.to_se3(), fixed),
+                None => graph.add_vertex(pose, fixed)
+            };
+
+            vertices.insert(keyframe.id, vertex);
+            before.insert(keyframe.id, original.get(&keyframe.id).copied().unwrap_or(pose));
+        }
+
+        for &(a, b) in &map.essential_graph(self.config.essential_graph_min_weight) {
+            if loop_connections.contains(&(a, b)) {
+                continue;
+            }
+
+            let measurement = before[&a] * before[&b].inverse();
+            graph.add_edge(vertices[&a], vertices[&b], measurement, Mat7::identity());
+        }
+
+        for &(a, b) in loop_connections {
+            graph.add_relative_edge(vertices[&a], vertices[&b]);
+        }
+
+        let initial = graph.vertices.clone();
+        let summary = optimize_pose_graph(&mut graph, self.config.pose_graph_iterations);
+
+        for (&keyframe, &vertex) in &vertices {
+            map.keyframe_mut(keyframe).unwrap().frame.pose = Some(graph.vertices[vertex].to_se3());
+        }
+
+        let points: Vec<MapPointId> = map.points().map(|point| point.id).collect();
+
+        for point in points {
+            let map_point = map.point(point).unwrap();
+            let keyframe = corrected_by.get(&point).copied().unwrap_or(map_point.reference_keyframe);
+
+            let Some(&vertex) = vertices.get(&keyframe) else {
+                continue;
+            };
+
+            let correction = graph.vertices[vertex].inverse() * initial[vertex];
+            let position = correction.transform(&map_point.position);
+
+            map.point_mut(point).unwrap().position = position;
+            map.update_normal_and_depth(point);
+        }
+
+        summary
+    }
+
+    /// Projects loop points into a corrected keyframe. Matched keypoints
+    /// observing another point have it replaced by the loop point.
+    fn fuse(&self, map: &mut Map, id: KeyFrameId, points: &[MapPointId]) {
+        for &point in points {
+            let Some(keyframe) = map.keyframe(id) else {
+                return;
+            };
+
+            let Some(map_point) = map.point(point) else {
+                continue;
+            };
+
+            if map_point.observations.contains_key(&id) {
+                continue;
+            }
+
+            let pose = Sim3::from_se3(&keyframe.frame.pose.unwrap());
+
+            let Some(index) = project_and_match(&keyframe.frame, &pose, map_point, self.config.fuse_radius, self.config.projection_max_distance, |_| false) else {
+                continue;
+            };
+
+            match keyframe.frame.map_points[index] {
+                Some(existing) => map.replace_point(existing, point),
+                None => map.add_observation(id, index, point)
+            }
+        }
+    }
+}
+
+/// Keypoint of `frame` best matching a map point projected with the world
+/// to camera similarity `scw`, searched near the projection at the octave
+/// predicted from its distance. Keypoints for which `skip` holds are not
+/// considered.
+fn project_and_match(
+    frame: &Frame,
+    scw: &Sim3,
+    point: &MapPoint,
+    radius: f64,
+    max_distance: u32,
+    skip: impl Fn(usize) -> bool
+) -> Option<usize> {
+    let camera_point = scw.transform(&point.position);
+
+    if camera_point.z() <= 0.0 {
+        return None;
+    }
+
+    let pixel = frame.camera.pinhole().project(&camera_point);
+
+    if !frame.is_in_image(&pixel) {
+        return None;
+    }
+
+    // Distances in the world frame, where the point's depth range is defined
+    let view = point.position - scw.inverse().translation;
+    let distance = view.norm();
+
+    if distance < 0.8 * point.min_distance || distance > 1.2 * point.max_distance {
+        return None;
+    }
+
+    if view.dot(&point.normal) < 0.5 * distance {
+        return None;
+    }
+
+    let octave = point.predict_octave(distance, &frame.scale);
+    let radius = radius * frame.scale.scale_factors[octave as usize];
+
+    let mut best = (NO_MATCH, None);
+
+    for index in frame.features_in_area(pixel.x(), pixel.y(), radius, octave.saturating_sub(1), octave) {
+        if skip(index) {
+            continue;
+        }
+
+        let distance = hamming_distance(&point.descriptor, &frame.descriptors[index]);
+
+        if distance < best.0 {
+            best = (distance, Some(index));
+        }
+    }
+
+    best.1.filter(|_| best.0 <= max_distance)
+}
+
+/// Loop closing running on its own thread, fed with the ids of keyframes
+/// processed by local mapping. Closed loops are reported on a channel.
+pub struct LoopClosingThread {
+    closures: flume::Receiver<LoopClosure>,
+    thread: JoinHandle<LoopCloser>
+}
+
+impl LoopClosingThread {
+    /// Starts processing `keyframes`, e.g. `LocalMappingThread::processed`
+    pub fn spawn(mut closer: LoopCloser, keyframes: flume::Receiver<KeyFrameId>) -> Self {
+        let (closure_sender, closures) = flume::unbounded();
+
+        let thread = std::thread::spawn(move || {
+            while let Ok(id) = keyframes.recv() {
+                if let Some(closure) = closer.process_keyframe(id) {
+                    let _ = closure_sender.send(closure);
+                }
+            }
+
+            closer
+        });
+
+        Self { closures, thread }
+    }
+
+    /// Loops closed so far, in order
+    pub fn closures(&self) -> &flume::Receiver<LoopClosure> {
+        &self.closures
+    }
+
+    /// Waits for the keyframe channel to close, i.e. for local mapping to
+    /// shut down, and stops the thread
+    pub fn shutdown(self) -> LoopCloser {
+        self.thread.join().expect("loop closing thread panicked")
+    }
+}
@@ -0,0 +1,603 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, RwLock};
+
+use crate::frame::{Frame, ScalePyramid};
+use crate::keyframe_database::KeyFrameDatabase;
+use crate::linalg::Vec3;
+use crate::matcher::hamming_distance;
+use crate::orb::CornerDescriptor;
+
+pub type KeyFrameId = u64;
+pub type MapPointId = u64;
+
+/// Map shared between the tracking, local mapping and loop closing threads
+pub type SharedMap = Arc<RwLock<Map>>;
+
+/// Keyframes sharing fewer map points than this are not connected in the
+/// covisibility graph, unless it is their strongest connection
+pub const COVISIBILITY_MIN_WEIGHT: usize = 15;
+
+/// Covisibility edges with at least this weight are part of the essential graph
+pub const ESSENTIAL_GRAPH_MIN_WEIGHT: usize = 100;
+
+/// Landmark triangulated from keyframe observations
+#[derive(Clone)]
+pub struct MapPoint {
+    pub id: MapPointId,
+    /// World coordinates
+    pub position: Vec3,
+    /// Observation with the smallest median Hamming distance to the others
+    pub descriptor: CornerDescriptor,
+    /// Mean unit vector from the observing camera centers to the point
+    pub normal: Vec3,
+    /// Range of distances from a camera within which the point can be
+    /// detected at some octave of the scale pyramid
+    pub min_distance: f64,
+    pub max_distance: f64,
+    /// Keyframe that created the point
+    pub reference_keyframe: KeyFrameId,
+    /// Keypoint index of the point in each keyframe that observes it
+    pub observations: BTreeMap<KeyFrameId, usize>,
+    /// Tracked frames in which the point was predicted to be visible...
+    pub visible: u32,
+    /// ...and those in which it was matched
+    pub found: u32
+}
+
+impl MapPoint {
+    pub fn found_ratio(&self) -> f64 {
+        self.found as f64 / self.visible as f64
+    }
+
+    /// Octave at which the point is expected to be detected from `distance`
+    pub fn predict_octave(&self, distance: f64, scale: &ScalePyramid) -> u32 {
+        let ratio = self.max_distance / distance;
+        let octave = (ratio.ln() / scale.scale_factor.ln()).ceil();
+
+        octave.clamp(0.0, (scale.levels() - 1) as f64) as u32
+    }
+}
+
+/// Frame selected to be part of the map. Its `map_points` associate
+/// keypoints with the map points they observe. Keyframes are connected in
+/// the covisibility graph by the number of map points they share, and
+/// form a spanning tree over its strongest edges.
+#[derive(Clone)]
+pub struct KeyFrame {
+    pub id: KeyFrameId,
+    pub frame: Frame,
+    covisibility: BTreeMap<KeyFrameId, usize>,
+    /// Covisible keyframes by decreasing weight
+    ordered_covisibles: Vec<(KeyFrameId, usize)>,
+    parent: Option<KeyFrameId>,
+    children: BTreeSet<KeyFrameId>,
+    loop_edges: BTreeSet<KeyFrameId>
+}
+
+impl KeyFrame {
+    /// Number of map points shared with another keyframe, if connected
+    pub fn covisibility_weight(&self, other: KeyFrameId) -> Option<usize> {
+        self.covisibility.get(&other).copied()
+    }
+
+    /// Connected keyframes and their weights, strongest first
+    pub fn covisibles(&self) -> &[(KeyFrameId, usize)] {
+        &self.ordered_covisibles
+    }
+
+    pub fn best_covisibles(&self, count: usize) -> impl Iterator<Item = KeyFrameId> + '_ {
+        self.ordered_covisibles.iter().take(count).map(|&(id, _)| id)
+    }
+
+    pub fn covisibles_with_weight(&self, min_weight: usize) -> impl Iterator<Item = KeyFrameId> + '_ {
+        self.ordered_covisibles.iter()
+            .take_while(move |&&(_, weight)| weight >= min_weight)
+            .map(|&(id, _)| id)
+    }
+
+    /// Parent in the spanning tree, `None` for the root
+    pub fn parent(&self) -> Option<KeyFrameId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &BTreeSet<KeyFrameId> {
+        &self.children
+    }
+
+    /// Keyframes this one was matched to when closing a loop
+    pub fn loop_edges(&self) -> &BTreeSet<KeyFrameId> {
+        &self.loop_edges
+    }
+
+    fn set_connection(&mut self, other: KeyFrameId, weight: usize) {
+        self.covisibility.insert(other, weight);
+        self.sort_covisibles();
+    }
+
+    fn remove_connection(&mut self, other: KeyFrameId) {
+        if self.covisibility.remove(&other).is_some() {
+            self.sort_covisibles();
+        }
+    }
+
+    fn sort_covisibles(&mut self) {
+        self.ordered_covisibles = self.covisibility.iter().map(|(&id, &weight)| (id, weight)).collect();
+        self.ordered_covisibles.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    }
+}
+
+/// Keyframes and map points, addressed by id, together with the
+/// covisibility graph, its spanning tree and the loop edges that make up
+/// the essential graph. Keyframes with a BoW vector are indexed in a
+/// `KeyFrameDatabase`.
+#[derive(Default)]
+pub struct Map {
+    keyframes: BTreeMap<KeyFrameId, KeyFrame>,
+    points: BTreeMap<MapPointId, MapPoint>,
+    database: KeyFrameDatabase,
+    /// Root of the spanning tree, never erased
+    origin: Option<KeyFrameId>,
+    /// Gravity in world coordinates, once the inertial state is initialised
+    gravity: Option<Vec3>,
+    next_keyframe_id: KeyFrameId,
+    next_point_id: MapPointId
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn shared(self) -> SharedMap {
+        Arc::new(RwLock::new(self))
+    }
+
+    /// Frames must have a pose to become keyframes. The first keyframe is
+    /// the root of the spanning tree. Frames are indexed in the keyframe
+    /// database by the BoW vector they have when added.
+    pub fn add_keyframe(&mut self, frame: Frame) -> KeyFrameId {
+        assert!(frame.pose.is_some());
+
+        let id = self.next_keyframe_id;
+        self.next_keyframe_id += 1;
+
+        self.origin.get_or_insert(id);
+
+        if let Some(bow) = &frame.bow {
+            self.database.add(id, bow);
+        }
+
+        self.keyframes.insert(id, KeyFrame {
+            id,
+            frame,
+            covisibility: BTreeMap::new(),
+            ordered_covisibles: Vec::new(),
+            parent: None,
+            children: BTreeSet::new(),
+            loop_edges: BTreeSet::new()
+        });
+
+        id
+    }
+
+    /// Creates a point observed by keypoint `index` of `keyframe`, which
+    /// becomes its reference keyframe
+    pub fn add_point(&mut self, position: Vec3, keyframe: KeyFrameId, index: usize) -> MapPointId {
+        let descriptor = self.keyframes[&keyframe].frame.descriptors[index];
+
+        let id = self.next_point_id;
+        self.next_point_id += 1;
+
+        self.points.insert(id, MapPoint {
+            id,
+            position,
+            descriptor,
+            normal: Vec3::zeros(),
+            min_distance: 0.0,
+            max_distance: 0.0,
+            reference_keyframe: keyframe,
+            observations: BTreeMap::new(),
+            visible: 1,
+            found: 1
+        });
+
+        self.add_observation(keyframe, index, id);
+        self.update_normal_and_depth(id);
+        id
+    }
+
+    /// Records that keypoint `index` of a keyframe observes a map point.
+    /// The point's descriptor, normal and depth are not updated.
+    pub fn add_observation(&mut self, keyframe: KeyFrameId, index: usize, point: MapPointId) {
+        let keyframe = self.keyframes.get_mut(&keyframe).expect("unknown keyframe");
+        let map_point = self.points.get_mut(&point).expect("unknown map point");
+
+        keyframe.frame.map_points[index] = Some(point);
+        map_point.observations.insert(keyframe.id, index);
+    }
+
+    /// Removes the observation of a point by a keyframe. As in ORB-SLAM,
+    /// points left with two observations or fewer are erased. Returns
+    /// whether the point was erased.
+    pub fn remove_observation(&mut self, keyframe: KeyFrameId, point: MapPointId) -> bool {
+        let Some(map_point) = self.points.get_mut(&point) else {
+            return false;
+        };
+
+        if let Some(index) = map_point.observations.remove(&keyframe) {
+            if let Some(keyframe) = self.keyframes.get_mut(&keyframe) {
+                keyframe.frame.map_points[index] = None;
+            }
+        }
+
+        if map_point.observations.len() <= 2 {
+            self.erase_point(point);
+            return true;
+        }
+
+        if map_point.reference_keyframe == keyframe {
+            map_point.reference_keyframe = *map_point.observations.keys().next().unwrap();
+        }
+
+        false
+    }
+
+    /// Removes a point and its observations
+    pub fn erase_point(&mut self, point: MapPointId) {
+        let Some(map_point) = self.points.remove(&point) else {
+            return;
+        };
+
+        for (keyframe, index) in map_point.observations {
+            if let Some(keyframe) = self.keyframes.get_mut(&keyframe) {
+                keyframe.frame.map_points[index] = None;
+            }
+        }
+    }
+
+    /// Merges a duplicate point into another. Observations of `old` move to
+    /// `new`, except in keyframes that already observe `new`.
+    pub fn replace_point(&mut self, old: MapPointId, new: MapPointId) {
+        if old == new || !self.points.contains_key(&new) {
+            return;
+        }
+
+        let Some(old_point) = self.points.remove(&old) else {
+            return;
+        };
+
+        let new_point = self.points.get_mut(&new).unwrap();
+        new_point.visible += old_point.visible;
+        new_point.found += old_point.found;
+
+        for (keyframe, index) in old_point.observations {
+            let Some(keyframe) = self.keyframes.get_mut(&keyframe) else {
+                continue;
+            };
+
+            match new_point.observations.entry(keyframe.id) {
+                Entry::Occupied(_) => keyframe.frame.map_points[index] = None,
+                Entry::Vacant(entry) => {
+                    keyframe.frame.map_points[index] = Some(new);
+                    entry.insert(index);
+                }
+            }
+        }
+
+        self.compute_distinctive_descriptor(new);
+    }
+
+    /// Counts a tracked frame in which the points were predicted to be visible
+    pub fn increase_visible(&mut self, points: impl IntoIterator<Item = MapPointId>) {
+        for point in points {
+            if let Some(point) = self.points.get_mut(&point) {
+                point.visible += 1;
+            }
+        }
+    }
+
+    /// Counts a tracked frame in which the points were matched
+    pub fn increase_found(&mut self, points: impl IntoIterator<Item = MapPointId>) {
+        for point in points {
+            if let Some(point) = self.points.get_mut(&point) {
+                point.found += 1;
+            }
+        }
+    }
+
+    /// Removes a keyframe from the map and the graphs. Its children in the
+    /// spanning tree are reattached to the most covisible keyframe among
+    /// its parent and already reattached siblings, as in ORB-SLAM. Children
+    /// left without a parent get one on their next `update_connections`.
+    /// The root of the spanning tree cannot be erased. The next keyframe of
+    /// the inertial chain takes over the preintegration to the erased one.
+    /// Returns whether the keyframe was erased.
+    pub fn erase_keyframe(&mut self, id: KeyFrameId) -> bool {
+        if self.origin == Some(id) || !self.keyframes.contains_key(&id) {
+            return false;
+        }
+
+        let next_inertial = self.next_inertial(id);
+        let keyframe = self.keyframes.remove(&id).unwrap();
+
+        if let (Some(imu), Some(next)) = (&keyframe.frame.imu, next_inertial) {
+            let next = self.keyframes.get_mut(&next).unwrap().frame.imu.as_mut().unwrap();
+
+            next.preintegration = match (&imu.preintegration, &next.preintegration) {
+                (Some(earlier), Some(later)) => {
+                    let mut merged = earlier.clone();
+                    merged.append(later);
+                    Some(merged)
+                },
+                _ => None
+            };
+        }
+
+        if let Some(bow) = &keyframe.frame.bow {
+            self.database.erase(id, bow);
+        }
+
+        for &(other, _) in &keyframe.ordered_covisibles {
+            if let Some(other) = self.keyframes.get_mut(&other) {
+                other.remove_connection(id);
+            }
+        }
+
+        for &other in &keyframe.loop_edges {
+            if let Some(other) = self.keyframes.get_mut(&other) {
+                other.loop_edges.remove(&id);
+            }
+        }
+
+        for point in keyframe.frame.map_points.iter().flatten() {
+            if let Some(map_point) = self.points.get_mut(point) {
+                map_point.observations.remove(&id);
+
+                if map_point.observations.len() <= 2 {
+                    self.erase_point(*point);
+                } else if map_point.reference_keyframe == id {
+                    map_point.reference_keyframe = *map_point.observations.keys().next().unwrap();
+                }
+            }
+        }
+
+        // Keyframes that were never connected have no parent either
+        let parent = keyframe.parent;
+        let mut candidates: BTreeSet<KeyFrameId> = parent.into_iter().collect();
+        let mut children = keyframe.children;
+
+        while !children.is_empty() {
+            // Strongest covisibility between a child and a candidate parent
+            let best = children.iter()
+                .flat_map(|child| {
+                    self.keyframes[child].covisibles().iter()
+                        .filter(|(other, _)| candidates.contains(other))
+                        .map(move |&(other, weight)| (weight, *child, other))
+                })
+                .max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+            let Some((_, child, new_parent)) = best else {
+                break;
+            };
+
+            self.change_parent(child, new_parent);
+            candidates.insert(child);
+            children.remove(&child);
+        }
+
+        for child in children {
+            match parent {
+                Some(parent) => self.change_parent(child, parent),
+                None => self.keyframes.get_mut(&child).unwrap().parent = None
+            }
+        }
+
+        if let Some(parent) = parent.and_then(|parent| self.keyframes.get_mut(&parent)) {
+            parent.children.remove(&id);
+        }
+
+        true
+    }
+
+    fn change_parent(&mut self, child: KeyFrameId, parent: KeyFrameId) {
+        self.keyframes.get_mut(&child).unwrap().parent = Some(parent);
+        self.keyframes.get_mut(&parent).unwrap().children.insert(child);
+    }
+
+    /// Recomputes the covisibility edges of a keyframe from the map points
+    /// it observes. Keyframes sharing at least `COVISIBILITY_MIN_WEIGHT`
+    /// points are connected, or the single best one if there are none. The
+    /// first time a keyframe is connected, its strongest covisible
+    /// keyframe becomes its parent in the spanning tree.
+    pub fn update_connections(&mut self, id: KeyFrameId) {
+        let mut counts: BTreeMap<KeyFrameId, usize> = BTreeMap::new();
+
+        for point in self.keyframes[&id].frame.map_points.iter().flatten() {
+            if let Some(map_point) = self.points.get(point) {
+                for &other in map_point.observations.keys().filter(|&&other| other != id) {
+                    *counts.entry(other).or_default() += 1;
+                }
+            }
+        }
+
+        let Some((&best, &best_weight)) = counts.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0))) else {
+            return;
+        };
+
+        let mut connections: BTreeMap<KeyFrameId, usize> = counts.into_iter()
+            .filter(|&(_, weight)| weight >= COVISIBILITY_MIN_WEIGHT)
+            .collect();
+
+        if connections.is_empty() {
+            connections.insert(best, best_weight);
+        }
+
+        let previous: Vec<KeyFrameId> = self.keyframes[&id].covisibility.keys().copied().collect();
+
+        for other in previous.into_iter().filter(|other| !connections.contains_key(other)) {
+            self.keyframes.get_mut(&other).unwrap().remove_connection(id);
+        }
+
+        for (&other, &weight) in &connections {
+            self.keyframes.get_mut(&other).unwrap().set_connection(id, weight);
+        }
+
+        let keyframe = self.keyframes.get_mut(&id).unwrap();
+        keyframe.covisibility = connections;
+        keyframe.sort_covisibles();
+
+        if keyframe.parent.is_none() && self.origin != Some(id) {
+            let parent = keyframe.ordered_covisibles[0].0;
+            self.change_parent(id, parent);
+        }
+    }
+
+    pub fn add_loop_edge(&mut self, a: KeyFrameId, b: KeyFrameId) {
+        self.keyframes.get_mut(&a).expect("unknown keyframe").loop_edges.insert(b);
+        self.keyframes.get_mut(&b).expect("unknown keyframe").loop_edges.insert(a);
+    }
+
+    /// Edges of the essential graph: the spanning tree, loop edges and
+    /// covisibility edges with at least `min_weight` shared points, each
+    /// listed once with the smaller id first
+    pub fn essential_graph(&self, min_weight: usize) -> BTreeSet<(KeyFrameId, KeyFrameId)> {
+        let edge = |a: KeyFrameId, b: KeyFrameId| (a.min(b), a.max(b));
+        let mut edges = BTreeSet::new();
+
+        for keyframe in self.keyframes.values() {
+            edges.extend(keyframe.parent.map(|parent| edge(keyframe.id, parent)));
+            edges.extend(keyframe.loop_edges.iter().map(|&other| edge(keyframe.id, other)));
+            edges.extend(keyframe.covisibles_with_weight(min_weight).map(|other| edge(keyframe.id, other)));
+        }
+
+        edges
+    }
+
+    /// Picks the observation descriptor with the smallest median distance
+    /// to the other observations
+    pub fn compute_distinctive_descriptor(&mut self, point: MapPointId) {
+        let Some(map_point) = self.points.get(&point) else {
+            return;
+        };
+
+        let descriptors: Vec<CornerDescriptor> = map_point.observations.iter()
+            .filter_map(|(keyframe, &index)| self.keyframes.get(keyframe).map(|kf| kf.frame.descriptors[index]))
+            .collect();
+
+        let best = descriptors.iter()
+            .min_by_key(|a| {
+                let mut distances: Vec<u32> = descriptors.iter().map(|b| hamming_distance(a, b)).collect();
+                distances.sort_unstable();
+                distances[(distances.len() - 1) / 2]
+            });
+
+        if let Some(&best) = best {
+            self.points.get_mut(&point).unwrap().descriptor = best;
+        }
+    }
+
+    /// Updates the mean viewing direction of a point and the distances at
+    /// which it can be detected, from the octave it was observed at by its
+    /// reference keyframe
+    pub fn update_normal_and_depth(&mut self, point: MapPointId) {
+        let Some(map_point) = self.points.get(&point) else {
+            return;
+        };
+
+        let Some(reference) = self.keyframes.get(&map_point.reference_keyframe) else {
+            return;
+        };
+
+        let mut normal = Vec3::zeros();
+
+        for keyframe in map_point.observations.keys().filter_map(|id| self.keyframes.get(id)) {
+            let center = keyframe.frame.pose.unwrap().camera_center();
+            normal += (map_point.position - center).normalize();
+        }
+
+        let offset = map_point.position - reference.frame.pose.unwrap().camera_center();
+        let scale = &reference.frame.scale;
+        let octave = reference.frame.octave(map_point.observations[&reference.id]);
+
+        let max_distance = offset.norm() * scale.scale_factors[octave as usize];
+        let min_distance = max_distance / scale.scale_factors[scale.scale_factors.len() - 1];
+
+        let map_point = self.points.get_mut(&point).unwrap();
+        map_point.normal = normal.normalize();
+        map_point.max_distance = max_distance;
+        map_point.min_distance = min_distance;
+    }
+
+    /// Map points of a keyframe observed by at least `min_observations`
+    /// keyframes
+    pub fn tracked_points(&self, keyframe: KeyFrameId, min_observations: usize) -> usize {
+        let Some(keyframe) = self.keyframes.get(&keyframe) else {
+            return 0;
+        };
+
+        keyframe.frame.map_points.iter()
+            .filter_map(|id| id.and_then(|id| self.points.get(&id)))
+            .filter(|point| point.observations.len() >= min_observations)
+            .count()
+    }
+
+    pub fn origin(&self) -> Option<KeyFrameId> {
+        self.origin
+    }
+
+    /// Gravity in world coordinates, set by `initialize_inertial_map`
+    pub fn gravity(&self) -> Option<Vec3> {
+        self.gravity
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vec3) {
+        self.gravity = Some(gravity);
+    }
+
+    /// Keyframe with an inertial state before `id` in the inertial chain
+    pub fn previous_inertial(&self, id: KeyFrameId) -> Option<KeyFrameId> {
+        self.keyframes.range(..id).rev().find(|(_, kf)| kf.frame.imu.is_some()).map(|(&id, _)| id)
+    }
+
+    /// Keyframe with an inertial state after `id` in the inertial chain
+    pub fn next_inertial(&self, id: KeyFrameId) -> Option<KeyFrameId> {
+        self.keyframes.range(id + 1..).find(|(_, kf)| kf.frame.imu.is_some()).map(|(&id, _)| id)
+    }
+
+    pub fn database(&self) -> &KeyFrameDatabase {
+        &self.database
+    }
+
+    pub fn keyframe(&self, id: KeyFrameId) -> Option<&KeyFrame> {
+        self.keyframes.get(&id)
+    }
+
+    pub fn keyframe_mut(&mut self, id: KeyFrameId) -> Option<&mut KeyFrame> {
+        self.keyframes.get_mut(&id)
+    }
+
+    pub fn point(&self, id: MapPointId) -> Option<&MapPoint> {
+        self.points.get(&id)
+    }
+
+    pub fn point_mut(&mut self, id: MapPointId) -> Option<&mut MapPoint> {
+        self.points.get_mut(&id)
+    }
+
+    pub fn keyframes(&self) -> impl Iterator<Item = &KeyFrame> {
+        self.keyframes.values()
+    }
+
+    pub fn points(&self) -> impl Iterator<Item = &MapPoint> {
+        self.points.values()
+    }
+
+    pub fn keyframe_count(&self) -> usize {
+        self.keyframes.len()
+    }
+
+    pub fn point_count(&self) -> usize {
+        self.points.len()
+    }
+}
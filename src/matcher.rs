@@ -0,0 +1,271 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::orb::{CornerData, CornerDescriptor};
+use crate::vocabulary::FeatureVector;
+
+/// Side length in level-0 pixels of one cell of the keypoint grid.
+/// Must match `CELL_SIZE` in `keypoint_grid.wgsl` and `guided_match.wgsl`.
+pub const GRID_CELL_SIZE: u32 = 16;
+
+/// Maximum number of keypoints stored per grid cell on the GPU.
+/// Must match `CELL_CAPACITY` in `keypoint_grid.wgsl` and `guided_match.wgsl`.
+pub const GRID_CELL_CAPACITY: u32 = 64;
+
+/// Marks a window that found no candidate keypoint.
+pub const NO_MATCH: u32 = u32::MAX;
+
+/// A predicted keypoint position, in level-0 pixels, together with
+/// the radius and octave range in which candidates are considered.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SearchWindow {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub min_octave: u32,
+    pub max_octave: u32
+}
+
+/// Best and second best candidate found inside a `SearchWindow`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowMatch {
+    pub index: u32,
+    pub distance: u32,
+    pub second_distance: u32
+}
+
+unsafe impl Zeroable for SearchWindow {
+    fn zeroed() -> Self {
+        Self { x: 0.0, y: 0.0, radius: 0.0, min_octave: 0, max_octave: 0 }
+    }
+}
+
+unsafe impl Zeroable for WindowMatch {
+    fn zeroed() -> Self {
+        Self { index: 0, distance: 0, second_distance: 0 }
+    }
+}
+
+unsafe impl Pod for SearchWindow {}
+unsafe impl Pod for WindowMatch {}
+
+impl WindowMatch {
+    pub const NONE: WindowMatch = WindowMatch {
+        index: NO_MATCH,
+        distance: NO_MATCH,
+        second_distance: NO_MATCH
+    };
+}
+
+/// An accepted correspondence between a query descriptor and a keypoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub query: u32,
+    pub train: u32,
+    pub distance: u32
+}
+
+pub struct MatcherConfig {
+    /// Matches with a larger Hamming distance are rejected
+    pub max_distance: u32,
+    /// Best distance must be below `ratio` times the second best distance
+    pub ratio: f32
+}
+
+impl Default for MatcherConfig {
+    fn default() -> Self {
+        // Values used by ORB-SLAM for search by projection
+        Self { max_distance: 100, ratio: 0.9 }
+    }
+}
+
+pub fn hamming_distance(a: &CornerDescriptor, b: &CornerDescriptor) -> u32 {
+    a.bits.iter()
+        .zip(b.bits.iter())
+        .map(|(a, b)| (a ^ b).count_ones())
+        .sum()
+}
+
+/// CPU counterpart of the grid built by `keypoint_grid.wgsl`. Cells
+/// are not capped here, so it can also be used for exact radius queries.
+#[derive(Clone)]
+pub struct KeypointGrid {
+    width: u32,
+    height: u32,
+    cells: Vec<Vec<u32>>,
+    points: Vec<([f32; 2], u32)>
+}
+
+impl KeypointGrid {
+    /// Buckets points given as level-0 position and octave. Points outside
+    /// the image are assigned to the nearest border cell.
+    pub fn new(image_size: wgpu::Extent3d, points: Vec<([f32; 2], u32)>) -> Self {
+        let width = image_size.width.div_ceil(GRID_CELL_SIZE);
+        let height = image_size.height.div_ceil(GRID_CELL_SIZE);
+
+        let mut cells = vec![Vec::new(); (width * height) as usize];
+
+        for (i, ([x, y], _)) in points.iter().enumerate() {
+            let cx = (x.max(0.0) as u32 / GRID_CELL_SIZE).min(width - 1);
+            let cy = (y.max(0.0) as u32 / GRID_CELL_SIZE).min(height - 1);
+            cells[(cy * width + cx) as usize].push(i as u32);
+        }
+
+        Self { width, height, cells, points }
+    }
+
+    /// Grid over the level-0 positions of corners, as on the GPU
+    pub fn from_corners(image_size: wgpu::Extent3d, corners: &[CornerData]) -> Self {
+        Self::new(image_size, corners.iter().map(|c| (c.scaled_position(), c.octave)).collect())
+    }
+
+    /// Indices of points that lie within `window`
+    pub fn query<'a>(&'a self, window: &'a SearchWindow) -> impl Iterator<Item = u32> + 'a {
+        let min_x = ((window.x - window.radius).max(0.0) as u32 / GRID_CELL_SIZE).min(self.width - 1);
+        let max_x = ((window.x + window.radius).max(0.0) as u32 / GRID_CELL_SIZE).min(self.width - 1);
+        let min_y = ((window.y - window.radius).max(0.0) as u32 / GRID_CELL_SIZE).min(self.height - 1);
+        let max_y = ((window.y + window.radius).max(0.0) as u32 / GRID_CELL_SIZE).min(self.height - 1);
+
+        (min_y..=max_y)
+            .flat_map(move |cy| (min_x..=max_x).map(move |cx| cy * self.width + cx))
+            .flat_map(move |cell| self.cells[cell as usize].iter().copied())
+            .filter(move |&i| {
+                let ([x, y], octave) = self.points[i as usize];
+                let (dx, dy) = (x - window.x, y - window.y);
+
+                octave >= window.min_octave &&
+                octave <= window.max_octave &&
+                dx * dx + dy * dy <= window.radius * window.radius
+            })
+    }
+}
+
+/// Reference implementation of `OrbProgram::match_windows`. `grid` indexes
+/// into `descriptors`.
+pub fn match_windows(
+    grid: &KeypointGrid,
+    descriptors: &[CornerDescriptor],
+    windows: &[SearchWindow],
+    queries: &[CornerDescriptor],
+    dst: &mut [WindowMatch]
+) {
+    for ((window, query), result) in windows.iter().zip(queries).zip(dst.iter_mut()) {
+        *result = WindowMatch::NONE;
+
+        for index in grid.query(window) {
+            let distance = hamming_distance(query, &descriptors[index as usize]);
+
+            if distance < result.distance {
+                result.second_distance = result.distance;
+                result.distance = distance;
+                result.index = index;
+            } else if distance < result.second_distance {
+                result.second_distance = distance;
+            }
+        }
+    }
+}
+
+/// Applies the distance threshold and ratio test to the raw results of
+/// a windowed search. Query `i` corresponds to `window_matches[i]`.
+pub fn filter_window_matches(config: &MatcherConfig, window_matches: &[WindowMatch]) -> Vec<Match> {
+    window_matches.iter()
+        .enumerate()
+        .filter(|(_, m)| m.index != NO_MATCH && m.distance <= config.max_distance)
+        .filter(|(_, m)| {
+            m.second_distance == NO_MATCH ||
+            (m.distance as f32) < config.ratio * (m.second_distance as f32)
+        })
+        .map(|(i, m)| Match { query: i as u32, train: m.index, distance: m.distance })
+        .collect()
+}
+
+/// Number of bins in the orientation difference histogram
+pub const ROTATION_HISTOGRAM_BINS: usize = 30;
+
+/// Keeps only matches whose change in keypoint orientation falls into one
+/// of the three most populated bins of a histogram over all matches. A
+/// second or third bin is discarded if it holds less than a tenth of the
+/// votes of the first, as in ORB-SLAM.
+pub fn filter_by_rotation(matches: &[Match], query_corners: &[CornerData], train_corners: &[CornerData]) -> Vec<Match> {
+    let bin_of = |m: &Match| {
+        let rotation = query_corners[m.query as usize].angle_radians() - train_corners[m.train as usize].angle_radians();
+        let rotation = rotation.rem_euclid(std::f32::consts::TAU);
+        let bin = (rotation / std::f32::consts::TAU * ROTATION_HISTOGRAM_BINS as f32) as usize;
+        bin % ROTATION_HISTOGRAM_BINS
+    };
+
+    let mut histogram = [0usize; ROTATION_HISTOGRAM_BINS];
+
+    for m in matches {
+        histogram[bin_of(m)] += 1;
+    }
+
+    let mut ranked: Vec<usize> = (0..ROTATION_HISTOGRAM_BINS).collect();
+    ranked.sort_by_key(|&bin| std::cmp::Reverse(histogram[bin]));
+
+    let max_votes = histogram[ranked[0]];
+    let mut keep = [false; ROTATION_HISTOGRAM_BINS];
+
+    for (rank, &bin) in ranked.iter().take(3).enumerate() {
+        if histogram[bin] > 0 && (rank == 0 || histogram[bin] * 10 >= max_votes) {
+            keep[bin] = true;
+        }
+    }
+
+    matches.iter()
+        .filter(|m| keep[bin_of(m)])
+        .copied()
+        .collect()
+}
+
+/// Matches features that descend through the same vocabulary node, as in
+/// ORB-SLAM's search by BoW. Each train feature is matched at most once.
+pub fn match_by_nodes(
+    config: &MatcherConfig,
+    query_features: &FeatureVector,
+    query: &[CornerDescriptor],
+    train_features: &FeatureVector,
+    train: &[CornerDescriptor]
+) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut train_matched = vec![false; train.len()];
+
+    for (node, query_indices) in &query_features.0 {
+        let Some(train_indices) = train_features.0.get(node) else {
+            continue;
+        };
+
+        for &q in query_indices {
+            let mut best = WindowMatch::NONE;
+
+            for &t in train_indices {
+                if train_matched[t as usize] {
+                    continue;
+                }
+
+                let distance = hamming_distance(&query[q as usize], &train[t as usize]);
+
+                if distance < best.distance {
+                    best.second_distance = best.distance;
+                    best.distance = distance;
+                    best.index = t;
+                } else if distance < best.second_distance {
+                    best.second_distance = distance;
+                }
+            }
+
+            let accepted = best.index != NO_MATCH &&
+                best.distance <= config.max_distance &&
+                (best.second_distance == NO_MATCH || (best.distance as f32) < config.ratio * best.second_distance as f32);
+
+            if accepted {
+                train_matched[best.index as usize] = true;
+                matches.push(Match { query: q, train: best.index, distance: best.distance });
+            }
+        }
+    }
+
+    matches
+}
@@ -0,0 +1,548 @@
+use crate::camera::CameraModel;
+use crate::frame::{Frame, ScalePyramid};
+use crate::geometry::SE3;
+use crate::imu::{ImuBias, NavState, Preintegrated};
+use crate::linalg::{Mat3, Mat6, Matrix, Vec2, Vec3, Vec6, Vector};
+
+/// 95% quantile of the chi-squared distribution with one degree of
+/// freedom, for distances to epipolar lines
+pub const CHI2_EPIPOLAR: f64 = 3.841;
+
+/// 95% quantile of the chi-squared distribution with two degrees of freedom
+pub const CHI2_MONO: f64 = 5.991;
+
+/// 95% quantile of the chi-squared distribution with three degrees of
+/// freedom, for observations with a right image coordinate
+pub const CHI2_STEREO: f64 = 7.815;
+
+/// Huber robust kernel applied to a squared, whitened error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Huber {
+    pub delta: f64
+}
+
+impl Huber {
+    pub fn cost(&self, chi2: f64) -> f64 {
+        let delta2 = self.delta * self.delta;
+
+        if chi2 <= delta2 {
+            chi2
+        } else {
+            2.0 * self.delta * chi2.sqrt() - delta2
+        }
+    }
+
+    /// Weight of the error in iteratively reweighted least squares
+    pub fn weight(&self, chi2: f64) -> f64 {
+        if chi2 <= self.delta * self.delta {
+            1.0
+        } else {
+            self.delta / chi2.sqrt()
+        }
+    }
+}
+
+/// Candidate accepted by `damped_step`
+pub(crate) struct DampedStep<C> {
+    pub candidate: C,
+    pub cost: f64,
+    /// The step was negligible, so further iterations would not move
+    pub converged: bool
+}
+
+/// Levenberg-Marquardt step shared by the optimisers. `solve` returns the
+/// step for a damping, with its squared norm, or `None` if the damped
+/// system is singular. The damping is raised tenfold until `apply` gives a
+/// candidate of lower `cost` than `current_cost`, and lowered tenfold once
+/// one is found. Returns `None` if none was found after ten dampings.
+pub(crate) fn damped_step<S, C>(
+    lambda: &mut f64,
+    current_cost: f64,
+    mut solve: impl FnMut(f64) -> Option<(S, f64)>,
+    mut apply: impl FnMut(&S) -> C,
+    mut cost: impl FnMut(&C) -> f64
+) -> Option<DampedStep<C>> {
+    for _ in 0..10 {
+        let Some((step, norm_squared)) = solve(*lambda) else {
+            *lambda *= 10.0;
+            continue;
+        };
+
+        let candidate = apply(&step);
+        let candidate_cost = cost(&candidate);
+
+        if candidate_cost < current_cost {
+            *lambda = (*lambda / 10.0).max(1e-12);
+            return Some(DampedStep { candidate, cost: candidate_cost, converged: norm_squared <= 1e-20 });
+        }
+
+        *lambda *= 10.0;
+    }
+
+    None
+}
+
+/// World point observed at a pixel of the frame being optimised
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoseObservation {
+    pub point: Vec3,
+    pub pixel: Vec2,
+    /// Undistorted x coordinate in the right image of a rectified stereo
+    /// or RGB-D frame
+    pub right: Option<f64>,
+    pub octave: u32
+}
+
+/// Inertial constraint on the frame optimised by tracking: the previous
+/// frame, held fixed, and the measurements preintegrated since
+#[derive(Clone, Debug)]
+pub struct InertialPrior {
+    /// World to camera pose of the previous frame
+    pub pose: SE3,
+    /// Body velocity of the previous frame
+    pub velocity: Vec3,
+    pub bias: ImuBias,
+    pub gravity: Vec3,
+    pub preintegration: Preintegrated
+}
+
+impl InertialPrior {
+    /// Pose and velocity of the frame predicted by the measurements
+    pub fn predict(&self) -> (SE3, Vec3) {
+        let camera_from_body = &self.preintegration.calibration.camera_from_body;
+        let state = NavState::from_camera_pose(&self.pose, camera_from_body, self.velocity);
+        let predicted = self.preintegration.predict(&state, &self.bias, &self.gravity);
+
+        (predicted.camera_pose(camera_from_body), predicted.velocity)
+    }
+
+    fn chi2(&self, pose: &SE3, velocity: &Vec3) -> f64 {
+        self.preintegration.chi2(&self.pose, &self.velocity, pose, velocity, &self.bias, &self.gravity)
+    }
+}
+
+pub struct PoseOptimizerConfig {
+    /// Rounds of optimisation, each followed by outlier classification
+    pub rounds: usize,
+    /// Levenberg-Marquardt iterations per round
+    pub iterations: usize,
+    /// Observations with a larger whitened squared error are outliers
+    pub chi2_threshold: f64,
+    /// `chi2_threshold` for observations with a right image coordinate
+    pub stereo_chi2_threshold: f64,
+    /// Number of leading rounds that use the robust kernel
+    pub robust_rounds: usize,
+    pub kernel: Huber,
+    /// `kernel` for observations with a right image coordinate
+    pub stereo_kernel: Huber
+}
+
+impl Default for PoseOptimizerConfig {
+    fn default() -> Self {
+        // Values used by ORB-SLAM's motion-only bundle adjustment, which
+        // removes the kernel after classifying the third round
+        Self {
+            rounds: 4,
+            iterations: 10,
+            chi2_threshold: CHI2_MONO,
+            stereo_chi2_threshold: CHI2_STEREO,
+            robust_rounds: 3,
+            kernel: Huber { delta: CHI2_MONO.sqrt() },
+            stereo_kernel: Huber { delta: CHI2_STEREO.sqrt() }
+        }
+    }
+}
+
+impl PoseOptimizerConfig {
+    fn threshold_and_kernel(&self, observation: &PoseObservation) -> (f64, Huber) {
+        if observation.right.is_some() {
+            (self.stereo_chi2_threshold, self.stereo_kernel)
+        } else {
+            (self.chi2_threshold, self.kernel)
+        }
+    }
+}
+
+/// Reprojection error of one observation, followed by the error of its
+/// right image coordinate or zero. The right camera of a rectified rig is
+/// translated by `baseline` along x, and right coordinates are undistorted
+/// like those of `Frame::undistorted`.
+fn residual(camera: &dyn CameraModel, baseline: f64, point: &Vec3, observation: &PoseObservation) -> Vec3 {
+    let error = camera.project(point) - observation.pixel;
+
+    let right_error = observation.right.map_or(0.0, |right| {
+        camera.pinhole().project(&(*point - Vec3::new(baseline, 0.0, 0.0))).x() - right
+    });
+
+    Vec3::new(error.x(), error.y(), right_error)
+}
+
+/// Error of one observation and its derivative with respect to the left
+/// perturbation `[omega, v]` of the pose
+fn linearize(camera: &dyn CameraModel, baseline: f64, pose: &SE3, observation: &PoseObservation) -> Option<(Vec3, Matrix<3, 6>)> {
+    let point = pose.transform(&observation.point);
+
+    if point.z() <= 0.0 {
+        return None;
+    }
+
+    let mut motion = Matrix::<3, 6>::zeros();
+    motion.set_block(0, 0, &(-point.hat()));
+    motion.set_block(0, 3, &Mat3::identity());
+
+    let mut jacobian = Matrix::<3, 6>::zeros();
+    jacobian.set_block(0, 0, &(camera.project_jacobian(&point) * motion));
+
+    if observation.right.is_some() {
+        let right_point = point - Vec3::new(baseline, 0.0, 0.0);
+        jacobian.set_block(2, 0, &(camera.pinhole().project_jacobian(&right_point).row(0) * motion));
+    }
+
+    Some((residual(camera, baseline, &point, observation), jacobian))
+}
+
+/// Whitened squared error, infinite for points behind the camera
+fn chi2(camera: &dyn CameraModel, baseline: f64, pose: &SE3, observation: &PoseObservation, inv_sigma2: f64) -> f64 {
+    let point = pose.transform(&observation.point);
+
+    if point.z() <= 0.0 {
+        return f64::INFINITY;
+    }
+
+    residual(camera, baseline, &point, observation).norm_squared() * inv_sigma2
+}
+
+/// Levenberg-Marquardt over the observations not marked as outliers
+#[allow(clippy::too_many_arguments)]
+fn levenberg_marquardt(
+    config: &PoseOptimizerConfig,
+    camera: &dyn CameraModel,
+    baseline: f64,
+    observations: &[PoseObservation],
+    inv_sigma2: &[f64],
+    outliers: &[bool],
+    robust: bool,
+    mut pose: SE3,
+    iterations: usize
+) -> SE3 {
+    let kernel = |observation: &PoseObservation| robust.then(|| config.threshold_and_kernel(observation).1);
+
+    let cost = |pose: &SE3| -> f64 {
+        (0..observations.len())
+            .filter(|&i| !outliers[i])
+            .map(|i| {
+                let chi2 = chi2(camera, baseline, pose, &observations[i], inv_sigma2[i]).min(1e12);
+                kernel(&observations[i]).map_or(chi2, |k| k.cost(chi2))
+            })
+            .sum()
+    };
+
+    let mut current_cost = cost(&pose);
+    let mut lambda = 1e-5;
+
+    for _ in 0..iterations {
+        let mut hessian = Mat6::zeros();
+        let mut gradient = Vec6::zeros();
+
+        for i in (0..observations.len()).filter(|&i| !outliers[i]) {
+            let Some((residual, jacobian)) = linearize(camera, baseline, &pose, &observations[i]) else {
+                continue;
+            };
+
+            let chi2 = residual.norm_squared() * inv_sigma2[i];
+            let weight = inv_sigma2[i] * kernel(&observations[i]).map_or(1.0, |k| k.weight(chi2));
+
+            hessian += jacobian.transpose() * jacobian * weight;
+            gradient += jacobian.transpose() * residual * weight;
+        }
+
+        let solve = |lambda: f64| {
+            let mut damped = hessian;
+            for k in 0..6 {
+                damped[(k, k)] += lambda * hessian[(k, k)].max(1e-9);
+            }
+
+            damped.cholesky_solve(&(-gradient)).map(|step| (step, step.norm_squared()))
+        };
+
+        let Some(step) = damped_step(&mut lambda, current_cost, solve, |step| pose.retract(step), &cost) else {
+            break;
+        };
+
+        pose = step.candidate;
+        current_cost = step.cost;
+
+        if step.converged {
+            break;
+        }
+    }
+
+    pose
+}
+
+/// Levenberg-Marquardt over the pose and velocity of a frame, with the
+/// observations not marked as outliers and the inertial prior
+#[allow(clippy::too_many_arguments)]
+fn inertial_levenberg_marquardt(
+    config: &PoseOptimizerConfig,
+    camera: &dyn CameraModel,
+    baseline: f64,
+    observations: &[PoseObservation],
+    inv_sigma2: &[f64],
+    outliers: &[bool],
+    robust: bool,
+    prior: &InertialPrior,
+    mut pose: SE3,
+    mut velocity: Vec3
+) -> (SE3, Vec3) {
+    let kernel = |observation: &PoseObservation| robust.then(|| config.threshold_and_kernel(observation).1);
+
+    let cost = |pose: &SE3, velocity: &Vec3| -> f64 {
+        let visual: f64 = (0..observations.len())
+            .filter(|&i| !outliers[i])
+            .map(|i| {
+                let chi2 = chi2(camera, baseline, pose, &observations[i], inv_sigma2[i]).min(1e12);
+                kernel(&observations[i]).map_or(chi2, |k| k.cost(chi2))
+            })
+            .sum();
+
+        visual + prior.chi2(pose, velocity)
+    };
+
+    let information = prior.preintegration.information();
+    let mut current_cost = cost(&pose, &velocity);
+    let mut lambda = 1e-5;
+
+    for _ in 0..config.iterations {
+        let mut hessian = Matrix::<9, 9>::zeros();
+        let mut gradient = Vector::<9>::zeros();
+
+        for i in (0..observations.len()).filter(|&i| !outliers[i]) {
+            let Some((residual, pose_jacobian)) = linearize(camera, baseline, &pose, &observations[i]) else {
+                continue;
+            };
+
+            let chi2 = residual.norm_squared() * inv_sigma2[i];
+            let weight = inv_sigma2[i] * kernel(&observations[i]).map_or(1.0, |k| k.weight(chi2));
+
+            let mut jacobian = Matrix::<3, 9>::zeros();
+            jacobian.set_block(0, 0, &pose_jacobian);
+
+            hessian += jacobian.transpose() * jacobian * weight;
+            gradient += jacobian.transpose() * residual * weight;
+        }
+
+        let inertial = prior.preintegration.linearize(&prior.pose, &prior.velocity, &pose, &velocity, &prior.bias, &prior.gravity);
+        let mut jacobian = Matrix::<9, 9>::zeros();
+        jacobian.set_block(0, 0, &inertial.pose_j);
+        jacobian.set_block(0, 6, &inertial.velocity_j);
+
+        let jacobian_t = jacobian.transpose() * information;
+        hessian += jacobian_t * jacobian;
+        gradient += jacobian_t * inertial.residual;
+
+        let solve = |lambda: f64| {
+            let mut damped = hessian;
+            for k in 0..9 {
+                damped[(k, k)] += lambda * hessian[(k, k)].max(1e-9);
+            }
+
+            damped.cholesky_solve(&(-gradient)).map(|step| (step, step.norm_squared()))
+        };
+
+        let Some(step) = damped_step(
+            &mut lambda,
+            current_cost,
+            solve,
+            |step| (pose.retract(&step.segment::<6>(0)), velocity + step.segment::<3>(6)),
+            |(pose, velocity)| cost(pose, velocity)
+        ) else {
+            break;
+        };
+
+        (pose, velocity) = step.candidate;
+        current_cost = step.cost;
+
+        if step.converged {
+            break;
+        }
+    }
+
+    (pose, velocity)
+}
+
+/// Motion-only bundle adjustment as in ORB-SLAM: optimises the world to
+/// camera pose while keeping points fixed, reclassifying observations as
+/// inliers or outliers after each round. Observations are weighted by the
+/// inverse variance of their octave, and those with a right image
+/// coordinate also constrain depth through `baseline`. `outliers` is
+/// updated in place and may mark observations to exclude from the start.
+/// Returns the refined pose and the number of inliers.
+pub fn optimize_pose(
+    config: &PoseOptimizerConfig,
+    camera: &dyn CameraModel,
+    baseline: f64,
+    scale: &ScalePyramid,
+    observations: &[PoseObservation],
+    outliers: &mut [bool],
+    mut pose: SE3
+) -> (SE3, usize) {
+    assert_eq!(observations.len(), outliers.len());
+
+    let inv_sigma2: Vec<f64> = observations.iter()
+        .map(|o| scale.inv_level_sigma2[o.octave as usize])
+        .collect();
+
+    let mut inliers = outliers.iter().filter(|&&outlier| !outlier).count();
+
+    if inliers < 3 {
+        return (pose, inliers);
+    }
+
+    for round in 0..config.rounds {
+        let robust = round < config.robust_rounds;
+        pose = levenberg_marquardt(config, camera, baseline, observations, &inv_sigma2, outliers, robust, pose, config.iterations);
+
+        // Observations rejected in earlier rounds may be reinstated
+        for (i, observation) in observations.iter().enumerate() {
+            let (threshold, _) = config.threshold_and_kernel(observation);
+            outliers[i] = chi2(camera, baseline, &pose, observation, inv_sigma2[i]) > threshold;
+        }
+
+        inliers = outliers.iter().filter(|&&outlier| !outlier).count();
+
+        if inliers < 10 {
+            break;
+        }
+    }
+
+    (pose, inliers)
+}
+
+/// Observations of the keypoints matched to `points`, with their indices
+/// and outlier flags
+fn frame_observations(frame: &Frame, points: &[Option<Vec3>], outliers: &[bool]) -> (Vec<usize>, Vec<PoseObservation>, Vec<bool>) {
+    assert!(points.len() == frame.len() && outliers.len() == frame.len());
+
+    let indices: Vec<usize> = (0..frame.len()).filter(|&i| points[i].is_some()).collect();
+    let stereo = frame.stereo.as_ref();
+
+    let observations = indices.iter()
+        .map(|&i| PoseObservation {
+            point: points[i].unwrap(),
+            pixel: frame.keypoints[i],
+            right: stereo.and_then(|stereo| stereo.right[i]),
+            octave: frame.octave(i)
+        })
+        .collect();
+
+    let matched_outliers = indices.iter().map(|&i| outliers[i]).collect();
+    (indices, observations, matched_outliers)
+}
+
+/// Optimises `frame.pose` against the world points matched to its
+/// keypoints. `points[i]` is the point matched to keypoint `i`, and
+/// `outliers[i]` is set for matches that should be discarded. Keypoints
+/// with a depth are also constrained by their right image coordinate.
+/// Returns the number of inliers, or `None` if the frame has no pose yet.
+pub fn optimize_frame_pose(
+    config: &PoseOptimizerConfig,
+    frame: &mut Frame,
+    points: &[Option<Vec3>],
+    outliers: &mut [bool]
+) -> Option<usize> {
+    let (indices, observations, mut matched_outliers) = frame_observations(frame, points, outliers);
+
+    let (pose, inliers) = optimize_pose(
+        config,
+        frame.camera.as_ref(),
+        frame.stereo.as_ref().map_or(0.0, |stereo| stereo.baseline),
+        &frame.scale,
+        &observations,
+        &mut matched_outliers,
+        frame.pose?
+    );
+
+    for (&i, &outlier) in indices.iter().zip(&matched_outliers) {
+        outliers[i] = outlier;
+    }
+
+    frame.pose = Some(pose);
+    Some(inliers)
+}
+
+/// Motion-only bundle adjustment with an inertial constraint to the
+/// previous frame, as ORB-SLAM3 tracks once the IMU is initialised. The
+/// velocity is optimised with the pose, and the preintegrated motion keeps
+/// both constrained when few or no points are matched, such as under
+/// motion blur. Biases stay at those of the previous frame; they are
+/// refined by local bundle adjustment. Returns the refined pose and
+/// velocity and the number of inliers.
+#[allow(clippy::too_many_arguments)]
+pub fn optimize_inertial_pose(
+    config: &PoseOptimizerConfig,
+    camera: &dyn CameraModel,
+    baseline: f64,
+    scale: &ScalePyramid,
+    observations: &[PoseObservation],
+    outliers: &mut [bool],
+    prior: &InertialPrior,
+    mut pose: SE3,
+    mut velocity: Vec3
+) -> (SE3, Vec3, usize) {
+    assert_eq!(observations.len(), outliers.len());
+
+    let inv_sigma2: Vec<f64> = observations.iter()
+        .map(|o| scale.inv_level_sigma2[o.octave as usize])
+        .collect();
+
+    let mut inliers = 0;
+
+    for round in 0..config.rounds {
+        let robust = round < config.robust_rounds;
+        (pose, velocity) = inertial_levenberg_marquardt(
+            config, camera, baseline, observations, &inv_sigma2, outliers, robust, prior, pose, velocity
+        );
+
+        for (i, observation) in observations.iter().enumerate() {
+            let (threshold, _) = config.threshold_and_kernel(observation);
+            outliers[i] = chi2(camera, baseline, &pose, observation, inv_sigma2[i]) > threshold;
+        }
+
+        inliers = outliers.iter().filter(|&&outlier| !outlier).count();
+    }
+
+    (pose, velocity, inliers)
+}
+
+/// `optimize_frame_pose` with an inertial prior, see
+/// `optimize_inertial_pose`. Starts from the predicted velocity and
+/// returns the number of inliers and the optimised velocity.
+pub fn optimize_inertial_frame_pose(
+    config: &PoseOptimizerConfig,
+    frame: &mut Frame,
+    points: &[Option<Vec3>],
+    outliers: &mut [bool],
+    prior: &InertialPrior
+) -> Option<(usize, Vec3)> {
+    let (indices, observations, mut matched_outliers) = frame_observations(frame, points, outliers);
+
+    let (pose, velocity, inliers) = optimize_inertial_pose(
+        config,
+        frame.camera.as_ref(),
+        frame.stereo.as_ref().map_or(0.0, |stereo| stereo.baseline),
+        &frame.scale,
+        &observations,
+        &mut matched_outliers,
+        prior,
+        frame.pose?,
+        prior.predict().1
+    );
+
+    for (&i, &outlier) in indices.iter().zip(&matched_outliers) {
+        outliers[i] = outlier;
+    }
+
+    frame.pose = Some(pose);
+    Some((inliers, velocity))
+}
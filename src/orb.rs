@@ -7,24 +7,45 @@ use tiny_wgpu::{
     Storage, Compute, ComputeProgram, BindGroupItem, ComputeKernel, RenderKernel
 };
 
+use std::sync::Arc;
+
+use crate::camera::{CameraModel, Undistortion};
+use crate::frame::{Frame, ScalePyramid};
+use crate::matcher::{SearchWindow, WindowMatch, GRID_CELL_CAPACITY, GRID_CELL_SIZE};
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct CornerData {
-    x: u32,
-    y: u32,
-    angle: u32,
-    octave: u32
+    pub x: u32,
+    pub y: u32,
+    /// Orientation in milliradians, in the range [-pi, pi]
+    pub angle: i32,
+    pub octave: u32,
+    /// Index of the camera whose image the corner was detected in
+    pub camera: u32
 }
 
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct CornerDescriptor {
-    bits: [u8; 32]
+    pub bits: [u8; 32]
+}
+
+impl CornerData {
+    /// Position of the corner in level-0 pixels. Corners are detected on
+    /// the mip level of their octave, so each octave halves the resolution.
+    pub fn scaled_position(&self) -> [f32; 2] {
+        [(self.x << self.octave) as f32, (self.y << self.octave) as f32]
+    }
+
+    pub fn angle_radians(&self) -> f32 {
+        self.angle as f32 / 1000.0
+    }
 }
 
 unsafe impl Zeroable for CornerData {
     fn zeroed() -> Self {
-        Self { x: 0, y: 0, angle: 0, octave: 0 }
+        Self { x: 0, y: 0, angle: 0, octave: 0, camera: 0 }
     }
 }
 
@@ -38,10 +59,22 @@ unsafe impl Pod for CornerData {}
 unsafe impl Pod for CornerDescriptor {}
 
 pub struct OrbConfig {
+    /// Size of the image of each camera
     pub image_size: wgpu::Extent3d,
+    /// Number of cameras whose images are extracted together, each in its
+    /// own layer of the hierarchy textures. Corners and descriptors are
+    /// computed for all cameras at once, but the undistortion, grayscale
+    /// and hierarchy render passes still run once per camera.
+    pub cameras: u32,
+    /// Features per camera. The cameras share a buffer of `cameras` times
+    /// as many, so one may exceed it when others have fewer.
     pub max_features: u32,
     pub hierarchy_depth: u32,
-    pub initial_threshold: f32
+    pub initial_threshold: f32,
+    pub max_search_windows: u32,
+    /// Remaps the input image of each camera before feature extraction,
+    /// either none or one per camera
+    pub undistortion: Vec<Undistortion>
 }
 
 pub struct OrbProgram {
@@ -62,49 +95,152 @@ impl ComputeProgram for OrbProgram {
     }
 }
 
-// Define the labels we are using for the image hierarchy
-seq_macro::seq!(N in 0..10 {
-    const MAX_HIERARCHY_DEPTH: usize = 10;
+const MAX_HIERARCHY_DEPTH: usize = 10;
+const MAX_CAMERAS: usize = 8;
 
-    const IMAGE_HIERARCHY_BLIT_BIND_GROUPS: [&str; MAX_HIERARCHY_DEPTH] = [
+// Define the labels we are using for the image hierarchy, at
+// `camera * MAX_HIERARCHY_DEPTH + mip`, see `level`
+seq_macro::seq!(N in 0..80 {
+    #[allow(clippy::unnecessary_cast)]
+    const IMAGE_HIERARCHY_BLIT_BIND_GROUPS: [&str; MAX_CAMERAS * MAX_HIERARCHY_DEPTH] = [
         #(
             const_format::formatcp!("image_hierarchy_blit_bind_group_{}", N as u32),
         )*
     ];
 
-    const IMAGE_HIERARCHY_VIEWS: [&str; MAX_HIERARCHY_DEPTH] = [
+    #[allow(clippy::unnecessary_cast)]
+    const IMAGE_HIERARCHY_VIEWS: [&str; MAX_CAMERAS * MAX_HIERARCHY_DEPTH] = [
         #(
             const_format::formatcp!("image_hierarchy_view_{}", N as u32),
         )*
     ];
 
-    const IMAGE_HIERARCHY_BLUR_TMP_VIEWS: [&str; MAX_HIERARCHY_DEPTH] = [
+    #[allow(clippy::unnecessary_cast)]
+    const IMAGE_HIERARCHY_BLUR_TMP_VIEWS: [&str; MAX_CAMERAS * MAX_HIERARCHY_DEPTH] = [
         #(
             const_format::formatcp!("image_hierarchy_blur_tmp_view_{}", N as u32),
         )*
     ];
 
-    const IMAGE_HIERARCHY_BLUR_VIEWS: [&str; MAX_HIERARCHY_DEPTH] = [
+    #[allow(clippy::unnecessary_cast)]
+    const IMAGE_HIERARCHY_BLUR_VIEWS: [&str; MAX_CAMERAS * MAX_HIERARCHY_DEPTH] = [
         #(
             const_format::formatcp!("image_hierarchy_blur_view_{}", N as u32),
         )*
     ];
 
-    const IMAGE_HIERARCHY_BLUR_TMP_BIND_GROUPS: [&str; MAX_HIERARCHY_DEPTH] = [
+    #[allow(clippy::unnecessary_cast)]
+    const IMAGE_HIERARCHY_BLUR_TMP_BIND_GROUPS: [&str; MAX_CAMERAS * MAX_HIERARCHY_DEPTH] = [
         #(
             const_format::formatcp!("image_hierarchy_blur_tmp_bind_group_{}", N as u32),
         )*
     ];
 
-    const IMAGE_HIERARCHY_BLUR_BIND_GROUPS: [&str; MAX_HIERARCHY_DEPTH] = [
+    #[allow(clippy::unnecessary_cast)]
+    const IMAGE_HIERARCHY_BLUR_BIND_GROUPS: [&str; MAX_CAMERAS * MAX_HIERARCHY_DEPTH] = [
         #(
             const_format::formatcp!("image_hierarchy_blur_view_{}", N as u32),
         )*
     ];
 });
 
+// Labels of the layer of each camera in the input textures
+seq_macro::seq!(N in 0..8 {
+    #[allow(clippy::unnecessary_cast)]
+    const INPUT_IMAGE_VIEWS: [&str; MAX_CAMERAS] = [
+        #(
+            const_format::formatcp!("input_image_view_{}", N as u32),
+        )*
+    ];
+
+    #[allow(clippy::unnecessary_cast)]
+    const UNDISTORTED_IMAGE_VIEWS: [&str; MAX_CAMERAS] = [
+        #(
+            const_format::formatcp!("undistorted_image_view_{}", N as u32),
+        )*
+    ];
+
+    #[allow(clippy::unnecessary_cast)]
+    const UNDISTORTION_MAP_VIEWS: [&str; MAX_CAMERAS] = [
+        #(
+            const_format::formatcp!("undistortion_map_view_{}", N as u32),
+        )*
+    ];
+
+    #[allow(clippy::unnecessary_cast)]
+    const GRAYSCALE_BIND_GROUPS: [&str; MAX_CAMERAS] = [
+        #(
+            const_format::formatcp!("color_to_grayscale_bind_group_{}", N as u32),
+        )*
+    ];
+
+    #[allow(clippy::unnecessary_cast)]
+    const UNDISTORT_BIND_GROUPS: [&str; MAX_CAMERAS] = [
+        #(
+            const_format::formatcp!("undistort_bind_group_{}", N as u32),
+        )*
+    ];
+});
+
+/// Index of the hierarchy labels of a mip level of a camera
+fn level(camera: u32, mip: u32) -> usize {
+    camera as usize * MAX_HIERARCHY_DEPTH + mip as usize
+}
+
+/// View of a single mip level of the layer of a camera
+fn layer_view(camera: u32, mip: u32) -> wgpu::TextureViewDescriptor<'static> {
+    wgpu::TextureViewDescriptor {
+        label: None,
+        format: None,
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        aspect: wgpu::TextureAspect::All,
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        base_array_layer: camera,
+        array_layer_count: Some(1)
+    }
+}
+
+/// Groups features read back from all cameras by the camera they were
+/// detected in, keeping their order
+pub fn split_by_camera(
+    corners: &[CornerData],
+    descriptors: &[CornerDescriptor],
+    cameras: u32
+) -> Vec<(Vec<CornerData>, Vec<CornerDescriptor>)> {
+    assert_eq!(corners.len(), descriptors.len());
+
+    let mut split = vec![(Vec::new(), Vec::new()); cameras as usize];
+
+    for (corner, descriptor) in corners.iter().zip(descriptors) {
+        let (corners, descriptors) = &mut split[corner.camera as usize];
+        corners.push(*corner);
+        descriptors.push(*descriptor);
+    }
+
+    split
+}
+
 impl OrbProgram {
+    /// Shares the device and queue of `compute`, such as the one of the
+    /// program extracting the other image of a stereo pair
+    pub fn new(config: OrbConfig, compute: &Compute) -> Self {
+        let compute = Compute {
+            instance: compute.instance.clone(),
+            adapter: compute.adapter.clone(),
+            device: compute.device.clone(),
+            queue: compute.queue.clone()
+        };
+
+        let mut program = Self { config, compute, storage: Storage::default() };
+        program.init();
+        program
+    }
+
     pub fn init(&mut self) {
+        assert!(self.config.cameras >= 1 && self.config.cameras as usize <= MAX_CAMERAS);
+        assert!(self.config.hierarchy_depth as usize <= MAX_HIERARCHY_DEPTH);
+        assert!(self.config.undistortion.is_empty() || self.config.undistortion.len() == self.config.cameras as usize);
 
         self.add_module("color_to_grayscale", wgpu::include_wgsl!("shaders/grayscale.wgsl"));
         self.add_module("blit", wgpu::include_wgsl!("shaders/blit.wgsl"));
@@ -112,13 +248,19 @@ impl OrbProgram {
         self.add_module("gaussian_blur_y", wgpu::include_wgsl!("shaders/gaussian_blur_y.wgsl"));
         self.add_module("fast", wgpu::include_wgsl!("shaders/fast.wgsl"));
         self.add_module("brief", wgpu::include_wgsl!("shaders/brief.wgsl"));
+        self.add_module("keypoint_grid", wgpu::include_wgsl!("shaders/keypoint_grid.wgsl"));
+        self.add_module("guided_match", wgpu::include_wgsl!("shaders/guided_match.wgsl"));
 
         self.add_texture(
             "input_image", 
             TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::COPY_SRC, 
             wgpu::TextureFormat::Rgba8Unorm, 
-            self.config.image_size
+            self.layered_size()
         );
+
+        for camera in 0..self.config.cameras {
+            self.add_texture_view("input_image", INPUT_IMAGE_VIEWS[camera as usize], layer_view(camera, 0));
+        }
         
         self.add_sampler(
             "linear_sampler",
@@ -138,14 +280,27 @@ impl OrbProgram {
             }
         );
 
-        self.add_bind_group("color_to_grayscale", &[
-            BindGroupItem::Sampler { label: "linear_sampler" },
-            BindGroupItem::Texture { label: "input_image" }
-        ]);
+        // Feature extraction reads the remapped image when undistorting
+        let grayscale_sources = if !self.config.undistortion.is_empty() {
+            self.initialize_undistortion();
+            UNDISTORTED_IMAGE_VIEWS
+        } else {
+            INPUT_IMAGE_VIEWS
+        };
+
+        for camera in 0..self.config.cameras as usize {
+            self.add_bind_group(GRAYSCALE_BIND_GROUPS[camera], &[
+                BindGroupItem::Sampler { label: "linear_sampler" },
+                BindGroupItem::TextureView {
+                    label: grayscale_sources[camera],
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true }
+                }
+            ]);
+        }
 
         self.add_render_pipelines(
             "color_to_grayscale", 
-            &["color_to_grayscale"], 
+            &[GRAYSCALE_BIND_GROUPS[0]], 
             &[RenderKernel { label: "color_to_grayscale", vertex: "vs_main", fragment: "fs_main" }],
             &[],
             &[Some(wgpu::TextureFormat::R16Float.into())], 
@@ -159,8 +314,8 @@ impl OrbProgram {
         self.add_buffer(
             "corners",
             BufferUsages::VERTEX | BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-            // Each feature is 12 u32
-            (self.config.max_features * 12 * 4) as u64
+            // Each feature is 5 u32
+            (self.feature_capacity() * 5 * 4) as u64
         );
 
         self.add_buffer(
@@ -177,7 +332,7 @@ impl OrbProgram {
 
         self.set_threshold(self.config.initial_threshold);
 
-        self.add_bind_group("fast", &[
+        self.add_layered_bind_group("fast", &[
             BindGroupItem::TextureView { label: "image_hierarchy_all", sample_type: wgpu::TextureSampleType::Float { filterable: true } },
             BindGroupItem::StorageBuffer { label: "corners", min_binding_size: 12 * 4, read_only: false },
             BindGroupItem::StorageBuffer { label: "counter", min_binding_size: 4, read_only: false },
@@ -195,10 +350,10 @@ impl OrbProgram {
         self.add_buffer(
             "descriptors", 
             BufferUsages::STORAGE | BufferUsages::COPY_SRC, 
-            (self.config.max_features * 8 * 4) as u64
+            (self.feature_capacity() * 8 * 4) as u64
         );
 
-        self.add_bind_group("descriptors", &[
+        self.add_layered_bind_group("descriptors", &[
             BindGroupItem::StorageBuffer { label: "corners", min_binding_size: 12 * 4, read_only: true },
             BindGroupItem::StorageBuffer { label: "counter", min_binding_size: 4, read_only: true },
             BindGroupItem::StorageBuffer { label: "descriptors", min_binding_size: 8 * 4, read_only: false },
@@ -216,175 +371,323 @@ impl OrbProgram {
         self.add_staging_buffer("counter");
         self.add_staging_buffer("corners");
         self.add_staging_buffer("descriptors");
+
+        self.initialize_guided_matching();
     }
 
-    fn initialize_image_hierarchy(&mut self) {
-        
-        {
-            let texture = self.compute().device.create_texture(&wgpu::TextureDescriptor {
-                label: None,
-                mip_level_count: self.config.hierarchy_depth,
-                size: self.config.image_size,
-                format: wgpu::TextureFormat::R16Float,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_DST,
-                view_formats: &[]
-            });
+    /// Features the buffers hold across all cameras
+    pub fn feature_capacity(&self) -> u32 {
+        self.config.max_features * self.config.cameras
+    }
 
-            self.storage_mut().textures.insert("image_hierarchy", texture);
+    /// Size of the textures holding one layer per camera
+    fn layered_size(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            depth_or_array_layers: self.config.cameras,
+            ..self.config.image_size
         }
+    }
 
-        {
-            let texture = &self.storage().textures["image_hierarchy"];
-            let view = texture.create_view(&wgpu::TextureViewDescriptor {
-                label: None,
-                format: None,
-                dimension: None,
-                aspect: wgpu::TextureAspect::All,
-                base_mip_level: 0,
-                mip_level_count: Some(self.config.hierarchy_depth),
-                base_array_layer: 0,
-                array_layer_count: None
+    fn add_texture_view(&mut self, texture: &'static str, label: &'static str, descriptor: wgpu::TextureViewDescriptor) {
+        let view = self.storage().textures[texture].create_view(&descriptor);
+        self.storage_mut().texture_views.insert(label, view);
+    }
+
+    /// Like `add_bind_group` for compute shaders, but binds texture views as
+    /// 2D arrays so a single dispatch can cover the layers of every camera
+    fn add_layered_bind_group(&mut self, label: &'static str, items: &[BindGroupItem]) {
+        let mut bind_group_layout_entries = Vec::new();
+        let mut bind_group_entries = Vec::new();
+
+        for (i, bind_group_item) in items.iter().enumerate() {
+            let (ty, resource) = match bind_group_item {
+                BindGroupItem::StorageBuffer { label, min_binding_size, read_only } => (
+                    wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: *read_only },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(*min_binding_size)
+                    },
+                    self.storage().buffers[label].as_entire_binding()
+                ),
+                BindGroupItem::UniformBuffer { label, min_binding_size } => (
+                    wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(*min_binding_size)
+                    },
+                    self.storage().buffers[label].as_entire_binding()
+                ),
+                BindGroupItem::TextureView { label, sample_type } => (
+                    wgpu::BindingType::Texture {
+                        sample_type: *sample_type,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false
+                    },
+                    wgpu::BindingResource::TextureView(&self.storage().texture_views[label])
+                ),
+                _ => panic!("Unsupported layered bind group item")
+            };
+
+            bind_group_layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: i as u32,
+                visibility: ShaderStages::COMPUTE,
+                ty,
+                count: None
+            });
+
+            bind_group_entries.push(wgpu::BindGroupEntry {
+                binding: i as u32,
+                resource
             });
-            self.storage_mut().texture_views.insert("image_hierarchy_all", view);
         }
 
-        for mip in 0..self.config.hierarchy_depth {
-            let texture = &self.storage().textures["image_hierarchy"];
+        let bind_group_layout = self.compute().device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &bind_group_layout_entries
+        });
 
-            let view = texture.create_view(&wgpu::TextureViewDescriptor {
-                label: None,
-                format: None,
-                dimension: None,
-                aspect: wgpu::TextureAspect::All,
-                base_mip_level: mip,
-                mip_level_count: Some(1),
-                base_array_layer: 0,
-                array_layer_count: None
-            });
+        let bind_group = self.compute().device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &bind_group_layout,
+            entries: &bind_group_entries
+        });
 
-            self.storage_mut().texture_views.insert(IMAGE_HIERARCHY_VIEWS[mip as usize], view);
-        }
+        self.storage_mut().bind_groups.insert(label, bind_group);
+        self.storage_mut().bind_group_layouts.insert(label, bind_group_layout);
+    }
+
+    fn initialize_guided_matching(&mut self) {
+        // Each camera has its own grid
+        let [grid_width, grid_height] = self.grid_size();
+        let cell_count = (grid_width * grid_height * self.config.cameras) as u64;
+
+        self.add_buffer(
+            "grid_counts",
+            BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            cell_count * 4
+        );
+
+        self.add_buffer(
+            "grid_cells",
+            BufferUsages::STORAGE,
+            cell_count * GRID_CELL_CAPACITY as u64 * 4
+        );
+
+        self.add_bind_group("keypoint_grid", &[
+            BindGroupItem::StorageBuffer { label: "corners", min_binding_size: 12 * 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "counter", min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "grid_counts", min_binding_size: 4, read_only: false },
+            BindGroupItem::StorageBuffer { label: "grid_cells", min_binding_size: 4, read_only: false },
+        ]);
+
+        self.add_compute_pipelines(
+            "keypoint_grid",
+            &[ "keypoint_grid" ],
+            &[ComputeKernel { label: "build_grid", entry_point: "build_grid" }],
+            &[wgpu::PushConstantRange { range: 0..8, stages: ShaderStages::COMPUTE }],
+            None
+        );
+
+        // Each search window is 5 u32
+        self.add_buffer(
+            "windows",
+            BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            (self.config.max_search_windows * 5 * 4) as u64
+        );
+
+        self.add_buffer(
+            "window_descriptors",
+            BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            (self.config.max_search_windows * 8 * 4) as u64
+        );
+
+        // Each window match is 3 u32
+        self.add_buffer(
+            "window_matches",
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            (self.config.max_search_windows * 3 * 4) as u64
+        );
+
+        self.add_bind_group("guided_match", &[
+            BindGroupItem::StorageBuffer { label: "corners", min_binding_size: 12 * 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "descriptors", min_binding_size: 8 * 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "grid_counts", min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "grid_cells", min_binding_size: 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "windows", min_binding_size: 5 * 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "window_descriptors", min_binding_size: 8 * 4, read_only: true },
+            BindGroupItem::StorageBuffer { label: "window_matches", min_binding_size: 3 * 4, read_only: false },
+        ]);
+
+        self.add_compute_pipelines(
+            "guided_match",
+            &[ "guided_match" ],
+            &[ComputeKernel { label: "guided_match", entry_point: "guided_match" }],
+            &[wgpu::PushConstantRange { range: 0..16, stages: ShaderStages::COMPUTE }],
+            None
+        );
+
+        self.add_staging_buffer("window_matches");
+    }
+
+    /// Number of keypoint grid cells along each axis
+    pub fn grid_size(&self) -> [u32; 2] {
+        [
+            self.config.image_size.width.div_ceil(GRID_CELL_SIZE),
+            self.config.image_size.height.div_ceil(GRID_CELL_SIZE)
+        ]
+    }
+
+    fn initialize_undistortion(&mut self) {
+        self.add_module("undistort", wgpu::include_wgsl!("shaders/undistort.wgsl"));
+
+        self.add_texture(
+            "undistortion_map",
+            TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            wgpu::TextureFormat::Rg32Float,
+            self.layered_size()
+        );
+
+        self.add_texture(
+            "undistorted_image",
+            TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+            wgpu::TextureFormat::Rgba8Unorm,
+            self.layered_size()
+        );
+
+        // The maps only depend on the cameras, so they are built once
+        for camera in 0..self.config.cameras {
+            let size = self.config.image_size;
+            let map = self.config.undistortion[camera as usize].map(size.width, size.height);
+
+            self.compute().queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.storage().textures["undistortion_map"],
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: camera },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(&map),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: (8 * size.width).into(),
+                    rows_per_image: None,
+                },
+                size
+            );
 
-        for target_mip in 1..self.config.hierarchy_depth as usize {
-            self.add_bind_group(IMAGE_HIERARCHY_BLIT_BIND_GROUPS[target_mip], &[
+            self.add_texture_view("undistortion_map", UNDISTORTION_MAP_VIEWS[camera as usize], layer_view(camera, 0));
+            self.add_texture_view("undistorted_image", UNDISTORTED_IMAGE_VIEWS[camera as usize], layer_view(camera, 0));
+
+            self.add_bind_group(UNDISTORT_BIND_GROUPS[camera as usize], &[
                 BindGroupItem::Sampler { label: "linear_sampler" },
-                BindGroupItem::TextureView { 
-                    label: IMAGE_HIERARCHY_VIEWS[target_mip - 1],
+                BindGroupItem::TextureView {
+                    label: INPUT_IMAGE_VIEWS[camera as usize],
                     sample_type: wgpu::TextureSampleType::Float { filterable: true }
+                },
+                BindGroupItem::TextureView {
+                    label: UNDISTORTION_MAP_VIEWS[camera as usize],
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false }
                 }
             ]);
         }
 
         self.add_render_pipelines(
-            "blit",
-            &[ IMAGE_HIERARCHY_BLIT_BIND_GROUPS[1] ],
-            &[ RenderKernel { label: "image_hierarchy_mipmap", vertex: "vs_main", fragment: "fs_main" } ],
+            "undistort",
+            &[UNDISTORT_BIND_GROUPS[0]],
+            &[RenderKernel { label: "undistort", vertex: "vs_main", fragment: "fs_main" }],
             &[],
-            &[ Some(wgpu::TextureFormat::R16Float.into()) ],
+            &[Some(wgpu::TextureFormat::Rgba8Unorm.into())],
             &[],
             None,
             None
         );
+    }
 
-        {
-            let texture = self.compute().device.create_texture(&wgpu::TextureDescriptor {
-                label: None,
-                mip_level_count: self.config.hierarchy_depth,
-                size: self.config.image_size,
-                format: wgpu::TextureFormat::R16Float,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
-                view_formats: &[]
-            });
-
-            self.storage_mut().textures.insert("blur_tmp_hierarchy", texture);
-        }
-
-        {
+    fn initialize_image_hierarchy(&mut self) {
+        
+        for label in ["image_hierarchy", "blur_tmp_hierarchy", "blur_hierarchy"] {
             let texture = self.compute().device.create_texture(&wgpu::TextureDescriptor {
                 label: None,
                 mip_level_count: self.config.hierarchy_depth,
-                size: self.config.image_size,
+                size: self.layered_size(),
                 format: wgpu::TextureFormat::R16Float,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_DST,
                 view_formats: &[]
             });
 
-            self.storage_mut().textures.insert("blur_hierarchy", texture);
+            self.storage_mut().textures.insert(label, texture);
         }
 
-        {
-            let texture = &self.storage().textures["blur_hierarchy"];
-            let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        // The compute passes read every mip level of every camera at once
+        for (texture, label) in [("image_hierarchy", "image_hierarchy_all"), ("blur_hierarchy", "blur_hierarchy_all")] {
+            self.add_texture_view(texture, label, wgpu::TextureViewDescriptor {
                 label: None,
                 format: None,
-                dimension: None,
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
                 aspect: wgpu::TextureAspect::All,
                 base_mip_level: 0,
                 mip_level_count: Some(self.config.hierarchy_depth),
                 base_array_layer: 0,
-                array_layer_count: None
+                array_layer_count: Some(self.config.cameras)
             });
-            self.storage_mut().texture_views.insert("blur_hierarchy_all", view);
         }
 
-        for mip in 0..self.config.hierarchy_depth {
-            let texture = &self.storage().textures["blur_tmp_hierarchy"];
-
-            let view = texture.create_view(&wgpu::TextureViewDescriptor {
-                label: None,
-                format: None,
-                dimension: None,
-                aspect: wgpu::TextureAspect::All,
-                base_mip_level: mip,
-                mip_level_count: Some(1),
-                base_array_layer: 0,
-                array_layer_count: None
-            });
+        // Render passes draw into a single mip level of a single camera
+        for camera in 0..self.config.cameras {
+            for mip in 0..self.config.hierarchy_depth {
+                let level = level(camera, mip);
 
-            self.storage_mut().texture_views.insert(IMAGE_HIERARCHY_BLUR_TMP_VIEWS[mip as usize], view);
+                self.add_texture_view("image_hierarchy", IMAGE_HIERARCHY_VIEWS[level], layer_view(camera, mip));
+                self.add_texture_view("blur_tmp_hierarchy", IMAGE_HIERARCHY_BLUR_TMP_VIEWS[level], layer_view(camera, mip));
+                self.add_texture_view("blur_hierarchy", IMAGE_HIERARCHY_BLUR_VIEWS[level], layer_view(camera, mip));
+            }
         }
 
-        for mip in 0..self.config.hierarchy_depth {
-            let texture = &self.storage().textures["blur_hierarchy"];
-
-            let view = texture.create_view(&wgpu::TextureViewDescriptor {
-                label: None,
-                format: None,
-                dimension: None,
-                aspect: wgpu::TextureAspect::All,
-                base_mip_level: mip,
-                mip_level_count: Some(1),
-                base_array_layer: 0,
-                array_layer_count: None
-            });
+        for camera in 0..self.config.cameras {
+            for target_mip in 1..self.config.hierarchy_depth {
+                self.add_bind_group(IMAGE_HIERARCHY_BLIT_BIND_GROUPS[level(camera, target_mip)], &[
+                    BindGroupItem::Sampler { label: "linear_sampler" },
+                    BindGroupItem::TextureView { 
+                        label: IMAGE_HIERARCHY_VIEWS[level(camera, target_mip - 1)],
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true }
+                    }
+                ]);
+            }
 
-            self.storage_mut().texture_views.insert(IMAGE_HIERARCHY_BLUR_VIEWS[mip as usize], view);
-        }
+            for target_mip in 0..self.config.hierarchy_depth {
+                let level = level(camera, target_mip);
 
-        for target_mip in 0..self.config.hierarchy_depth as usize {
-            self.add_bind_group(IMAGE_HIERARCHY_BLUR_TMP_BIND_GROUPS[target_mip], &[
-                BindGroupItem::Sampler { label: "linear_sampler" },
-                BindGroupItem::TextureView { 
-                    label: IMAGE_HIERARCHY_VIEWS[target_mip],
-                    sample_type: wgpu::TextureSampleType::Float { filterable: true }
-                }
-            ]);
+                self.add_bind_group(IMAGE_HIERARCHY_BLUR_TMP_BIND_GROUPS[level], &[
+                    BindGroupItem::Sampler { label: "linear_sampler" },
+                    BindGroupItem::TextureView { 
+                        label: IMAGE_HIERARCHY_VIEWS[level],
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true }
+                    }
+                ]);
 
-            self.add_bind_group(IMAGE_HIERARCHY_BLUR_BIND_GROUPS[target_mip], &[
-                BindGroupItem::Sampler { label: "linear_sampler" },
-                BindGroupItem::TextureView { 
-                    label: IMAGE_HIERARCHY_BLUR_TMP_VIEWS[target_mip],
-                    sample_type: wgpu::TextureSampleType::Float { filterable: true }
-                }
-            ]);
+                self.add_bind_group(IMAGE_HIERARCHY_BLUR_BIND_GROUPS[level], &[
+                    BindGroupItem::Sampler { label: "linear_sampler" },
+                    BindGroupItem::TextureView { 
+                        label: IMAGE_HIERARCHY_BLUR_TMP_VIEWS[level],
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true }
+                    }
+                ]);
+            }
         }
 
+        self.add_render_pipelines(
+            "blit",
+            &[ IMAGE_HIERARCHY_BLIT_BIND_GROUPS[1] ],
+            &[ RenderKernel { label: "image_hierarchy_mipmap", vertex: "vs_main", fragment: "fs_main" } ],
+            &[],
+            &[ Some(wgpu::TextureFormat::R16Float.into()) ],
+            &[],
+            None,
+            None
+        );
+
         self.add_render_pipelines(
             "gaussian_blur_x",
             &[ IMAGE_HIERARCHY_BLUR_TMP_BIND_GROUPS[1] ],
@@ -408,61 +711,40 @@ impl OrbProgram {
         );
     }
 
-    fn generate_hierarchy(&self, encoder: &mut wgpu::CommandEncoder) {
-        // Render mip levels for un-blurred image
-        for target_mip in 1..(self.config.hierarchy_depth as usize) {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &self.storage().texture_views[IMAGE_HIERARCHY_VIEWS[target_mip]],
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: wgpu::StoreOp::Store
-                    }
-                })],
-                ..Default::default()
-            });
+    fn draw_fullscreen(&self, encoder: &mut wgpu::CommandEncoder, target: &'static str, pipeline: &'static str, bind_group: &'static str) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.storage().texture_views[target],
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store
+                }
+            })],
+            ..Default::default()
+        });
 
-            rpass.set_pipeline(&self.storage().render_pipelines["image_hierarchy_mipmap"]);
-            rpass.set_bind_group(0, &self.storage().bind_groups[IMAGE_HIERARCHY_BLIT_BIND_GROUPS[target_mip]], &[]);
-            rpass.draw(0..3, 0..1);
+        rpass.set_pipeline(&self.storage().render_pipelines[pipeline]);
+        rpass.set_bind_group(0, &self.storage().bind_groups[bind_group], &[]);
+        rpass.draw(0..3, 0..1);
+    }
+
+    fn generate_hierarchy(&self, encoder: &mut wgpu::CommandEncoder, camera: u32) {
+        // Render mip levels for un-blurred image
+        for target_mip in 1..self.config.hierarchy_depth {
+            let level = level(camera, target_mip);
+            self.draw_fullscreen(encoder, IMAGE_HIERARCHY_VIEWS[level], "image_hierarchy_mipmap", IMAGE_HIERARCHY_BLIT_BIND_GROUPS[level]);
         }
 
         // Render mip levels for blurred image
-        for target_mip in 0..(self.config.hierarchy_depth as usize) {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &self.storage().texture_views[IMAGE_HIERARCHY_BLUR_TMP_VIEWS[target_mip]],
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: wgpu::StoreOp::Store
-                    }
-                })],
-                ..Default::default()
-            });
-
-            rpass.set_pipeline(&self.storage().render_pipelines["gaussian_blur_x"]);
-            rpass.set_bind_group(0, &self.storage().bind_groups[IMAGE_HIERARCHY_BLUR_TMP_BIND_GROUPS[target_mip]], &[]);
-            rpass.draw(0..3, 0..1);
+        for target_mip in 0..self.config.hierarchy_depth {
+            let level = level(camera, target_mip);
+            self.draw_fullscreen(encoder, IMAGE_HIERARCHY_BLUR_TMP_VIEWS[level], "gaussian_blur_x", IMAGE_HIERARCHY_BLUR_TMP_BIND_GROUPS[level]);
         }
 
-        for target_mip in 0..(self.config.hierarchy_depth as usize) {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &self.storage().texture_views[IMAGE_HIERARCHY_BLUR_VIEWS[target_mip]],
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: wgpu::StoreOp::Store
-                    }
-                })],
-                ..Default::default()
-            });
-            
-            rpass.set_pipeline(&self.storage().render_pipelines["gaussian_blur_y"]);
-            rpass.set_bind_group(0, &self.storage().bind_groups[IMAGE_HIERARCHY_BLUR_BIND_GROUPS[target_mip]], &[]);
-            rpass.draw(0..3, 0..1);
+        for target_mip in 0..self.config.hierarchy_depth {
+            let level = level(camera, target_mip);
+            self.draw_fullscreen(encoder, IMAGE_HIERARCHY_BLUR_VIEWS[level], "gaussian_blur_y", IMAGE_HIERARCHY_BLUR_BIND_GROUPS[level]);
         }
     }
 
@@ -474,28 +756,18 @@ impl OrbProgram {
 
         encoder.clear_buffer(&self.storage().buffers["counter"], 0, None);
 
-        // Grayscale image
-        {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[
-                    Some(wgpu::RenderPassColorAttachment { 
-                        view: &self.storage().texture_views[&IMAGE_HIERARCHY_VIEWS[0]], 
-                        resolve_target: None, 
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                            store: wgpu::StoreOp::Store
-                        },
-                    })
-                ],
-                ..Default::default()
-            });
+        // A render pass draws into a single layer, so these run per camera
+        for camera in 0..self.config.cameras as usize {
+            // Undistort / rectify image
+            if !self.config.undistortion.is_empty() {
+                self.draw_fullscreen(&mut encoder, UNDISTORTED_IMAGE_VIEWS[camera], "undistort", UNDISTORT_BIND_GROUPS[camera]);
+            }
 
-            rpass.set_pipeline(&self.storage().render_pipelines["color_to_grayscale"]);
-            rpass.set_bind_group(0, &self.storage().bind_groups["color_to_grayscale"], &[]);
-            rpass.draw(0..3, 0..1);
-        }
+            // Grayscale image
+            self.draw_fullscreen(&mut encoder, IMAGE_HIERARCHY_VIEWS[level(camera as u32, 0)], "color_to_grayscale", GRAYSCALE_BIND_GROUPS[camera]);
 
-        self.generate_hierarchy(&mut encoder);
+            self.generate_hierarchy(&mut encoder, camera as u32);
+        }
 
         // Compute corners
         let mut width = self.config.image_size.width;
@@ -508,10 +780,11 @@ impl OrbProgram {
 
             for i in 0..(self.config.hierarchy_depth as usize) {
                 cpass.set_push_constants(0, bytemuck::cast_slice(&[ i as u32 ]));
+                // One layer per camera
                 cpass.dispatch_workgroups(
-                    (width + 7) / 8,
-                    (height + 7) / 8,
-                    1
+                    width.div_ceil(8),
+                    height.div_ceil(8),
+                    self.config.cameras
                 ); 
 
                 width /= 2;
@@ -528,7 +801,24 @@ impl OrbProgram {
 
             cpass.dispatch_workgroups(
                 1,
-                (self.config.max_features + 7) / 8, 
+                self.feature_capacity().div_ceil(8),
+                1
+            );
+        }
+
+        // Bucket corners into the keypoint grid used by guided matching
+        encoder.clear_buffer(&self.storage().buffers["grid_counts"], 0, None);
+
+        {
+            let mut cpass = encoder.begin_compute_pass(&Default::default());
+
+            cpass.set_pipeline(&self.storage().compute_pipelines["build_grid"]);
+            cpass.set_bind_group(0, &self.storage().bind_groups["keypoint_grid"], &[]);
+            cpass.set_push_constants(0, bytemuck::cast_slice(&self.grid_size()));
+
+            cpass.dispatch_workgroups(
+                self.feature_capacity().div_ceil(64),
+                1,
                 1
             );
         }
@@ -553,9 +843,60 @@ impl OrbProgram {
             *bytemuck::cast_slice(&dst).iter().next().unwrap()
         };
 
-        return corner_count;
+        corner_count
     }
     
+    /// Reads back the corners and descriptors of the last `extract_corners`
+    /// call, with the features of all cameras interleaved. Every staging
+    /// buffer must be read before the next extraction.
+    pub fn read_features(&self, corner_count: u32) -> (Vec<CornerData>, Vec<CornerDescriptor>) {
+        let count = corner_count.min(self.feature_capacity()) as usize;
+
+        // Staging buffers cannot be read with an empty range
+        let mut corners = vec![CornerData::zeroed(); count.max(1)];
+        let mut descriptors = vec![CornerDescriptor::zeroed(); count.max(1)];
+
+        self.read_corners(&mut corners);
+        self.read_descriptors(&mut descriptors);
+
+        corners.truncate(count);
+        descriptors.truncate(count);
+
+        (corners, descriptors)
+    }
+
+    /// Extracts features from the image last written with `write_input_image`.
+    /// With undistortion enabled, `camera` should be the target pinhole camera.
+    /// Only for single camera programs, others use `extract_frames`.
+    pub fn extract_frame(&self, id: u64, timestamp: f64, camera: Arc<dyn CameraModel>) -> Frame {
+        assert_eq!(self.config.cameras, 1, "extract_frames extracts the frames of multiple cameras");
+        self.extract_frames(id, timestamp, &[camera]).pop().unwrap()
+    }
+
+    /// Extracts one frame per camera from the images last written with
+    /// `write_camera_image`, all in a single submission. Frame ids are
+    /// consecutive from `first_id` in camera order.
+    pub fn extract_frames(&self, first_id: u64, timestamp: f64, cameras: &[Arc<dyn CameraModel>]) -> Vec<Frame> {
+        assert_eq!(cameras.len(), self.config.cameras as usize);
+
+        let corner_count = self.extract_corners();
+        let (corners, descriptors) = self.read_features(corner_count);
+
+        split_by_camera(&corners, &descriptors, self.config.cameras)
+            .into_iter()
+            .zip(cameras)
+            .enumerate()
+            .map(|(i, ((corners, descriptors), camera))| {
+                Frame::new(first_id + i as u64, timestamp, camera.clone(), self.config.image_size, self.scale_pyramid(), corners, descriptors)
+            })
+            .collect()
+    }
+
+    /// Each octave is a mip level, so the scale doubles per octave
+    pub fn scale_pyramid(&self) -> ScalePyramid {
+        ScalePyramid::new(self.config.hierarchy_depth, 2.0)
+    }
+
     pub fn read_corners(&self, dst: &mut [CornerData]) {      
         self.read_staging_buffer("corners", dst);
     }
@@ -564,15 +905,71 @@ impl OrbProgram {
         self.read_staging_buffer("descriptors", dst);
     }
 
+    /// Searches the keypoints of `camera` from the last `extract_corners` call
+    /// inside each window and writes the best and second best candidate for
+    /// each query descriptor into `dst`. Queries are `windows[i]` paired with
+    /// `queries[i]`. Match indices refer to the features of `read_features`.
+    pub fn match_windows(&self, camera: u32, windows: &[SearchWindow], queries: &[CornerDescriptor], dst: &mut [WindowMatch]) {
+        assert!(camera < self.config.cameras);
+        assert!(windows.len() <= self.config.max_search_windows as usize);
+        assert!(windows.len() == queries.len() && windows.len() == dst.len());
+
+        if windows.is_empty() {
+            return;
+        }
+
+        self.compute().queue.write_buffer(&self.storage().buffers["windows"], 0, bytemuck::cast_slice(windows));
+        self.compute().queue.write_buffer(&self.storage().buffers["window_descriptors"], 0, bytemuck::cast_slice(queries));
+
+        let mut encoder = self.compute().device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: None
+        });
+
+        {
+            let [grid_width, grid_height] = self.grid_size();
+            let window_count = windows.len() as u32;
+
+            let mut cpass = encoder.begin_compute_pass(&Default::default());
+
+            cpass.set_pipeline(&self.storage().compute_pipelines["guided_match"]);
+            cpass.set_bind_group(0, &self.storage().bind_groups["guided_match"], &[]);
+            cpass.set_push_constants(0, bytemuck::cast_slice(&[grid_width, grid_height, window_count, camera]));
+
+            cpass.dispatch_workgroups(
+                window_count.div_ceil(64),
+                1,
+                1
+            );
+        }
+
+        self.copy_buffer_to_staging(&mut encoder, "window_matches");
+
+        self.compute().queue.submit(Some(encoder.finish()));
+
+        self.prepare_staging_buffer("window_matches");
+
+        self.compute().device.poll(wgpu::MaintainBase::Wait);
+
+        self.read_staging_buffer("window_matches", dst);
+    }
+
+    /// Writes the image of the first camera
     pub fn write_input_image(&self, bytes: &[u8]) {
+        self.write_camera_image(0, bytes);
+    }
+
+    /// Writes the RGBA image of `camera` into its layer of the input texture
+    pub fn write_camera_image(&self, camera: u32, bytes: &[u8]) {
+        assert!(camera < self.config.cameras);
+
         self.compute().queue.write_texture(
             wgpu::ImageCopyTexture {
                 texture: &self.storage().textures["input_image"],
                 mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: camera },
                 aspect: wgpu::TextureAspect::All,
             },
-            &bytes,
+            bytes,
             wgpu::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: (4 * self.config.image_size.width).into(),
@@ -0,0 +1,471 @@
+use crate::camera::CameraModel;
+use crate::geometry::{SE3, SO3};
+use crate::linalg::{DMatrix, Mat3, Mat6, Matrix, Vec2, Vec3, Vec6};
+use crate::optimizer::CHI2_MONO;
+use crate::random::Rng;
+use crate::ransac::{ransac, Estimator, RansacConfig};
+
+/// Pairs of control points whose distances constrain the EPnP betas
+const CONTROL_PAIRS: [(usize, usize); 6] = [(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)];
+
+/// Rigid alignment `b = R a + t` of two point sets in least squares sense
+fn align(a: &[Vec3], b: &[Vec3]) -> SE3 {
+    let n = a.len() as f64;
+    let centroid_a = a.iter().fold(Vec3::zeros(), |acc, p| acc + *p) * (1.0 / n);
+    let centroid_b = b.iter().fold(Vec3::zeros(), |acc, p| acc + *p) * (1.0 / n);
+
+    let mut covariance = Mat3::zeros();
+    for (pa, pb) in a.iter().zip(b) {
+        covariance += (*pb - centroid_b) * (*pa - centroid_a).transpose();
+    }
+
+    let rotation = SO3::from_matrix(&covariance);
+
+    SE3::new(rotation, centroid_b - rotation * centroid_a)
+}
+
+/// EPnP (Lepetit, Moreno-Noguer and Fua, 2009). Estimates the world to
+/// camera pose from at least four world points and their normalised image
+/// coordinates. Coplanar points fall back to a homography decomposition.
+pub fn epnp(points: &[Vec3], normalized: &[Vec2]) -> Option<SE3> {
+    let n = points.len();
+    assert_eq!(n, normalized.len());
+
+    if n < 4 {
+        return None;
+    }
+
+    // Control points at the centroid and along the principal axes
+    let centroid = points.iter().fold(Vec3::zeros(), |acc, p| acc + *p) * (1.0 / n as f64);
+
+    let mut scatter = Mat3::zeros();
+    for p in points {
+        let d = *p - centroid;
+        scatter += d * d.transpose();
+    }
+
+    let (axes, variances, _) = scatter.svd();
+
+    // Collinear points do not determine the rotation about their line
+    if variances[1] <= 1e-12 * variances[0] {
+        return None;
+    }
+
+    // The control points would be coplanar
+    if variances[2] <= 1e-8 * variances[0] {
+        return planar_pose(points, normalized, &centroid, &axes, &variances);
+    }
+
+    let mut control = [centroid; 4];
+    for i in 0..3 {
+        control[i + 1] = centroid + axes.column(i) * (variances[i] / n as f64).sqrt();
+    }
+
+    // Barycentric coordinates of each point with respect to the control points
+    let basis = Mat3::from_columns(&(control[1] - control[0]), &(control[2] - control[0]), &(control[3] - control[0]));
+    let basis_inverse = basis.try_inverse()?;
+
+    let alphas: Vec<[f64; 4]> = points.iter()
+        .map(|p| {
+            let a = basis_inverse * (*p - control[0]);
+            [1.0 - a.x() - a.y() - a.z(), a.x(), a.y(), a.z()]
+        })
+        .collect();
+
+    // Projection constraints on the camera frame control points
+    let mut m = DMatrix::zeros(2 * n, 12);
+    for (i, (alpha, uv)) in alphas.iter().zip(normalized).enumerate() {
+        for (j, a) in alpha.iter().enumerate() {
+            m[(2 * i, 3 * j)] = *a;
+            m[(2 * i, 3 * j + 2)] = -a * uv.x();
+            m[(2 * i + 1, 3 * j + 1)] = *a;
+            m[(2 * i + 1, 3 * j + 2)] = -a * uv.y();
+        }
+    }
+
+    // Right singular vectors of the four smallest singular values
+    let svd = m.svd();
+    let kernel: Vec<Vec<f64>> = (0..4).map(|k| svd.v.column(11 - k)).collect();
+
+    let (l, rho) = distance_constraints(&kernel, &control);
+
+    let candidates = [
+        betas_approx_1(&l, &rho),
+        betas_approx_2(&l, &rho),
+        betas_approx_3(&l, &rho)
+    ];
+
+    candidates.into_iter()
+        .map(|betas| {
+            let betas = refine_betas(&l, &rho, betas);
+            let pose = pose_from_betas(&kernel, &betas, &alphas, points);
+            let error = pose.map_or(f64::INFINITY, |pose| reprojection_error(&pose, points, normalized));
+            (pose, error)
+        })
+        .filter(|(_, error)| error.is_finite())
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .and_then(|(pose, _)| pose)
+}
+
+/// Pose of points on a plane from the homography between plane and image
+/// coordinates (Zhang, 2000), for point sets EPnP cannot handle. The plane
+/// is spanned by the first two principal `axes` through `centroid`.
+fn planar_pose(points: &[Vec3], normalized: &[Vec2], centroid: &Vec3, axes: &Mat3, variances: &Vec3) -> Option<SE3> {
+    let n = points.len();
+    let (axis_u, axis_v) = (axes.column(0), axes.column(1));
+    let scale_u = (variances[0] / n as f64).sqrt();
+    let scale_v = (variances[1] / n as f64).sqrt();
+
+    // Direct linear transform from plane coordinates scaled to unit spread
+    let mut a = DMatrix::zeros(2 * n, 9);
+    for (i, (p, uv)) in points.iter().zip(normalized).enumerate() {
+        let d = *p - *centroid;
+        let plane = [d.dot(&axis_u) / scale_u, d.dot(&axis_v) / scale_v, 1.0];
+
+        for (j, value) in plane.iter().enumerate() {
+            a[(2 * i, j)] = *value;
+            a[(2 * i, 6 + j)] = -uv.x() * value;
+            a[(2 * i + 1, 3 + j)] = *value;
+            a[(2 * i + 1, 6 + j)] = -uv.y() * value;
+        }
+    }
+
+    let h = a.null_vector();
+    let column = |j: usize| Vec3::new(h[j], h[3 + j], h[6 + j]);
+
+    // H = s [R u_axis * scale_u, R v_axis * scale_v, R centroid + t]
+    let (r1, r2) = (column(0) * (1.0 / scale_u), column(1) * (1.0 / scale_v));
+    let mut s = 2.0 / (r1.norm() + r2.norm());
+
+    // The centroid is in front of the camera
+    if column(2).z() < 0.0 {
+        s = -s;
+    }
+
+    let (r1, r2) = (r1 * s, r2 * s);
+    let camera_axes = SO3::from_matrix(&Mat3::from_columns(&r1, &r2, &r1.cross(&r2)));
+    let world_axes = Mat3::from_columns(&axis_u, &axis_v, &axis_u.cross(&axis_v));
+    let rotation = SO3::from_matrix(&(camera_axes.matrix() * world_axes.transpose()));
+
+    let pose = SE3::new(rotation, column(2) * s - rotation * *centroid);
+    (pose.rotation.matrix().is_finite() && pose.translation.is_finite()).then_some(pose)
+}
+
+/// The six constraints that the camera frame control points keep their
+/// mutual distances, as `L * B = rho` over the ten products of betas
+/// `[b11, b12, b22, b13, b23, b33, b14, b24, b34, b44]`
+fn distance_constraints(kernel: &[Vec<f64>], control: &[Vec3; 4]) -> (DMatrix, Vec<f64>) {
+    let mut l = DMatrix::zeros(6, 10);
+    let mut rho = Vec::with_capacity(6);
+
+    for (row, &(a, b)) in CONTROL_PAIRS.iter().enumerate() {
+        let dv: Vec<Vec3> = kernel.iter()
+            .map(|v| Vec3::new(v[3 * a] - v[3 * b], v[3 * a + 1] - v[3 * b + 1], v[3 * a + 2] - v[3 * b + 2]))
+            .collect();
+
+        let values = [
+            dv[0].dot(&dv[0]),
+            2.0 * dv[0].dot(&dv[1]),
+            dv[1].dot(&dv[1]),
+            2.0 * dv[0].dot(&dv[2]),
+            2.0 * dv[1].dot(&dv[2]),
+            dv[2].dot(&dv[2]),
+            2.0 * dv[0].dot(&dv[3]),
+            2.0 * dv[1].dot(&dv[3]),
+            2.0 * dv[2].dot(&dv[3]),
+            dv[3].dot(&dv[3])
+        ];
+
+        for (column, value) in values.iter().enumerate() {
+            l[(row, column)] = *value;
+        }
+
+        rho.push((control[a] - control[b]).norm_squared());
+    }
+
+    (l, rho)
+}
+
+fn solve_columns(l: &DMatrix, rho: &[f64], columns: &[usize]) -> Vec<f64> {
+    let mut a = DMatrix::zeros(6, columns.len());
+    for row in 0..6 {
+        for (k, &column) in columns.iter().enumerate() {
+            a[(row, k)] = l[(row, column)];
+        }
+    }
+    a.solve_least_squares(rho)
+}
+
+/// Solution with one kernel vector, ignoring the other betas
+fn betas_approx_1(l: &DMatrix, rho: &[f64]) -> [f64; 4] {
+    let b = solve_columns(l, rho, &[0, 1, 3, 6]);
+
+    if b[0] < 0.0 {
+        let s = (-b[0]).sqrt();
+        [s, -b[1] / s, -b[2] / s, -b[3] / s]
+    } else {
+        let s = b[0].sqrt();
+        if s == 0.0 {
+            return [0.0; 4];
+        }
+        [s, b[1] / s, b[2] / s, b[3] / s]
+    }
+}
+
+/// Solution with two kernel vectors
+fn betas_approx_2(l: &DMatrix, rho: &[f64]) -> [f64; 4] {
+    let b = solve_columns(l, rho, &[0, 1, 2]);
+    let (mut b1, b2) = betas_from_squares(b[0], b[2]);
+
+    if b[1] < 0.0 {
+        b1 = -b1;
+    }
+
+    [b1, b2, 0.0, 0.0]
+}
+
+/// Solution with three kernel vectors
+fn betas_approx_3(l: &DMatrix, rho: &[f64]) -> [f64; 4] {
+    let b = solve_columns(l, rho, &[0, 1, 2, 3, 4]);
+    let (mut b1, b2) = betas_from_squares(b[0], b[2]);
+
+    if b[1] < 0.0 {
+        b1 = -b1;
+    }
+
+    let b3 = if b1 != 0.0 { b[3] / b1 } else { 0.0 };
+    [b1, b2, b3, 0.0]
+}
+
+fn betas_from_squares(b11: f64, b22: f64) -> (f64, f64) {
+    if b11 < 0.0 {
+        ((-b11).sqrt(), if b22 < 0.0 { (-b22).sqrt() } else { 0.0 })
+    } else {
+        (b11.sqrt(), if b22 > 0.0 { b22.sqrt() } else { 0.0 })
+    }
+}
+
+/// Gauss-Newton on the distance constraints
+fn refine_betas(l: &DMatrix, rho: &[f64], mut betas: [f64; 4]) -> [f64; 4] {
+    for _ in 0..5 {
+        let b = betas;
+        let mut a = DMatrix::zeros(6, 4);
+        let mut residual = vec![0.0; 6];
+
+        for row in 0..6 {
+            let l = |k: usize| l[(row, k)];
+
+            a[(row, 0)] = 2.0 * l(0) * b[0] + l(1) * b[1] + l(3) * b[2] + l(6) * b[3];
+            a[(row, 1)] = l(1) * b[0] + 2.0 * l(2) * b[1] + l(4) * b[2] + l(7) * b[3];
+            a[(row, 2)] = l(3) * b[0] + l(4) * b[1] + 2.0 * l(5) * b[2] + l(8) * b[3];
+            a[(row, 3)] = l(6) * b[0] + l(7) * b[1] + l(8) * b[2] + 2.0 * l(9) * b[3];
+
+            residual[row] = rho[row] - (
+                l(0) * b[0] * b[0] + l(1) * b[0] * b[1] + l(2) * b[1] * b[1] +
+                l(3) * b[0] * b[2] + l(4) * b[1] * b[2] + l(5) * b[2] * b[2] +
+                l(6) * b[0] * b[3] + l(7) * b[1] * b[3] + l(8) * b[2] * b[3] + l(9) * b[3] * b[3]
+            );
+        }
+
+        let step = a.solve_least_squares(&residual);
+        for (beta, step) in betas.iter_mut().zip(&step) {
+            *beta += step;
+        }
+    }
+
+    betas
+}
+
+fn pose_from_betas(kernel: &[Vec<f64>], betas: &[f64; 4], alphas: &[[f64; 4]], points: &[Vec3]) -> Option<SE3> {
+    let control: [Vec3; 4] = std::array::from_fn(|j| {
+        kernel.iter()
+            .zip(betas)
+            .fold(Vec3::zeros(), |acc, (v, beta)| acc + Vec3::new(v[3 * j], v[3 * j + 1], v[3 * j + 2]) * *beta)
+    });
+
+    let mut camera_points: Vec<Vec3> = alphas.iter()
+        .map(|alpha| (0..4).fold(Vec3::zeros(), |acc, j| acc + control[j] * alpha[j]))
+        .collect();
+
+    // The kernel is only defined up to sign
+    if camera_points[0].z() < 0.0 {
+        for p in camera_points.iter_mut() {
+            *p = -*p;
+        }
+    }
+
+    let pose = align(points, &camera_points);
+    pose.rotation.matrix().is_finite().then_some(pose)
+}
+
+fn reprojection_error(pose: &SE3, points: &[Vec3], normalized: &[Vec2]) -> f64 {
+    points.iter()
+        .zip(normalized)
+        .map(|(p, uv)| (pose.transform(p).hnormalize() - *uv).norm())
+        .sum::<f64>() / points.len() as f64
+}
+
+/// 2D-3D correspondences for `ransac`. Residuals are squared reprojection
+/// errors in pixels divided by the variance of each observation.
+pub struct PnpEstimator<'a> {
+    pub camera: &'a dyn CameraModel,
+    /// World points
+    pub points: &'a [Vec3],
+    /// Observed pixels
+    pub pixels: &'a [Vec2],
+    /// Variance of each observation in pixels squared, e.g. by octave
+    pub sigma2: &'a [f64],
+    normalized: Vec<Vec2>
+}
+
+impl<'a> PnpEstimator<'a> {
+    pub fn new(camera: &'a dyn CameraModel, points: &'a [Vec3], pixels: &'a [Vec2], sigma2: &'a [f64]) -> Self {
+        assert!(points.len() == pixels.len() && points.len() == sigma2.len());
+
+        let normalized = pixels.iter()
+            .map(|pixel| camera.unproject(pixel).hnormalize())
+            .collect();
+
+        Self { camera, points, pixels, sigma2, normalized }
+    }
+}
+
+impl Estimator for PnpEstimator<'_> {
+    type Model = SE3;
+
+    const SAMPLE_SIZE: usize = 4;
+
+    fn data_count(&self) -> usize {
+        self.points.len()
+    }
+
+    fn fit(&self, sample: &[usize]) -> Vec<SE3> {
+        let points: Vec<Vec3> = sample.iter().map(|&i| self.points[i]).collect();
+        let normalized: Vec<Vec2> = sample.iter().map(|&i| self.normalized[i]).collect();
+        epnp(&points, &normalized).into_iter().collect()
+    }
+
+    fn residual(&self, pose: &SE3, index: usize) -> f64 {
+        let point = pose.transform(&self.points[index]);
+
+        if point.z() <= 0.0 {
+            return f64::INFINITY;
+        }
+
+        (self.camera.project(&point) - self.pixels[index]).norm_squared() / self.sigma2[index]
+    }
+}
+
+pub struct PnpConfig {
+    /// Residuals are chi-squared distributed with two degrees of freedom,
+    /// so the threshold defaults to their 95% quantile
+    pub ransac: RansacConfig,
+    /// Gauss-Newton iterations of the final refinement
+    pub refine_iterations: usize
+}
+
+impl Default for PnpConfig {
+    fn default() -> Self {
+        Self {
+            ransac: RansacConfig {
+                threshold: CHI2_MONO,
+                max_iterations: 300,
+                ..Default::default()
+            },
+            refine_iterations: 10
+        }
+    }
+}
+
+pub struct PnpResult {
+    /// World to camera transformation
+    pub pose: SE3,
+    pub inliers: Vec<bool>,
+    pub inlier_count: usize,
+    /// Covariance of the left perturbation `[omega, v]` of the pose
+    pub covariance: Mat6
+}
+
+/// Minimises the reprojection error of the masked correspondences over the
+/// pose with Gauss-Newton. Returns the refined pose and the information
+/// matrix of the perturbation `[omega, v]`.
+pub fn refine_pose(estimator: &PnpEstimator, mask: &[bool], mut pose: SE3, iterations: usize) -> (SE3, Mat6) {
+    let mut information = Mat6::zeros();
+
+    for iteration in 0..=iterations {
+        let mut hessian = Mat6::zeros();
+        let mut gradient = Vec6::zeros();
+
+        for i in (0..estimator.points.len()).filter(|&i| mask[i]) {
+            let point = pose.transform(&estimator.points[i]);
+
+            if point.z() <= 0.0 {
+                continue;
+            }
+
+            let residual = estimator.camera.project(&point) - estimator.pixels[i];
+            let projection = estimator.camera.project_jacobian(&point);
+
+            // Derivative of the camera frame point with respect to [omega, v]
+            let mut motion = Matrix::<3, 6>::zeros();
+            motion.set_block(0, 0, &(-point.hat()));
+            motion.set_block(0, 3, &Mat3::identity());
+
+            let jacobian = projection * motion;
+            let weight = 1.0 / estimator.sigma2[i];
+
+            hessian += jacobian.transpose() * jacobian * weight;
+            gradient += jacobian.transpose() * residual * weight;
+        }
+
+        information = hessian;
+
+        if iteration == iterations {
+            break;
+        }
+
+        let Some(step) = hessian.cholesky_solve(&(-gradient)) else {
+            break;
+        };
+
+        pose = pose.retract(&step);
+
+        if step.norm_squared() < 1e-20 {
+            break;
+        }
+    }
+
+    (pose, information)
+}
+
+/// Absolute pose from 2D-3D correspondences: EPnP inside RANSAC, followed
+/// by Gauss-Newton refinement on the inliers. Results only depend on the
+/// state of `rng`.
+pub fn solve_pnp(
+    camera: &dyn CameraModel,
+    points: &[Vec3],
+    pixels: &[Vec2],
+    sigma2: &[f64],
+    config: &PnpConfig,
+    rng: &mut Rng
+) -> Option<PnpResult> {
+    let estimator = PnpEstimator::new(camera, points, pixels, sigma2);
+    let result = ransac(&estimator, &config.ransac, rng)?;
+
+    // Refine, then re-classify with the refined pose and refine again
+    let (pose, _) = refine_pose(&estimator, &result.inliers, result.model, config.refine_iterations);
+
+    let inliers: Vec<bool> = (0..points.len())
+        .map(|i| estimator.residual(&pose, i) < config.ransac.threshold)
+        .collect();
+    let inlier_count = inliers.iter().filter(|&&inlier| inlier).count();
+
+    if inlier_count < PnpEstimator::SAMPLE_SIZE {
+        return None;
+    }
+
+    let (pose, information) = refine_pose(&estimator, &inliers, pose, config.refine_iterations);
+    let covariance = information.try_inverse()?;
+
+    Some(PnpResult { pose, inliers, inlier_count, covariance })
+}
@@ -0,0 +1,411 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use crate::geometry::{Sim3, SE3, SO3};
+use crate::linalg::{EnvelopeMatrix, Mat3, Mat6, Mat7, Matrix, Vec3, Vec7};
+use crate::optimizer::{damped_step, Huber};
+
+/// Relative constraint between two vertices of a `PoseGraph`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoseGraphEdge {
+    pub a: usize,
+    pub b: usize,
+    /// Measured `S_a * S_b^-1`
+    pub measurement: Sim3,
+    /// Inverse covariance of the error `log(S_ab * S_b * S_a^-1)`
+    pub information: Mat7,
+    /// Applied to the squared, whitened error when set
+    pub kernel: Option<Huber>
+}
+
+/// Graph of world to camera similarities constrained by relative
+/// measurements, as used by ORB-SLAM to distribute a loop correction over
+/// the essential graph. Fixed vertices are not optimised, and vertices with
+/// a fixed scale are optimised as rigid body transformations.
+#[derive(Clone, Debug, Default)]
+pub struct PoseGraph {
+    pub vertices: Vec<Sim3>,
+    pub fixed: Vec<bool>,
+    pub fixed_scale: Vec<bool>,
+    pub edges: Vec<PoseGraphEdge>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoseGraphSummary {
+    pub initial_cost: f64,
+    pub final_cost: f64,
+    /// Iterations whose step was accepted
+    pub iterations: usize
+}
+
+impl PoseGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_vertex(&mut self, pose: Sim3, fixed: bool) -> usize {
+        self.vertices.push(pose);
+        self.fixed.push(fixed);
+        self.fixed_scale.push(false);
+        self.vertices.len() - 1
+    }
+
+    /// Vertex whose scale stays one
+    pub fn add_se3_vertex(&mut self, pose: SE3, fixed: bool) -> usize {
+        let index = self.add_vertex(Sim3::from_se3(&pose), fixed);
+        self.fixed_scale[index] = true;
+        index
+    }
+
+    pub fn add_edge(&mut self, a: usize, b: usize, measurement: Sim3, information: Mat7) {
+        self.edges.push(PoseGraphEdge { a, b, measurement, information, kernel: None });
+    }
+
+    /// Edge measuring a rigid body transformation, which leaves the
+    /// relative scale of its vertices unconstrained
+    pub fn add_se3_edge(&mut self, a: usize, b: usize, measurement: SE3, information: Mat6) {
+        let mut information_7 = Mat7::zeros();
+        information_7.set_block(0, 0, &information);
+        self.add_edge(a, b, Sim3::from_se3(&measurement), information_7);
+    }
+
+    /// Sets the robust kernel of every edge
+    pub fn set_kernel(&mut self, kernel: Option<Huber>) {
+        for edge in &mut self.edges {
+            edge.kernel = kernel;
+        }
+    }
+
+    /// Constrains two vertices to keep their current relative
+    /// transformation, with identity information as in ORB-SLAM
+    pub fn add_relative_edge(&mut self, a: usize, b: usize) {
+        let measurement = self.vertices[a] * self.vertices[b].inverse();
+        self.add_edge(a, b, measurement, Mat7::identity());
+    }
+
+    pub fn error(&self, edge: &PoseGraphEdge) -> Vec7 {
+        edge_error(&self.vertices, edge)
+    }
+
+    /// Sum of the squared errors weighted by their information, through
+    /// the robust kernel of each edge
+    pub fn cost(&self) -> f64 {
+        cost(&self.vertices, &self.edges)
+    }
+
+    /// Reads the g2o text format with `VERTEX_SE3:QUAT`, `EDGE_SE3:QUAT`
+    /// and `FIX` lines, as used by the sphere and garage benchmarks
+    pub fn load_g2o(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Self::read_g2o(io::BufReader::new(file))
+    }
+
+    /// g2o vertices are camera to world poses, so they are inverted into
+    /// world to camera vertices with a fixed scale. Edge measurements
+    /// `T_a^-1 T_b` equal this graph's `S_a S_b^-1`; their information is
+    /// converted from g2o's error, translation and quaternion vector part
+    /// of `Z^-1 T_a^-1 T_b`, to first order.
+    pub fn read_g2o(reader: impl BufRead) -> io::Result<Self> {
+        let mut graph = Self::new();
+        let mut indices: BTreeMap<u64, usize> = BTreeMap::new();
+        let mut fixed = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+
+            let Some(tag) = fields.next().filter(|tag| !tag.starts_with('#')) else {
+                continue;
+            };
+
+            let values: Vec<f64> = fields
+                .map(|v| v.parse().map_err(|_| invalid("invalid g2o value")))
+                .collect::<io::Result<_>>()?;
+
+            let id = |value: f64| {
+                (value >= 0.0 && value.fract() == 0.0).then_some(value as u64).ok_or_else(|| invalid("invalid g2o vertex id"))
+            };
+
+            match tag {
+                "VERTEX_SE3:QUAT" => {
+                    let [vertex, x, y, z, qx, qy, qz, qw] = values[..] else {
+                        return Err(invalid("invalid g2o vertex"));
+                    };
+
+                    let pose = SE3::new(SO3::from_quaternion([qw, qx, qy, qz]), Vec3::new(x, y, z));
+
+                    if indices.insert(id(vertex)?, graph.add_se3_vertex(pose.inverse(), false)).is_some() {
+                        return Err(invalid("duplicate g2o vertex id"));
+                    }
+                },
+                "EDGE_SE3:QUAT" => {
+                    if values.len() != 30 {
+                        return Err(invalid("invalid g2o edge"));
+                    }
+
+                    let index = |value: f64| indices.get(&id(value)?).copied().ok_or_else(|| invalid("g2o edge before its vertices"));
+                    let (a, b) = (index(values[0])?, index(values[1])?);
+
+                    let rotation = SO3::from_quaternion([values[8], values[5], values[6], values[7]]);
+                    let measurement = SE3::new(rotation, Vec3::new(values[2], values[3], values[4]));
+
+                    let mut information = Mat6::zeros();
+                    let mut entries = values[9..].iter();
+
+                    for i in 0..6 {
+                        for j in i..6 {
+                            let value = *entries.next().unwrap();
+                            information[(i, j)] = value;
+                            information[(j, i)] = value;
+                        }
+                    }
+
+                    let conversion = g2o_error_jacobian(&measurement);
+                    graph.add_se3_edge(a, b, measurement, conversion.transpose() * information * conversion);
+                },
+                "FIX" => {
+                    for &vertex in &values {
+                        fixed.push(id(vertex)?);
+                    }
+                },
+                _ => return Err(invalid("unsupported g2o element"))
+            }
+        }
+
+        for vertex in fixed {
+            let index = indices.get(&vertex).ok_or_else(|| invalid("fixed g2o vertex does not exist"))?;
+            graph.fixed[*index] = true;
+        }
+
+        Ok(graph)
+    }
+
+    /// Writes the g2o text format read by `read_g2o`, with vertices numbered
+    /// by index. Scale is dropped: vertices are written as `to_se3` and
+    /// edges as the rigid part of their measurement and information.
+    pub fn save_g2o(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        let mut writer = io::BufWriter::new(file);
+        self.write_g2o(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn write_g2o(&self, mut writer: impl Write) -> io::Result<()> {
+        for (index, vertex) in self.vertices.iter().enumerate() {
+            let pose = vertex.to_se3().inverse();
+            let [qw, qx, qy, qz] = pose.rotation.to_quaternion();
+            let [x, y, z] = pose.translation.to_array();
+
+            writeln!(writer, "VERTEX_SE3:QUAT {index} {x} {y} {z} {qx} {qy} {qz} {qw}")?;
+        }
+
+        for edge in &self.edges {
+            let measurement = edge.measurement.to_se3();
+            let [qw, qx, qy, qz] = measurement.rotation.to_quaternion();
+            let [x, y, z] = measurement.translation.to_array();
+
+            write!(writer, "EDGE_SE3:QUAT {} {} {x} {y} {z} {qx} {qy} {qz} {qw}", edge.a, edge.b)?;
+
+            let conversion = g2o_error_jacobian(&measurement).try_inverse().unwrap_or(Mat6::identity());
+            let information = conversion.transpose() * edge.information.block::<6, 6>(0, 0) * conversion;
+
+            for i in 0..6 {
+                for j in i..6 {
+                    write!(writer, " {}", information[(i, j)])?;
+                }
+            }
+
+            writeln!(writer)?;
+        }
+
+        let fixed: Vec<String> = (0..self.vertices.len()).filter(|&i| self.fixed[i]).map(|i| i.to_string()).collect();
+
+        if !fixed.is_empty() {
+            writeln!(writer, "FIX {}", fixed.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Derivative of g2o's error of an edge measuring `z`, translation then
+/// quaternion vector part of `Z^-1 T_a^-1 T_b`, with respect to this
+/// graph's error `e = log(Z S_b S_a^-1)`. The two are related by
+/// `Z^-1 T_a^-1 T_b = exp(-Ad(Z^-1) e)`, and the quaternion vector part of
+/// a small rotation is half its rotation vector.
+fn g2o_error_jacobian(z: &SE3) -> Mat6 {
+    let mut reorder = Mat6::zeros();
+    reorder.set_block(0, 3, &Mat3::identity());
+    reorder.set_block(3, 0, &(Mat3::identity() * 0.5));
+
+    -(reorder * z.inverse().adjoint())
+}
+
+fn edge_error(vertices: &[Sim3], edge: &PoseGraphEdge) -> Vec7 {
+    (edge.measurement * vertices[edge.b] * vertices[edge.a].inverse()).log()
+}
+
+fn edge_chi2(error: &Vec7, edge: &PoseGraphEdge) -> f64 {
+    (error.transpose() * edge.information * *error)[(0, 0)]
+}
+
+fn cost(vertices: &[Sim3], edges: &[PoseGraphEdge]) -> f64 {
+    edges.iter()
+        .map(|edge| {
+            let chi2 = edge_chi2(&edge_error(vertices, edge), edge);
+            edge.kernel.map_or(chi2, |kernel| kernel.cost(chi2))
+        })
+        .sum()
+}
+
+/// Derivatives of the error of an edge with respect to the left
+/// perturbations of its two vertices. With `E = S_ab S_b S_a^-1`,
+/// perturbing `S_b` multiplies `E` by `exp(Ad(S_ab) d)` on the left and
+/// perturbing `S_a` by `exp(-Ad(E) d)`.
+fn edge_jacobians(vertices: &[Sim3], edge: &PoseGraphEdge) -> (Vec7, Mat7, Mat7) {
+    let relative = edge.measurement * vertices[edge.b] * vertices[edge.a].inverse();
+    let error = relative.log();
+    let inverse_jacobian = Sim3::left_jacobian(&error).try_inverse().unwrap_or(Mat7::identity());
+
+    let jacobian_a = -(inverse_jacobian * relative.adjoint());
+    let jacobian_b = inverse_jacobian * edge.measurement.adjoint();
+
+    (error, jacobian_a, jacobian_b)
+}
+
+/// Levenberg-Marquardt over the free vertices of a pose graph. The normal
+/// equations are solved by envelope Cholesky in vertex order, so graphs
+/// whose vertices follow the trajectory stay sparse. Robust kernels are
+/// applied by reweighting each edge at the start of an iteration.
+pub fn optimize_pose_graph(graph: &mut PoseGraph, iterations: usize) -> PoseGraphSummary {
+    // Slot of each free vertex in the normal equations
+    let mut slots = vec![None; graph.vertices.len()];
+    let mut free_count = 0;
+
+    for (slot, &fixed) in slots.iter_mut().zip(&graph.fixed) {
+        if !fixed {
+            *slot = Some(free_count);
+            free_count += 1;
+        }
+    }
+
+    // First free vertex each one is connected to
+    let mut first: Vec<usize> = (0..free_count).collect();
+
+    for edge in &graph.edges {
+        if let (Some(a), Some(b)) = (slots[edge.a], slots[edge.b]) {
+            let (high, low) = (a.max(b), a.min(b));
+            first[high] = first[high].min(low);
+        }
+    }
+
+    let first: Vec<usize> = (0..7 * free_count).map(|row| 7 * first[row / 7]).collect();
+
+    // Scale rows of vertices with a fixed scale are left out of the normal
+    // equations, and get a unit diagonal so the step there is zero
+    let mut active = vec![true; 7 * free_count];
+
+    for (slot, &fixed_scale) in slots.iter().zip(&graph.fixed_scale) {
+        if let (Some(slot), true) = (slot, fixed_scale) {
+            active[7 * slot + 6] = false;
+        }
+    }
+
+    let initial_cost = graph.cost();
+    let mut current_cost = initial_cost;
+    let mut accepted = 0;
+    let mut lambda = 1e-5;
+
+    for _ in 0..iterations {
+        let mut hessian = EnvelopeMatrix::new(first.clone());
+        let mut gradient = vec![0.0; 7 * free_count];
+
+        for k in (0..7 * free_count).filter(|&k| !active[k]) {
+            hessian.add(k, k, 1.0);
+        }
+
+        for edge in &graph.edges {
+            let (error, jacobian_a, jacobian_b) = edge_jacobians(&graph.vertices, edge);
+            let blocks = [(slots[edge.a], jacobian_a), (slots[edge.b], jacobian_b)];
+
+            let weight = edge.kernel.map_or(1.0, |kernel| kernel.weight(edge_chi2(&error, edge)));
+            let information = edge.information * weight;
+
+            for &(slot_i, jacobian_i) in &blocks {
+                let Some(i) = slot_i else {
+                    continue;
+                };
+
+                let weighted: Matrix<7, 7> = jacobian_i.transpose() * information;
+                let g = weighted * error;
+
+                for r in (0..7).filter(|&r| active[7 * i + r]) {
+                    gradient[7 * i + r] += g[r];
+                }
+
+                for &(slot_j, jacobian_j) in &blocks {
+                    let Some(j) = slot_j.filter(|&j| j <= i) else {
+                        continue;
+                    };
+
+                    let block = weighted * jacobian_j;
+
+                    for r in 0..7 {
+                        for c in 0..7 {
+                            // Each entry once: the diagonal block's upper half
+                            // is its own transpose
+                            if (j < i || c <= r) && active[7 * i + r] && active[7 * j + c] {
+                                hessian.add(7 * i + r, 7 * j + c, block[(r, c)]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let solve = |lambda: f64| {
+            let mut damped = hessian.clone();
+            for k in 0..7 * free_count {
+                damped.add(k, k, lambda * hessian.get(k, k).max(1e-9));
+            }
+
+            let rhs: Vec<f64> = gradient.iter().map(|g| -g).collect();
+
+            damped.cholesky_solve(&rhs).map(|step| {
+                let norm_squared = step.iter().map(|s| s * s).sum();
+                (step, norm_squared)
+            })
+        };
+
+        let apply = |step: &Vec<f64>| -> Vec<Sim3> {
+            graph.vertices.iter()
+                .zip(&slots)
+                .map(|(vertex, slot)| {
+                    slot.map_or(*vertex, |slot| {
+                        let delta = Vec7::from_array(std::array::from_fn(|r| step[7 * slot + r]));
+                        Sim3::exp(&delta) * *vertex
+                    })
+                })
+                .collect()
+        };
+
+        let Some(step) = damped_step(&mut lambda, current_cost, solve, apply, |vertices| cost(vertices, &graph.edges)) else {
+            break;
+        };
+
+        graph.vertices = step.candidate;
+        current_cost = step.cost;
+        accepted += 1;
+
+        if step.converged {
+            break;
+        }
+    }
+
+    PoseGraphSummary { initial_cost, final_cost: current_cost, iterations: accepted }
+}
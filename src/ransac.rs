@@ -0,0 +1,172 @@
+use crate::random::Rng;
+
+/// Model fitting problem solved by `ransac`, over data points addressed by
+/// index.
+pub trait Estimator {
+    type Model: Clone;
+
+    /// Number of data points in a minimal sample
+    const SAMPLE_SIZE: usize;
+
+    fn data_count(&self) -> usize;
+
+    /// Models consistent with a minimal sample. Minimal solvers may return
+    /// several solutions, or none for degenerate samples.
+    fn fit(&self, sample: &[usize]) -> Vec<Self::Model>;
+
+    /// Error of data point `index` under `model`, in the units of
+    /// `RansacConfig::threshold`
+    fn residual(&self, model: &Self::Model, index: usize) -> f64;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sampling {
+    /// Minimal samples drawn uniformly from all data
+    Uniform,
+    /// PROSAC: data must be sorted by decreasing quality (e.g. increasing
+    /// descriptor distance) and samples are drawn from a growing prefix
+    Prosac
+}
+
+#[derive(Clone, Debug)]
+pub struct RansacConfig {
+    /// Data points with a smaller residual are inliers
+    pub threshold: f64,
+    /// Probability of drawing at least one outlier free sample, used to
+    /// stop early
+    pub confidence: f64,
+    pub min_iterations: usize,
+    pub max_iterations: usize,
+    pub sampling: Sampling
+}
+
+impl Default for RansacConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            confidence: 0.99,
+            min_iterations: 0,
+            max_iterations: 1000,
+            sampling: Sampling::Uniform
+        }
+    }
+}
+
+pub struct RansacResult<M> {
+    pub model: M,
+    pub inliers: Vec<bool>,
+    pub inlier_count: usize,
+    pub iterations: usize
+}
+
+impl<M> RansacResult<M> {
+    pub fn inlier_indices(&self) -> Vec<usize> {
+        (0..self.inliers.len()).filter(|&i| self.inliers[i]).collect()
+    }
+}
+
+/// Number of iterations after which an outlier free sample has been drawn
+/// with probability `confidence`
+fn required_iterations(inlier_ratio: f64, sample_size: usize, confidence: f64) -> usize {
+    let all_inliers = inlier_ratio.powi(sample_size as i32);
+
+    if all_inliers >= 1.0 {
+        return 0;
+    }
+
+    if all_inliers <= 0.0 {
+        return usize::MAX;
+    }
+
+    let iterations = (1.0 - confidence).ln() / (1.0 - all_inliers).ln();
+    iterations.ceil().min(usize::MAX as f64) as usize
+}
+
+/// Growth schedule of the PROSAC sampling prefix (Chum and Matas, 2005)
+struct ProsacSampler {
+    sample_size: usize,
+    data_count: usize,
+    /// Current prefix length
+    n: usize,
+    /// Expected number of samples drawn from the first `n` data points
+    t_n: f64,
+    /// Iteration at which the prefix grows next
+    t_n_prime: usize,
+    t: usize
+}
+
+impl ProsacSampler {
+    fn new(sample_size: usize, data_count: usize, max_iterations: usize) -> Self {
+        let mut t_n = max_iterations as f64;
+        for i in 0..sample_size {
+            t_n *= (sample_size - i) as f64 / (data_count - i) as f64;
+        }
+
+        Self { sample_size, data_count, n: sample_size, t_n, t_n_prime: 1, t: 0 }
+    }
+
+    fn sample(&mut self, rng: &mut Rng) -> Vec<usize> {
+        self.t += 1;
+
+        if self.t == self.t_n_prime && self.n < self.data_count {
+            let t_n_next = self.t_n * (self.n + 1) as f64 / (self.n + 1 - self.sample_size) as f64;
+            self.t_n_prime += (t_n_next - self.t_n).ceil().max(1.0) as usize;
+            self.t_n = t_n_next;
+            self.n += 1;
+        }
+
+        if self.t_n_prime >= self.t {
+            // The newest point of the prefix and the rest from before it
+            let mut sample = rng.sample_indices(self.n - 1, self.sample_size - 1);
+            sample.push(self.n - 1);
+            sample
+        } else {
+            rng.sample_indices(self.n, self.sample_size)
+        }
+    }
+}
+
+/// Robustly fits a model by hypothesising from minimal samples and keeping
+/// the hypothesis with the most inliers. Returns `None` when there are
+/// fewer data points than a minimal sample or no hypothesis was found.
+pub fn ransac<E: Estimator>(estimator: &E, config: &RansacConfig, rng: &mut Rng) -> Option<RansacResult<E::Model>> {
+    let data_count = estimator.data_count();
+
+    if data_count < E::SAMPLE_SIZE {
+        return None;
+    }
+
+    let mut prosac = (config.sampling == Sampling::Prosac)
+        .then(|| ProsacSampler::new(E::SAMPLE_SIZE, data_count, config.max_iterations));
+
+    let mut best: Option<RansacResult<E::Model>> = None;
+    let mut needed = config.max_iterations;
+    let mut iterations = 0;
+
+    while iterations < needed.max(config.min_iterations).min(config.max_iterations) {
+        iterations += 1;
+
+        let sample = match &mut prosac {
+            Some(sampler) => sampler.sample(rng),
+            None => rng.sample_indices(data_count, E::SAMPLE_SIZE)
+        };
+
+        for model in estimator.fit(&sample) {
+            let inliers: Vec<bool> = (0..data_count)
+                .map(|i| estimator.residual(&model, i) < config.threshold)
+                .collect();
+            let inlier_count = inliers.iter().filter(|&&inlier| inlier).count();
+
+            if best.as_ref().is_some_and(|b| b.inlier_count >= inlier_count) {
+                continue;
+            }
+
+            let ratio = inlier_count as f64 / data_count as f64;
+            needed = required_iterations(ratio, E::SAMPLE_SIZE, config.confidence);
+
+            best = Some(RansacResult { model, inliers, inlier_count, iterations: 0 });
+        }
+    }
+
+    best.map(|result| RansacResult { iterations, ..result })
+}
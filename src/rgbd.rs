@@ -0,0 +1,114 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::camera::CameraModel;
+use crate::frame::Frame;
+use crate::image::{self, DecodedImage, Image};
+use crate::linalg::Vec2;
+use crate::orb::OrbProgram;
+
+pub struct RgbdConfig {
+    /// Raw depth units per metre
+    pub depth_scale: f64,
+    /// Depths outside this range, in metres, are invalid
+    pub min_depth: f64,
+    pub max_depth: f64,
+    /// Baseline of the virtual stereo rig the right image coordinates of
+    /// keypoints are computed for, in metres
+    pub baseline: f64,
+    /// Keypoints closer than this many baselines are close points
+    pub close_depth_factor: f64
+}
+
+impl Default for RgbdConfig {
+    fn default() -> Self {
+        // Values used by ORB-SLAM for the TUM RGB-D dataset, where every
+        // positive depth is valid
+        Self {
+            depth_scale: 5000.0,
+            min_depth: 0.0,
+            max_depth: f64::INFINITY,
+            baseline: 40.0 / 517.3,
+            close_depth_factor: 40.0
+        }
+    }
+}
+
+/// Depth image registered to the colour image, in metres. Pixels without
+/// a measurement are zero.
+#[derive(Clone)]
+pub struct DepthImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>
+}
+
+impl DepthImage {
+    /// Converts single channel raw depths with `depth_scale` units per
+    /// metre
+    pub fn from_raw(image: &Image<u16>, depth_scale: f64) -> Self {
+        assert_eq!(image.channels, 1);
+
+        Self {
+            width: image.width,
+            height: image.height,
+            data: image.data.iter().map(|&raw| (raw as f64 / depth_scale) as f32).collect()
+        }
+    }
+
+    /// Reads a 16 bit single channel depth map as stored by the TUM RGB-D
+    /// dataset, where a depth of 5000 is one metre
+    pub fn load_tum(path: impl AsRef<Path>, depth_scale: f64) -> io::Result<Self> {
+        match image::load(path)? {
+            DecodedImage::U16(image) if image.channels == 1 => Ok(Self::from_raw(&image, depth_scale)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "depth maps must be 16 bit single channel images"))
+        }
+    }
+
+    /// Depth at the pixel containing `pixel`, if inside the image
+    pub fn at(&self, pixel: &Vec2) -> Option<f64> {
+        let (x, y) = (pixel.x().floor(), pixel.y().floor());
+
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return None;
+        }
+
+        Some(self.data[(y as u32 * self.width + x as u32) as usize] as f64)
+    }
+}
+
+/// Samples the depth of each keypoint of `frame` at its detected position,
+/// keeping depths inside the validity range, and sets the right image
+/// coordinates the tracker uses as for a stereo frame.
+pub fn set_frame_depths(config: &RgbdConfig, frame: &mut Frame, depth: &DepthImage) {
+    let depths = frame.keypoints.iter()
+        .map(|keypoint| depth.at(keypoint).filter(|&d| d > 0.0 && d > config.min_depth && d <= config.max_depth))
+        .collect();
+
+    frame.set_depths(config.baseline, config.close_depth_factor * config.baseline, depths);
+}
+
+/// Extracts features from a colour image, in the layout expected by
+/// `OrbProgram::write_input_image`, and samples the registered depth image
+/// at the keypoints. Depth is sampled on the CPU, where the keypoints are
+/// read back to anyway, so the program is unchanged. The program must not
+/// undistort, since the depth image is registered to the input image.
+pub fn extract_rgbd_frame(
+    config: &RgbdConfig,
+    program: &OrbProgram,
+    id: u64,
+    timestamp: f64,
+    camera: Arc<dyn CameraModel>,
+    color: &Image<u8>,
+    depth: &DepthImage
+) -> Frame {
+    assert!(program.config.undistortion.is_empty());
+    assert!(depth.width == program.config.image_size.width && depth.height == program.config.image_size.height);
+
+    program.write_input_image(&color.data);
+
+    let mut frame = program.extract_frame(id, timestamp, camera);
+    set_frame_depths(config, &mut frame, depth);
+    frame
+}
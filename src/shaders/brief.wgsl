@@ -1,8 +1,9 @@
 struct Feature {
     x: u32,
     y: u32,
-    angle: u32,
-    octave: u32
+    angle: i32,
+    octave: u32,
+    camera: u32
 }
 
 @group(0) @binding(0)
@@ -15,7 +16,7 @@ var<storage, read> counter: u32;
 var<storage, read_write> descriptors: array<array<u32, 8>>;
 
 @group(0) @binding(3)
-var blur_hierarchy: texture_2d<f32>;
+var blur_hierarchy: texture_2d_array<f32>;
 
 @compute
 @workgroup_size(8, 8, 1)
@@ -30,14 +31,18 @@ fn brief(
 
     let corner = corners[feature_id];
     let octave = i32(corner.octave);
+    let layer = corner.camera;
 
     let pos = vec2i(i32(corner.x), i32(corner.y));
     let angle = f32(corner.angle) / 1000.0;
     let ct = cos(angle);
     let st = sin(angle);
+    // Steer the pattern by the keypoint orientation. The rotation and the
+    // comparison below follow OpenCV's ORB so descriptors are compatible
+    // with vocabularies trained on it.
     let rotation_matrix = mat2x2f(
-        ct, -st,
-        st, ct
+        ct, st,
+        -st, ct
     );
 
     var bits = 0u;
@@ -53,13 +58,13 @@ fn brief(
         let rotated_point_a = rotation_matrix * unrotated_point_a;
         let rotated_point_b = rotation_matrix * unrotated_point_b;
 
-        let texel_a = vec2i(rotated_point_a) + pos;
-        let texel_b = vec2i(rotated_point_b) + pos;
+        let texel_a = vec2i(round(rotated_point_a)) + pos;
+        let texel_b = vec2i(round(rotated_point_b)) + pos;
 
-        let value_a = textureLoad(blur_hierarchy, texel_a, octave);
-        let value_b = textureLoad(blur_hierarchy, texel_b, octave);
+        let value_a = textureLoad(blur_hierarchy, texel_a, layer, octave);
+        let value_b = textureLoad(blur_hierarchy, texel_b, layer, octave);
 
-        if value_a.x > value_b.x {
+        if value_a.x < value_b.x {
             bits |= 1u << i;
         }
     }
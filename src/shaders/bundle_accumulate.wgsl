@@ -0,0 +1,47 @@
+// Size of `Linearized` in `bundle_linearize.wgsl`, in floats
+const LINEARIZED_SIZE: u32 = 58u;
+
+@group(0) @binding(0)
+var<storage, read> linearized: array<f32>;
+
+@group(0) @binding(1)
+var<storage, read> offsets: array<u32>;
+
+@group(0) @binding(2)
+var<storage, read> indices: array<u32>;
+
+@group(0) @binding(3)
+var<storage, read_write> blocks: array<f32>;
+
+struct PushConstants {
+    count: u32,
+    // Range of the fields of `Linearized` to sum
+    first: u32,
+    length: u32
+}
+
+var<push_constant> constants: PushConstants;
+
+// Sums a range of the linearized observations of each pose or point,
+// listed in `indices` between consecutive `offsets`
+@compute
+@workgroup_size(64, 1, 1)
+fn accumulate(
+    @builtin(global_invocation_id) global_id: vec3u
+) {
+    let node = global_id.x;
+
+    if node >= constants.count {
+        return;
+    }
+
+    for (var i = 0u; i < constants.length; i ++) {
+        var sum = 0.0;
+
+        for (var k = offsets[node]; k < offsets[node + 1u]; k ++) {
+            sum += linearized[indices[k] * LINEARIZED_SIZE + constants.first + i];
+        }
+
+        blocks[node * constants.length + i] = sum;
+    }
+}
@@ -0,0 +1,298 @@
+struct Pose {
+    // Row major world to camera rotation
+    rotation: array<f32, 9>,
+    translation: array<f32, 3>,
+    model: u32,
+    // fx, fy, cx, cy
+    intrinsics: array<f32, 4>,
+    // k1, k2, p1, p2, k3 for radial-tangential, k1 to k4 for Kannala-Brandt
+    distortion: array<f32, 5>
+}
+
+struct Point {
+    x: f32,
+    y: f32,
+    z: f32
+}
+
+struct Observation {
+    pose: u32,
+    point: u32,
+    pixel: array<f32, 2>,
+    inv_sigma2: f32,
+    // One for outliers
+    outlier: u32
+}
+
+// Pose and point blocks are contiguous, for `bundle_accumulate.wgsl` to sum
+struct Linearized {
+    residual: array<f32, 2>,
+    // Zero behind the camera
+    valid: u32,
+    weight: f32,
+    // Upper triangle of the 6x6 block, row by row
+    pose_hessian: array<f32, 21>,
+    pose_gradient: array<f32, 6>,
+    // Upper triangle of the 3x3 block, row by row
+    point_hessian: array<f32, 6>,
+    point_gradient: array<f32, 3>,
+    // Row major 6x3 pose-point block
+    coupling: array<f32, 18>
+}
+
+// Rows of the 2x3 derivative of the projection
+struct Jacobian {
+    u: vec3f,
+    v: vec3f
+}
+
+const PINHOLE: u32 = 0u;
+const RAD_TAN: u32 = 1u;
+const KANNALA_BRANDT: u32 = 2u;
+
+@group(0) @binding(0)
+var<storage, read> poses: array<Pose>;
+
+@group(0) @binding(1)
+var<storage, read> points: array<Point>;
+
+@group(0) @binding(2)
+var<storage, read> observations: array<Observation>;
+
+@group(0) @binding(3)
+var<storage, read_write> linearized: array<Linearized>;
+
+struct PushConstants {
+    count: u32,
+    // Huber threshold on the whitened error, zero without a kernel
+    huber_delta: f32
+}
+
+var<push_constant> constants: PushConstants;
+
+fn radial_tangential(distortion: array<f32, 5>, p: vec2f) -> vec2f {
+    let k1 = distortion[0];
+    let k2 = distortion[1];
+    let p1 = distortion[2];
+    let p2 = distortion[3];
+    let k3 = distortion[4];
+
+    let r2 = dot(p, p);
+    let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+
+    return vec2f(
+        p.x * radial + 2.0 * p1 * p.x * p.y + p2 * (r2 + 2.0 * p.x * p.x),
+        p.y * radial + p1 * (r2 + 2.0 * p.y * p.y) + 2.0 * p2 * p.x * p.y
+    );
+}
+
+fn theta_d(distortion: array<f32, 5>, theta: f32) -> f32 {
+    let t2 = theta * theta;
+    return theta * (1.0 + t2 * (distortion[0] + t2 * (distortion[1] + t2 * (distortion[2] + t2 * distortion[3]))));
+}
+
+fn theta_d_derivative(distortion: array<f32, 5>, theta: f32) -> f32 {
+    let t2 = theta * theta;
+    return 1.0 + t2 * (3.0 * distortion[0] + t2 * (5.0 * distortion[1] + t2 * (7.0 * distortion[2] + t2 * 9.0 * distortion[3])));
+}
+
+fn project(pose: Pose, p: vec3f) -> vec2f {
+    let focal = vec2f(pose.intrinsics[0], pose.intrinsics[1]);
+    let center = vec2f(pose.intrinsics[2], pose.intrinsics[3]);
+
+    switch pose.model {
+        case RAD_TAN: {
+            return focal * radial_tangential(pose.distortion, p.xy / p.z) + center;
+        }
+        case KANNALA_BRANDT: {
+            let r = length(p.xy);
+
+            if r < 1e-12 {
+                return center;
+            }
+
+            let theta = atan2(r, p.z);
+            return focal * p.xy * (theta_d(pose.distortion, theta) / r) + center;
+        }
+        default: {
+            return focal * p.xy / p.z + center;
+        }
+    }
+}
+
+fn pinhole_jacobian(pose: Pose, p: vec3f) -> Jacobian {
+    let inv_z = 1.0 / p.z;
+    let fx = pose.intrinsics[0];
+    let fy = pose.intrinsics[1];
+
+    return Jacobian(
+        vec3f(fx * inv_z, 0.0, -fx * p.x * inv_z * inv_z),
+        vec3f(0.0, fy * inv_z, -fy * p.y * inv_z * inv_z)
+    );
+}
+
+fn radial_tangential_jacobian(pose: Pose, p: vec3f) -> Jacobian {
+    let k1 = pose.distortion[0];
+    let k2 = pose.distortion[1];
+    let p1 = pose.distortion[2];
+    let p2 = pose.distortion[3];
+    let k3 = pose.distortion[4];
+
+    let inv_z = 1.0 / p.z;
+    let x = p.x * inv_z;
+    let y = p.y * inv_z;
+
+    let r2 = x * x + y * y;
+    let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+    let d_radial = 2.0 * k1 + r2 * (4.0 * k2 + 6.0 * k3 * r2);
+
+    let d00 = radial + d_radial * x * x + 2.0 * p1 * y + 6.0 * p2 * x;
+    let d01 = d_radial * x * y + 2.0 * p1 * x + 2.0 * p2 * y;
+    let d11 = radial + d_radial * y * y + 6.0 * p1 * y + 2.0 * p2 * x;
+
+    // Derivatives of the normalised coordinates
+    let n0 = vec3f(inv_z, 0.0, -x * inv_z);
+    let n1 = vec3f(0.0, inv_z, -y * inv_z);
+
+    return Jacobian(
+        pose.intrinsics[0] * (d00 * n0 + d01 * n1),
+        pose.intrinsics[1] * (d01 * n0 + d11 * n1)
+    );
+}
+
+fn kannala_brandt_jacobian(pose: Pose, p: vec3f) -> Jacobian {
+    let r2 = p.x * p.x + p.y * p.y;
+    let r = sqrt(r2);
+
+    // Near the optical axis the model is a pinhole camera
+    if r < 1e-9 {
+        return pinhole_jacobian(pose, p);
+    }
+
+    let theta = atan2(r, p.z);
+    let distorted = theta_d(pose.distortion, theta);
+    let d_distorted = theta_d_derivative(pose.distortion, theta);
+
+    let rho2 = r2 + p.z * p.z;
+    let d_theta = vec3f(p.x * p.z / (r * rho2), p.y * p.z / (r * rho2), -r / rho2);
+
+    // Derivatives of theta_d / r
+    let psi = distorted / r;
+    let d_psi = d_distorted * d_theta / r - vec3f(distorted * p.x, distorted * p.y, 0.0) / (r2 * r);
+
+    let fx = pose.intrinsics[0];
+    let fy = pose.intrinsics[1];
+
+    return Jacobian(
+        fx * (vec3f(psi, 0.0, 0.0) + p.x * d_psi),
+        fy * (vec3f(0.0, psi, 0.0) + p.y * d_psi)
+    );
+}
+
+fn project_jacobian(pose: Pose, p: vec3f) -> Jacobian {
+    switch pose.model {
+        case RAD_TAN: {
+            return radial_tangential_jacobian(pose, p);
+        }
+        case KANNALA_BRANDT: {
+            return kannala_brandt_jacobian(pose, p);
+        }
+        default: {
+            return pinhole_jacobian(pose, p);
+        }
+    }
+}
+
+@compute
+@workgroup_size(64, 1, 1)
+fn linearize(
+    @builtin(global_invocation_id) global_id: vec3u
+) {
+    let index = global_id.x;
+
+    if index >= constants.count {
+        return;
+    }
+
+    let observation = observations[index];
+    let pose = poses[observation.pose];
+    let world = points[observation.point];
+
+    let r = pose.rotation;
+    let row0 = vec3f(r[0], r[1], r[2]);
+    let row1 = vec3f(r[3], r[4], r[5]);
+    let row2 = vec3f(r[6], r[7], r[8]);
+
+    let x = vec3f(world.x, world.y, world.z);
+    let p = vec3f(dot(row0, x), dot(row1, x), dot(row2, x)) + vec3f(pose.translation[0], pose.translation[1], pose.translation[2]);
+
+    var result: Linearized;
+
+    if p.z <= 0.0 {
+        linearized[index] = result;
+        return;
+    }
+
+    let residual = project(pose, p) - vec2f(observation.pixel[0], observation.pixel[1]);
+    result.residual = array<f32, 2>(residual.x, residual.y);
+    result.valid = 1u;
+
+    if observation.outlier != 0u {
+        linearized[index] = result;
+        return;
+    }
+
+    let chi2 = dot(residual, residual) * observation.inv_sigma2;
+    var weight = observation.inv_sigma2;
+
+    if constants.huber_delta > 0.0 && chi2 > constants.huber_delta * constants.huber_delta {
+        weight *= constants.huber_delta / sqrt(chi2);
+    }
+
+    result.weight = weight;
+
+    let projection = project_jacobian(pose, p);
+
+    // Left perturbation [omega, v]: d p = -[p]x omega + v, so each row a
+    // of the projection derivative becomes [p x a, a]
+    var pose_jacobian = array<array<f32, 6>, 2>();
+    var rows = array<vec3f, 2>(projection.u, projection.v);
+
+    // Point derivative a^T R
+    var point_jacobian = array<vec3f, 2>();
+
+    for (var k = 0u; k < 2u; k ++) {
+        let a = rows[k];
+        let omega = cross(p, a);
+        pose_jacobian[k] = array<f32, 6>(omega.x, omega.y, omega.z, a.x, a.y, a.z);
+        point_jacobian[k] = a.x * row0 + a.y * row1 + a.z * row2;
+    }
+
+    var slot = 0u;
+
+    for (var i = 0u; i < 6u; i ++) {
+        for (var j = i; j < 6u; j ++) {
+            result.pose_hessian[slot] = weight * (pose_jacobian[0][i] * pose_jacobian[0][j] + pose_jacobian[1][i] * pose_jacobian[1][j]);
+            slot ++;
+        }
+
+        result.pose_gradient[i] = weight * (pose_jacobian[0][i] * residual.x + pose_jacobian[1][i] * residual.y);
+
+        for (var j = 0u; j < 3u; j ++) {
+            result.coupling[3u * i + j] = weight * (pose_jacobian[0][i] * point_jacobian[0][j] + pose_jacobian[1][i] * point_jacobian[1][j]);
+        }
+    }
+
+    slot = 0u;
+
+    for (var i = 0u; i < 3u; i ++) {
+        for (var j = i; j < 3u; j ++) {
+            result.point_hessian[slot] = weight * (point_jacobian[0][i] * point_jacobian[0][j] + point_jacobian[1][i] * point_jacobian[1][j]);
+            slot ++;
+        }
+
+        result.point_gradient[i] = weight * (point_jacobian[0][i] * residual.x + point_jacobian[1][i] * residual.y);
+    }
+
+    linearized[index] = result;
+}
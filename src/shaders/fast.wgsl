@@ -1,12 +1,14 @@
 struct Feature {
     x: u32,
     y: u32,
-    angle: u32,
-    octave: u32
+    angle: i32,
+    octave: u32,
+    camera: u32
 }
 
 @group(0) @binding(0)
-var texture: texture_2d<f32>;
+// One layer per camera
+var texture: texture_2d_array<f32>;
 
 @group(0) @binding(1)
 var<storage, read_write> corners: array<Feature>;
@@ -75,7 +77,7 @@ fn compute_fast(
     // to properly calculate its BRIEF descriptor
     
     if all(global_id.xy > vec2u(16, 16)) && all(global_id.xy < textureDimensions(texture) - vec2u(16, 16)) {
-        let center_value = textureLoad(texture, global_id.xy, i32(octave)).x;
+        let center_value = textureLoad(texture, global_id.xy, global_id.z, i32(octave)).x;
 
         let id_i32 = vec2i(global_id.xy);
 
@@ -83,7 +85,7 @@ fn compute_fast(
         var num_under = 0u;
         
         for (var i = 0u; i < 4u; i ++) {
-            let corner_value = textureLoad(texture, id_i32 + CORNERS_4[i], i32(octave)).x;
+            let corner_value = textureLoad(texture, id_i32 + CORNERS_4[i], global_id.z, i32(octave)).x;
             let diff = corner_value - center_value;
             if diff > threshold {
                 num_over ++;
@@ -100,7 +102,7 @@ fn compute_fast(
             var centroid = vec2f(0, 0);
 
             for (var i = 0u; i < 16u; i ++) {
-                let corner_value = textureLoad(texture, id_i32 + CORNERS_16[i], i32(octave)).x;
+                let corner_value = textureLoad(texture, id_i32 + CORNERS_16[i], global_id.z, i32(octave)).x;
                 let diff = corner_value - center_value;
                 
                 centroid += corner_value * vec2f(CORNERS_16[i]);
@@ -150,8 +152,9 @@ fn compute_fast(
 
         feature.x = global_id.x;
         feature.y = global_id.y;
-        feature.angle = u32(angle * 1000.0);
+        feature.angle = i32(angle * 1000.0);
         feature.octave = octave;
+        feature.camera = global_id.z;
 
         corners[global_index] = feature;
     }
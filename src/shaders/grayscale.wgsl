@@ -22,7 +22,9 @@ fn vs_main(
     let position = vertices[vertex_index];
     var output: VertexOutput;
     output.position = vec4f(position, 0.0, 1.0);
-    output.texcoord = position * 0.5 + 0.5;
+    // Flip y so texture rows match image rows: texture coordinates grow
+    // downwards while clip space grows upwards
+    output.texcoord = vec2f(position.x * 0.5 + 0.5, 0.5 - position.y * 0.5);
     return output;
 }
 
@@ -0,0 +1,118 @@
+struct Feature {
+    x: u32,
+    y: u32,
+    angle: i32,
+    octave: u32,
+    camera: u32
+}
+
+struct SearchWindow {
+    x: f32,
+    y: f32,
+    radius: f32,
+    min_octave: u32,
+    max_octave: u32
+}
+
+struct WindowMatch {
+    index: u32,
+    distance: u32,
+    second_distance: u32
+}
+
+const CELL_SIZE: u32 = 16u;
+const CELL_CAPACITY: u32 = 64u;
+const NO_MATCH: u32 = 0xffffffffu;
+
+@group(0) @binding(0)
+var<storage, read> corners: array<Feature>;
+
+@group(0) @binding(1)
+var<storage, read> descriptors: array<array<u32, 8>>;
+
+@group(0) @binding(2)
+var<storage, read> grid_counts: array<u32>;
+
+@group(0) @binding(3)
+var<storage, read> grid_cells: array<u32>;
+
+@group(0) @binding(4)
+var<storage, read> windows: array<SearchWindow>;
+
+@group(0) @binding(5)
+var<storage, read> window_descriptors: array<array<u32, 8>>;
+
+@group(0) @binding(6)
+var<storage, read_write> window_matches: array<WindowMatch>;
+
+struct PushConstants {
+    grid_size: vec2u,
+    window_count: u32,
+    // Only keypoints of this camera are searched
+    camera: u32
+}
+
+var<push_constant> constants: PushConstants;
+
+@compute
+@workgroup_size(64, 1, 1)
+fn guided_match(
+    @builtin(global_invocation_id) global_id: vec3u
+) {
+    let window_id = global_id.x;
+
+    if window_id >= constants.window_count {
+        return;
+    }
+
+    let window = windows[window_id];
+    var query = window_descriptors[window_id];
+    let center = vec2f(window.x, window.y);
+
+    let max_cell = vec2i(constants.grid_size) - 1;
+    let min_cell = clamp(vec2i(floor((center - window.radius) / f32(CELL_SIZE))), vec2i(0), max_cell);
+    let end_cell = clamp(vec2i(floor((center + window.radius) / f32(CELL_SIZE))), vec2i(0), max_cell);
+
+    var result: WindowMatch;
+    result.index = NO_MATCH;
+    result.distance = NO_MATCH;
+    result.second_distance = NO_MATCH;
+
+    for (var cy = min_cell.y; cy <= end_cell.y; cy ++) {
+        for (var cx = min_cell.x; cx <= end_cell.x; cx ++) {
+            let cell_index = (constants.camera * constants.grid_size.y + u32(cy)) * constants.grid_size.x + u32(cx);
+            let count = min(grid_counts[cell_index], CELL_CAPACITY);
+
+            for (var slot = 0u; slot < count; slot ++) {
+                let feature_id = grid_cells[cell_index * CELL_CAPACITY + slot];
+                let corner = corners[feature_id];
+
+                if corner.octave < window.min_octave || corner.octave > window.max_octave {
+                    continue;
+                }
+
+                let position = vec2f(vec2u(corner.x, corner.y) << vec2u(corner.octave));
+                let offset = position - center;
+
+                if dot(offset, offset) > window.radius * window.radius {
+                    continue;
+                }
+
+                var distance = 0u;
+                for (var i = 0u; i < 8u; i ++) {
+                    distance += countOneBits(query[i] ^ descriptors[feature_id][i]);
+                }
+
+                if distance < result.distance {
+                    result.second_distance = result.distance;
+                    result.distance = distance;
+                    result.index = feature_id;
+                } else if distance < result.second_distance {
+                    result.second_distance = distance;
+                }
+            }
+        }
+    }
+
+    window_matches[window_id] = result;
+}
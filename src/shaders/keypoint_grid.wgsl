@@ -0,0 +1,52 @@
+struct Feature {
+    x: u32,
+    y: u32,
+    angle: i32,
+    octave: u32,
+    camera: u32
+}
+
+const CELL_SIZE: u32 = 16u;
+const CELL_CAPACITY: u32 = 64u;
+
+@group(0) @binding(0)
+var<storage, read> corners: array<Feature>;
+
+@group(0) @binding(1)
+var<storage, read> counter: u32;
+
+@group(0) @binding(2)
+var<storage, read_write> grid_counts: array<atomic<u32>>;
+
+@group(0) @binding(3)
+var<storage, read_write> grid_cells: array<u32>;
+
+// Number of cells along each axis
+var<push_constant> grid_size: vec2u;
+
+@compute
+@workgroup_size(64, 1, 1)
+fn build_grid(
+    @builtin(global_invocation_id) global_id: vec3u
+) {
+    let feature_id = global_id.x;
+
+    if feature_id >= counter {
+        return;
+    }
+
+    let corner = corners[feature_id];
+
+    // Corners are stored in the coordinates of their own octave
+    let position = vec2u(corner.x, corner.y) << vec2u(corner.octave);
+    let cell = min(position / CELL_SIZE, grid_size - 1u);
+    // Each camera has its own grid, stored one after another
+    let cell_index = (corner.camera * grid_size.y + cell.y) * grid_size.x + cell.x;
+
+    let slot = atomicAdd(&grid_counts[cell_index], 1u);
+
+    // Overflowing keypoints are dropped from the grid
+    if slot < CELL_CAPACITY {
+        grid_cells[cell_index * CELL_CAPACITY + slot] = feature_id;
+    }
+}
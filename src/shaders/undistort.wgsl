@@ -0,0 +1,40 @@
+@group(0) @binding(0)
+var texture_sampler: sampler;
+
+@group(0) @binding(1)
+var texture: texture_2d<f32>;
+
+// Texture coordinates into `texture` for each output pixel
+@group(0) @binding(2)
+var undistortion_map: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4f
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32
+) -> VertexOutput {
+    var vertices = array(
+        vec2f(-1.0, 3.0),
+        vec2f(3.0, -1.0),
+        vec2f(-1.0, -1.0)
+    );
+
+    var output: VertexOutput;
+    output.position = vec4f(vertices[vertex_index], 0.0, 1.0);
+    return output;
+}
+
+@fragment
+fn fs_main(input: VertexOutput) -> @location(0) vec4f {
+    let texcoord = textureLoad(undistortion_map, vec2u(input.position.xy), 0).xy;
+
+    // Rays that miss the input image
+    if any(texcoord < vec2f(0.0)) || any(texcoord > vec2f(1.0)) {
+        return vec4f(0.0);
+    }
+
+    return textureSampleLevel(texture, texture_sampler, texcoord, 0.0);
+}
@@ -0,0 +1,273 @@
+use crate::camera::CameraModel;
+use crate::geometry::{Sim3, SO3};
+use crate::linalg::{DMatrix, Mat3, Mat4, Mat7, Matrix, Vec2, Vec3, Vec7};
+use crate::random::Rng;
+use crate::ransac::{ransac, Estimator, RansacConfig};
+
+/// 99% quantile of the chi-squared distribution with two degrees of freedom
+const CHI2_2D_99: f64 = 9.210;
+
+/// Similarity `S` minimising `sum |b_i - S a_i|^2`. The rotation is the
+/// quaternion of Horn's closed-form solution (Horn, 1987) and the scale that
+/// of ORB-SLAM's Sim3Solver, or one when `fixed_scale`. Returns `None` for
+/// fewer than three points or a degenerate configuration.
+pub fn horn(a: &[Vec3], b: &[Vec3], fixed_scale: bool) -> Option<Sim3> {
+    assert_eq!(a.len(), b.len());
+
+    if a.len() < 3 {
+        return None;
+    }
+
+    let n = a.len() as f64;
+    let centroid_a = a.iter().fold(Vec3::zeros(), |acc, p| acc + *p) * (1.0 / n);
+    let centroid_b = b.iter().fold(Vec3::zeros(), |acc, p| acc + *p) * (1.0 / n);
+
+    let relative_a: Vec<Vec3> = a.iter().map(|p| *p - centroid_a).collect();
+    let relative_b: Vec<Vec3> = b.iter().map(|p| *p - centroid_b).collect();
+
+    let mut m = Mat3::zeros();
+    for (pa, pb) in relative_a.iter().zip(&relative_b) {
+        m += *pa * pb.transpose();
+    }
+
+    let s = |i: usize, j: usize| m[(i, j)];
+    let (sxx, sxy, sxz) = (s(0, 0), s(0, 1), s(0, 2));
+    let (syx, syy, syz) = (s(1, 0), s(1, 1), s(1, 2));
+    let (szx, szy, szz) = (s(2, 0), s(2, 1), s(2, 2));
+
+    // The quaternion [w, x, y, z] of the rotation is the eigenvector of the
+    // largest eigenvalue of this symmetric matrix
+    let mut q = Mat4::from_rows([
+        [sxx + syy + szz, syz - szy, szx - sxz, sxy - syx],
+        [syz - szy, sxx - syy - szz, sxy + syx, szx + sxz],
+        [szx - sxz, sxy + syx, -sxx + syy - szz, syz + szy],
+        [sxy - syx, szx + sxz, syz + szy, -sxx - syy + szz]
+    ]);
+
+    // Shifting by the norm, an upper bound of the eigenvalues, makes it
+    // positive semi-definite so the singular vectors are its eigenvectors
+    let shift = q.norm();
+    for i in 0..4 {
+        q[(i, i)] += shift;
+    }
+
+    let svd = DMatrix::from_fixed(&q).svd();
+
+    if svd.singular_values[0] - svd.singular_values[1] <= 1e-12 * svd.singular_values[0] {
+        return None;
+    }
+
+    let quaternion = svd.v.column(0);
+    let rotation = SO3::from_quaternion([quaternion[0], quaternion[1], quaternion[2], quaternion[3]]);
+
+    let spread: f64 = relative_a.iter().map(|p| p.norm_squared()).sum();
+
+    if spread <= 1e-12 {
+        return None;
+    }
+
+    let scale = if fixed_scale {
+        1.0
+    } else {
+        relative_a.iter()
+            .zip(&relative_b)
+            .map(|(pa, pb)| pb.dot(&(rotation * *pa)))
+            .sum::<f64>() / spread
+    };
+
+    if !(scale > 0.0 && scale.is_finite()) {
+        return None;
+    }
+
+    Some(Sim3::new(rotation, centroid_b - (rotation * centroid_a) * scale, scale))
+}
+
+/// Map point seen by two keyframes, with its position in each camera frame
+/// and the keypoint observing it in each
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sim3Match {
+    pub points: [Vec3; 2],
+    pub pixels: [Vec2; 2],
+    /// Variance of each keypoint position in pixels squared, e.g. by octave
+    pub sigma2: [f64; 2]
+}
+
+/// Matches between two keyframes for `ransac`. Models are `S12`, which maps
+/// the second camera frame to the first. Residuals are the larger of the
+/// squared reprojection errors in both keyframes divided by the variance of
+/// the keypoint, so both ends of the match must agree.
+pub struct Sim3Estimator<'a> {
+    pub cameras: [&'a dyn CameraModel; 2],
+    pub matches: &'a [Sim3Match],
+    pub fixed_scale: bool
+}
+
+impl Sim3Estimator<'_> {
+    fn reprojection_error(&self, camera: usize, point: &Vec3, m: &Sim3Match) -> f64 {
+        if point.z() <= 0.0 {
+            return f64::INFINITY;
+        }
+
+        (self.cameras[camera].project(point) - m.pixels[camera]).norm_squared() / m.sigma2[camera]
+    }
+}
+
+impl Estimator for Sim3Estimator<'_> {
+    type Model = Sim3;
+
+    const SAMPLE_SIZE: usize = 3;
+
+    fn data_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    fn fit(&self, sample: &[usize]) -> Vec<Sim3> {
+        let points_1: Vec<Vec3> = sample.iter().map(|&i| self.matches[i].points[0]).collect();
+        let points_2: Vec<Vec3> = sample.iter().map(|&i| self.matches[i].points[1]).collect();
+        horn(&points_2, &points_1, self.fixed_scale).into_iter().collect()
+    }
+
+    fn residual(&self, s12: &Sim3, index: usize) -> f64 {
+        let m = &self.matches[index];
+        let error_1 = self.reprojection_error(0, &s12.transform(&m.points[1]), m);
+        let error_2 = self.reprojection_error(1, &s12.inverse().transform(&m.points[0]), m);
+        error_1.max(error_2)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Sim3Config {
+    /// Residuals are chi-squared distributed with two degrees of freedom,
+    /// so the threshold defaults to their 99% quantile as in ORB-SLAM
+    pub ransac: RansacConfig,
+    /// Gauss-Newton iterations of the final refinement
+    pub refine_iterations: usize,
+    /// Solutions with fewer inliers are rejected
+    pub min_inliers: usize,
+    /// Estimate a rigid transformation, for maps whose scale is observable.
+    /// Loop closing always does for stereo and RGB-D keyframes.
+    pub fixed_scale: bool
+}
+
+impl Default for Sim3Config {
+    fn default() -> Self {
+        // Values used by ORB-SLAM's loop closing
+        Self {
+            ransac: RansacConfig {
+                threshold: CHI2_2D_99,
+                max_iterations: 300,
+                ..Default::default()
+            },
+            refine_iterations: 10,
+            min_inliers: 20,
+            fixed_scale: false
+        }
+    }
+}
+
+pub struct Sim3Result {
+    /// Transformation from the second camera frame to the first
+    pub sim3: Sim3,
+    pub inliers: Vec<bool>,
+    pub inlier_count: usize
+}
+
+/// Minimises the reprojection errors of the masked matches in both
+/// keyframes over `S12` with Gauss-Newton, on the left perturbation
+/// `[omega, v, sigma]`
+pub fn refine_sim3(estimator: &Sim3Estimator, mask: &[bool], mut s12: Sim3, iterations: usize) -> Sim3 {
+    for _ in 0..iterations {
+        let s21 = s12.inverse();
+        let mut hessian = Mat7::zeros();
+        let mut gradient = Vec7::zeros();
+
+        for (m, _) in estimator.matches.iter().zip(mask).filter(|(_, &inlier)| inlier) {
+            let point_1 = s12.transform(&m.points[1]);
+            let point_2 = s21.transform(&m.points[0]);
+
+            if point_1.z() <= 0.0 || point_2.z() <= 0.0 {
+                continue;
+            }
+
+            // Derivatives of both camera frame points with respect to the perturbation
+            let mut motion_1 = Matrix::<3, 7>::zeros();
+            motion_1.set_block(0, 0, &(-point_1.hat()));
+            motion_1.set_block(0, 3, &Mat3::identity());
+            motion_1.set_column(6, &point_1);
+
+            let mut motion_2 = Matrix::<3, 7>::zeros();
+            motion_2.set_block(0, 0, &m.points[0].hat());
+            motion_2.set_block(0, 3, &(-Mat3::identity()));
+            motion_2.set_column(6, &(-m.points[0]));
+            let motion_2 = s21.rotation.matrix() * motion_2 * s21.scale;
+
+            let terms = [
+                (0, point_1, motion_1),
+                (1, point_2, motion_2)
+            ];
+
+            for (camera, point, mut motion) in terms {
+                if estimator.fixed_scale {
+                    motion.set_column(6, &Vec3::zeros());
+                }
+
+                let residual = estimator.cameras[camera].project(&point) - m.pixels[camera];
+                let jacobian = estimator.cameras[camera].project_jacobian(&point) * motion;
+                let weight = 1.0 / m.sigma2[camera];
+
+                hessian += jacobian.transpose() * jacobian * weight;
+                gradient += jacobian.transpose() * residual * weight;
+            }
+        }
+
+        if estimator.fixed_scale {
+            hessian[(6, 6)] = 1.0;
+        }
+
+        let Some(step) = hessian.cholesky_solve(&(-gradient)) else {
+            break;
+        };
+
+        s12 = Sim3::exp(&step) * s12;
+
+        if step.norm_squared() < 1e-20 {
+            break;
+        }
+    }
+
+    s12
+}
+
+/// Similarity between two keyframes from matched map points: Horn's
+/// method inside RANSAC, followed by Gauss-Newton refinement of the
+/// reprojection errors of the inliers. Results only depend on the state of
+/// `rng`.
+pub fn solve_sim3(
+    cameras: [&dyn CameraModel; 2],
+    matches: &[Sim3Match],
+    config: &Sim3Config,
+    rng: &mut Rng
+) -> Option<Sim3Result> {
+    let estimator = Sim3Estimator { cameras, matches, fixed_scale: config.fixed_scale };
+    let result = ransac(&estimator, &config.ransac, rng)?;
+
+    if result.inlier_count < config.min_inliers {
+        return None;
+    }
+
+    // Refine, then re-classify with the refined similarity and refine again
+    let sim3 = refine_sim3(&estimator, &result.inliers, result.model, config.refine_iterations);
+
+    let inliers: Vec<bool> = (0..matches.len())
+        .map(|i| estimator.residual(&sim3, i) < config.ransac.threshold)
+        .collect();
+    let inlier_count = inliers.iter().filter(|&&inlier| inlier).count();
+
+    if inlier_count < config.min_inliers {
+        return None;
+    }
+
+    let sim3 = refine_sim3(&estimator, &inliers, sim3, config.refine_iterations);
+
+    Some(Sim3Result { sim3, inliers, inlier_count })
+}
@@ -0,0 +1,299 @@
+use std::sync::Arc;
+
+use crate::camera::CameraModel;
+use crate::frame::Frame;
+use crate::geometry::SE3;
+use crate::image::Image;
+use crate::map::{KeyFrameId, Map};
+use crate::matcher::hamming_distance;
+use crate::orb::{OrbConfig, OrbProgram};
+
+pub struct StereoConfig {
+    /// Keypoints closer than this many baselines are close points
+    pub close_depth_factor: f64,
+    /// Row matches with a larger Hamming distance are rejected
+    pub max_distance: u32,
+    /// Half size of the SAD window, in pixels of the keypoint's octave
+    pub sad_half_window: i32,
+    /// The SAD window is slid this many pixels either side of the
+    /// descriptor match
+    pub sad_search: i32,
+    /// Matches whose best SAD exceeds this factor times the median are
+    /// rejected
+    pub sad_outlier_factor: f64,
+    /// Fewer keypoints with depth than this and stereo initialisation waits
+    pub min_initial_points: usize
+}
+
+impl Default for StereoConfig {
+    fn default() -> Self {
+        // Values used by ORB-SLAM's stereo frames
+        Self {
+            close_depth_factor: 40.0,
+            max_distance: 75,
+            sad_half_window: 5,
+            sad_search: 5,
+            sad_outlier_factor: 1.5 * 1.4,
+            min_initial_points: 500
+        }
+    }
+}
+
+/// CPU copy of the un-blurred image hierarchy of `OrbProgram`, in which
+/// stereo matches are refined to sub-pixel precision
+pub struct GrayPyramid {
+    pub levels: Vec<Image<f32>>
+}
+
+impl GrayPyramid {
+    /// Converts to grayscale with the weights of the grayscale shader, then
+    /// each level averages 2x2 pixels of the previous one like a mip level
+    pub fn new(image: &Image<u8>, depth: u32) -> Self {
+        let data = image.data.chunks_exact(image.channels as usize)
+            .map(|pixel| match *pixel {
+                [g] | [g, _] => g as f32 / 255.0,
+                [r, g, b] | [r, g, b, _] => (0.229 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) / 255.0,
+                _ => unreachable!()
+            })
+            .collect();
+
+        let mut levels = vec![Image { width: image.width, height: image.height, channels: 1, data }];
+
+        for _ in 1..depth {
+            let previous = levels.last().unwrap();
+            let (width, height) = ((previous.width / 2).max(1), (previous.height / 2).max(1));
+
+            let sample = |x: u32, y: u32| {
+                let (x, y) = (x.min(previous.width - 1), y.min(previous.height - 1));
+                previous.data[(y * previous.width + x) as usize]
+            };
+
+            let data = (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| 0.25 * (sample(2 * x, 2 * y) + sample(2 * x + 1, 2 * y) + sample(2 * x, 2 * y + 1) + sample(2 * x + 1, 2 * y + 1)))
+                .collect();
+
+            levels.push(Image { width, height, channels: 1, data });
+        }
+
+        Self { levels }
+    }
+
+    /// Sum of absolute differences between the windows centred at `a` in
+    /// `self` and at `b` in `other`, each relative to its centre intensity.
+    /// None when either window leaves its image.
+    fn sad(&self, other: &GrayPyramid, level: usize, a: [i32; 2], b: [i32; 2], half_window: i32) -> Option<f32> {
+        let (image_a, image_b) = (&self.levels[level], &other.levels[level]);
+
+        for (image, [x, y]) in [(image_a, a), (image_b, b)] {
+            if x - half_window < 0 || y - half_window < 0 || x + half_window >= image.width as i32 || y + half_window >= image.height as i32 {
+                return None;
+            }
+        }
+
+        let at = |image: &Image<f32>, x: i32, y: i32| image.data[(y as u32 * image.width + x as u32) as usize];
+        let (centre_a, centre_b) = (at(image_a, a[0], a[1]), at(image_b, b[0], b[1]));
+
+        let mut sum = 0.0;
+
+        for dy in -half_window..=half_window {
+            for dx in -half_window..=half_window {
+                let value_a = at(image_a, a[0] + dx, a[1] + dy) - centre_a;
+                let value_b = at(image_b, b[0] + dx, b[1] + dy) - centre_b;
+                sum += (value_a - value_b).abs();
+            }
+        }
+
+        Some(sum)
+    }
+}
+
+/// Right image x coordinate of each left keypoint of a rectified stereo
+/// pair, following ORB-SLAM's ComputeStereoMatches. Right keypoints are
+/// candidates for the left keypoints on the rows within two octave scales
+/// of theirs, at a neighbouring octave and with a disparity between zero
+/// and the one of a point one baseline away. The best descriptor match is
+/// refined by sliding a SAD window along the row at the keypoint's octave
+/// and fitting a parabola to the best three positions. Matches with an
+/// unusually large SAD are discarded.
+pub fn match_stereo(
+    config: &StereoConfig,
+    left: &Frame,
+    right: &Frame,
+    left_images: &GrayPyramid,
+    right_images: &GrayPyramid
+) -> Vec<Option<f64>> {
+    let scale = &left.scale;
+    let height = left.image_size.height as usize;
+
+    // Right keypoints that may match a left keypoint on each row
+    let mut rows: Vec<Vec<usize>> = vec![Vec::new(); height];
+
+    for (i, keypoint) in right.keypoints.iter().enumerate() {
+        let radius = 2.0 * scale.scale_factors[right.octave(i) as usize];
+        let min_row = (keypoint.y() - radius).floor().max(0.0) as usize;
+        let max_row = ((keypoint.y() + radius).ceil() as usize).min(height - 1);
+
+        for row in &mut rows[min_row..=max_row] {
+            row.push(i);
+        }
+    }
+
+    // A point one baseline away has a disparity of fx
+    let max_disparity = left.camera.pinhole().fx;
+
+    let mut right_x = vec![None; left.len()];
+    let mut sads: Vec<(f32, usize)> = Vec::new();
+
+    for (i, keypoint) in left.keypoints.iter().enumerate() {
+        let octave = left.octave(i);
+        let (u, v) = (keypoint.x(), keypoint.y());
+
+        let Some(candidates) = rows.get(v as usize) else {
+            continue;
+        };
+
+        let (min_u, max_u) = (u - max_disparity, u);
+
+        let best = candidates.iter()
+            .filter(|&&j| right.octave(j).abs_diff(octave) <= 1)
+            .filter(|&&j| (min_u..=max_u).contains(&right.keypoints[j].x()))
+            .map(|&j| (hamming_distance(&left.descriptors[i], &right.descriptors[j]), j))
+            .min();
+
+        let Some((_, j)) = best.filter(|&(distance, _)| distance < config.max_distance) else {
+            continue;
+        };
+
+        // Sub-pixel refinement at the octave of the left keypoint
+        let level = octave as usize;
+        let inv_scale = scale.inv_scale_factors[level];
+        let left_centre = [(u * inv_scale).round() as i32, (v * inv_scale).round() as i32];
+        let right_u = (right.keypoints[j].x() * inv_scale).round() as i32;
+
+        let search = config.sad_search;
+        let window_sads: Option<Vec<f32>> = (-search..=search)
+            .map(|offset| left_images.sad(right_images, level, left_centre, [right_u + offset, left_centre[1]], config.sad_half_window))
+            .collect();
+
+        let Some(window_sads) = window_sads else {
+            continue;
+        };
+
+        let (best_index, &best_sad) = window_sads.iter()
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+
+        // The minimum must be inside the search range for a parabola fit
+        if best_index == 0 || best_index == window_sads.len() - 1 {
+            continue;
+        }
+
+        let (before, at, after) = (window_sads[best_index - 1] as f64, best_sad as f64, window_sads[best_index + 1] as f64);
+        let curvature = before + after - 2.0 * at;
+
+        if curvature <= 0.0 {
+            continue;
+        }
+
+        let delta = (before - after) / (2.0 * curvature);
+
+        if delta.abs() > 1.0 {
+            continue;
+        }
+
+        let best_u = scale.scale_factors[level] * (right_u as f64 + (best_index as i32 - search) as f64 + delta);
+        let disparity = u - best_u;
+
+        if disparity < 0.0 || disparity >= max_disparity {
+            continue;
+        }
+
+        // Points at infinity get a tiny disparity
+        right_x[i] = Some(if disparity > 0.0 { best_u } else { u - 0.01 });
+        sads.push((best_sad, i));
+    }
+
+    if sads.is_empty() {
+        return right_x;
+    }
+
+    sads.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let median = sads[sads.len() / 2].0 as f64;
+    let threshold = config.sad_outlier_factor * median;
+
+    for &(_, i) in sads.iter().rev().take_while(|&&(sad, _)| sad as f64 > threshold) {
+        right_x[i] = None;
+    }
+
+    right_x
+}
+
+/// Feature extraction from both images of a rectified stereo pair, with one
+/// `OrbProgram` per image sharing a device. Stereo matches are refined in
+/// CPU copies of the input images, so the images must already be
+/// rectified rather than remapped by the programs.
+pub struct StereoProgram {
+    pub config: StereoConfig,
+    /// Distance between the optical centres of the two cameras, in metres
+    pub baseline: f64,
+    pub left: OrbProgram,
+    pub right: OrbProgram
+}
+
+impl StereoProgram {
+    /// The right program is created on the device of `left`
+    pub fn new(config: StereoConfig, baseline: f64, left: OrbProgram, right_config: OrbConfig) -> Self {
+        assert!(left.config.undistortion.is_empty() && right_config.undistortion.is_empty());
+
+        let right = OrbProgram::new(right_config, &left.compute);
+        Self { config, baseline, left, right }
+    }
+
+    /// Extracts features from both images, in the layout expected by
+    /// `OrbProgram::write_input_image`, and sets the depths of the left
+    /// keypoints matched in the right image. `camera` is the rectified
+    /// pinhole camera of the left image.
+    pub fn extract_frame(&self, id: u64, timestamp: f64, camera: Arc<dyn CameraModel>, left: &Image<u8>, right: &Image<u8>) -> Frame {
+        self.left.write_input_image(&left.data);
+        self.right.write_input_image(&right.data);
+
+        let mut frame = self.left.extract_frame(id, timestamp, camera.clone());
+        let right_frame = self.right.extract_frame(id, timestamp, camera);
+
+        let depth = self.left.config.hierarchy_depth;
+        let right_x = match_stereo(&self.config, &frame, &right_frame, &GrayPyramid::new(left, depth), &GrayPyramid::new(right, depth));
+
+        frame.set_stereo(self.baseline, self.config.close_depth_factor * self.baseline, right_x);
+        frame
+    }
+}
+
+/// Starts a map from a single stereo or RGB-D frame, as ORB-SLAM does: the
+/// frame becomes the first keyframe at the world origin, and every keypoint
+/// with a depth creates a map point. None when fewer than
+/// `min_initial_points` keypoints have a depth. The BoW of the frame should
+/// be computed first, so the keyframe is indexed for relocalisation.
+pub fn initialize_stereo_map(config: &StereoConfig, map: &mut Map, mut frame: Frame) -> Option<KeyFrameId> {
+    let with_depth = (0..frame.len()).filter(|&i| frame.depth(i).is_some()).count();
+
+    if with_depth < config.min_initial_points {
+        return None;
+    }
+
+    frame.pose = Some(SE3::identity());
+    frame.map_points.fill(None);
+
+    let id = map.add_keyframe(frame);
+
+    for i in 0..map.keyframe(id).unwrap().frame.len() {
+        if let Some(position) = map.keyframe(id).unwrap().frame.unproject_stereo(i) {
+            let point = map.add_point(position, id, i);
+            map.compute_distinctive_descriptor(point);
+        }
+    }
+
+    Some(id)
+}
@@ -0,0 +1,689 @@
+use std::sync::Arc;
+
+use crate::camera::CameraModel;
+use crate::frame::Frame;
+use crate::geometry::SE3;
+use crate::imu::{inertial_keyframes, ImuBias, ImuCalibration, ImuMeasurement, ImuState, NavState, Preintegrated};
+use crate::keyframe_database::relocalisation_candidates;
+use crate::linalg::{Vec2, Vec3};
+use crate::map::{KeyFrame, KeyFrameId, Map, MapPointId};
+use crate::matcher::{filter_by_rotation, match_by_nodes, Match, MatcherConfig, SearchWindow, WindowMatch, NO_MATCH};
+use crate::optimizer::{optimize_frame_pose, optimize_inertial_frame_pose, InertialPrior, PoseOptimizerConfig};
+use crate::pnp::{solve_pnp, PnpConfig};
+use crate::random::Rng;
+use crate::vocabulary::{FeatureVector, Vocabulary};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackingState {
+    /// No map to track against yet
+    NotInitialised,
+    Ok,
+    /// The frame could not be tracked against the map, and its pose was
+    /// estimated from the IMU alone. Tracking is lost once this lasts
+    /// longer than `TrackerConfig::recently_lost_time`.
+    RecentlyLost,
+    /// The last frame could not be tracked, and following frames are
+    /// relocalised against the whole map
+    Lost,
+    /// The frame was localised again after tracking was lost, and is
+    /// tracked normally from the next frame on
+    Relocalised
+}
+
+pub struct TrackerConfig {
+    /// Search radius around projected points, in pixels at octave 0. It is
+    /// scaled by the scale factor of the octave the point was observed at.
+    pub projection_radius: f64,
+    /// Matches by projection with a larger Hamming distance are rejected
+    pub projection_max_distance: u32,
+    /// Fewer matches by projection than this and the search is repeated
+    /// with twice the radius, then abandoned
+    pub min_projection_matches: usize,
+    /// Matching against the reference keyframe
+    pub bow_matcher: MatcherConfig,
+    /// Fewer BoW matches than this and reference keyframe tracking fails
+    pub min_bow_matches: usize,
+    /// Fewer inliers than this after pose optimisation and tracking fails
+    pub min_inliers: usize,
+    /// Matching against relocalisation candidates
+    pub relocalisation_matcher: MatcherConfig,
+    /// Pose of a lost frame from the map points of a candidate
+    pub pnp: PnpConfig,
+    /// Search radius of the guided search for more points of a candidate
+    /// when relocalising, in pixels at octave 0
+    pub relocalisation_radius: f64,
+    /// Relocalisation succeeds with at least this many inliers
+    pub relocalisation_min_inliers: usize,
+    /// Level of the vocabulary at which features are grouped, counted up
+    /// from the words
+    pub levels_up: u32,
+    pub optimizer: PoseOptimizerConfig,
+    /// Seconds visual-inertial tracking continues from the IMU alone
+    /// before it is lost
+    pub recently_lost_time: f64
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        // Values used by ORB-SLAM's monocular tracking
+        Self {
+            projection_radius: 15.0,
+            projection_max_distance: 100,
+            min_projection_matches: 20,
+            bow_matcher: MatcherConfig { max_distance: 50, ratio: 0.7 },
+            min_bow_matches: 15,
+            min_inliers: 10,
+            relocalisation_matcher: MatcherConfig { max_distance: 50, ratio: 0.75 },
+            pnp: PnpConfig::default(),
+            relocalisation_radius: 10.0,
+            relocalisation_min_inliers: 50,
+            levels_up: 4,
+            optimizer: PoseOptimizerConfig::default(),
+            recently_lost_time: 5.0
+        }
+    }
+}
+
+/// Frame to frame tracking against the map. The pose of each frame is
+/// predicted with a constant velocity model and refined against the map
+/// points seen in the last frame. When that fails the frame is matched to
+/// the reference keyframe through the vocabulary instead. Once lost, frames
+/// are relocalised against keyframes from the keyframe database.
+///
+/// With an IMU, see `with_imu`, the measurements between frames are
+/// preintegrated. Once the map has a gravity the pose is predicted from
+/// them instead, and optimised together with the velocity under the
+/// inertial constraint, which keeps frames with few matches tracked.
+pub struct Tracker {
+    pub config: TrackerConfig,
+    vocabulary: Arc<Vocabulary>,
+    /// Last frame to current frame motion
+    velocity: Option<SE3>,
+    last_frame: Option<Frame>,
+    reference_keyframe: Option<KeyFrameId>,
+    /// Map points predicted to be visible in the last frame
+    visible_points: Vec<MapPointId>,
+    state: TrackingState,
+    /// Sampling of relocalisation RANSAC
+    rng: Rng,
+    imu: Option<ImuTracking>
+}
+
+/// Inertial state of a visual-inertial tracker
+struct ImuTracking {
+    calibration: ImuCalibration,
+    /// Measurements not integrated yet, in time order
+    measurements: Vec<ImuMeasurement>,
+    /// Bias of the last keyframe, which frames are integrated with
+    bias: ImuBias,
+    /// Measurements from the last keyframe to the last frame, `None`
+    /// before the first keyframe
+    since_keyframe: Option<Preintegrated>,
+    /// Frame id of the last keyframe
+    keyframe: Option<u64>,
+    /// Body velocity of the last frame, once known in a map with gravity
+    velocity: Option<Vec3>,
+    /// Whether a velocity was known since the map has a gravity
+    anchored: bool,
+    /// Whether the last frame was tracked against a map with gravity
+    tracked_inertial: bool,
+    /// Timestamp of the last frame, which measurements are integrated from
+    last_timestamp: Option<f64>,
+    /// Timestamp of the last frame tracked against the map
+    last_tracked: f64
+}
+
+impl Tracker {
+    pub fn new(config: TrackerConfig, vocabulary: Arc<Vocabulary>) -> Self {
+        Self {
+            config,
+            vocabulary,
+            velocity: None,
+            last_frame: None,
+            reference_keyframe: None,
+            visible_points: Vec::new(),
+            state: TrackingState::NotInitialised,
+            rng: Rng::new(0),
+            imu: None
+        }
+    }
+
+    /// Tracks with the measurements of an IMU, which must be added with
+    /// `add_imu_measurement` before the frames they precede
+    pub fn with_imu(mut self, calibration: ImuCalibration) -> Self {
+        self.imu = Some(ImuTracking {
+            calibration,
+            measurements: Vec::new(),
+            bias: ImuBias::default(),
+            since_keyframe: None,
+            keyframe: None,
+            velocity: None,
+            anchored: false,
+            tracked_inertial: false,
+            last_timestamp: None,
+            last_tracked: 0.0
+        });
+        self
+    }
+
+    pub fn add_imu_measurement(&mut self, measurement: ImuMeasurement) {
+        if let Some(imu) = &mut self.imu {
+            imu.measurements.push(measurement);
+        }
+    }
+
+    /// Called when the last frame was inserted into the map as a keyframe,
+    /// so the preintegration of the next keyframes starts from it
+    pub fn keyframe_inserted(&mut self) {
+        let id = self.last_frame.as_ref().map(|frame| frame.id);
+
+        if let Some(imu) = &mut self.imu {
+            imu.since_keyframe = Some(Preintegrated::new(imu.calibration, imu.bias));
+            imu.keyframe = id;
+        }
+    }
+
+    pub fn state(&self) -> TrackingState {
+        self.state
+    }
+
+    /// Last successfully tracked frame, with its pose and map point matches
+    pub fn last_frame(&self) -> Option<&Frame> {
+        self.last_frame.as_ref()
+    }
+
+    /// Map points of the last and previous frame that project inside the
+    /// last frame. Together with its matched points these update the
+    /// statistics used to cull map points, see `Map::increase_visible`.
+    pub fn visible_points(&self) -> &[MapPointId] {
+        &self.visible_points
+    }
+
+    pub fn reference_keyframe(&self) -> Option<KeyFrameId> {
+        self.reference_keyframe
+    }
+
+    /// Starts tracking from a frame with a pose whose keypoints are
+    /// associated with map points, usually the second frame of the
+    /// initialisation, which became `reference_keyframe`.
+    pub fn initialize(&mut self, frame: Frame, reference_keyframe: KeyFrameId) {
+        assert!(frame.pose.is_some());
+
+        self.velocity = None;
+        self.last_frame = Some(frame);
+        self.reference_keyframe = Some(reference_keyframe);
+        self.state = TrackingState::Ok;
+
+        if let Some(imu) = &mut self.imu {
+            imu.last_timestamp = self.last_frame.as_ref().map(|frame| frame.timestamp);
+        }
+
+        self.keyframe_inserted();
+    }
+
+    /// Keyframes must have their BoW computed to be used as reference
+    pub fn set_reference_keyframe(&mut self, keyframe: KeyFrameId) {
+        self.reference_keyframe = Some(keyframe);
+    }
+
+    /// Estimates the pose of `frame` and its map point matches. On success
+    /// it becomes the last frame; on failure its pose is left unset.
+    /// Visual-inertial frames also get their inertial state.
+    pub fn track(&mut self, mut frame: Frame, map: &Map) -> (Frame, TrackingState) {
+        let since_frame = self.integrate_imu(&mut frame, map);
+
+        if self.state == TrackingState::Lost {
+            return self.relocalise(frame, map);
+        }
+
+        let Some(last) = &self.last_frame else {
+            return (frame, TrackingState::NotInitialised);
+        };
+
+        let last_pose = last.pose.expect("last frame has no pose");
+        let prior = since_frame.as_ref().and_then(|since_frame| self.inertial_prior(since_frame, last_pose, map));
+
+        let mut tracked = false;
+        let mut start = last_pose;
+
+        if let Some(prior) = &prior {
+            let (pose, velocity) = prior.predict();
+            start = pose;
+            frame.pose = Some(pose);
+            frame.imu.as_mut().unwrap().velocity = velocity;
+            tracked = self.track_with_motion_model(&mut frame, last, map, Some(prior));
+        } else if let Some(velocity) = self.velocity {
+            frame.pose = Some(velocity * last_pose);
+            tracked = self.track_with_motion_model(&mut frame, last, map, None);
+        }
+
+        // Estimate constrained by the IMU, kept should the map be lost
+        let inertial = prior.as_ref().and(frame.pose).zip(frame.imu.as_ref().map(|imu| imu.velocity));
+
+        if !tracked {
+            frame.pose = Some(start);
+            tracked = self.track_reference_keyframe(&mut frame, map, prior.as_ref());
+        }
+
+        let recently_lost = self.imu.as_ref()
+            .is_some_and(|imu| frame.timestamp - imu.last_tracked <= self.config.recently_lost_time);
+
+        if tracked {
+            self.visible_points = visible_points(&frame, last, map);
+            self.state = TrackingState::Ok;
+        } else if let (Some((pose, velocity)), true) = (inertial, recently_lost) {
+            frame.pose = Some(pose);
+            frame.imu.as_mut().unwrap().velocity = velocity;
+            frame.map_points.fill(None);
+            self.visible_points.clear();
+            self.state = TrackingState::RecentlyLost;
+        } else {
+            frame.pose = None;
+            frame.map_points.fill(None);
+            self.visible_points.clear();
+            self.state = TrackingState::Lost;
+        }
+
+        self.velocity = frame.pose.map(|pose| pose * last_pose.inverse());
+        self.update_imu(&mut frame, map, last_pose, since_frame.as_ref(), tracked);
+
+        if frame.pose.is_some() {
+            self.last_frame = Some(frame.clone());
+        }
+
+        (frame, self.state)
+    }
+
+    /// Preintegrates the measurements up to `frame`, which gets the
+    /// preintegration from the last keyframe, and returns those since the
+    /// last frame. Once the map has a gravity, the first inertial state is
+    /// predicted from the keyframe the map was initialised with, for which
+    /// monocular maps were also scaled.
+    fn integrate_imu(&mut self, frame: &mut Frame, map: &Map) -> Option<Preintegrated> {
+        let imu = self.imu.as_mut()?;
+
+        let mut since_frame = Preintegrated::new(imu.calibration, imu.bias);
+
+        if let Some(start) = imu.last_timestamp {
+            since_frame.integrate_interval(&imu.measurements, start, frame.timestamp);
+        }
+
+        // Keep the measurement holding at the frame
+        let holding = imu.measurements.iter().rposition(|m| m.timestamp <= frame.timestamp).unwrap_or(0);
+        imu.measurements.drain(..holding);
+        imu.last_timestamp = Some(frame.timestamp);
+
+        let newest = inertial_keyframes(map).last().and_then(|&id| map.keyframe(id));
+
+        if let (Some(gravity), Some(keyframe)) = (map.gravity(), newest) {
+            let state = keyframe.frame.imu.as_ref().unwrap();
+            let since_keyframe = imu.since_keyframe.as_ref().filter(|_| imu.keyframe == Some(keyframe.frame.id));
+
+            if let (false, Some(since_keyframe), Some(last)) = (imu.anchored, since_keyframe, &mut self.last_frame) {
+                let camera_from_body = &imu.calibration.camera_from_body;
+                let start = NavState::from_camera_pose(&keyframe.frame.pose.unwrap(), camera_from_body, state.velocity);
+                let predicted = since_keyframe.predict(&start, &state.bias, &gravity);
+
+                last.pose = Some(predicted.camera_pose(camera_from_body));
+                imu.velocity = Some(predicted.velocity);
+                imu.anchored = true;
+                self.velocity = None;
+            }
+
+            imu.bias = state.bias;
+        }
+
+        if let Some(since_keyframe) = &mut imu.since_keyframe {
+            since_keyframe.append(&since_frame);
+        }
+
+        frame.imu = Some(ImuState {
+            velocity: Vec3::zeros(),
+            bias: imu.bias,
+            preintegration: imu.since_keyframe.clone()
+        });
+
+        Some(since_frame)
+    }
+
+    /// Inertial constraint from the last frame, once its velocity is known
+    fn inertial_prior(&self, since_frame: &Preintegrated, last_pose: SE3, map: &Map) -> Option<InertialPrior> {
+        let imu = self.imu.as_ref()?;
+
+        Some(InertialPrior {
+            pose: last_pose,
+            velocity: imu.velocity?,
+            bias: imu.bias,
+            gravity: map.gravity()?,
+            preintegration: since_frame.clone()
+        })
+    }
+
+    /// Keeps the velocity of a frame with a pose, optimised with it or
+    /// else estimated from the poses of two frames tracked against a map
+    /// with gravity
+    fn update_imu(&mut self, frame: &mut Frame, map: &Map, last_pose: SE3, since_frame: Option<&Preintegrated>, tracked: bool) {
+        let (Some(imu), Some(state), Some(since_frame)) = (&mut self.imu, &mut frame.imu, since_frame) else {
+            return;
+        };
+
+        let gravity = map.gravity();
+
+        imu.velocity = match (frame.pose, gravity) {
+            (Some(_), Some(_)) if imu.velocity.is_some() => Some(state.velocity),
+            (Some(pose), Some(gravity)) if tracked && imu.tracked_inertial => since_frame.end_velocity(&last_pose, &pose, &imu.bias, &gravity),
+            _ => None
+        };
+
+        state.velocity = imu.velocity.unwrap_or_default();
+        imu.anchored |= imu.velocity.is_some();
+        imu.tracked_inertial = tracked && gravity.is_some();
+
+        if tracked {
+            imu.last_tracked = frame.timestamp;
+        }
+    }
+
+    fn relocalise(&mut self, mut frame: Frame, map: &Map) -> (Frame, TrackingState) {
+        match self.relocalise_frame(&mut frame, map) {
+            Some(keyframe) => {
+                self.visible_points = visible_points(&frame, &keyframe.frame, map);
+                self.reference_keyframe = Some(keyframe.id);
+                self.velocity = None;
+                self.last_frame = Some(frame.clone());
+                self.state = TrackingState::Relocalised;
+
+                if let Some(imu) = &mut self.imu {
+                    imu.tracked_inertial = map.gravity().is_some();
+                    imu.last_tracked = frame.timestamp;
+                }
+            },
+            None => {
+                frame.pose = None;
+                frame.map_points.fill(None);
+            }
+        }
+
+        (frame, self.state)
+    }
+
+    /// Relocalisation as in ORB-SLAM: the map points of each candidate
+    /// keyframe are matched through the vocabulary, a pose is estimated with
+    /// PnP RANSAC and optimised, and if too few inliers remain more points of
+    /// the candidate are searched by projection. Returns the keyframe the
+    /// frame was localised against.
+    fn relocalise_frame<'a>(&mut self, frame: &mut Frame, map: &'a Map) -> Option<&'a KeyFrame> {
+        frame.compute_bow(&self.vocabulary, self.config.levels_up);
+
+        let candidates = relocalisation_candidates(map, &self.vocabulary, frame.bow.as_ref()?);
+
+        for id in candidates {
+            let Some(keyframe) = map.keyframe(id) else {
+                continue;
+            };
+
+            let Some(mapped) = mapped_features(&keyframe.frame) else {
+                continue;
+            };
+
+            let matches = match_by_nodes(
+                &self.config.relocalisation_matcher,
+                &mapped,
+                &keyframe.frame.descriptors,
+                frame.features.as_ref().unwrap(),
+                &frame.descriptors
+            );
+
+            let matches: Vec<(Match, MapPointId, Vec3)> = filter_by_rotation(&matches, &keyframe.frame.corners, &frame.corners)
+                .into_iter()
+                .filter_map(|m| {
+                    let id = keyframe.frame.map_points[m.query as usize]?;
+                    Some((m, id, map.point(id)?.position))
+                })
+                .collect();
+
+            if matches.len() < self.config.min_bow_matches {
+                continue;
+            }
+
+            let points: Vec<Vec3> = matches.iter().map(|&(_, _, point)| point).collect();
+            let pixels: Vec<Vec2> = matches.iter().map(|(m, _, _)| frame.keypoints[m.train as usize]).collect();
+            let sigma2: Vec<f64> = matches.iter()
+                .map(|(m, _, _)| frame.scale.level_sigma2[frame.octave(m.train as usize) as usize])
+                .collect();
+
+            let Some(result) = solve_pnp(frame.camera.as_ref(), &points, &pixels, &sigma2, &self.config.pnp, &mut self.rng) else {
+                continue;
+            };
+
+            frame.pose = Some(result.pose);
+            frame.map_points.fill(None);
+
+            for ((m, point, _), _) in matches.iter().zip(&result.inliers).filter(|(_, &inlier)| inlier) {
+                frame.map_points[m.train as usize] = Some(*point);
+            }
+
+            let mut inliers = self.optimize(frame, map, None);
+
+            if inliers < self.config.min_inliers {
+                continue;
+            }
+
+            if inliers < self.config.relocalisation_min_inliers {
+                let found: Vec<MapPointId> = frame.map_points.iter().flatten().copied().collect();
+                let additional = search_by_projection(&self.config, frame, &keyframe.frame, map, self.config.relocalisation_radius);
+
+                for m in additional {
+                    let point = keyframe.frame.map_points[m.query as usize];
+
+                    if frame.map_points[m.train as usize].is_none() && point.is_some_and(|point| !found.contains(&point)) {
+                        frame.map_points[m.train as usize] = point;
+                    }
+                }
+
+                inliers = self.optimize(frame, map, None);
+            }
+
+            if inliers >= self.config.relocalisation_min_inliers {
+                return Some(keyframe);
+            }
+        }
+
+        None
+    }
+
+    /// With an inertial prior the pose is optimised however few points
+    /// match, so it is constrained by the IMU should tracking fail
+    fn track_with_motion_model(&self, frame: &mut Frame, last: &Frame, map: &Map, prior: Option<&InertialPrior>) -> bool {
+        let mut matches = search_by_projection(&self.config, frame, last, map, self.config.projection_radius);
+
+        if matches.len() < self.config.min_projection_matches {
+            matches = search_by_projection(&self.config, frame, last, map, 2.0 * self.config.projection_radius);
+        }
+
+        if matches.len() < self.config.min_projection_matches && prior.is_none() {
+            return false;
+        }
+
+        frame.map_points.fill(None);
+
+        for m in &matches {
+            frame.map_points[m.train as usize] = last.map_points[m.query as usize];
+        }
+
+        self.optimize(frame, map, prior) >= self.config.min_inliers && matches.len() >= self.config.min_projection_matches
+    }
+
+    fn track_reference_keyframe(&self, frame: &mut Frame, map: &Map, prior: Option<&InertialPrior>) -> bool {
+        let Some(keyframe) = self.reference_keyframe.and_then(|id| map.keyframe(id)) else {
+            return false;
+        };
+
+        // Only keyframe features with a map point are worth matching
+        let Some(mapped) = mapped_features(&keyframe.frame) else {
+            return false;
+        };
+
+        frame.compute_bow(&self.vocabulary, self.config.levels_up);
+
+        let matches = match_by_nodes(
+            &self.config.bow_matcher,
+            &mapped,
+            &keyframe.frame.descriptors,
+            frame.features.as_ref().unwrap(),
+            &frame.descriptors
+        );
+
+        let matches = filter_by_rotation(&matches, &keyframe.frame.corners, &frame.corners);
+
+        if matches.len() < self.config.min_bow_matches {
+            return false;
+        }
+
+        frame.map_points.fill(None);
+
+        for m in &matches {
+            frame.map_points[m.train as usize] = keyframe.frame.map_points[m.query as usize];
+        }
+
+        self.optimize(frame, map, prior) >= self.config.min_inliers
+    }
+
+    /// Refines the pose against the matched map points, and the velocity
+    /// too under an inertial prior, and drops the matches classified as
+    /// outliers. Returns the number of inliers.
+    fn optimize(&self, frame: &mut Frame, map: &Map, prior: Option<&InertialPrior>) -> usize {
+        let points: Vec<Option<Vec3>> = frame.map_points.iter()
+            .map(|id| id.and_then(|id| map.point(id)).map(|p| p.position))
+            .collect();
+
+        frame.outliers.fill(false);
+
+        let mut outliers = std::mem::take(&mut frame.outliers);
+
+        match prior {
+            Some(prior) => {
+                if let Some((_, velocity)) = optimize_inertial_frame_pose(&self.config.optimizer, frame, &points, &mut outliers, prior) {
+                    frame.imu.as_mut().unwrap().velocity = velocity;
+                }
+            },
+            None => {
+                optimize_frame_pose(&self.config.optimizer, frame, &points, &mut outliers);
+            }
+        }
+
+        for (i, &outlier) in outliers.iter().enumerate() {
+            if outlier || points[i].is_none() {
+                frame.map_points[i] = None;
+            }
+        }
+
+        outliers.fill(false);
+        frame.outliers = outliers;
+
+        frame.map_points.iter().filter(|id| id.is_some()).count()
+    }
+}
+
+/// Features of a frame restricted to the keypoints that observe map points
+pub(crate) fn mapped_features(frame: &Frame) -> Option<FeatureVector> {
+    let features = frame.features.as_ref()?;
+
+    let nodes = features.0.iter()
+        .map(|(&node, indices)| {
+            let mapped: Vec<u32> = indices.iter().copied().filter(|&i| frame.map_points[i as usize].is_some()).collect();
+            (node, mapped)
+        })
+        .filter(|(_, indices)| !indices.is_empty())
+        .collect();
+
+    Some(FeatureVector(nodes))
+}
+
+/// Map points of either frame that project inside `frame`
+fn visible_points(frame: &Frame, last: &Frame, map: &Map) -> Vec<MapPointId> {
+    let pose = frame.pose.unwrap();
+    let pinhole = frame.camera.pinhole();
+
+    let mut visible: Vec<MapPointId> = last.map_points.iter()
+        .chain(&frame.map_points)
+        .flatten()
+        .copied()
+        .filter(|&id| {
+            map.point(id).is_some_and(|point| {
+                let camera_point = pose.transform(&point.position);
+                camera_point.z() > 0.0 && frame.is_in_image(&pinhole.project(&camera_point))
+            })
+        })
+        .collect();
+
+    visible.sort_unstable();
+    visible.dedup();
+    visible
+}
+
+/// Matches the map points seen in `last` to keypoints of `frame` near
+/// their projection under the predicted `frame.pose`, searching the octave
+/// of the last observation and its neighbours. Each keypoint of `frame`
+/// keeps its closest match, and matches are filtered by rotation
+/// consistency. Query indexes `last`, train indexes `frame`.
+pub fn search_by_projection(config: &TrackerConfig, frame: &Frame, last: &Frame, map: &Map, radius: f64) -> Vec<Match> {
+    let pose = frame.pose.expect("frame has no predicted pose");
+    let pinhole = frame.camera.pinhole();
+    let max_octave = frame.scale.levels() - 1;
+
+    let mut queries = Vec::new();
+    let mut windows = Vec::new();
+    let mut descriptors = Vec::new();
+
+    for (i, id) in last.map_points.iter().enumerate() {
+        let Some(point) = id.and_then(|id| map.point(id)) else {
+            continue;
+        };
+
+        let camera_point = pose.transform(&point.position);
+
+        if camera_point.z() <= 0.0 {
+            continue;
+        }
+
+        let pixel = pinhole.project(&camera_point);
+
+        if !frame.is_in_image(&pixel) {
+            continue;
+        }
+
+        let octave = last.octave(i);
+
+        queries.push(i as u32);
+        descriptors.push(point.descriptor);
+        windows.push(SearchWindow {
+            x: pixel.x() as f32,
+            y: pixel.y() as f32,
+            radius: (radius * frame.scale.scale_factors[octave as usize]) as f32,
+            min_octave: octave.saturating_sub(1),
+            max_octave: (octave + 1).min(max_octave)
+        });
+    }
+
+    let mut window_matches = vec![WindowMatch::NONE; windows.len()];
+    frame.match_windows(&windows, &descriptors, &mut window_matches);
+
+    let mut best_for_train = vec![Match { query: NO_MATCH, train: NO_MATCH, distance: NO_MATCH }; frame.len()];
+
+    for (&query, m) in queries.iter().zip(&window_matches) {
+        if m.index == NO_MATCH || m.distance > config.projection_max_distance {
+            continue;
+        }
+
+        let best = &mut best_for_train[m.index as usize];
+
+        if m.distance < best.distance {
+            *best = Match { query, train: m.index, distance: m.distance };
+        }
+    }
+
+    let matches: Vec<Match> = best_for_train.into_iter().filter(|m| m.query != NO_MATCH).collect();
+
+    filter_by_rotation(&matches, &last.corners, &frame.corners)
+}
@@ -0,0 +1,554 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use crate::image;
+use crate::matcher::hamming_distance;
+use crate::orb::{CornerDescriptor, OrbProgram};
+use crate::random::Rng;
+
+pub type WordId = u32;
+pub type NodeId = u32;
+
+/// Word weighting, numbered as in DBoW2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeightingType {
+    TfIdf = 0,
+    Tf = 1,
+    Idf = 2,
+    Binary = 3
+}
+
+/// Similarity score between BoW vectors, numbered as in DBoW2.
+/// The Kullback-Leibler score is not supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScoringType {
+    L1Norm = 0,
+    L2Norm = 1,
+    ChiSquare = 2,
+    Bhattacharyya = 4,
+    DotProduct = 5
+}
+
+impl WeightingType {
+    fn from_index(index: u32) -> Option<Self> {
+        match index {
+            0 => Some(Self::TfIdf),
+            1 => Some(Self::Tf),
+            2 => Some(Self::Idf),
+            3 => Some(Self::Binary),
+            _ => None
+        }
+    }
+}
+
+impl ScoringType {
+    fn from_index(index: u32) -> Option<Self> {
+        match index {
+            0 => Some(Self::L1Norm),
+            1 => Some(Self::L2Norm),
+            2 => Some(Self::ChiSquare),
+            4 => Some(Self::Bhattacharyya),
+            5 => Some(Self::DotProduct),
+            _ => None
+        }
+    }
+}
+
+/// Sparse histogram of word weights, sorted by word id.
+#[derive(Clone, Debug, Default)]
+pub struct BowVector(pub BTreeMap<WordId, f64>);
+
+/// Direct index from vocabulary nodes at a fixed level to the indices
+/// of the features that descend through them.
+#[derive(Clone, Debug, Default)]
+pub struct FeatureVector(pub BTreeMap<NodeId, Vec<u32>>);
+
+struct Node {
+    parent: NodeId,
+    children: Vec<NodeId>,
+    descriptor: CornerDescriptor,
+    weight: f64,
+    word: Option<WordId>
+}
+
+/// Hierarchical k-medians tree over ORB descriptors, compatible with the
+/// DBoW2 vocabularies used by ORB-SLAM.
+pub struct Vocabulary {
+    k: u32,
+    levels: u32,
+    weighting: WeightingType,
+    scoring: ScoringType,
+    nodes: Vec<Node>,
+    words: Vec<NodeId>
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn root() -> Node {
+    Node {
+        parent: 0,
+        children: Vec::new(),
+        descriptor: CornerDescriptor { bits: [0; 32] },
+        weight: 0.0,
+        word: None
+    }
+}
+
+/// Bitwise majority vote, the binary descriptor equivalent of a median.
+fn majority(descriptors: &[&CornerDescriptor]) -> CornerDescriptor {
+    let mut counts = [0usize; 256];
+
+    for descriptor in descriptors {
+        for (i, count) in counts.iter_mut().enumerate() {
+            *count += ((descriptor.bits[i / 8] >> (i % 8)) & 1) as usize;
+        }
+    }
+
+    let half = descriptors.len().div_ceil(2);
+    let mut result = CornerDescriptor { bits: [0; 32] };
+
+    for (i, &count) in counts.iter().enumerate() {
+        if count >= half {
+            result.bits[i / 8] |= 1 << (i % 8);
+        }
+    }
+
+    result
+}
+
+impl Vocabulary {
+    /// An empty vocabulary with branching factor `k` and depth `levels`
+    pub fn new(k: u32, levels: u32, weighting: WeightingType, scoring: ScoringType) -> Self {
+        Self { k, levels, weighting, scoring, nodes: vec![root()], words: Vec::new() }
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn branching_factor(&self) -> u32 {
+        self.k
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.levels
+    }
+
+    /// Builds the tree from the descriptors of each training image, then
+    /// computes word weights from the same images.
+    pub fn create(&mut self, training: &[Vec<CornerDescriptor>], rng: &mut Rng) {
+        self.nodes = vec![root()];
+        self.words.clear();
+
+        let descriptors: Vec<&CornerDescriptor> = training.iter().flatten().collect();
+        self.k_medians_step(0, &descriptors, 1, rng);
+
+        for (id, node) in self.nodes.iter_mut().enumerate() {
+            if id > 0 && node.children.is_empty() {
+                node.word = Some(self.words.len() as WordId);
+                self.words.push(id as NodeId);
+            }
+        }
+
+        self.set_node_weights(training);
+    }
+
+    fn k_medians_step(&mut self, parent: NodeId, descriptors: &[&CornerDescriptor], level: u32, rng: &mut Rng) {
+        if descriptors.is_empty() {
+            return;
+        }
+
+        let k = self.k as usize;
+
+        let (clusters, groups) = if descriptors.len() <= k {
+            // Each descriptor becomes its own cluster
+            let clusters = descriptors.iter().map(|&&d| d).collect::<Vec<_>>();
+            let groups = (0..descriptors.len()).map(|i| vec![i]).collect::<Vec<_>>();
+            (clusters, groups)
+        } else {
+            let mut clusters = Self::seed_clusters(descriptors, k, rng);
+            let mut association = vec![usize::MAX; descriptors.len()];
+
+            loop {
+                let mut changed = false;
+
+                for (i, descriptor) in descriptors.iter().enumerate() {
+                    let nearest = (0..clusters.len())
+                        .min_by_key(|&c| hamming_distance(descriptor, &clusters[c]))
+                        .unwrap();
+
+                    if association[i] != nearest {
+                        association[i] = nearest;
+                        changed = true;
+                    }
+                }
+
+                let mut groups = vec![Vec::new(); clusters.len()];
+                for (i, &cluster) in association.iter().enumerate() {
+                    groups[cluster].push(i);
+                }
+
+                if !changed {
+                    break (clusters, groups);
+                }
+
+                clusters = groups.iter()
+                    .zip(clusters.iter())
+                    .map(|(group, &previous)| {
+                        if group.is_empty() {
+                            previous
+                        } else {
+                            majority(&group.iter().map(|&i| descriptors[i]).collect::<Vec<_>>())
+                        }
+                    })
+                    .collect();
+            }
+        };
+
+        let first_child = self.nodes.len() as NodeId;
+
+        for descriptor in clusters {
+            let id = self.nodes.len() as NodeId;
+            self.nodes.push(Node { parent, descriptor, ..root() });
+            self.nodes[parent as usize].children.push(id);
+        }
+
+        if level < self.levels {
+            for (i, group) in groups.iter().enumerate() {
+                if group.len() > 1 {
+                    let child_descriptors: Vec<_> = group.iter().map(|&j| descriptors[j]).collect();
+                    self.k_medians_step(first_child + i as NodeId, &child_descriptors, level + 1, rng);
+                }
+            }
+        }
+    }
+
+    /// k-means++ seeding with Hamming distances
+    fn seed_clusters(descriptors: &[&CornerDescriptor], k: usize, rng: &mut Rng) -> Vec<CornerDescriptor> {
+        let mut clusters = vec![*descriptors[rng.below(descriptors.len())]];
+        let mut min_distances: Vec<f64> = descriptors.iter()
+            .map(|d| hamming_distance(d, &clusters[0]) as f64)
+            .collect();
+
+        while clusters.len() < k {
+            let total: f64 = min_distances.iter().sum();
+
+            // All remaining descriptors coincide with a cluster
+            if total == 0.0 {
+                break;
+            }
+
+            let mut cut = rng.next_f64() * total;
+            let mut chosen = descriptors.len() - 1;
+
+            for (i, &distance) in min_distances.iter().enumerate() {
+                cut -= distance;
+                if cut <= 0.0 && distance > 0.0 {
+                    chosen = i;
+                    break;
+                }
+            }
+
+            let cluster = *descriptors[chosen];
+
+            for (distance, descriptor) in min_distances.iter_mut().zip(descriptors) {
+                *distance = distance.min(hamming_distance(descriptor, &cluster) as f64);
+            }
+
+            clusters.push(cluster);
+        }
+
+        clusters
+    }
+
+    fn set_node_weights(&mut self, training: &[Vec<CornerDescriptor>]) {
+        match self.weighting {
+            WeightingType::Tf | WeightingType::Binary => {
+                for &id in &self.words {
+                    self.nodes[id as usize].weight = 1.0;
+                }
+            },
+            WeightingType::Idf | WeightingType::TfIdf => {
+                // Number of images in which each word appears
+                let mut document_counts = vec![0usize; self.words.len()];
+
+                for image in training {
+                    let mut seen = vec![false; self.words.len()];
+
+                    for descriptor in image {
+                        let (word, _, _) = self.descend(descriptor, 0);
+                        seen[word as usize] = true;
+                    }
+
+                    for (count, seen) in document_counts.iter_mut().zip(seen) {
+                        *count += seen as usize;
+                    }
+                }
+
+                let image_count = training.len() as f64;
+
+                for (word, &count) in document_counts.iter().enumerate() {
+                    if count > 0 {
+                        let id = self.words[word];
+                        self.nodes[id as usize].weight = (image_count / count as f64).ln();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Word, word weight and the ancestor node `levels_up` levels above the
+    /// word. Every node without children must be a word.
+    fn descend(&self, descriptor: &CornerDescriptor, levels_up: u32) -> (WordId, f64, NodeId) {
+        let node_level = self.levels as i64 - levels_up as i64;
+
+        let mut id: NodeId = 0;
+        let mut ancestor: NodeId = 0;
+        let mut level = 0;
+
+        while !self.nodes[id as usize].children.is_empty() {
+            level += 1;
+
+            id = *self.nodes[id as usize].children.iter()
+                .min_by_key(|&&child| hamming_distance(descriptor, &self.nodes[child as usize].descriptor))
+                .unwrap();
+
+            if level == node_level {
+                ancestor = id;
+            }
+        }
+
+        let node = &self.nodes[id as usize];
+        (node.word.unwrap(), node.weight, ancestor)
+    }
+
+    /// Word id of a single descriptor
+    pub fn word(&self, descriptor: &CornerDescriptor) -> WordId {
+        self.descend(descriptor, 0).0
+    }
+
+    /// Converts the descriptors of an image into its BoW vector, and a
+    /// feature vector indexing them by their ancestor `levels_up` levels
+    /// above the words. ORB-SLAM uses `levels_up = 4`.
+    pub fn transform(&self, descriptors: &[CornerDescriptor], levels_up: u32) -> (BowVector, FeatureVector) {
+        let mut bow = BowVector::default();
+        let mut features = FeatureVector::default();
+
+        if self.words.is_empty() {
+            return (bow, features);
+        }
+
+        for (i, descriptor) in descriptors.iter().enumerate() {
+            let (word, weight, node) = self.descend(descriptor, levels_up);
+
+            if weight > 0.0 {
+                match self.weighting {
+                    WeightingType::Tf | WeightingType::TfIdf => *bow.0.entry(word).or_insert(0.0) += weight,
+                    WeightingType::Idf | WeightingType::Binary => { bow.0.entry(word).or_insert(weight); }
+                }
+
+                features.0.entry(node).or_default().push(i as u32);
+            }
+        }
+
+        match self.scoring {
+            ScoringType::L1Norm | ScoringType::ChiSquare | ScoringType::Bhattacharyya => {
+                let norm: f64 = bow.0.values().map(|v| v.abs()).sum();
+                if norm > 0.0 {
+                    bow.0.values_mut().for_each(|v| *v /= norm);
+                }
+            },
+            ScoringType::L2Norm => {
+                let norm: f64 = bow.0.values().map(|v| v * v).sum::<f64>().sqrt();
+                if norm > 0.0 {
+                    bow.0.values_mut().for_each(|v| *v /= norm);
+                }
+            },
+            ScoringType::DotProduct => {
+                if matches!(self.weighting, WeightingType::Tf | WeightingType::TfIdf) && !bow.0.is_empty() {
+                    let count = bow.0.len() as f64;
+                    bow.0.values_mut().for_each(|v| *v /= count);
+                }
+            }
+        }
+
+        (bow, features)
+    }
+
+    /// Similarity of two BoW vectors produced by this vocabulary
+    pub fn score(&self, a: &BowVector, b: &BowVector) -> f64 {
+        let common = a.0.iter().filter_map(|(word, &va)| b.0.get(word).map(|&vb| (va, vb)));
+
+        match self.scoring {
+            ScoringType::L1Norm => {
+                let score: f64 = common.map(|(va, vb)| (va - vb).abs() - va.abs() - vb.abs()).sum();
+                -score / 2.0
+            },
+            ScoringType::L2Norm => {
+                let score: f64 = common.map(|(va, vb)| va * vb).sum();
+                if score >= 1.0 { 1.0 } else { 1.0 - (1.0 - score).sqrt() }
+            },
+            ScoringType::ChiSquare => {
+                let score: f64 = common
+                    .filter(|(va, vb)| va + vb != 0.0)
+                    .map(|(va, vb)| va * vb / (va + vb))
+                    .sum();
+                2.0 * score
+            },
+            ScoringType::Bhattacharyya => common.map(|(va, vb)| (va * vb).sqrt()).sum(),
+            ScoringType::DotProduct => common.map(|(va, vb)| va * vb).sum()
+        }
+    }
+
+    /// Reads the DBoW2 text format used by ORBvoc.txt
+    pub fn load_text(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Self::read_text(io::BufReader::new(file))
+    }
+
+    pub fn read_text(reader: impl BufRead) -> io::Result<Self> {
+        let mut lines = reader.lines();
+
+        let header = lines.next().ok_or_else(|| invalid("missing vocabulary header"))??;
+        let header: Vec<u32> = header.split_whitespace()
+            .map(|v| v.parse().map_err(|_| invalid("invalid vocabulary header")))
+            .collect::<io::Result<_>>()?;
+
+        let [k, levels, scoring, weighting] = header[..] else {
+            return Err(invalid("invalid vocabulary header"));
+        };
+
+        let scoring = ScoringType::from_index(scoring).ok_or_else(|| invalid("unsupported scoring type"))?;
+        let weighting = WeightingType::from_index(weighting).ok_or_else(|| invalid("unsupported weighting type"))?;
+
+        let mut vocabulary = Self::new(k, levels, weighting, scoring);
+
+        for line in lines {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut next = || fields.next().ok_or_else(|| invalid("truncated vocabulary node"));
+
+            let parent: NodeId = next()?.parse().map_err(|_| invalid("invalid parent id"))?;
+            let is_leaf = next()? == "1";
+
+            let mut descriptor = CornerDescriptor { bits: [0; 32] };
+            for byte in descriptor.bits.iter_mut() {
+                *byte = next()?.parse().map_err(|_| invalid("invalid descriptor byte"))?;
+            }
+
+            let weight: f64 = next()?.parse().map_err(|_| invalid("invalid node weight"))?;
+
+            let id = vocabulary.nodes.len() as NodeId;
+
+            if parent as usize >= vocabulary.nodes.len() {
+                return Err(invalid("node appears before its parent"));
+            }
+
+            let word = if is_leaf {
+                vocabulary.words.push(id);
+                Some(vocabulary.words.len() as WordId - 1)
+            } else {
+                None
+            };
+
+            vocabulary.nodes.push(Node { parent, children: Vec::new(), descriptor, weight, word });
+            vocabulary.nodes[parent as usize].children.push(id);
+        }
+
+        // Descending must end at a word, which truncated files break
+        if vocabulary.nodes.len() == 1 {
+            return Err(invalid("vocabulary has no nodes"));
+        }
+
+        if vocabulary.nodes.iter().any(|node| node.children.is_empty() && node.word.is_none()) {
+            return Err(invalid("vocabulary has a node without children that is not a word"));
+        }
+
+        Ok(vocabulary)
+    }
+
+    /// Writes the DBoW2 text format used by ORBvoc.txt
+    pub fn save_text(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        let mut writer = io::BufWriter::new(file);
+        self.write_text(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn write_text(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "{} {} {} {}", self.k, self.levels, self.scoring as u32, self.weighting as u32)?;
+
+        for node in &self.nodes[1..] {
+            write!(writer, "{} {}", node.parent, node.children.is_empty() as u32)?;
+
+            for byte in node.descriptor.bits {
+                write!(writer, " {}", byte)?;
+            }
+
+            writeln!(writer, " {}", node.weight)?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs the extractor over every supported image in `directory`, in file
+    /// name order, and returns the descriptors of each image. Images must
+    /// have the size the program was configured with.
+    pub fn extract_directory(program: &OrbProgram, directory: impl AsRef<Path>) -> io::Result<Vec<Vec<CornerDescriptor>>> {
+        let mut paths: Vec<_> = std::fs::read_dir(directory)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<_>>()?;
+
+        paths.retain(|path| image::is_supported(path));
+        paths.sort();
+
+        let mut training = Vec::new();
+
+        for path in paths {
+            let image = image::load(&path)?.to_rgba8();
+            let size = program.config.image_size;
+
+            if image.width != size.width || image.height != size.height {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} does not match the configured image size", path.display())
+                ));
+            }
+
+            program.write_input_image(&image.data);
+
+            let corner_count = program.extract_corners();
+            let (_, descriptors) = program.read_features(corner_count);
+
+            training.push(descriptors);
+        }
+
+        Ok(training)
+    }
+
+    /// Builds a vocabulary from the images in `directory`
+    pub fn train_from_directory(
+        program: &OrbProgram,
+        directory: impl AsRef<Path>,
+        k: u32,
+        levels: u32,
+        weighting: WeightingType,
+        scoring: ScoringType,
+        rng: &mut Rng
+    ) -> io::Result<Self> {
+        let training = Self::extract_directory(program, directory)?;
+
+        let mut vocabulary = Self::new(k, levels, weighting, scoring);
+        vocabulary.create(&training, rng);
+
+        Ok(vocabulary)
+    }
+}
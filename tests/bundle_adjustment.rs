@@ -0,0 +1,385 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use tinyslam::bundle_adjustment::{
+    adjust_bundle, local_bundle_adjustment, solve_bundle, BundleAdjustmentConfig, BundleObservation, BundleProblem, CpuLinearizer, Linearizer
+};
+use tinyslam::bundle_program::{BundleProgram, BundleProgramConfig};
+use tinyslam::camera::{CameraModel, KannalaBrandt, Pinhole, RadTan};
+use tinyslam::frame::{Frame, ScalePyramid};
+use tinyslam::geometry::{SE3, SO3};
+use tinyslam::linalg::{Matrix, Vec2, Vec3, Vec6};
+use tinyslam::map::Map;
+use tinyslam::optimizer::Huber;
+use tinyslam::orb::{CornerData, CornerDescriptor};
+use tinyslam::random::Rng;
+use tiny_wgpu::Compute;
+
+fn camera() -> Arc<dyn CameraModel> {
+    Arc::new(Pinhole::new(500.0, 500.0, 320.0, 240.0))
+}
+
+fn uniform(rng: &mut Rng, magnitude: f64) -> Vec3 {
+    Vec3::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5, rng.next_f64() - 0.5) * (2.0 * magnitude)
+}
+
+/// Cameras moving sideways while looking at points 4 to 10 units ahead
+fn true_poses(count: usize) -> Vec<SE3> {
+    (0..count)
+        .map(|i| SE3::new(SO3::exp(&Vec3::new(0.0, 0.03 * i as f64, 0.0)), Vec3::new(-0.25 * i as f64, 0.0, 0.0)))
+        .collect()
+}
+
+fn in_image(pixel: &Vec2) -> bool {
+    pixel.x() >= 0.0 && pixel.y() >= 0.0 && pixel.x() < 640.0 && pixel.y() < 480.0
+}
+
+/// Points seen by every camera
+fn true_points(rng: &mut Rng, poses: &[SE3], count: usize) -> Vec<Vec3> {
+    let camera = camera();
+    let mut points = Vec::new();
+
+    while points.len() < count {
+        let point = Vec3::new(rng.next_f64() * 8.0 - 3.0, rng.next_f64() * 6.0 - 3.0, 4.0 + rng.next_f64() * 6.0);
+
+        if poses.iter().all(|pose| in_image(&camera.project(&pose.transform(&point)))) {
+            points.push(point);
+        }
+    }
+
+    points
+}
+
+/// Problem over the true scene with pixel noise of up to `noise`, where the
+/// first `fixed` poses are fixed
+fn problem(rng: &mut Rng, poses: &[SE3], points: &[Vec3], fixed: usize, noise: f64) -> BundleProblem {
+    let camera = camera();
+    let mut observations = Vec::new();
+
+    for (p, pose) in poses.iter().enumerate() {
+        for (j, point) in points.iter().enumerate() {
+            let camera_point = pose.transform(point);
+            let pixel = camera.project(&camera_point);
+
+            if camera_point.z() > 0.0 && in_image(&pixel) {
+                let offset = Vec2::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5) * (2.0 * noise);
+                observations.push(BundleObservation { pose: p, point: j, pixel: pixel + offset, inv_sigma2: 1.0 });
+            }
+        }
+    }
+
+    BundleProblem {
+        poses: poses.to_vec(),
+        fixed: (0..poses.len()).map(|i| i < fixed).collect(),
+        cameras: vec![camera; poses.len()],
+        points: points.to_vec(),
+        observations,
+        inertial: None
+    }
+}
+
+/// Perturbs the free poses and every point
+fn perturb(rng: &mut Rng, problem: &mut BundleProblem, rotation: f64, translation: f64, point: f64) {
+    for (pose, &fixed) in problem.poses.iter_mut().zip(&problem.fixed) {
+        if !fixed {
+            let mut delta = [0.0; 6];
+            let r = uniform(rng, rotation);
+            let t = uniform(rng, translation);
+            delta[..3].copy_from_slice(&r.to_array());
+            delta[3..].copy_from_slice(&t.to_array());
+            *pose = pose.retract(&Vec6::from_array(delta));
+        }
+    }
+
+    for p in &mut problem.points {
+        *p += uniform(rng, point);
+    }
+}
+
+fn max_pose_error(a: &[SE3], b: &[SE3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a.inverse() * *b).log().norm()).fold(0.0, f64::max)
+}
+
+fn max_point_error(a: &[Vec3], b: &[Vec3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (*a - *b).norm()).fold(0.0, f64::max)
+}
+
+#[test]
+fn converges_to_the_true_scene() {
+    let mut rng = Rng::new(1);
+    let poses = true_poses(6);
+    let points = true_points(&mut rng, &poses, 150);
+
+    // Two fixed poses fix the gauge, including the monocular scale
+    let mut problem = problem(&mut rng, &poses, &points, 2, 0.0);
+    perturb(&mut rng, &mut problem, 0.02, 0.05, 0.1);
+
+    let outliers = vec![false; problem.observations.len()];
+    let summary = solve_bundle(&CpuLinearizer, &mut problem, &outliers, None, 30, &AtomicBool::new(false));
+
+    assert!(summary.initial_cost > 1e3, "initial cost {}", summary.initial_cost);
+    assert!(summary.final_cost < 1e-8, "final cost {}", summary.final_cost);
+    assert!(max_pose_error(&problem.poses, &poses) < 1e-6);
+    assert!(max_point_error(&problem.points, &points) < 1e-5);
+    assert_eq!(problem.poses[..2], poses[..2]);
+}
+
+#[test]
+fn rejects_outliers_with_robust_rounds() {
+    let mut rng = Rng::new(2);
+    let poses = true_poses(5);
+    let points = true_points(&mut rng, &poses, 200);
+
+    let mut problem = problem(&mut rng, &poses, &points, 2, 0.5);
+    let mut corrupted = vec![false; problem.observations.len()];
+
+    for (observation, corrupted) in problem.observations.iter_mut().zip(&mut corrupted) {
+        if rng.next_f64() < 0.05 {
+            observation.pixel += Vec2::new(15.0, -12.0);
+            *corrupted = true;
+        }
+    }
+
+    perturb(&mut rng, &mut problem, 0.01, 0.03, 0.05);
+
+    let outliers = adjust_bundle(&BundleAdjustmentConfig::default(), &CpuLinearizer, &mut problem, &AtomicBool::new(false));
+
+    let missed = corrupted.iter().zip(&outliers).filter(|&(&c, &o)| c && !o).count();
+    let rejected = corrupted.iter().zip(&outliers).filter(|&(&c, &o)| !c && o).count();
+
+    assert_eq!(missed, 0, "corrupted observations accepted");
+    assert!(rejected * 100 < outliers.len(), "{rejected} inliers rejected");
+    assert!(max_pose_error(&problem.poses, &poses) < 5e-3);
+}
+
+#[test]
+fn abort_flag_stops_the_solver() {
+    let mut rng = Rng::new(3);
+    let poses = true_poses(4);
+    let points = true_points(&mut rng, &poses, 50);
+
+    let mut problem = problem(&mut rng, &poses, &points, 1, 0.0);
+    perturb(&mut rng, &mut problem, 0.01, 0.01, 0.01);
+    let before = problem.poses.clone();
+
+    let outliers = vec![false; problem.observations.len()];
+    let summary = solve_bundle(&CpuLinearizer, &mut problem, &outliers, None, 10, &AtomicBool::new(true));
+
+    assert_eq!(summary.iterations, 0);
+    assert_eq!(problem.poses, before);
+}
+
+/// Keyframe whose keypoints are the exact projections of the visible points,
+/// with the index of the point each keypoint came from
+fn keyframe_frame(id: u64, pose: &SE3, points: &[Vec3]) -> (Frame, Vec<usize>) {
+    let camera = camera();
+    let mut corners = Vec::new();
+    let mut descriptors = Vec::new();
+    let mut indices = Vec::new();
+
+    for (i, point) in points.iter().enumerate() {
+        let camera_point = pose.transform(point);
+        let pixel = camera.project(&camera_point);
+
+        if camera_point.z() > 0.0 && in_image(&pixel) {
+            corners.push(CornerData { x: pixel.x() as u32, y: pixel.y() as u32, angle: 0, octave: 0, camera: 0 });
+            descriptors.push(CornerDescriptor { bits: [i as u8; 32] });
+            indices.push(i);
+        }
+    }
+
+    let size = wgpu::Extent3d { width: 640, height: 480, depth_or_array_layers: 1 };
+    let mut frame = Frame::new(id, id as f64, camera, size, ScalePyramid::new(8, 1.2), corners, descriptors);
+
+    // Sub-pixel positions, which corners round away
+    for (keypoint, &i) in frame.keypoints.iter_mut().zip(&indices) {
+        *keypoint = pose.transform(&points[i]).hnormalize() * 500.0 + Vec2::new(320.0, 240.0);
+    }
+
+    frame.pose = Some(*pose);
+    (frame, indices)
+}
+
+#[test]
+fn local_bundle_adjustment_refines_the_map() {
+    let mut rng = Rng::new(4);
+    let poses = true_poses(5);
+    let points = true_points(&mut rng, &poses, 200);
+
+    let mut map = Map::new();
+    let mut ids = Vec::new();
+    let mut point_ids = vec![None; points.len()];
+    let mut last_indices = Vec::new();
+
+    for (n, pose) in poses.iter().enumerate() {
+        let (mut frame, indices) = keyframe_frame(n as u64, pose, &points);
+
+        // The origin keeps its pose, the others start perturbed
+        if n > 0 {
+            frame.pose = Some(pose.retract(&Vec6::from_array([0.005, -0.004, 0.003, 0.02, -0.01, 0.015])));
+        }
+
+        let id = map.add_keyframe(frame);
+        ids.push(id);
+
+        for (keypoint, &i) in indices.iter().enumerate() {
+            match point_ids[i] {
+                Some(point) => map.add_observation(id, keypoint, point),
+                None => point_ids[i] = Some(map.add_point(points[i] + uniform(&mut rng, 0.05), id, keypoint))
+            }
+        }
+
+        last_indices = indices;
+    }
+
+    for &id in &ids {
+        map.update_connections(id);
+    }
+
+    // Swap the points of two distant keypoints in the newest keyframe
+    let last = *ids.last().unwrap();
+    let a = 0;
+    let b = (1..last_indices.len()).find(|&k| (points[last_indices[k]] - points[last_indices[a]]).norm() > 3.0).unwrap();
+    let (point_a, point_b) = (point_ids[last_indices[a]].unwrap(), point_ids[last_indices[b]].unwrap());
+
+    map.remove_observation(last, point_a);
+    map.remove_observation(last, point_b);
+    map.add_observation(last, a, point_b);
+    map.add_observation(last, b, point_a);
+
+    let origin = map.keyframe(ids[0]).unwrap().frame.pose.unwrap();
+    let map = map.shared();
+
+    assert!(local_bundle_adjustment(&BundleAdjustmentConfig::default(), &CpuLinearizer, &map, last, &AtomicBool::new(false)));
+
+    let map = map.read().unwrap();
+    assert_eq!(map.keyframe(ids[0]).unwrap().frame.pose.unwrap(), origin);
+
+    // The perturbation was about 0.03; the remaining error comes from the
+    // scale drifting with the noisy initial points
+    let estimated: Vec<SE3> = ids.iter().map(|&id| map.keyframe(id).unwrap().frame.pose.unwrap()).collect();
+    assert!(max_pose_error(&estimated, &poses) < 2e-3, "pose error {}", max_pose_error(&estimated, &poses));
+
+    let frame = &map.keyframe(last).unwrap().frame;
+    assert_eq!(frame.map_points[a], None);
+    assert_eq!(frame.map_points[b], None);
+    assert!(!map.point(point_a).unwrap().observations.contains_key(&last));
+}
+
+/// Device for the GPU tests on any adapter with push constants, falling
+/// back to a software adapter, or `None` if there is no such adapter
+fn compute() -> Option<Compute> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor { backends: wgpu::Backends::all(), ..Default::default() });
+
+    let adapter = [false, true].into_iter().find_map(|force_fallback_adapter| {
+        let options = wgpu::RequestAdapterOptions { force_fallback_adapter, ..Default::default() };
+        pollster::block_on(instance.request_adapter(&options))
+            .filter(|adapter| adapter.features().contains(wgpu::Features::PUSH_CONSTANTS))
+    })?;
+
+    let descriptor = wgpu::DeviceDescriptor {
+        label: None,
+        required_features: wgpu::Features::PUSH_CONSTANTS,
+        required_limits: wgpu::Limits { max_push_constant_size: 16, ..wgpu::Limits::downlevel_defaults() }
+    };
+    let (device, queue) = pollster::block_on(adapter.request_device(&descriptor, None)).ok()?;
+
+    Some(Compute {
+        instance: Arc::new(instance),
+        adapter: Arc::new(adapter),
+        device: Arc::new(device),
+        queue: Arc::new(queue)
+    })
+}
+
+fn program(compute: &Compute) -> BundleProgram {
+    BundleProgram::new(BundleProgramConfig { max_poses: 16, max_points: 512, max_observations: 4096 }, compute)
+}
+
+fn assert_close<const R: usize, const C: usize>(gpu: &Matrix<R, C>, cpu: &Matrix<R, C>, what: &str) {
+    let error = (*gpu - *cpu).norm();
+    assert!(error <= 1e-3 * cpu.norm() + 1e-3, "{what}: error {error} for norm {}", cpu.norm());
+}
+
+/// Gradients are Jacobians times residuals, so the f32 rounding of the
+/// residuals is scaled by the Jacobians, whose size is the square root of
+/// the size of the Hessian block. Near the optimum the gradient itself is
+/// small and cannot bound that.
+fn assert_gradient_close<const N: usize>(gpu: &Matrix<N, 1>, cpu: &Matrix<N, 1>, block: &Matrix<N, N>, what: &str) {
+    let error = (*gpu - *cpu).norm();
+    let tolerance = 1e-3 * cpu.norm() + 1e-3 * block.norm().sqrt();
+    assert!(error <= tolerance, "{what}: error {error} for norm {} and tolerance {tolerance}", cpu.norm());
+}
+
+#[test]
+fn gpu_normal_equations_match_the_cpu() {
+    let Some(compute) = compute() else {
+        eprintln!("no adapter with push constants, not even a fallback one, skipping the GPU linearizer test");
+        return;
+    };
+
+    let mut rng = Rng::new(5);
+    let poses = true_poses(4);
+    let points = true_points(&mut rng, &poses, 300);
+
+    let mut problem = problem(&mut rng, &poses, &points, 1, 2.0);
+    perturb(&mut rng, &mut problem, 0.01, 0.03, 0.05);
+
+    // Every camera model the shader implements
+    let pinhole = Pinhole::new(500.0, 500.0, 320.0, 240.0);
+    problem.cameras[2] = Arc::new(RadTan::new(pinhole, [-0.2, 0.05, 0.001, -0.002, 0.01]));
+    problem.cameras[3] = Arc::new(KannalaBrandt::new(pinhole, [0.02, -0.01, 0.003, -0.001]));
+
+    let outliers: Vec<bool> = (0..problem.observations.len()).map(|i| i % 7 == 3).collect();
+    let program = program(&compute);
+
+    for kernel in [None, Some(Huber { delta: 5.991f64.sqrt() })] {
+        let cpu = CpuLinearizer.normal_equations(&problem, &outliers, kernel);
+        let gpu = program.normal_equations(&problem, &outliers, kernel);
+
+        for (gpu, cpu) in gpu.residuals.iter().zip(&cpu.residuals) {
+            assert_close(&gpu.unwrap(), &cpu.unwrap(), "residual");
+        }
+
+        for (gpu, cpu) in gpu.coupling.iter().zip(&cpu.coupling) {
+            assert_eq!(gpu.is_some(), cpu.is_some());
+
+            if let (Some(gpu), Some(cpu)) = (gpu, cpu) {
+                assert_close(gpu, cpu, "coupling");
+            }
+        }
+
+        for pose in 0..problem.poses.len() {
+            assert_close(&gpu.pose_blocks[pose], &cpu.pose_blocks[pose], "pose block");
+            assert_gradient_close(&gpu.pose_gradients[pose], &cpu.pose_gradients[pose], &cpu.pose_blocks[pose], "pose gradient");
+        }
+
+        for point in 0..problem.points.len() {
+            assert_close(&gpu.point_blocks[point], &cpu.point_blocks[point], "point block");
+            assert_gradient_close(&gpu.point_gradients[point], &cpu.point_gradients[point], &cpu.point_blocks[point], "point gradient");
+        }
+    }
+}
+
+#[test]
+fn gpu_linearizer_converges() {
+    let Some(compute) = compute() else {
+        eprintln!("no adapter with push constants, not even a fallback one, skipping the GPU linearizer test");
+        return;
+    };
+
+    let mut rng = Rng::new(6);
+    let poses = true_poses(5);
+    let points = true_points(&mut rng, &poses, 150);
+
+    let mut problem = problem(&mut rng, &poses, &points, 2, 0.0);
+    perturb(&mut rng, &mut problem, 0.02, 0.05, 0.1);
+
+    let outliers = vec![false; problem.observations.len()];
+    let summary = solve_bundle(&program(&compute), &mut problem, &outliers, None, 30, &AtomicBool::new(false));
+
+    // Single precision limits the accuracy of the steps, not of the cost
+    assert!(summary.final_cost < 1e-6 * summary.initial_cost, "final cost {}", summary.final_cost);
+    assert!(max_pose_error(&problem.poses, &poses) < 1e-4);
+    assert!(max_point_error(&problem.points, &points) < 1e-3);
+}
@@ -0,0 +1,184 @@
+use tinyslam::camera::{CameraModel, KannalaBrandt, Pinhole, RadTan, Undistortion};
+use tinyslam::geometry::SO3;
+use tinyslam::linalg::{Matrix, Vec2, Vec3, Vector};
+use tinyslam::random::Rng;
+
+const SAMPLES: usize = 200;
+
+fn pinhole() -> Pinhole {
+    Pinhole::new(458.654, 457.296, 367.215, 248.375)
+}
+
+/// EuRoC cam0
+fn radtan() -> RadTan {
+    RadTan::new(pinhole(), [-0.28340811, 0.07395907, 0.00019359, 1.76187114e-05, 0.0])
+}
+
+/// TUM-VI cam0
+fn kannala_brandt() -> KannalaBrandt {
+    KannalaBrandt::new(
+        Pinhole::new(190.978, 190.973, 254.932, 256.897),
+        [0.0034823894, 0.0007150348, -0.0020532361, 0.0002029367]
+    )
+}
+
+fn cameras() -> [(&'static str, Box<dyn CameraModel>, f64); 3] {
+    // Largest angle from the optical axis each model is sampled at
+    [
+        ("pinhole", Box::new(pinhole()), 0.7),
+        ("radtan", Box::new(radtan()), 0.6),
+        ("kannala-brandt", Box::new(kannala_brandt()), 1.4)
+    ]
+}
+
+/// Point at depth 0.5 to 5.5 with the given maximum angle from the optical axis
+fn random_point(rng: &mut Rng, max_angle: f64) -> Vec3 {
+    let angle = rng.next_f64() * max_angle;
+    let azimuth = rng.next_f64() * std::f64::consts::TAU;
+    let depth = 0.5 + 5.0 * rng.next_f64();
+
+    Vec3::new(angle.sin() * azimuth.cos(), angle.sin() * azimuth.sin(), angle.cos()) * depth
+}
+
+fn unit<const N: usize>(i: usize) -> Vector<N> {
+    let mut v = Vector::<N>::zeros();
+    v[i] = 1.0;
+    v
+}
+
+/// Central difference estimate of the Jacobian of `f` at `x`
+fn numerical_jacobian<const R: usize, const C: usize>(f: impl Fn(&Vector<C>) -> Vector<R>, x: &Vector<C>) -> Matrix<R, C> {
+    let h = 1e-6;
+    let mut jacobian = Matrix::<R, C>::zeros();
+
+    for i in 0..C {
+        let column = (f(&(*x + unit::<C>(i) * h)) - f(&(*x + unit::<C>(i) * -h))) * (0.5 / h);
+        jacobian.set_column(i, &column);
+    }
+
+    jacobian
+}
+
+#[test]
+fn project_jacobians_match_numerical_derivatives() {
+    let mut rng = Rng::new(1);
+
+    for (name, camera, max_angle) in cameras() {
+        let points = (0..SAMPLES).map(|_| random_point(&mut rng, max_angle)).chain([Vec3::new(0.0, 0.0, 2.0)]);
+
+        for point in points {
+            let analytic = camera.project_jacobian(&point);
+            let numerical = numerical_jacobian(|p| camera.project(p), &point);
+
+            let error = (analytic - numerical).norm() / numerical.norm();
+            assert!(error < 1e-6, "{name} at {:?}: {analytic:?} != {numerical:?}", point.to_array());
+        }
+    }
+}
+
+#[test]
+fn distort_jacobian_matches_numerical_derivatives() {
+    let mut rng = Rng::new(2);
+    let camera = RadTan::new(pinhole(), [-0.28, 0.07, 0.002, -0.001, 0.01]);
+
+    for _ in 0..SAMPLES {
+        let p = Vec2::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5) * 1.2;
+
+        let analytic = camera.distort_jacobian(&p);
+        let numerical = numerical_jacobian(|p| camera.distort(p), &p);
+
+        assert!((analytic - numerical).norm() < 1e-8, "{analytic:?} != {numerical:?}");
+    }
+}
+
+#[test]
+fn unproject_inverts_project() {
+    let mut rng = Rng::new(3);
+
+    for (name, camera, max_angle) in cameras() {
+        for _ in 0..SAMPLES {
+            let point = random_point(&mut rng, max_angle);
+            let pixel = camera.project(&point);
+            let bearing = camera.unproject(&pixel);
+
+            assert!((bearing.norm() - 1.0).abs() < 1e-12, "{name}");
+            assert!((bearing - point.normalize()).norm() < 1e-9, "{name} at {:?}", point.to_array());
+            assert!((camera.project(&bearing) - pixel).norm() < 1e-6, "{name}");
+        }
+
+        // The principal point is on the optical axis
+        let center = camera.pinhole();
+        let axis = camera.unproject(&Vec2::new(center.cx, center.cy));
+        assert!((axis - Vec3::new(0.0, 0.0, 1.0)).norm() < 1e-12, "{name}");
+    }
+}
+
+#[test]
+fn undistort_matches_the_pinhole_projection() {
+    let mut rng = Rng::new(4);
+    let camera = radtan();
+
+    for _ in 0..SAMPLES {
+        let point = random_point(&mut rng, 0.6);
+        let undistorted = camera.undistort(&camera.project(&point));
+
+        assert!((undistorted - pinhole().project(&point)).norm() < 1e-6);
+    }
+}
+
+/// Source pixel an undistortion map entry samples, undoing the half-texel offset
+fn source_pixel(entry: [f32; 2], width: u32, height: u32) -> Vec2 {
+    Vec2::new(entry[0] as f64 * width as f64 - 0.5, entry[1] as f64 * height as f64 - 0.5)
+}
+
+#[test]
+fn pinhole_undistortion_map_is_the_identity() {
+    let (width, height) = (64, 48);
+    let map = Undistortion::new(Box::new(Pinhole::new(50.0, 50.0, 31.5, 23.5))).map(width, height);
+
+    assert_eq!(map.len(), (width * height) as usize);
+
+    for (i, &entry) in map.iter().enumerate() {
+        let pixel = Vec2::new((i as u32 % width) as f64, (i as u32 / width) as f64);
+        assert!((source_pixel(entry, width, height) - pixel).norm() < 1e-3, "{i}");
+    }
+}
+
+#[test]
+fn undistortion_map_samples_the_distorted_pixel_of_each_ray() {
+    let (width, height) = (752, 480);
+    let camera = radtan();
+    let map = Undistortion::new(Box::new(camera)).map(width, height);
+
+    for y in (0..height).step_by(37) {
+        for x in (0..width).step_by(41) {
+            let source = source_pixel(map[(y * width + x) as usize], width, height);
+            let target = Vec2::new(x as f64, y as f64);
+
+            // Undistorting the sampled pixel lands back on the output pixel
+            assert!((camera.undistort(&source) - target).norm() < 1e-2, "{x} {y}");
+        }
+    }
+}
+
+#[test]
+fn rectified_undistortion_map_applies_the_rotation() {
+    let (width, height) = (64, 48);
+    let target = Pinhole::new(50.0, 50.0, 31.5, 23.5);
+
+    // A small rotation about the y axis shifts the image sideways
+    let rotation = SO3::exp(&Vec3::new(0.0, 0.1, 0.0));
+    let map = Undistortion::rectified(Box::new(pinhole()), target, rotation.matrix()).map(width, height);
+
+    for (i, &entry) in map.iter().enumerate() {
+        let p = target.normalize(&Vec2::new((i as u32 % width) as f64, (i as u32 / width) as f64));
+        let expected = pinhole().project(&(rotation * Vec3::new(p.x(), p.y(), 1.0)));
+
+        assert!((source_pixel(entry, width, height) - expected).norm() < 1e-2, "{i}");
+    }
+
+    // Rays turned behind the camera have no source pixel
+    let behind = SO3::exp(&Vec3::new(0.0, std::f64::consts::PI, 0.0));
+    let map = Undistortion::rectified(Box::new(pinhole()), target, behind.matrix()).map(width, height);
+    assert!(map.iter().all(|&entry| entry == [-1.0, -1.0]));
+}
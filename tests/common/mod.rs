@@ -0,0 +1,178 @@
+//! Synthetic scenes shared by the tracking, map and local mapping tests:
+//! random world points with random descriptors, frames that observe them
+//! at their exact projections, and maps built from such frames.
+
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use tinyslam::camera::{CameraModel, Pinhole};
+use tinyslam::frame::{Frame, ScalePyramid};
+use tinyslam::geometry::{SE3, SO3};
+use tinyslam::linalg::Vec3;
+use tinyslam::map::{KeyFrameId, Map, MapPointId};
+use tinyslam::orb::{CornerData, CornerDescriptor};
+use tinyslam::random::Rng;
+use tinyslam::vocabulary::{ScoringType, Vocabulary, WeightingType};
+
+pub const WIDTH: u32 = 640;
+pub const HEIGHT: u32 = 480;
+
+/// Bits flipped in each observed descriptor
+pub const DESCRIPTOR_NOISE: usize = 4;
+
+pub const LEVELS_UP: u32 = 1;
+
+pub fn camera() -> Arc<Pinhole> {
+    Arc::new(Pinhole::new(450.0, 450.0, 320.0, 240.0))
+}
+
+pub fn image_size() -> wgpu::Extent3d {
+    wgpu::Extent3d { width: WIDTH, height: HEIGHT, depth_or_array_layers: 1 }
+}
+
+/// Camera looking down the z axis from `x` along the x axis
+pub fn pose_at(x: f64) -> SE3 {
+    SE3::new(SO3::identity(), Vec3::new(-x, 0.0, 0.0))
+}
+
+pub fn pose_error(a: &SE3, b: &SE3) -> f64 {
+    (a.rotation.inverse() * b.rotation).log().norm() + (a.translation - b.translation).norm()
+}
+
+pub struct World {
+    pub points: Vec<Vec3>,
+    pub descriptors: Vec<CornerDescriptor>
+}
+
+/// Keypoints of a frame, with the world point each one observes
+pub struct Observation {
+    pub frame: Frame,
+    pub points: Vec<usize>
+}
+
+impl World {
+    /// Points between 4 and 10 metres in front of cameras on the x axis
+    /// between `-width / 2` and `width / 2`
+    pub fn new(rng: &mut Rng, count: usize, width: f64) -> Self {
+        let points = (0..count)
+            .map(|_| {
+                let z = 4.0 + 6.0 * rng.next_f64();
+                Vec3::new((rng.next_f64() - 0.5) * (width + z), (rng.next_f64() - 0.5) * 0.9 * z, z)
+            })
+            .collect();
+
+        let descriptors = (0..count)
+            .map(|_| CornerDescriptor { bits: std::array::from_fn(|_| rng.next_u64() as u8) })
+            .collect();
+
+        Self { points, descriptors }
+    }
+
+    /// Vocabulary trained on the descriptors of the world
+    pub fn vocabulary(&self, rng: &mut Rng) -> Arc<Vocabulary> {
+        let training: Vec<Vec<CornerDescriptor>> = self.descriptors.chunks(50).map(|c| c.to_vec()).collect();
+
+        let mut vocabulary = Vocabulary::new(6, 3, WeightingType::TfIdf, ScoringType::L1Norm);
+        vocabulary.create(&training, rng);
+        Arc::new(vocabulary)
+    }
+
+    /// Frame with a keypoint at the rounded projection of each visible
+    /// point, with a slightly corrupted copy of its descriptor. The frame
+    /// has no pose.
+    pub fn observe(&self, rng: &mut Rng, id: u64, pose: &SE3) -> Observation {
+        let camera = camera();
+        let mut corners = Vec::new();
+        let mut descriptors = Vec::new();
+        let mut points = Vec::new();
+
+        for (i, point) in self.points.iter().enumerate() {
+            let camera_point = pose.transform(point);
+
+            if camera_point.z() <= 0.5 {
+                continue;
+            }
+
+            let pixel = camera.project(&camera_point);
+
+            if pixel.x() < 5.0 || pixel.y() < 5.0 || pixel.x() > WIDTH as f64 - 5.0 || pixel.y() > HEIGHT as f64 - 5.0 {
+                continue;
+            }
+
+            let mut descriptor = self.descriptors[i];
+            for _ in 0..DESCRIPTOR_NOISE {
+                let bit = rng.below(256);
+                descriptor.bits[bit / 8] ^= 1 << (bit % 8);
+            }
+
+            corners.push(CornerData { x: pixel.x().round() as u32, y: pixel.y().round() as u32, angle: 0, octave: 0, camera: 0 });
+            descriptors.push(descriptor);
+            points.push(i);
+        }
+
+        let frame = Frame::new(id, id as f64 / 30.0, camera, image_size(), ScalePyramid::new(8, 1.2), corners, descriptors);
+        Observation { frame, points }
+    }
+}
+
+/// Map built from keyframes observing the world
+pub struct SyntheticMap {
+    pub map: Map,
+    pub keyframes: Vec<KeyFrameId>,
+    /// World point each keypoint of each keyframe observes
+    pub observed: Vec<Vec<usize>>,
+    /// Map point of each world point, if one was created
+    pub points: Vec<Option<MapPointId>>
+}
+
+/// Adds a keyframe at each pose, with its BoW vector, and a map point at
+/// the true position of every world point it observes. Connections, point
+/// descriptors, normals and depths are updated as local mapping would.
+pub fn build_map(world: &World, vocabulary: &Vocabulary, rng: &mut Rng, poses: &[SE3]) -> SyntheticMap {
+    build_map_with(world, vocabulary, rng, poses, |_, _| true)
+}
+
+/// Like `build_map`, but keyframe `k` only observes world point `w` through
+/// a map point if `mapped(k, w)`
+pub fn build_map_with(
+    world: &World,
+    vocabulary: &Vocabulary,
+    rng: &mut Rng,
+    poses: &[SE3],
+    mapped: impl Fn(usize, usize) -> bool
+) -> SyntheticMap {
+    let mut map = Map::new();
+    let mut keyframes = Vec::new();
+    let mut observed = Vec::new();
+    let mut points = vec![None; world.points.len()];
+
+    for (k, pose) in poses.iter().enumerate() {
+        let Observation { mut frame, points: world_points } = world.observe(rng, k as u64, pose);
+        frame.pose = Some(*pose);
+        frame.compute_bow(vocabulary, LEVELS_UP);
+
+        let keyframe = map.add_keyframe(frame);
+        keyframes.push(keyframe);
+
+        for (index, &world_point) in world_points.iter().enumerate().filter(|&(_, &w)| mapped(k, w)) {
+            match points[world_point] {
+                Some(point) => map.add_observation(keyframe, index, point),
+                None => points[world_point] = Some(map.add_point(world.points[world_point], keyframe, index))
+            }
+        }
+
+        observed.push(world_points);
+    }
+
+    for point in points.iter().flatten() {
+        map.compute_distinctive_descriptor(*point);
+        map.update_normal_and_depth(*point);
+    }
+
+    for &keyframe in &keyframes {
+        map.update_connections(keyframe);
+    }
+
+    SyntheticMap { map, keyframes, observed, points }
+}
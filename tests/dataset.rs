@@ -0,0 +1,83 @@
+use tinyslam::dataset::{associate, read_trajectory, write_trajectory, TumSequence, TUM_MAX_DIFFERENCE};
+use tinyslam::geometry::{SE3, SO3};
+use tinyslam::linalg::Vec3;
+
+fn assert_pose_eq(a: &SE3, b: &SE3) {
+    let (qa, qb) = (a.rotation.to_quaternion(), b.rotation.to_quaternion());
+    let (ta, tb) = (a.translation.to_array(), b.translation.to_array());
+
+    assert!(qa.iter().zip(&qb).all(|(x, y)| (x - y).abs() < 1e-9), "{qa:?} != {qb:?}");
+    assert!(ta.iter().zip(&tb).all(|(x, y)| (x - y).abs() < 1e-9), "{ta:?} != {tb:?}");
+}
+
+#[test]
+fn associates_closest_timestamps_once() {
+    let rgb = [1.000, 1.033, 1.066, 1.500];
+    let depth = [0.995, 1.030, 1.040, 1.070];
+
+    // 1.040 is within range of 1.033, but 1.030 is closer, so it stays unmatched
+    assert_eq!(associate(&rgb, &depth, TUM_MAX_DIFFERENCE), vec![(0, 0), (1, 1), (2, 3)]);
+    assert!(associate(&rgb, &depth, 0.001).is_empty());
+}
+
+#[test]
+fn trajectory_round_trips_through_tum_format() {
+    let pose = SE3::new(SO3::from_quaternion([0.9, 0.1, -0.3, 0.2]), Vec3::new(0.5, -1.0, 2.0));
+    let trajectory = vec![(1305031102.175304, pose), (1305031102.211214, SE3::identity())];
+
+    let mut text = Vec::new();
+    write_trajectory(&mut text, &trajectory).unwrap();
+    let text = String::from_utf8(text).unwrap();
+
+    // Camera to world translation comes right after the timestamp
+    let center = pose.inverse().translation.to_array();
+    let fields: Vec<f64> = text.lines().next().unwrap().split_whitespace().map(|v| v.parse().unwrap()).collect();
+
+    assert_eq!(fields.len(), 8);
+    assert_eq!(fields[0], 1305031102.175304);
+    assert!((0..3).all(|i| (fields[1 + i] - center[i]).abs() < 1e-9));
+
+    let read = read_trajectory(text.as_bytes()).unwrap();
+
+    assert_eq!(read.len(), 2);
+    for ((ta, a), (tb, b)) in read.iter().zip(&trajectory) {
+        assert_eq!(ta, tb);
+        assert_pose_eq(a, b);
+    }
+
+    assert!(read_trajectory("1.0 0 0 0 0 0 1".as_bytes()).is_err());
+}
+
+#[test]
+fn opens_sequences_from_file_lists() {
+    let directory = std::env::temp_dir().join(format!("tinyslam_tum_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+
+    std::fs::write(directory.join("rgb.txt"), "# color images\n# file: 'rgbd_dataset_freiburg1_xyz.bag'\n# timestamp filename\n1.000 rgb/1.000.png\n1.033 rgb/1.033.png\n2.000 rgb/2.000.png\n").unwrap();
+    std::fs::write(directory.join("depth.txt"), "# depth maps\n1.005 depth/1.005.png\n1.030 depth/1.030.png\n").unwrap();
+    std::fs::write(directory.join("groundtruth.txt"), "# timestamp tx ty tz qx qy qz qw\n0.990 1.0 2.0 3.0 0 0 0 1\n").unwrap();
+
+    let sequence = TumSequence::open(&directory, TUM_MAX_DIFFERENCE).unwrap();
+
+    let frames: Vec<_> = sequence.frames.iter()
+        .map(|frame| (frame.timestamp, frame.rgb.strip_prefix(&directory).unwrap().to_owned(), frame.depth.strip_prefix(&directory).unwrap().to_owned()))
+        .collect();
+
+    assert_eq!(frames, vec![
+        (1.000, "rgb/1.000.png".into(), "depth/1.005.png".into()),
+        (1.033, "rgb/1.033.png".into(), "depth/1.030.png".into())
+    ]);
+
+    // Ground truth poses are camera to world in the file
+    assert_eq!(sequence.groundtruth.len(), 1);
+    assert_pose_eq(&sequence.groundtruth[0].1, &SE3::new(SO3::identity(), Vec3::new(-1.0, -2.0, -3.0)));
+
+    // Associations take precedence over the file lists
+    std::fs::write(directory.join("associations.txt"), "2.000 rgb/2.000.png 1.030 depth/1.030.png\n").unwrap();
+
+    let sequence = TumSequence::open(&directory, TUM_MAX_DIFFERENCE).unwrap();
+    assert_eq!(sequence.frames.len(), 1);
+    assert_eq!(sequence.frames[0].timestamp, 2.000);
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,129 @@
+use tinyslam::essential::{estimate_relative_pose, five_point};
+use tinyslam::geometry::{SE3, SO3};
+use tinyslam::linalg::{Mat3, Vec3};
+use tinyslam::random::Rng;
+use tinyslam::ransac::{RansacConfig, Sampling};
+
+/// Bearing vector perturbed by roughly `noise` radians
+fn perturb(bearing: Vec3, noise: f64, rng: &mut Rng) -> Vec3 {
+    let offset = Vec3::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5, rng.next_f64() - 0.5);
+    (bearing + offset * (2.0 * noise)).normalize()
+}
+
+struct Scene {
+    pose: SE3,
+    bearings1: Vec<Vec3>,
+    bearings2: Vec<Vec3>,
+    outlier: Vec<bool>
+}
+
+/// Points in front of both cameras seen with angular noise, followed by
+/// random bearing pairs
+fn scene(seed: u64, inliers: usize, outliers: usize, noise: f64) -> Scene {
+    let mut rng = Rng::new(seed);
+    let pose = SE3::new(SO3::exp(&(Vec3::new(0.2, 1.0, -0.1).normalize() * 0.15)), Vec3::new(-0.8, 0.1, 0.3));
+
+    let mut bearings1 = Vec::new();
+    let mut bearings2 = Vec::new();
+
+    while bearings1.len() < inliers {
+        let point = Vec3::new(
+            rng.next_f64() * 6.0 - 3.0,
+            rng.next_f64() * 4.0 - 2.0,
+            2.0 + rng.next_f64() * 8.0
+        );
+        let point2 = pose.transform(&point);
+
+        if point2.z() <= 0.5 {
+            continue;
+        }
+
+        bearings1.push(perturb(point.normalize(), noise, &mut rng));
+        bearings2.push(perturb(point2.normalize(), noise, &mut rng));
+    }
+
+    for _ in 0..outliers {
+        let random = |rng: &mut Rng| Vec3::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5, 1.0).normalize();
+        bearings1.push(random(&mut rng));
+        bearings2.push(random(&mut rng));
+    }
+
+    let outlier = (0..inliers + outliers).map(|i| i >= inliers).collect();
+
+    Scene { pose, bearings1, bearings2, outlier }
+}
+
+fn rotation_error(a: &Mat3, b: &Mat3) -> f64 {
+    let cos = ((a.transpose() * *b).trace() - 1.0) / 2.0;
+    cos.clamp(-1.0, 1.0).acos()
+}
+
+#[test]
+fn five_point_recovers_exact_essential_matrix() {
+    let scene = scene(1, 5, 0, 0.0);
+    let t = scene.pose.translation.normalize();
+    let truth = t.hat() * scene.pose.rotation.matrix();
+    let truth = truth * (1.0 / truth.norm());
+
+    let solutions = five_point(&scene.bearings1, &scene.bearings2);
+    assert!(!solutions.is_empty());
+
+    let best = solutions.iter()
+        .map(|e| (*e - truth).norm().min((*e + truth).norm()))
+        .fold(f64::INFINITY, f64::min);
+
+    assert!(best < 1e-6, "closest solution is {best} from the ground truth");
+
+    for e in &solutions {
+        for (b1, b2) in scene.bearings1.iter().zip(&scene.bearings2) {
+            assert!(b2.dot(&(*e * *b1)).abs() < 1e-8);
+        }
+    }
+}
+
+fn check_relative_pose(sampling: Sampling) {
+    let scene = scene(7, 200, 80, 0.001);
+    let config = RansacConfig {
+        threshold: 0.004,
+        sampling,
+        ..Default::default()
+    };
+
+    let result = estimate_relative_pose(&scene.bearings1, &scene.bearings2, &config, &mut Rng::new(42))
+        .expect("no relative pose found");
+
+    let rotation_error = rotation_error(&result.pose.rotation.matrix(), &scene.pose.rotation.matrix());
+    let translation_error = result.pose.translation.dot(&scene.pose.translation.normalize()).clamp(-1.0, 1.0).acos();
+
+    assert!(rotation_error < 0.01, "rotation error {rotation_error}");
+    assert!(translation_error < 0.05, "translation error {translation_error}");
+
+    let true_inliers = (0..scene.outlier.len()).filter(|&i| result.inliers[i] && !scene.outlier[i]).count();
+    let false_inliers = (0..scene.outlier.len()).filter(|&i| result.inliers[i] && scene.outlier[i]).count();
+
+    assert!(true_inliers >= 190, "only {true_inliers} of 200 inliers found");
+    assert!(false_inliers <= 4, "{false_inliers} outliers accepted");
+}
+
+#[test]
+fn ransac_rejects_outliers() {
+    check_relative_pose(Sampling::Uniform);
+}
+
+#[test]
+fn prosac_rejects_outliers() {
+    // Outliers come last, as if matches were sorted by descriptor distance
+    check_relative_pose(Sampling::Prosac);
+}
+
+#[test]
+fn seeded_results_are_reproducible() {
+    let scene = scene(3, 100, 50, 0.001);
+    let config = RansacConfig { threshold: 0.004, ..Default::default() };
+
+    let a = estimate_relative_pose(&scene.bearings1, &scene.bearings2, &config, &mut Rng::new(5)).unwrap();
+    let b = estimate_relative_pose(&scene.bearings1, &scene.bearings2, &config, &mut Rng::new(5)).unwrap();
+
+    assert_eq!(a.pose, b.pose);
+    assert_eq!(a.inliers, b.inliers);
+}
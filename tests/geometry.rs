@@ -0,0 +1,293 @@
+use tinyslam::geometry::{Sim3, SE3, SO3};
+use tinyslam::linalg::{Matrix, Vec3, Vec6, Vec7, Vector};
+use tinyslam::random::Rng;
+
+/// Number of random elements each property is checked on
+const SAMPLES: usize = 200;
+
+fn random_vector<const N: usize>(rng: &mut Rng, magnitude: f64) -> Vector<N> {
+    Vector::<N>::from_array(std::array::from_fn(|_| (2.0 * rng.next_f64() - 1.0) * magnitude))
+}
+
+/// Rotation vector with angle below pi
+fn random_rotation_vector(rng: &mut Rng) -> Vec3 {
+    let axis = random_vector::<3>(rng, 1.0).normalize();
+    axis * (rng.next_f64() * 3.1)
+}
+
+fn random_so3(rng: &mut Rng) -> SO3 {
+    SO3::exp(&random_rotation_vector(rng))
+}
+
+fn random_se3(rng: &mut Rng) -> SE3 {
+    SE3::new(random_so3(rng), random_vector(rng, 5.0))
+}
+
+fn random_sim3(rng: &mut Rng) -> Sim3 {
+    Sim3::new(random_so3(rng), random_vector(rng, 5.0), (2.0 * rng.next_f64() - 1.0).exp())
+}
+
+fn se3_distance(a: &SE3, b: &SE3) -> f64 {
+    (a.rotation.matrix() - b.rotation.matrix()).norm() + (a.translation - b.translation).norm()
+}
+
+fn sim3_distance(a: &Sim3, b: &Sim3) -> f64 {
+    (a.rotation.matrix() - b.rotation.matrix()).norm() +
+        (a.translation - b.translation).norm() +
+        (a.scale - b.scale).abs()
+}
+
+fn unit<const N: usize>(i: usize) -> Vector<N> {
+    let mut v = Vector::<N>::zeros();
+    v[i] = 1.0;
+    v
+}
+
+/// Central difference estimate of the Jacobian of `f` at zero
+fn numerical_jacobian<const N: usize>(f: impl Fn(&Vector<N>) -> Vector<N>) -> Matrix<N, N> {
+    let h = 1e-6;
+    let mut jacobian = Matrix::<N, N>::zeros();
+
+    for i in 0..N {
+        let column = (f(&(unit::<N>(i) * h)) - f(&(unit::<N>(i) * -h))) * (0.5 / h);
+        jacobian.set_column(i, &column);
+    }
+
+    jacobian
+}
+
+#[test]
+fn so3_group_axioms() {
+    let mut rng = Rng::new(1);
+
+    for _ in 0..SAMPLES {
+        let (a, b, c) = (random_so3(&mut rng), random_so3(&mut rng), random_so3(&mut rng));
+
+        assert!((((a * b) * c).matrix() - (a * (b * c)).matrix()).norm() < 1e-12);
+        assert!(((a * SO3::identity()).matrix() - a.matrix()).norm() < 1e-15);
+        assert!(((a * a.inverse()).matrix() - SO3::identity().matrix()).norm() < 1e-12);
+        assert!((a.matrix().determinant() - 1.0).abs() < 1e-12);
+
+        let p = random_vector::<3>(&mut rng, 5.0);
+        assert!(((a * b) * p - a * (b * p)).norm() < 1e-12);
+    }
+}
+
+#[test]
+fn se3_group_axioms() {
+    let mut rng = Rng::new(2);
+
+    for _ in 0..SAMPLES {
+        let (a, b, c) = (random_se3(&mut rng), random_se3(&mut rng), random_se3(&mut rng));
+
+        assert!(se3_distance(&((a * b) * c), &(a * (b * c))) < 1e-12);
+        assert!(se3_distance(&(a * SE3::identity()), &a) < 1e-15);
+        assert!(se3_distance(&(SE3::identity() * a), &a) < 1e-15);
+        assert!(se3_distance(&(a * a.inverse()), &SE3::identity()) < 1e-12);
+
+        let p = random_vector::<3>(&mut rng, 5.0);
+        assert!(((a * b).transform(&p) - a.transform(&b.transform(&p))).norm() < 1e-12);
+        assert!((a.inverse().transform(&a.transform(&p)) - p).norm() < 1e-12);
+    }
+}
+
+#[test]
+fn sim3_group_axioms() {
+    let mut rng = Rng::new(3);
+
+    for _ in 0..SAMPLES {
+        let (a, b, c) = (random_sim3(&mut rng), random_sim3(&mut rng), random_sim3(&mut rng));
+
+        assert!(sim3_distance(&((a * b) * c), &(a * (b * c))) < 1e-11);
+        assert!(sim3_distance(&(a * Sim3::identity()), &a) < 1e-15);
+        assert!(sim3_distance(&(a * a.inverse()), &Sim3::identity()) < 1e-12);
+
+        let p = random_vector::<3>(&mut rng, 5.0);
+        assert!(((a * b).transform(&p) - a.transform(&b.transform(&p))).norm() < 1e-11);
+        assert!((a.inverse().transform(&a.transform(&p)) - p).norm() < 1e-11);
+    }
+}
+
+#[test]
+fn exp_and_log_are_inverse() {
+    let mut rng = Rng::new(4);
+
+    for _ in 0..SAMPLES {
+        let omega = random_rotation_vector(&mut rng);
+        assert!((SO3::exp(&omega).log() - omega).norm() < 1e-9);
+
+        let mut xi = Vec6::zeros();
+        xi.set_segment(0, &omega);
+        xi.set_segment(3, &random_vector::<3>(&mut rng, 5.0));
+        assert!((SE3::exp(&xi).log() - xi).norm() < 1e-9);
+
+        let mut zeta = Vec7::zeros();
+        zeta.set_segment(0, &omega);
+        zeta.set_segment(3, &random_vector::<3>(&mut rng, 5.0));
+        zeta[6] = 2.0 * rng.next_f64() - 1.0;
+        assert!((Sim3::exp(&zeta).log() - zeta).norm() < 1e-9);
+
+        let a = random_se3(&mut rng);
+        assert!(se3_distance(&SE3::exp(&a.log()), &a) < 1e-9);
+
+        let s = random_sim3(&mut rng);
+        assert!(sim3_distance(&Sim3::exp(&s.log()), &s) < 1e-9);
+    }
+}
+
+#[test]
+fn exp_and_log_near_singularities() {
+    let mut rng = Rng::new(5);
+
+    for _ in 0..SAMPLES {
+        let axis = random_vector::<3>(&mut rng, 1.0).normalize();
+
+        for angle in [0.0, 1e-9, 1e-7, 1e-5, std::f64::consts::PI - 1e-7, std::f64::consts::PI] {
+            let omega = axis * angle;
+            let rotation = SO3::exp(&omega);
+            let recovered = SO3::exp(&rotation.log());
+
+            assert!((recovered.matrix() - rotation.matrix()).norm() < 1e-9, "angle {angle}");
+        }
+
+        // Sim(3) with no rotation or no scale change
+        let mut zeta = Vec7::zeros();
+        zeta.set_segment(3, &random_vector::<3>(&mut rng, 5.0));
+        zeta[6] = 0.5;
+        assert!((Sim3::exp(&zeta).log() - zeta).norm() < 1e-9);
+
+        zeta.set_segment(0, &random_rotation_vector(&mut rng));
+        zeta[6] = 0.0;
+        assert!((Sim3::exp(&zeta).log() - zeta).norm() < 1e-9);
+    }
+}
+
+#[test]
+fn adjoints_conjugate_the_exponential() {
+    let mut rng = Rng::new(6);
+
+    for _ in 0..SAMPLES {
+        let r = random_so3(&mut rng);
+        let omega = random_vector::<3>(&mut rng, 1.0);
+        let lhs = r * SO3::exp(&omega) * r.inverse();
+        assert!((lhs.matrix() - SO3::exp(&(r.adjoint() * omega)).matrix()).norm() < 1e-12);
+
+        let t = random_se3(&mut rng);
+        let xi = random_vector::<6>(&mut rng, 1.0);
+        let lhs = t * SE3::exp(&xi) * t.inverse();
+        assert!(se3_distance(&lhs, &SE3::exp(&(t.adjoint() * xi))) < 1e-10);
+
+        let s = random_sim3(&mut rng);
+        let zeta = random_vector::<7>(&mut rng, 1.0);
+        let lhs = s * Sim3::exp(&zeta) * s.inverse();
+        assert!(sim3_distance(&lhs, &Sim3::exp(&(s.adjoint() * zeta))) < 1e-10);
+    }
+}
+
+#[test]
+fn so3_jacobians_match_numerical_derivatives() {
+    let mut rng = Rng::new(7);
+
+    for _ in 0..SAMPLES {
+        let omega = random_rotation_vector(&mut rng) * 0.95;
+        let rotation = SO3::exp(&omega);
+
+        let left = numerical_jacobian(|d| (SO3::exp(&(omega + *d)) * rotation.inverse()).log());
+        let right = numerical_jacobian(|d| (rotation.inverse() * SO3::exp(&(omega + *d))).log());
+
+        assert!((left - SO3::left_jacobian(&omega)).norm() < 1e-6);
+        assert!((right - SO3::right_jacobian(&omega)).norm() < 1e-6);
+
+        let identity = SO3::left_jacobian(&omega) * SO3::left_jacobian_inverse(&omega);
+        assert!((identity - Matrix::<3, 3>::identity()).norm() < 1e-9);
+
+        let identity = SO3::right_jacobian(&omega) * SO3::right_jacobian_inverse(&omega);
+        assert!((identity - Matrix::<3, 3>::identity()).norm() < 1e-9);
+    }
+}
+
+#[test]
+fn se3_jacobians_match_numerical_derivatives() {
+    let mut rng = Rng::new(8);
+
+    for _ in 0..SAMPLES {
+        let mut xi = random_vector::<6>(&mut rng, 2.0);
+        xi.set_segment(0, &(random_rotation_vector(&mut rng) * 0.95));
+        let pose = SE3::exp(&xi);
+
+        let left = numerical_jacobian(|d| (SE3::exp(&(xi + *d)) * pose.inverse()).log());
+        let right = numerical_jacobian(|d| (pose.inverse() * SE3::exp(&(xi + *d))).log());
+
+        assert!((left - SE3::left_jacobian(&xi)).norm() < 1e-5);
+        assert!((right - SE3::right_jacobian(&xi)).norm() < 1e-5);
+
+        let identity = SE3::left_jacobian(&xi) * SE3::left_jacobian_inverse(&xi);
+        assert!((identity - Matrix::<6, 6>::identity()).norm() < 1e-9);
+
+        let identity = SE3::right_jacobian(&xi) * SE3::right_jacobian_inverse(&xi);
+        assert!((identity - Matrix::<6, 6>::identity()).norm() < 1e-9);
+    }
+}
+
+#[test]
+fn sim3_jacobians_match_numerical_derivatives() {
+    let mut rng = Rng::new(9);
+
+    for _ in 0..SAMPLES {
+        let mut zeta = random_vector::<7>(&mut rng, 1.0);
+        zeta.set_segment(0, &(random_rotation_vector(&mut rng) * 0.95));
+        let s = Sim3::exp(&zeta);
+
+        let left = numerical_jacobian(|d| (Sim3::exp(&(zeta + *d)) * s.inverse()).log());
+        let right = numerical_jacobian(|d| (s.inverse() * Sim3::exp(&(zeta + *d))).log());
+
+        assert!((left - Sim3::left_jacobian(&zeta)).norm() < 1e-5);
+        assert!((right - Sim3::right_jacobian(&zeta)).norm() < 1e-5);
+    }
+}
+
+#[test]
+fn interpolation_follows_the_geodesic() {
+    let mut rng = Rng::new(10);
+
+    for _ in 0..SAMPLES {
+        let (a, b) = (random_se3(&mut rng), random_se3(&mut rng));
+
+        assert!(se3_distance(&a.interpolate(&b, 0.0), &a) < 1e-9);
+        assert!(se3_distance(&a.interpolate(&b, 1.0), &b) < 1e-9);
+
+        // Halfway twice from the start reaches the end
+        let middle = a.interpolate(&b, 0.5);
+        let delta = a.inverse() * middle;
+        assert!(se3_distance(&(middle * delta), &b) < 1e-9);
+
+        let (r, q) = (random_so3(&mut rng), random_so3(&mut rng));
+        let angle = (r.inverse() * q).log().norm();
+        let quarter = r.interpolate(&q, 0.25);
+        assert!(((r.inverse() * quarter).log().norm() - 0.25 * angle).abs() < 1e-9);
+
+        let (s, u) = (random_sim3(&mut rng), random_sim3(&mut rng));
+        assert!(sim3_distance(&s.interpolate(&u, 1.0), &u) < 1e-9);
+        assert!((s.interpolate(&u, 0.5).scale - (s.scale * u.scale).sqrt()).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn quaternions_round_trip() {
+    let mut rng = Rng::new(11);
+
+    for _ in 0..SAMPLES {
+        let rotation = random_so3(&mut rng);
+        let quaternion = rotation.to_quaternion();
+
+        assert!(quaternion[0] >= 0.0);
+        assert!((quaternion.iter().map(|q| q * q).sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!((SO3::from_quaternion(quaternion).matrix() - rotation.matrix()).norm() < 1e-9);
+    }
+
+    // Half turns, where w vanishes and another component is pivoted on
+    for axis in [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)] {
+        let rotation = SO3::exp(&(axis * std::f64::consts::PI));
+        assert!((SO3::from_quaternion(rotation.to_quaternion()).matrix() - rotation.matrix()).norm() < 1e-9);
+    }
+}
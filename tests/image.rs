@@ -0,0 +1,124 @@
+use std::io;
+use std::path::Path;
+
+use tinyslam::image::{decode_png, decode_pnm, load, DecodedImage};
+
+// The PNG fixtures cycle through all five row filters and split IDAT over two
+// chunks. rgb8.png uses dynamic Huffman blocks, rgb8_stored.png uncompressed
+// blocks, and gray16.png and palette.png fixed Huffman blocks.
+const WIDTH: u32 = 37;
+const HEIGHT: u32 = 23;
+
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)).unwrap()
+}
+
+fn expected_rgb8() -> Vec<u8> {
+    (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).flat_map(move |x| [(x * 7 % 256) as u8, (y * 11 % 256) as u8, (x * y % 256) as u8]))
+        .collect()
+}
+
+fn unwrap_u8(image: DecodedImage) -> (u32, u32, u32, Vec<u8>) {
+    match image {
+        DecodedImage::U8(image) => (image.width, image.height, image.channels, image.data),
+        DecodedImage::U16(_) => panic!("expected 8 bit samples")
+    }
+}
+
+fn unwrap_u16(image: DecodedImage) -> (u32, u32, u32, Vec<u16>) {
+    match image {
+        DecodedImage::U16(image) => (image.width, image.height, image.channels, image.data),
+        DecodedImage::U8(_) => panic!("expected 16 bit samples")
+    }
+}
+
+#[test]
+fn decodes_rgb_png_with_every_filter() {
+    for name in ["rgb8.png", "rgb8_stored.png"] {
+        let (width, height, channels, data) = unwrap_u8(decode_png(&fixture(name)).unwrap());
+
+        assert_eq!((width, height, channels), (WIDTH, HEIGHT, 3), "{name}");
+        assert!(data == expected_rgb8(), "{name}");
+    }
+}
+
+#[test]
+fn decodes_16_bit_grayscale_png() {
+    let (width, height, channels, data) = unwrap_u16(decode_png(&fixture("gray16.png")).unwrap());
+
+    let expected: Vec<u16> = (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| ((x * 1000 + y * 37) % 65536) as u16))
+        .collect();
+
+    assert_eq!((width, height, channels), (WIDTH, HEIGHT, 1));
+    assert_eq!(data, expected);
+}
+
+#[test]
+fn expands_palette_png_to_rgb() {
+    let (width, height, channels, data) = unwrap_u8(decode_png(&fixture("palette.png")).unwrap());
+
+    let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+    let expected: Vec<u8> = (0..4).flat_map(|y| (0..5).flat_map(move |x| colors[(x + y) % 3])).collect();
+
+    assert_eq!((width, height, channels), (5, 4, 3));
+    assert_eq!(data, expected);
+}
+
+#[test]
+fn rejects_corrupt_png() {
+    let bytes = fixture("rgb8.png");
+
+    assert!(decode_png(&bytes[1..]).is_err());
+    assert!(decode_png(&bytes[..bytes.len() / 2]).is_err());
+
+    // Flipping bits of the compressed stream must not panic
+    for i in 40..bytes.len() - 12 {
+        let mut corrupt = bytes.clone();
+        corrupt[i] ^= 0x5a;
+        let _ = decode_png(&corrupt);
+    }
+}
+
+#[test]
+fn decodes_binary_pnm() {
+    let mut pgm = b"P5\n# comment\n3 2\n255\n".to_vec();
+    pgm.extend_from_slice(&[0, 1, 2, 3, 4, 5]);
+    assert_eq!(unwrap_u8(decode_pnm(&pgm).unwrap()), (3, 2, 1, vec![0, 1, 2, 3, 4, 5]));
+
+    let mut ppm = b"P6 1 2 65535\n".to_vec();
+    ppm.extend_from_slice(&[0x12, 0x34, 0, 1, 0xff, 0xff, 0, 0, 0, 2, 0x80, 0]);
+    assert_eq!(unwrap_u16(decode_pnm(&ppm).unwrap()), (1, 2, 3, vec![0x1234, 1, 0xffff, 0, 2, 0x8000]));
+
+    // 16 bit samples keep their most significant byte
+    let rgba = decode_pnm(&ppm).unwrap().to_rgba8();
+    assert_eq!(rgba.data, vec![0x12, 0, 0xff, 255, 0, 0, 0x80, 255]);
+}
+
+#[test]
+fn rejects_invalid_pnm() {
+    let error = |bytes: &[u8]| decode_pnm(bytes).err().map(|e| e.kind());
+
+    assert_eq!(error(b"P3\n1 1\n255\n0 0 0"), Some(io::ErrorKind::InvalidData));
+    assert_eq!(error(b"P5\n2 2\n255\n\x00\x01\x02"), Some(io::ErrorKind::InvalidData));
+    assert_eq!(error(b"P5\n2 2"), Some(io::ErrorKind::InvalidData));
+
+    // Dimensions whose product overflows are an error rather than a panic
+    assert_eq!(error(b"P6\n4294967295 4294967295\n65535\n\x00"), Some(io::ErrorKind::InvalidData));
+    assert_eq!(error(b"P5\n65536 65536\n255\n\x00"), Some(io::ErrorKind::InvalidData));
+}
+
+#[test]
+fn loads_by_extension() {
+    let path = std::env::temp_dir().join(format!("tinyslam_image_{}.pgm", std::process::id()));
+    std::fs::write(&path, b"P5 2 1 255\n\x0a\x0b").unwrap();
+
+    let image = load(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(unwrap_u8(image.unwrap()), (2, 1, 1, vec![10, 11]));
+
+    let (width, height, _, _) = unwrap_u8(load(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/rgb8.png")).unwrap());
+    assert_eq!((width, height), (WIDTH, HEIGHT));
+}
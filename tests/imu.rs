@@ -0,0 +1,293 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use tinyslam::bundle_adjustment::{solve_bundle, BundleProblem, CpuLinearizer, InertialEdge, InertialState, InertialTerms};
+use tinyslam::camera::{CameraModel, Pinhole};
+use tinyslam::frame::{Frame, ScalePyramid};
+use tinyslam::geometry::{SE3, SO3};
+use tinyslam::imu::{
+    initialize_inertial, ImuBias, ImuCalibration, ImuMeasurement, ImuState, InertialInitConfig, NavState, Preintegrated, GRAVITY
+};
+use tinyslam::linalg::{Vec2, Vec3, Vector};
+use tinyslam::map::Map;
+use tinyslam::optimizer::{optimize_inertial_pose, InertialPrior, PoseObservation, PoseOptimizerConfig};
+use tinyslam::orb::{CornerData, CornerDescriptor};
+
+const RATE: f64 = 1000.0;
+
+fn gravity() -> Vec3 {
+    Vec3::new(0.0, 0.0, -GRAVITY)
+}
+
+fn calibration() -> ImuCalibration {
+    let camera_from_body = SE3::new(SO3::exp(&Vec3::new(-1.5, 0.1, 0.05)), Vec3::new(0.02, -0.06, 0.01));
+    ImuCalibration { camera_from_body, ..ImuCalibration::default() }
+}
+
+/// Angular velocity of the body, constant in body coordinates
+fn angular_velocity() -> Vec3 {
+    Vec3::new(0.3, -0.2, 0.5)
+}
+
+/// Body state of a smooth trajectory with varying acceleration
+fn state(t: f64) -> NavState {
+    NavState {
+        rotation: SO3::exp(&(angular_velocity() * t)),
+        position: Vec3::new(t.sin(), 0.5 * (2.0 * t).cos(), 0.3 * t * t),
+        velocity: Vec3::new(t.cos(), -(2.0 * t).sin(), 0.6 * t)
+    }
+}
+
+fn world_acceleration(t: f64) -> Vec3 {
+    Vec3::new(-t.sin(), -2.0 * (2.0 * t).cos(), 0.6)
+}
+
+/// Measurements of the trajectory from `start` to `end`, offset by `bias`.
+/// Each holds the acceleration at the middle of its period in the body
+/// orientation at its start, which integrates to second order.
+fn measurements(start: f64, end: f64, bias: &ImuBias) -> Vec<ImuMeasurement> {
+    let count = ((end - start) * RATE).round() as usize;
+
+    (0..count)
+        .map(|k| {
+            let timestamp = start + k as f64 / RATE;
+            let rotation = state(timestamp).rotation;
+
+            ImuMeasurement {
+                timestamp,
+                acceleration: rotation.inverse() * (world_acceleration(timestamp + 0.5 / RATE) - gravity()) + bias.accel,
+                angular_velocity: angular_velocity() + bias.gyro
+            }
+        })
+        .collect()
+}
+
+fn preintegrate(start: f64, end: f64, measured: &ImuBias, integrated: ImuBias) -> Preintegrated {
+    let mut preintegration = Preintegrated::new(calibration(), integrated);
+    preintegration.integrate_interval(&measurements(start, end, measured), start, end);
+    preintegration
+}
+
+fn camera_pose(t: f64) -> SE3 {
+    state(t).camera_pose(&calibration().camera_from_body)
+}
+
+fn pose_error(a: &SE3, b: &SE3) -> f64 {
+    (a.translation - b.translation).norm() + (a.rotation.matrix() - b.rotation.matrix()).norm()
+}
+
+#[test]
+fn preintegration_predicts_the_trajectory() {
+    let bias = ImuBias::default();
+    let preintegration = preintegrate(0.5, 1.5, &bias, bias);
+
+    assert!((preintegration.dt - 1.0).abs() < 1e-9);
+
+    let predicted = preintegration.predict(&state(0.5), &bias, &gravity());
+    let truth = state(1.5);
+
+    assert!((predicted.position - truth.position).norm() < 1e-4);
+    assert!((predicted.velocity - truth.velocity).norm() < 1e-4);
+    assert!((predicted.rotation.matrix() - truth.rotation.matrix()).norm() < 1e-6);
+
+    // The true motion has a negligible error, and noise grows with time
+    let (pose_i, pose_j) = (camera_pose(0.5), camera_pose(1.5));
+    let chi2 = preintegration.chi2(&pose_i, &state(0.5).velocity, &pose_j, &truth.velocity, &bias, &gravity());
+    assert!(chi2 < 1e-2, "{chi2}");
+
+    let shorter = preintegrate(0.5, 1.0, &bias, bias);
+    assert!(shorter.covariance[(8, 8)] < preintegration.covariance[(8, 8)]);
+    assert!(shorter.walk_covariance[(0, 0)] < preintegration.walk_covariance[(0, 0)]);
+}
+
+#[test]
+fn bias_jacobians_correct_the_deltas() {
+    let measured = ImuBias { gyro: Vec3::new(0.004, -0.003, 0.002), accel: Vec3::new(0.03, 0.02, -0.04) };
+    let mut preintegration = preintegrate(0.0, 0.5, &measured, ImuBias::default());
+
+    let rotation = preintegration.rotation(&measured);
+    let velocity = preintegration.velocity(&measured);
+    let position = preintegration.position(&measured);
+
+    preintegration.reintegrate(measured);
+
+    assert!((rotation.matrix() - preintegration.delta_rotation.matrix()).norm() < 1e-5);
+    assert!((velocity - preintegration.delta_velocity).norm() < 1e-4);
+    assert!((position - preintegration.delta_position).norm() < 1e-5);
+
+    // Integrated with the right bias, the deltas are those of the trajectory
+    let predicted = preintegration.predict(&state(0.0), &measured, &gravity());
+    assert!((predicted.position - state(0.5).position).norm() < 1e-4);
+}
+
+#[test]
+fn initialisation_recovers_gravity_bias_scale_and_velocities() {
+    let bias = ImuBias { gyro: Vec3::new(0.01, -0.02, 0.015), accel: Vec3::zeros() };
+    let times: Vec<f64> = (0..13).map(|k| 0.25 * k as f64).collect();
+
+    // A monocular map at half the true scale
+    let scale = 2.0;
+    let poses: Vec<SE3> = times.iter()
+        .map(|&t| {
+            let pose = camera_pose(t);
+            SE3::new(pose.rotation, pose.translation * (1.0 / scale))
+        })
+        .collect();
+
+    let preintegrations: Vec<Preintegrated> = times.windows(2)
+        .map(|t| preintegrate(t[0], t[1], &bias, ImuBias::default()))
+        .collect();
+
+    let result = initialize_inertial(&InertialInitConfig::default(), &poses, &preintegrations, true).unwrap();
+
+    assert!((result.scale - scale).abs() < 1e-2, "{}", result.scale);
+    assert!((result.gravity - gravity()).norm() < 1e-2);
+    assert!((result.bias.gyro - bias.gyro).norm() < 1e-4);
+    assert!(result.bias.accel.norm() < 1e-2);
+
+    for (&t, velocity) in times.iter().zip(&result.velocities) {
+        assert!((*velocity - state(t).velocity).norm() < 2e-2);
+    }
+
+    // Too few keyframes
+    assert!(initialize_inertial(&InertialInitConfig::default(), &poses[..2], &preintegrations[..1], true).is_none());
+}
+
+#[test]
+fn inertial_prior_tracks_frames_with_few_observations() {
+    let bias = ImuBias::default();
+    let camera = Pinhole::new(400.0, 400.0, 320.0, 240.0);
+    let scale = ScalePyramid::new(8, 1.2);
+    let config = PoseOptimizerConfig::default();
+
+    let prior = InertialPrior {
+        pose: camera_pose(1.0),
+        velocity: state(1.0).velocity,
+        bias,
+        gravity: gravity(),
+        preintegration: preintegrate(1.0, 1.05, &bias, bias)
+    };
+
+    let truth = camera_pose(1.05);
+    let start = SE3::new(truth.rotation * SO3::exp(&Vec3::new(0.02, -0.01, 0.01)), truth.translation + Vec3::new(0.05, 0.03, -0.04));
+
+    // Without observations, as when motion blur leaves no corners
+    let (pose, velocity, inliers) = optimize_inertial_pose(&config, &camera, 0.0, &scale, &[], &mut [], &prior, start, Vec3::zeros());
+
+    assert_eq!(inliers, 0);
+    assert!(pose_error(&pose, &truth) < 1e-4);
+    assert!((velocity - state(1.05).velocity).norm() < 1e-3);
+
+    // A few observations, one of them an outlier
+    let observations: Vec<PoseObservation> = [(300.0, 200.0, 2.0), (350.0, 260.0, 3.0), (280.0, 250.0, 2.5), (330.0, 230.0, 4.0)]
+        .iter()
+        .enumerate()
+        .map(|(i, &(x, y, depth))| {
+            let pixel = Vec2::new(x, y);
+            let ray = camera.unproject(&pixel);
+            let point = truth.inverse().transform(&(ray * (depth / ray.z())));
+            let pixel = if i == 3 { Vec2::new(x + 40.0, y) } else { pixel };
+
+            PoseObservation { point, pixel, right: None, octave: 0 }
+        })
+        .collect();
+
+    let mut outliers = vec![false; observations.len()];
+    let (pose, _, inliers) = optimize_inertial_pose(&config, &camera, 0.0, &scale, &observations, &mut outliers, &prior, start, Vec3::zeros());
+
+    assert_eq!(inliers, 3);
+    assert_eq!(outliers, [false, false, false, true]);
+    assert!(pose_error(&pose, &truth) < 1e-4);
+}
+
+#[test]
+fn bundle_adjustment_recovers_inertial_states() {
+    let bias = ImuBias::default();
+    let times = [0.0, 0.2, 0.4, 0.6];
+    let camera: Arc<dyn CameraModel> = Arc::new(Pinhole::new(400.0, 400.0, 320.0, 240.0));
+
+    let edges = (1..times.len())
+        .map(|k| InertialEdge { from: k - 1, to: k, preintegration: preintegrate(times[k - 1], times[k], &bias, bias) })
+        .collect();
+
+    // The first pose is fixed and the others perturbed
+    let states = times.iter()
+        .enumerate()
+        .map(|(k, &t)| Some(InertialState { velocity: state(t).velocity + Vec3::new(0.1, -0.1, 0.05) * k as f64, bias }))
+        .collect();
+
+    let poses = times.iter()
+        .enumerate()
+        .map(|(k, &t)| camera_pose(t).retract(&(Vector::<6>::from_array([0.01, -0.02, 0.01, 0.03, 0.02, -0.03]) * k as f64)))
+        .collect();
+
+    let mut problem = BundleProblem {
+        poses,
+        fixed: vec![true, false, false, false],
+        cameras: vec![camera; times.len()],
+        points: Vec::new(),
+        observations: Vec::new(),
+        inertial: Some(InertialTerms { gravity: gravity(), states, edges })
+    };
+
+    let summary = solve_bundle(&CpuLinearizer, &mut problem, &[], None, 20, &AtomicBool::new(false));
+
+    assert!(summary.final_cost < 1e-6 * summary.initial_cost);
+
+    let states = &problem.inertial.as_ref().unwrap().states;
+
+    for (k, &t) in times.iter().enumerate() {
+        let estimate = states[k].unwrap();
+        assert!(pose_error(&problem.poses[k], &camera_pose(t)) < 1e-4);
+        assert!((estimate.velocity - state(t).velocity).norm() < 1e-3);
+        assert!(estimate.bias.gyro.norm() < 1e-4 && estimate.bias.accel.norm() < 1e-3);
+    }
+}
+
+#[test]
+fn erased_keyframes_merge_their_preintegration() {
+    let bias = ImuBias::default();
+    let times = [0.0, 0.3, 0.7];
+    let mut map = Map::new();
+
+    let ids: Vec<_> = times.iter()
+        .enumerate()
+        .map(|(k, &t)| {
+            let size = wgpu::Extent3d { width: 640, height: 480, depth_or_array_layers: 1 };
+            let corners = vec![CornerData { x: 320, y: 240, angle: 0, octave: 0, camera: 0 }];
+            let descriptors = vec![CornerDescriptor { bits: [0; 32] }];
+            let camera = Arc::new(Pinhole::new(400.0, 400.0, 320.0, 240.0));
+            let mut frame = Frame::new(k as u64, t, camera, size, ScalePyramid::new(8, 1.2), corners, descriptors);
+
+            frame.pose = Some(camera_pose(t));
+            frame.imu = Some(ImuState {
+                velocity: state(t).velocity,
+                bias,
+                preintegration: (k > 0).then(|| preintegrate(times[k - 1], t, &bias, bias))
+            });
+
+            map.add_keyframe(frame)
+        })
+        .collect();
+
+    // A point seen by all of them connects the keyframes
+    let point = map.add_point(Vec3::new(0.0, 0.0, 5.0), ids[0], 0);
+
+    for &id in &ids {
+        map.add_observation(id, 0, point);
+    }
+
+    for &id in &ids {
+        map.update_connections(id);
+    }
+
+    assert!(map.erase_keyframe(ids[1]));
+    assert_eq!(map.previous_inertial(ids[2]), Some(ids[0]));
+
+    let merged = map.keyframe(ids[2]).unwrap().frame.imu.as_ref().unwrap().preintegration.clone().unwrap();
+    let direct = preintegrate(0.0, 0.7, &bias, bias);
+
+    assert!((merged.dt - 0.7).abs() < 1e-9);
+    assert!((merged.delta_position - direct.delta_position).norm() < 1e-9);
+    assert!((merged.covariance[(8, 8)] - direct.covariance[(8, 8)]).abs() < 1e-12);
+}
@@ -0,0 +1,126 @@
+use tinyslam::camera::{CameraModel, Pinhole};
+use tinyslam::geometry::{SE3, SO3};
+use tinyslam::initializer::{reconstruct, Initialization, InitializerConfig, TwoViewModel};
+use tinyslam::linalg::{Vec2, Vec3};
+use tinyslam::random::Rng;
+
+const POINTS: usize = 300;
+
+fn pinhole() -> Pinhole {
+    Pinhole::new(500.0, 500.0, 320.0, 240.0)
+}
+
+fn ground_truth_pose() -> SE3 {
+    SE3::new(SO3::exp(&Vec3::new(0.02, -0.05, 0.01)), Vec3::new(-1.2, 0.1, 0.05))
+}
+
+/// Points seen by both cameras, either on a tilted plane or spread in depth
+fn scene(rng: &mut Rng, pose: &SE3, planar: bool) -> Vec<Vec3> {
+    let mut points = Vec::new();
+
+    while points.len() < POINTS {
+        let (x, y) = (rng.next_f64() * 6.0 - 3.0, rng.next_f64() * 4.0 - 2.0);
+        let z = if planar { 6.0 + 0.3 * x - 0.2 * y } else { 3.0 + 6.0 * rng.next_f64() };
+        let point = Vec3::new(x * z / 6.0, y * z / 6.0, z);
+
+        let inside = |p: Vec3| {
+            let pixel = pinhole().project(&p);
+            p.z() > 0.0 && (0.0..640.0).contains(&pixel.x()) && (0.0..480.0).contains(&pixel.y())
+        };
+
+        if inside(point) && inside(pose.transform(&point)) {
+            points.push(point);
+        }
+    }
+
+    points
+}
+
+/// Projections with up to half a pixel of noise
+fn observe(rng: &mut Rng, pose: &SE3, points: &[Vec3]) -> Vec<Vec2> {
+    points.iter()
+        .map(|p| pinhole().project(&pose.transform(p)) + Vec2::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5))
+        .collect()
+}
+
+fn reconstruct_scene(planar: bool, pose: &SE3, seed: u64) -> (Vec<Vec3>, Option<Initialization>) {
+    let mut rng = Rng::new(seed);
+    let points = scene(&mut rng, pose, planar);
+
+    let reference = observe(&mut rng, &SE3::identity(), &points);
+    let current = observe(&mut rng, pose, &points);
+
+    let initialization = reconstruct(&InitializerConfig::default(), &pinhole(), &reference, &current, &mut rng);
+    (points, initialization)
+}
+
+/// Pose and structure match the ground truth up to the scale of the baseline
+fn assert_matches_ground_truth(initialization: &Initialization, pose: &SE3, points: &[Vec3]) {
+    let scale = pose.translation.norm();
+
+    let rotation_error = (initialization.pose.rotation.inverse() * pose.rotation).log().norm();
+    assert!(rotation_error < 0.03, "rotation error {rotation_error}");
+
+    let direction_error = (initialization.pose.translation.normalize() - pose.translation.normalize()).norm();
+    assert!(direction_error < 0.1, "translation direction error {direction_error}");
+    assert!((initialization.pose.translation.norm() - 1.0).abs() < 1e-9);
+
+    let mut triangulated = 0;
+
+    for (estimate, truth) in initialization.points.iter().zip(points) {
+        if let Some(estimate) = estimate {
+            let truth = *truth * (1.0 / scale);
+            assert!((*estimate - truth).norm() < 0.1 * truth.norm(), "{:?} != {:?}", estimate.to_array(), truth.to_array());
+            triangulated += 1;
+        }
+    }
+
+    assert!(triangulated > POINTS * 8 / 10, "{triangulated} points triangulated");
+}
+
+/// Parallax of the 51st most oblique point, as `Initialization::parallax` reports it
+fn expected_parallax(pose: &SE3, points: &[Vec3]) -> f64 {
+    let center = pose.camera_center();
+    let mut parallaxes: Vec<f64> = points.iter()
+        .map(|p| (p.dot(&(*p - center)) / (p.norm() * (*p - center).norm())).acos().to_degrees())
+        .collect();
+
+    parallaxes.sort_by(|a, b| b.total_cmp(a));
+    parallaxes[50]
+}
+
+fn assert_parallax(initialization: &Initialization, pose: &SE3, points: &[Vec3]) {
+    // The estimated pose is not refined, so allow a few percent of error
+    let expected = expected_parallax(pose, points);
+    assert!((initialization.parallax - expected).abs() < 0.07 * expected, "{} != {expected}", initialization.parallax);
+}
+
+#[test]
+fn planar_scenes_select_the_homography() {
+    let pose = ground_truth_pose();
+    let (points, initialization) = reconstruct_scene(true, &pose, 1);
+    let initialization = initialization.expect("planar scene should initialise");
+
+    assert_eq!(initialization.model, TwoViewModel::Homography);
+    assert_matches_ground_truth(&initialization, &pose, &points);
+    assert_parallax(&initialization, &pose, &points);
+}
+
+#[test]
+fn general_scenes_select_the_fundamental_matrix() {
+    let pose = ground_truth_pose();
+    let (points, initialization) = reconstruct_scene(false, &pose, 2);
+    let initialization = initialization.expect("general scene should initialise");
+
+    assert_eq!(initialization.model, TwoViewModel::Fundamental);
+    assert_matches_ground_truth(&initialization, &pose, &points);
+    assert_parallax(&initialization, &pose, &points);
+}
+
+#[test]
+fn pure_rotation_does_not_initialise() {
+    let pose = SE3::new(SO3::exp(&Vec3::new(0.0, 0.08, 0.0)), Vec3::zeros());
+    let (_, initialization) = reconstruct_scene(false, &pose, 3);
+
+    assert!(initialization.is_none());
+}
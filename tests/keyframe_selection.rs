@@ -0,0 +1,149 @@
+use tinyslam::keyframe_selection::{KeyFrameDecision, KeyFrameSelection, KeyFrameSelectionConfig, Sensor, TrackingStatistics};
+
+const INSERT: KeyFrameDecision = KeyFrameDecision { insert: true, interrupt_mapping: false };
+const SKIP: KeyFrameDecision = KeyFrameDecision { insert: false, interrupt_mapping: false };
+const INTERRUPT: KeyFrameDecision = KeyFrameDecision { insert: false, interrupt_mapping: true };
+
+fn selection(sensor: Sensor) -> KeyFrameSelection {
+    KeyFrameSelection::new(KeyFrameSelectionConfig::default(), sensor)
+}
+
+/// Tracking in an established map, one frame after the last keyframe
+fn statistics() -> TrackingStatistics {
+    TrackingStatistics {
+        frame_id: 101,
+        last_keyframe_frame_id: 100,
+        keyframes_in_map: 10,
+        tracked_inliers: 200,
+        reference_tracked_points: 200,
+        local_mapping_idle: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn monocular_inserts_when_tracking_drops_below_ratio() {
+    let selection = selection(Sensor::Monocular);
+
+    // Script of inliers over consecutive frames, with local mapping idle
+    let script = [(200, SKIP), (185, SKIP), (181, SKIP), (179, INSERT), (120, INSERT), (15, SKIP), (16, INSERT)];
+
+    for (frame, (inliers, expected)) in script.into_iter().enumerate() {
+        let stats = TrackingStatistics {
+            frame_id: 101 + frame as u64,
+            tracked_inliers: inliers,
+            ..statistics()
+        };
+
+        assert_eq!(selection.decide(&stats), expected, "{inliers} inliers");
+    }
+}
+
+#[test]
+fn busy_local_mapping_delays_insertion() {
+    let selection = selection(Sensor::Monocular);
+
+    let busy = TrackingStatistics {
+        tracked_inliers: 100,
+        local_mapping_idle: false,
+        ..statistics()
+    };
+
+    // Too early: nothing happens while local mapping works
+    assert_eq!(selection.decide(&busy), SKIP);
+
+    // A keyframe is overdue, so local mapping is asked to make room
+    let overdue = TrackingStatistics { frame_id: 130, ..busy.clone() };
+    assert_eq!(selection.decide(&overdue), INTERRUPT);
+
+    let idle = TrackingStatistics { local_mapping_idle: true, ..overdue.clone() };
+    assert_eq!(selection.decide(&idle), INSERT);
+
+    let stopped = TrackingStatistics { local_mapping_stopped: true, ..idle };
+    assert_eq!(selection.decide(&stopped), SKIP);
+}
+
+#[test]
+fn min_frames_must_pass_before_idle_insertion() {
+    let config = KeyFrameSelectionConfig { min_frames: 5, ..Default::default() };
+    let selection = KeyFrameSelection::new(config, Sensor::Monocular);
+
+    for frame in 101..=110 {
+        let stats = TrackingStatistics { frame_id: frame, tracked_inliers: 100, ..statistics() };
+        let expected = if frame >= 105 { INSERT } else { SKIP };
+
+        assert_eq!(selection.decide(&stats), expected, "frame {frame}");
+    }
+}
+
+#[test]
+fn no_insertion_right_after_relocalisation() {
+    let selection = selection(Sensor::Monocular);
+
+    let base = TrackingStatistics {
+        last_relocalisation_frame_id: Some(100),
+        keyframes_in_map: 40,
+        tracked_inliers: 50,
+        ..statistics()
+    };
+
+    assert_eq!(selection.decide(&base), SKIP);
+    assert_eq!(selection.decide(&TrackingStatistics { frame_id: 130, ..base.clone() }), INSERT);
+
+    // Small maps accept keyframes immediately
+    assert_eq!(selection.decide(&TrackingStatistics { keyframes_in_map: 20, ..base }), INSERT);
+}
+
+#[test]
+fn single_keyframe_map_uses_initial_ratio() {
+    let selection = selection(Sensor::Monocular);
+    let stats = TrackingStatistics { keyframes_in_map: 1, ..statistics() };
+
+    assert_eq!(selection.decide(&TrackingStatistics { tracked_inliers: 81, ..stats.clone() }), SKIP);
+    assert_eq!(selection.decide(&TrackingStatistics { tracked_inliers: 79, ..stats }), INSERT);
+}
+
+#[test]
+fn stereo_inserts_for_close_points() {
+    let selection = selection(Sensor::Stereo);
+
+    let close = TrackingStatistics {
+        tracked_close: 60,
+        untracked_close: 120,
+        local_mapping_idle: false,
+        queued_keyframes: 1,
+        ..statistics()
+    };
+
+    // All reference points are tracked, but new close points are available
+    assert_eq!(selection.decide(&close), KeyFrameDecision { insert: true, interrupt_mapping: true });
+
+    // Too many keyframes already queued
+    assert_eq!(selection.decide(&TrackingStatistics { queued_keyframes: 3, ..close.clone() }), INTERRUPT);
+
+    // Enough close points tracked
+    assert_eq!(selection.decide(&TrackingStatistics { tracked_close: 150, ..close.clone() }), SKIP);
+
+    // The same statistics never trigger a monocular keyframe
+    assert_eq!(self::selection(Sensor::Monocular).decide(&close), SKIP);
+}
+
+#[test]
+fn stereo_uses_lower_ratio_and_weak_tracking() {
+    let selection = selection(Sensor::Rgbd);
+
+    assert_eq!(selection.decide(&TrackingStatistics { tracked_inliers: 160, ..statistics() }), SKIP);
+    assert_eq!(selection.decide(&TrackingStatistics { tracked_inliers: 140, ..statistics() }), INSERT);
+
+    // Weak tracking overrides elapsed frames while local mapping is busy
+    let weak = TrackingStatistics { tracked_inliers: 40, local_mapping_idle: false, ..statistics() };
+    assert_eq!(selection.decide(&weak), KeyFrameDecision { insert: true, interrupt_mapping: true });
+}
+
+#[test]
+fn min_observations_relaxed_for_small_maps() {
+    let selection = selection(Sensor::Monocular);
+
+    assert_eq!(selection.min_observations(2), 2);
+    assert_eq!(selection.min_observations(3), 3);
+}
@@ -0,0 +1,355 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use common::{build_map_with, pose_at, Observation, SyntheticMap, World, LEVELS_UP};
+use tinyslam::frame::Frame;
+use tinyslam::local_mapping::{LocalMapper, LocalMappingConfig, LocalMappingThread};
+use tinyslam::map::{KeyFrameId, Map, MapPointId};
+use tinyslam::random::Rng;
+use tinyslam::vocabulary::Vocabulary;
+
+/// Local mapper over a synthetic map, which knows the world point each
+/// keypoint of each keyframe observes
+struct Scene {
+    rng: Rng,
+    world: World,
+    points: Vec<Option<MapPointId>>,
+    observed: BTreeMap<KeyFrameId, Vec<usize>>,
+    mapper: LocalMapper
+}
+
+fn config() -> LocalMappingConfig {
+    // Features are grouped at the level the keyframes of the map used
+    LocalMappingConfig { levels_up: LEVELS_UP, ..Default::default() }
+}
+
+fn scene(seed: u64, poses: &[f64], config: LocalMappingConfig, mapped: impl Fn(usize, usize) -> bool) -> Scene {
+    let mut rng = Rng::new(seed);
+    let world = World::new(&mut rng, 400, 4.0);
+    let vocabulary: Arc<Vocabulary> = world.vocabulary(&mut rng);
+
+    let poses: Vec<_> = poses.iter().map(|&x| pose_at(x)).collect();
+    let SyntheticMap { map, keyframes, observed, points } = build_map_with(&world, &vocabulary, &mut rng, &poses, mapped);
+
+    Scene {
+        rng,
+        world,
+        points,
+        observed: keyframes.into_iter().zip(observed).collect(),
+        mapper: LocalMapper::new(config, map.shared(), vocabulary)
+    }
+}
+
+impl Scene {
+    /// Tracked frame whose keypoints match the existing points of the world
+    /// points accepted by `link`
+    fn frame(&mut self, id: u64, x: f64, link: impl Fn(usize) -> bool) -> (Frame, Vec<usize>) {
+        let Observation { mut frame, points } = self.world.observe(&mut self.rng, id, &pose_at(x));
+        frame.pose = Some(pose_at(x));
+
+        for (i, &w) in points.iter().enumerate() {
+            frame.map_points[i] = self.points[w].filter(|_| link(w));
+        }
+
+        (frame, points)
+    }
+
+    fn process(&mut self, id: u64, x: f64, queue_empty: bool, link: impl Fn(usize) -> bool) -> KeyFrameId {
+        let (frame, points) = self.frame(id, x, link);
+        let keyframe = self.mapper.process_keyframe(frame, queue_empty);
+        self.observed.insert(keyframe, points);
+        keyframe
+    }
+
+    fn with_map<T>(&self, f: impl FnOnce(&Map) -> T) -> T {
+        f(&self.mapper.map().read().unwrap())
+    }
+
+    /// World point a map point was created for, checking that every
+    /// keypoint observing it observes that world point
+    fn world_point(&self, map: &Map, point: MapPointId) -> usize {
+        let observations = &map.point(point).unwrap().observations;
+        let worlds: Vec<usize> = observations.iter().map(|(keyframe, &index)| self.observed[keyframe][index]).collect();
+
+        assert!(worlds.iter().all(|&w| w == worlds[0]), "point {point} observes world points {worlds:?}");
+        worlds[0]
+    }
+}
+
+#[test]
+fn new_points_are_triangulated_with_covisible_keyframes() {
+    // Only the even world points have map points
+    let mut scene = scene(1, &[0.0, 0.5], config(), |_, w| w % 2 == 0);
+    let existing = scene.with_map(|map| map.point_count());
+
+    let keyframe = scene.process(10, 1.0, false, |_| true);
+
+    scene.with_map(|map| {
+        let new: Vec<MapPointId> = map.points().map(|p| p.id).filter(|&id| !scene.points.contains(&Some(id))).collect();
+        assert_eq!(map.point_count(), existing + new.len());
+
+        // Odd world points seen by the new keyframe and another one
+        let observed = |k: &KeyFrameId| scene.observed[k].iter().copied().filter(|w| w % 2 == 1).collect::<Vec<_>>();
+        let in_new = observed(&keyframe);
+        let triangulable = in_new.iter()
+            .filter(|w| scene.observed.keys().filter(|&&k| k != keyframe).any(|k| observed(k).contains(w)))
+            .count();
+
+        assert!(new.len() > triangulable * 8 / 10, "{} of {triangulable} points triangulated", new.len());
+
+        for &point in &new {
+            let map_point = map.point(point).unwrap();
+            assert_eq!(map_point.observations.len(), 2);
+            assert!(map_point.observations.contains_key(&keyframe));
+
+            let w = scene.world_point(map, point);
+            let truth = scene.world.points[w];
+            assert!((map_point.position - truth).norm() < 0.05 * truth.z(), "point {point}");
+        }
+    });
+}
+
+#[test]
+fn recent_points_found_in_too_few_frames_are_culled() {
+    let mut scene = scene(2, &[0.0, 0.5], config(), |_, w| w % 2 == 0);
+    scene.process(10, 1.0, false, |_| true);
+
+    let new: Vec<MapPointId> = scene.with_map(|map| {
+        map.points().map(|p| p.id).filter(|&id| !scene.points.contains(&Some(id))).collect()
+    });
+    assert!(new.len() > 50);
+
+    // Found in an eighth of the frames predicted to see them, and in exactly
+    // the minimum quarter
+    let (rare, borderline) = new.split_at(new.len() / 2);
+
+    {
+        let mut map = scene.mapper.map().write().unwrap();
+
+        for &point in rare {
+            let point = map.point_mut(point).unwrap();
+            (point.visible, point.found) = (8, 1);
+        }
+
+        for &point in borderline {
+            let point = map.point_mut(point).unwrap();
+            (point.visible, point.found) = (4, 1);
+        }
+    }
+
+    // Only link the original points, so the new ones keep two observations
+    let points = scene.points.clone();
+    scene.process(11, 1.5, false, |w| points[w].is_some());
+
+    scene.with_map(|map| {
+        assert!(rare.iter().all(|&point| map.point(point).is_none()));
+        assert!(borderline.iter().all(|&point| map.point(point).is_some()));
+    });
+}
+
+#[test]
+fn duplicate_points_are_fused() {
+    // Keyframe 2 observes no point for world points 1 and 2 modulo 5
+    let unmapped = |w: usize| w % 5 == 1 || w % 5 == 2;
+    let config = LocalMappingConfig { redundancy_ratio: 1.0, ..config() };
+    let mut scene = scene(3, &[0.0, 0.25, 0.5], config, |k, w| k != 2 || !unmapped(w));
+    let third = *scene.observed.keys().nth(2).unwrap();
+
+    // Instead it has duplicate points of its own for those 1 modulo 5
+    let duplicates: BTreeMap<usize, MapPointId> = {
+        let mut map = scene.mapper.map().write().unwrap();
+
+        let duplicates = scene.observed[&third].iter()
+            .enumerate()
+            .filter(|&(_, &w)| w % 5 == 1 && scene.points[w].is_some())
+            .map(|(index, &w)| (w, map.add_point(scene.world.points[w], third, index)))
+            .collect();
+
+        map.update_connections(third);
+        duplicates
+    };
+
+    assert!(duplicates.len() > 20);
+
+    let new = scene.process(10, 0.75, true, |_| true);
+
+    scene.with_map(|map| {
+        let keyframe = map.keyframe(third).unwrap();
+        let fused = |w: &usize| unmapped(*w) && scene.observed[&new].contains(w);
+
+        // Points of the new keyframe replace the duplicates, which have
+        // fewer observations, and are added where the keyframe had none
+        let mut checked = 0;
+
+        for (index, w) in scene.observed[&third].iter().enumerate().filter(|(_, w)| fused(w)) {
+            let Some(point) = scene.points[*w] else {
+                continue;
+            };
+
+            assert_eq!(keyframe.frame.map_points[index], Some(point), "world point {w}");
+            assert!(map.point(point).unwrap().observations.contains_key(&third));
+            checked += 1;
+        }
+
+        assert!(checked > 50);
+
+        for (w, &point) in duplicates.iter().filter(|(w, _)| fused(w)) {
+            assert!(map.point(point).is_none(), "world point {w}");
+        }
+    });
+}
+
+#[test]
+fn redundant_keyframes_are_culled() {
+    // Keyframes 1, 2 and 3 each have their own points for a twentieth,
+    // three twentieths and three twentieths of the world
+    let private = |w: usize| match w % 20 {
+        0 => Some(1),
+        1..=3 => Some(2),
+        4..=6 => Some(3),
+        _ => None
+    };
+
+    let mut scene = scene(4, &[0.0, 0.02, 0.04, 0.06], config(), |k, w| private(w).is_none_or(|owner| owner == k));
+    let keyframes: Vec<KeyFrameId> = scene.observed.keys().copied().collect();
+
+    let new = scene.process(10, 0.08, true, |w| private(w).is_none());
+
+    scene.with_map(|map| {
+        // The origin is kept whatever its redundancy
+        assert!(map.keyframe(keyframes[0]).is_some());
+
+        // Ninety-five percent of the points of keyframe 1 are seen by three
+        // other keyframes, but only eighty-five percent of those of 2 and 3
+        assert!(map.keyframe(keyframes[1]).is_none());
+        assert!(map.keyframe(keyframes[2]).is_some());
+        assert!(map.keyframe(keyframes[3]).is_some());
+        assert!(map.keyframe(new).is_some());
+    });
+}
+
+#[test]
+fn thread_is_busy_until_inserted_keyframes_are_processed() {
+    let mut scene = scene(5, &[0.0, 0.5], config(), |_, _| true);
+    let frames: Vec<Frame> = (0..3).map(|i| scene.frame(10 + i, 0.75 + 0.25 * i as f64, |_| true).0).collect();
+
+    let thread = LocalMappingThread::spawn(scene.mapper);
+    assert!(thread.is_idle());
+
+    for frame in frames {
+        thread.insert_keyframe(frame);
+
+        // Counted as soon as it is inserted, even if the thread took it already
+        assert!(!thread.is_idle());
+    }
+
+    let processed: Vec<KeyFrameId> = (0..3).map(|_| thread.processed().recv().unwrap()).collect();
+    assert_eq!(processed, vec![2, 3, 4]);
+
+    while !thread.is_idle() {
+        std::thread::yield_now();
+    }
+
+    assert_eq!(thread.queued(), 0);
+
+    thread.shutdown();
+}
+
+#[test]
+fn processing_a_keyframe_keeps_a_pending_interrupt() {
+    let mut scene = scene(6, &[0.0, 0.5], config(), |_, _| true);
+    let abort = scene.mapper.abort_flag();
+
+    // As if a keyframe had been inserted while this one was taken
+    abort.store(true, Ordering::SeqCst);
+    scene.process(10, 1.0, true, |_| true);
+
+    assert!(abort.load(Ordering::SeqCst));
+}
+
+/// Gives a frame the true depths of the world points it observes that
+/// `close` accepts, all of them close points
+fn set_close_depths(frame: &mut Frame, world: &World, points: &[usize], x: f64, close: impl Fn(usize) -> bool) {
+    let depths = points.iter()
+        .map(|&w| close(w).then(|| pose_at(x).transform(&world.points[w]).z()))
+        .collect();
+
+    frame.set_depths(0.01, f64::INFINITY, depths);
+}
+
+#[test]
+fn stereo_maps_cull_recent_points_with_fewer_observations() {
+    // Points triangulated by keyframe 2 and also observed by the other
+    // keyframe of the map survive two keyframes later in monocular maps only
+    let culled = |stereo: bool| {
+        let mut scene = scene(7, &[0.0, 0.5], config(), |_, w| w % 2 == 0);
+        let third = scene.process(10, 1.0, false, |_| true);
+
+        let new: Vec<MapPointId> = {
+            let mut map = scene.mapper.map().write().unwrap();
+            let new: Vec<MapPointId> = map.points().map(|p| p.id).filter(|&id| !scene.points.contains(&Some(id))).collect();
+
+            for &point in &new {
+                let w = scene.world_point(&map, point);
+                let observers: Vec<KeyFrameId> = map.point(point).unwrap().observations.keys().copied().collect();
+
+                for (&keyframe, observed) in &scene.observed {
+                    let index = observed.iter().position(|&other| other == w);
+
+                    if let (false, Some(index)) = (observers.contains(&keyframe), index) {
+                        map.add_observation(keyframe, index, point);
+                    }
+                }
+            }
+
+            new.into_iter().filter(|&point| map.point(point).unwrap().observations.len() == 3).collect()
+        };
+
+        assert!(new.len() > 20);
+
+        // Only link the original points, so the new ones keep three observations
+        let points = scene.points.clone();
+        scene.process(11, 1.5, false, |w| points[w].is_some());
+
+        let (mut frame, observed) = scene.frame(12, 2.0, |w| points[w].is_some());
+        if stereo {
+            set_close_depths(&mut frame, &scene.world, &observed, 2.0, |_| true);
+        }
+
+        let current = scene.mapper.process_keyframe(frame, false);
+        assert_eq!(current - third, 2);
+
+        scene.with_map(|map| new.iter().filter(|&&point| map.point(point).is_none()).count() == new.len())
+    };
+
+    assert!(!culled(false));
+    assert!(culled(true));
+}
+
+#[test]
+fn stereo_keyframes_only_count_close_points_as_redundant() {
+    // As in `redundant_keyframes_are_culled`, where keyframe 1 is culled
+    let private = |w: usize| match w % 20 {
+        0 => Some(1),
+        1..=3 => Some(2),
+        4..=6 => Some(3),
+        _ => None
+    };
+
+    let mut scene = scene(8, &[0.0, 0.02, 0.04, 0.06], config(), |k, w| private(w).is_none_or(|owner| owner == k));
+    let keyframes: Vec<KeyFrameId> = scene.observed.keys().copied().collect();
+
+    // Only its own points are close, and no other keyframe sees them
+    {
+        let mut map = scene.mapper.map().write().unwrap();
+        let frame = &mut map.keyframe_mut(keyframes[1]).unwrap().frame;
+        set_close_depths(frame, &scene.world, &scene.observed[&keyframes[1]], 0.02, |w| private(w) == Some(1));
+    }
+
+    scene.process(10, 0.08, true, |w| private(w).is_none());
+
+    scene.with_map(|map| assert!(map.keyframe(keyframes[1]).is_some()));
+}
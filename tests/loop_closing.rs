@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+use tinyslam::camera::{CameraModel, Pinhole};
+use tinyslam::geometry::{Sim3, SO3};
+use tinyslam::keyframe_database::KeyFrameDatabase;
+use tinyslam::linalg::{DMatrix, EnvelopeMatrix, Mat7, Vec2, Vec3, Vector};
+use tinyslam::pose_graph::{optimize_pose_graph, PoseGraph};
+use tinyslam::random::Rng;
+use tinyslam::sim3::{horn, solve_sim3, Sim3Config, Sim3Match};
+use tinyslam::vocabulary::BowVector;
+
+fn random_vector<const N: usize>(rng: &mut Rng, magnitude: f64) -> Vector<N> {
+    Vector::<N>::from_array(std::array::from_fn(|_| (2.0 * rng.next_f64() - 1.0) * magnitude))
+}
+
+fn sim3_distance(a: &Sim3, b: &Sim3) -> f64 {
+    (a.rotation.matrix() - b.rotation.matrix()).norm() +
+        (a.translation - b.translation).norm() +
+        (a.scale - b.scale).abs()
+}
+
+fn camera() -> Pinhole {
+    Pinhole::new(458.0, 457.0, 367.0, 248.0)
+}
+
+#[test]
+fn horn_recovers_similarities() {
+    let mut rng = Rng::new(1);
+
+    for _ in 0..50 {
+        let truth = Sim3::new(SO3::exp(&random_vector(&mut rng, 1.5)), random_vector(&mut rng, 3.0), (2.0 * rng.next_f64() - 1.0).exp());
+        let a: Vec<Vec3> = (0..10).map(|_| random_vector(&mut rng, 2.0)).collect();
+        let b: Vec<Vec3> = a.iter().map(|p| truth.transform(p)).collect();
+
+        let estimate = horn(&a, &b, false).unwrap();
+        assert!(sim3_distance(&estimate, &truth) < 1e-9, "{estimate:?} != {truth:?}");
+
+        let rigid = horn(&a, &b, true).unwrap();
+        assert_eq!(rigid.scale, 1.0);
+        assert!((rigid.rotation.matrix() - truth.rotation.matrix()).norm() < 1e-9);
+    }
+}
+
+#[test]
+fn horn_rejects_degenerate_points() {
+    let a = vec![Vec3::new(1.0, 2.0, 3.0); 4];
+    assert!(horn(&a, &a, false).is_none());
+    assert!(horn(&a[..2], &a[..2], false).is_none());
+}
+
+#[test]
+fn sim3_ransac_rejects_outliers() {
+    let mut rng = Rng::new(2);
+    let camera = camera();
+
+    // Second camera frame to first, with a scale drift of 30%
+    let truth = Sim3::new(SO3::exp(&Vec3::new(0.05, -0.2, 0.02)), Vec3::new(0.3, -0.1, 0.2), 1.3);
+    let inverse = truth.inverse();
+
+    let mut matches = Vec::new();
+    let mut outliers = Vec::new();
+
+    while matches.len() < 150 {
+        let pixel = Vec2::new(rng.next_f64() * 700.0 + 20.0, rng.next_f64() * 440.0 + 20.0);
+        let depth = 2.0 + rng.next_f64() * 6.0;
+        let point_1 = camera.unproject(&pixel) * depth;
+        let point_2 = inverse.transform(&point_1);
+
+        if point_2.z() <= 0.0 {
+            continue;
+        }
+
+        let is_outlier = rng.next_f64() < 0.3;
+        let pixel_2 = if is_outlier {
+            Vec2::new(rng.next_f64() * 752.0, rng.next_f64() * 480.0)
+        } else {
+            camera.project(&point_2) + random_vector(&mut rng, 0.5)
+        };
+
+        matches.push(Sim3Match {
+            points: [point_1, point_2 + random_vector(&mut rng, 0.01)],
+            pixels: [pixel + random_vector(&mut rng, 0.5), pixel_2],
+            sigma2: [1.0, 1.0]
+        });
+        outliers.push(is_outlier);
+    }
+
+    let result = solve_sim3([&camera, &camera], &matches, &Sim3Config::default(), &mut rng).unwrap();
+
+    assert!(sim3_distance(&result.sim3, &truth) < 0.02, "{:?}", result.sim3);
+
+    let wrong = result.inliers.iter().zip(&outliers).filter(|(&inlier, &outlier)| inlier && outlier).count();
+    assert!(wrong <= 2, "{wrong} outliers accepted");
+    assert!(result.inlier_count > 90);
+}
+
+#[test]
+fn pose_graph_distributes_loop_error() {
+    let mut rng = Rng::new(3);
+
+    // Camera moving around a circle, with ground truth world to camera poses
+    let count = 20;
+    let truth: Vec<Sim3> = (0..count)
+        .map(|i| {
+            let angle = i as f64 * std::f64::consts::TAU / count as f64;
+            let rotation = SO3::exp(&Vec3::new(0.0, angle, 0.0));
+            let center = Vec3::new(5.0 * angle.cos(), 0.0, 5.0 * angle.sin());
+            Sim3::new(rotation.inverse(), -(rotation.inverse() * center), 1.0)
+        })
+        .collect();
+
+    let mut graph = PoseGraph::new();
+
+    // Odometry with a drift in scale and rotation accumulating along the chain
+    let mut drifted = truth[0];
+    graph.add_vertex(drifted, true);
+
+    for i in 1..count {
+        let relative = truth[i] * truth[i - 1].inverse();
+        let mut drift = random_vector::<7>(&mut rng, 0.01);
+        drift[6] = 0.02;
+        drifted = Sim3::exp(&drift) * relative * drifted;
+        graph.add_vertex(drifted, false);
+    }
+
+    for i in 1..count {
+        graph.add_edge(i - 1, i, truth[i - 1] * truth[i].inverse(), Mat7::identity());
+    }
+
+    graph.add_edge(count - 1, 0, truth[count - 1] * truth[0].inverse(), Mat7::identity());
+
+    let summary = optimize_pose_graph(&mut graph, 20);
+
+    assert!(summary.final_cost < 1e-12 * summary.initial_cost.max(1.0), "{summary:?}");
+
+    for (estimate, truth) in graph.vertices.iter().zip(&truth) {
+        assert!(sim3_distance(estimate, truth) < 1e-6);
+    }
+}
+
+#[test]
+fn pose_graph_error_is_zero_for_consistent_edges() {
+    let mut rng = Rng::new(4);
+    let mut graph = PoseGraph::new();
+
+    for _ in 0..3 {
+        let pose = Sim3::exp(&random_vector::<7>(&mut rng, 1.0));
+        graph.add_vertex(pose, false);
+    }
+
+    graph.add_relative_edge(0, 1);
+    graph.add_relative_edge(2, 1);
+
+    for edge in &graph.edges {
+        assert!(graph.error(edge).norm() < 1e-9);
+    }
+
+    let before = graph.vertices.clone();
+    optimize_pose_graph(&mut graph, 10);
+
+    for (after, before) in graph.vertices.iter().zip(&before) {
+        assert!(sim3_distance(after, before) < 1e-9);
+    }
+}
+
+#[test]
+fn envelope_cholesky_matches_dense() {
+    let mut rng = Rng::new(5);
+    let n: usize = 12;
+
+    // Banded positive definite matrix with a long range entry, as closing a loop adds
+    let mut first: Vec<usize> = (0..n).map(|i| i.saturating_sub(2)).collect();
+    first[n - 1] = 0;
+
+    let mut dense = DMatrix::identity(n);
+    for i in 0..n {
+        for j in first[i]..i {
+            let value = rng.next_f64() - 0.5;
+            dense[(i, j)] = value;
+            dense[(j, i)] = value;
+        }
+        dense[(i, i)] = 4.0;
+    }
+
+    let mut envelope = EnvelopeMatrix::new(first.clone());
+    for i in 0..n {
+        for j in first[i]..=i {
+            envelope.add(i, j, dense[(i, j)]);
+        }
+    }
+
+    let b: Vec<f64> = (0..n).map(|_| rng.next_f64()).collect();
+    let expected = dense.cholesky_solve(&b).unwrap();
+    let actual = envelope.cholesky_solve(&b).unwrap();
+
+    for (a, e) in actual.iter().zip(&expected) {
+        assert!((a - e).abs() < 1e-12);
+    }
+
+    assert_eq!(envelope.get(0, n - 1), dense[(n - 1, 0)]);
+    assert_eq!(envelope.get(0, 5), 0.0);
+}
+
+#[test]
+fn keyframe_database_counts_common_words() {
+    let bow = |words: &[u32]| BowVector(words.iter().map(|&w| (w, 1.0)).collect::<BTreeMap<_, _>>());
+
+    let mut database = KeyFrameDatabase::new();
+    database.add(0, &bow(&[1, 2, 3]));
+    database.add(1, &bow(&[3, 4]));
+    database.add(2, &bow(&[5]));
+
+    let query = bow(&[2, 3, 4]);
+    assert_eq!(database.common_words(&query), BTreeMap::from([(0, 2), (1, 2)]));
+
+    database.erase(0, &bow(&[1, 2, 3]));
+    assert_eq!(database.common_words(&query), BTreeMap::from([(1, 2)]));
+}
@@ -0,0 +1,267 @@
+mod common;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use tinyslam::frame::{Frame, ScalePyramid};
+use tinyslam::geometry::SE3;
+use tinyslam::linalg::Vec3;
+use tinyslam::map::{KeyFrameId, Map, MapPointId};
+use tinyslam::orb::{CornerData, CornerDescriptor};
+
+const KEYPOINTS: usize = 200;
+
+/// Map whose keyframes share exactly the given numbers of points
+#[derive(Default)]
+struct Builder {
+    map: Map,
+    /// Next keypoint without a map point in each keyframe
+    next: BTreeMap<KeyFrameId, usize>
+}
+
+impl Builder {
+    fn keyframe(&mut self) -> KeyFrameId {
+        let corners = (0..KEYPOINTS as u32)
+            .map(|i| CornerData { x: 10 + i % 60 * 10, y: 10 + i / 60 * 10, angle: 0, octave: 0, camera: 0 })
+            .collect();
+
+        let descriptors = vec![CornerDescriptor { bits: [0; 32] }; KEYPOINTS];
+
+        let mut frame = Frame::new(0, 0.0, common::camera(), common::image_size(), ScalePyramid::new(8, 1.2), corners, descriptors);
+        frame.pose = Some(SE3::identity());
+
+        let id = self.map.add_keyframe(frame);
+        self.next.insert(id, 0);
+        id
+    }
+
+    fn next_index(&mut self, keyframe: KeyFrameId) -> usize {
+        let next = self.next.get_mut(&keyframe).unwrap();
+        *next += 1;
+        *next - 1
+    }
+
+    /// Adds `count` points observed by every keyframe of `keyframes`
+    fn share(&mut self, keyframes: &[KeyFrameId], count: usize) -> Vec<MapPointId> {
+        (0..count)
+            .map(|_| {
+                let index = self.next_index(keyframes[0]);
+                let point = self.map.add_point(Vec3::new(0.0, 0.0, 5.0), keyframes[0], index);
+
+                for &keyframe in &keyframes[1..] {
+                    let index = self.next_index(keyframe);
+                    self.map.add_observation(keyframe, index, point);
+                }
+
+                point
+            })
+            .collect()
+    }
+
+    fn parent(&self, keyframe: KeyFrameId) -> Option<KeyFrameId> {
+        self.map.keyframe(keyframe).unwrap().parent()
+    }
+
+    fn children(&self, keyframe: KeyFrameId) -> Vec<KeyFrameId> {
+        self.map.keyframe(keyframe).unwrap().children().iter().copied().collect()
+    }
+
+    fn weight(&self, a: KeyFrameId, b: KeyFrameId) -> Option<usize> {
+        self.map.keyframe(a).unwrap().covisibility_weight(b)
+    }
+}
+
+/// Keyframes connected as local mapping would, each when inserted:
+///
+/// ```text
+/// a -- b   40      a
+/// b -- c   30      └ b
+/// b -- d   50        ├ c
+/// a -- c   16        └ d
+/// c -- d   20          └ e
+/// d -- e   25
+/// a -- e    5
+/// ```
+fn graph() -> (Builder, [KeyFrameId; 5]) {
+    let mut builder = Builder::default();
+
+    let a = builder.keyframe();
+
+    let b = builder.keyframe();
+    builder.share(&[a, b], 40);
+    builder.map.update_connections(b);
+
+    let c = builder.keyframe();
+    builder.share(&[b, c], 30);
+    builder.share(&[a, c], 16);
+    builder.map.update_connections(c);
+
+    let d = builder.keyframe();
+    builder.share(&[b, d], 50);
+    builder.share(&[c, d], 20);
+    builder.map.update_connections(d);
+
+    let e = builder.keyframe();
+    builder.share(&[d, e], 25);
+    builder.share(&[a, e], 5);
+    builder.map.update_connections(e);
+
+    (builder, [a, b, c, d, e])
+}
+
+#[test]
+fn covisibility_weights_count_shared_points() {
+    let (mut builder, [a, b, c, d, e]) = graph();
+
+    for (x, y, weight) in [(a, b, 40), (b, c, 30), (b, d, 50), (a, c, 16), (c, d, 20), (d, e, 25)] {
+        assert_eq!(builder.weight(x, y), Some(weight));
+        assert_eq!(builder.weight(y, x), Some(weight));
+    }
+
+    // Below the minimum weight, while a stronger connection exists
+    assert_eq!(builder.weight(a, e), None);
+    assert_eq!(builder.weight(e, a), None);
+
+    assert_eq!(builder.map.keyframe(b).unwrap().covisibles(), &[(d, 50), (a, 40), (c, 30)]);
+    assert_eq!(builder.map.keyframe(a).unwrap().best_covisibles(1).collect::<Vec<_>>(), vec![b]);
+
+    // A keyframe with only weak connections keeps the strongest
+    let f = builder.keyframe();
+    builder.share(&[e, f], 3);
+    builder.share(&[c, f], 7);
+    builder.map.update_connections(f);
+    assert_eq!(builder.map.keyframe(f).unwrap().covisibles(), &[(c, 7)]);
+    assert_eq!(builder.weight(c, f), Some(7));
+
+    // Updating drops connections that lost their shared points
+    let shared: Vec<MapPointId> = builder.map.keyframe(e).unwrap().frame.map_points.iter()
+        .flatten()
+        .copied()
+        .filter(|&point| builder.map.point(point).unwrap().observations.contains_key(&d))
+        .collect();
+
+    for point in shared {
+        builder.map.erase_point(point);
+    }
+
+    builder.share(&[a, e], 10);
+    builder.map.update_connections(e);
+    assert_eq!(builder.weight(d, e), None);
+    assert_eq!(builder.map.keyframe(e).unwrap().covisibles(), &[(a, 15)]);
+}
+
+#[test]
+fn spanning_tree_follows_the_strongest_connection() {
+    let (builder, [a, b, c, d, e]) = graph();
+
+    assert_eq!(builder.parent(a), None);
+    assert_eq!(builder.parent(b), Some(a));
+    assert_eq!(builder.parent(c), Some(b));
+    assert_eq!(builder.parent(d), Some(b));
+    assert_eq!(builder.parent(e), Some(d));
+
+    assert_eq!(builder.children(a), vec![b]);
+    assert_eq!(builder.children(b), vec![c, d]);
+    assert_eq!(builder.children(d), vec![e]);
+}
+
+#[test]
+fn erasing_a_keyframe_reparents_its_children() {
+    let (mut builder, [a, b, c, d, e]) = graph();
+    let points = builder.map.point_count();
+
+    // The root is kept
+    assert!(!builder.map.erase_keyframe(a));
+
+    assert!(builder.map.erase_keyframe(b));
+    assert!(builder.map.keyframe(b).is_none());
+    assert!(!builder.map.erase_keyframe(b));
+
+    // c connects to the old parent, then d to c, its strongest candidate
+    assert_eq!(builder.parent(c), Some(a));
+    assert_eq!(builder.parent(d), Some(c));
+    assert_eq!(builder.parent(e), Some(d));
+    assert_eq!(builder.children(a), vec![c]);
+    assert_eq!(builder.children(c), vec![d]);
+
+    assert_eq!(builder.weight(a, b), None);
+    assert!(builder.map.keyframe(d).unwrap().covisibles().iter().all(|&(other, _)| other != b));
+
+    // Points left with a single observation are erased
+    assert_eq!(builder.map.point_count(), points - 40 - 30 - 50);
+}
+
+#[test]
+fn erasing_an_unconnected_keyframe_leaves_its_children_for_later() {
+    let (mut builder, [a, ..]) = graph();
+
+    // Inserted without shared points, so never given a parent
+    let f = builder.keyframe();
+    builder.map.update_connections(f);
+    assert_eq!(builder.parent(f), None);
+
+    let g = builder.keyframe();
+    builder.share(&[f, g], 30);
+    builder.share(&[a, g], 20);
+    builder.map.update_connections(g);
+    assert_eq!(builder.parent(g), Some(f));
+
+    assert!(builder.map.erase_keyframe(f));
+    assert_eq!(builder.parent(g), None);
+
+    builder.map.update_connections(g);
+    assert_eq!(builder.parent(g), Some(a));
+    assert!(builder.children(a).contains(&g));
+}
+
+#[test]
+fn replacing_a_point_moves_its_observations() {
+    let (mut builder, [a, b, c, ..]) = graph();
+
+    let old = builder.share(&[a, b], 1)[0];
+    let new = builder.share(&[b, c], 1)[0];
+
+    let index_in_a = builder.map.point(old).unwrap().observations[&a];
+    let index_in_b = builder.map.point(old).unwrap().observations[&b];
+
+    builder.map.point_mut(old).unwrap().visible = 5;
+    builder.map.point_mut(old).unwrap().found = 3;
+
+    builder.map.replace_point(old, new);
+
+    assert!(builder.map.point(old).is_none());
+
+    let point = builder.map.point(new).unwrap();
+    assert_eq!(point.observations.keys().copied().collect::<Vec<_>>(), vec![a, b, c]);
+    assert_eq!(point.observations[&a], index_in_a);
+    assert_eq!((point.visible, point.found), (6, 4));
+
+    // b already observed the new point, so its observation of the old one is dropped
+    assert_eq!(builder.map.keyframe(a).unwrap().frame.map_points[index_in_a], Some(new));
+    assert_eq!(builder.map.keyframe(b).unwrap().frame.map_points[index_in_b], None);
+
+    // Replacing with itself or a missing point does nothing
+    builder.map.replace_point(new, new);
+    builder.map.replace_point(new, 12345);
+    assert!(builder.map.point(new).is_some());
+}
+
+#[test]
+fn essential_graph_has_tree_loop_and_strong_covisibility_edges() {
+    let (mut builder, [a, b, c, d, e]) = graph();
+    builder.map.add_loop_edge(a, e);
+
+    let tree_and_loop = BTreeSet::from([(a, b), (b, c), (b, d), (d, e), (a, e)]);
+    assert_eq!(builder.map.essential_graph(100), tree_and_loop);
+    assert_eq!(builder.map.essential_graph(30), tree_and_loop);
+
+    let mut with_covisibility = tree_and_loop;
+    with_covisibility.extend([(a, c), (c, d)]);
+    assert_eq!(builder.map.essential_graph(16), with_covisibility);
+
+    assert!(builder.map.keyframe(a).unwrap().loop_edges().contains(&e));
+    assert!(builder.map.keyframe(e).unwrap().loop_edges().contains(&a));
+
+    // Erasing a keyframe removes its loop edges
+    assert!(builder.map.erase_keyframe(e));
+    assert!(builder.map.keyframe(a).unwrap().loop_edges().is_empty());
+}
@@ -0,0 +1,120 @@
+use tinyslam::matcher::{
+    filter_window_matches, hamming_distance, match_windows, KeypointGrid, MatcherConfig, SearchWindow, WindowMatch,
+    GRID_CELL_CAPACITY, NO_MATCH
+};
+use tinyslam::orb::CornerDescriptor;
+use tinyslam::random::Rng;
+
+const SIZE: wgpu::Extent3d = wgpu::Extent3d { width: 640, height: 480, depth_or_array_layers: 1 };
+
+fn random_points(rng: &mut Rng, count: usize) -> Vec<([f32; 2], u32)> {
+    (0..count)
+        .map(|_| ([(rng.next_f64() * 640.0) as f32, (rng.next_f64() * 480.0) as f32], rng.below(4) as u32))
+        .collect()
+}
+
+fn descriptor(bits: u32) -> CornerDescriptor {
+    // Sets the lowest `bits` bits, so distances are differences of counts
+    let mut descriptor = CornerDescriptor { bits: [0; 32] };
+
+    for bit in 0..bits as usize {
+        descriptor.bits[bit / 8] |= 1 << (bit % 8);
+    }
+
+    descriptor
+}
+
+#[test]
+fn grid_queries_match_brute_force() {
+    let mut rng = Rng::new(1);
+    let points = random_points(&mut rng, 2000);
+    let grid = KeypointGrid::new(SIZE, points.clone());
+
+    for _ in 0..200 {
+        let window = SearchWindow {
+            x: (rng.next_f64() * 700.0 - 30.0) as f32,
+            y: (rng.next_f64() * 540.0 - 30.0) as f32,
+            radius: (rng.next_f64() * 40.0) as f32,
+            min_octave: rng.below(2) as u32,
+            max_octave: 2 + rng.below(2) as u32
+        };
+
+        let mut found: Vec<u32> = grid.query(&window).collect();
+        found.sort_unstable();
+
+        let expected: Vec<u32> = points.iter()
+            .enumerate()
+            .filter(|(_, ([x, y], octave))| {
+                let (dx, dy) = (x - window.x, y - window.y);
+                *octave >= window.min_octave && *octave <= window.max_octave && dx * dx + dy * dy <= window.radius * window.radius
+            })
+            .map(|(i, _)| i as u32)
+            .collect();
+
+        assert_eq!(found, expected, "{window:?}");
+    }
+}
+
+#[test]
+fn points_outside_the_image_go_to_border_cells() {
+    let grid = KeypointGrid::new(SIZE, vec![([-5.0, -5.0], 0), ([650.0, 490.0], 0)]);
+
+    let corner = |x, y| SearchWindow { x, y, radius: 20.0, min_octave: 0, max_octave: 0 };
+
+    assert_eq!(grid.query(&corner(-5.0, -5.0)).collect::<Vec<_>>(), vec![0]);
+    assert_eq!(grid.query(&corner(645.0, 485.0)).collect::<Vec<_>>(), vec![1]);
+}
+
+#[test]
+fn cpu_grid_keeps_every_point_of_a_full_cell() {
+    // The GPU grid drops keypoints past the cell capacity; the CPU reference
+    // does not, so exact radius queries see every keypoint
+    let count = GRID_CELL_CAPACITY as usize + 36;
+    let points = (0..count).map(|i| ([100.0 + (i % 10) as f32, 100.0 + (i / 10) as f32], 0)).collect();
+    let grid = KeypointGrid::new(SIZE, points);
+
+    let window = SearchWindow { x: 104.0, y: 104.0, radius: 20.0, min_octave: 0, max_octave: 0 };
+    assert_eq!(grid.query(&window).count(), count);
+}
+
+#[test]
+fn windows_report_best_and_second_best_candidates() {
+    let points = vec![([10.0, 10.0], 0), ([12.0, 10.0], 0), ([14.0, 10.0], 0), ([200.0, 200.0], 0)];
+    let descriptors = vec![descriptor(30), descriptor(5), descriptor(12), descriptor(0)];
+    let grid = KeypointGrid::new(SIZE, points);
+
+    let windows = [
+        SearchWindow { x: 12.0, y: 10.0, radius: 5.0, min_octave: 0, max_octave: 0 },
+        SearchWindow { x: 400.0, y: 400.0, radius: 5.0, min_octave: 0, max_octave: 0 },
+        SearchWindow { x: 200.0, y: 200.0, radius: 1.0, min_octave: 0, max_octave: 0 }
+    ];
+    let queries = [descriptor(0); 3];
+    let mut dst = [WindowMatch::NONE; 3];
+
+    match_windows(&grid, &descriptors, &windows, &queries, &mut dst);
+
+    assert_eq!(hamming_distance(&queries[0], &descriptors[1]), 5);
+    assert_eq!(dst[0], WindowMatch { index: 1, distance: 5, second_distance: 12 });
+    assert_eq!(dst[1], WindowMatch::NONE);
+    assert_eq!(dst[2], WindowMatch { index: 3, distance: 0, second_distance: NO_MATCH });
+}
+
+#[test]
+fn window_matches_are_filtered_by_distance_and_ratio() {
+    let config = MatcherConfig { max_distance: 50, ratio: 0.8 };
+
+    let window_matches = [
+        WindowMatch { index: 7, distance: 20, second_distance: 40 },
+        // Ambiguous: 30 is not below 0.8 * 35
+        WindowMatch { index: 8, distance: 30, second_distance: 35 },
+        WindowMatch { index: 9, distance: 60, second_distance: NO_MATCH },
+        WindowMatch::NONE,
+        // A single candidate passes the ratio test
+        WindowMatch { index: 3, distance: 50, second_distance: NO_MATCH }
+    ];
+
+    let matches = filter_window_matches(&config, &window_matches);
+    let pairs: Vec<_> = matches.iter().map(|m| (m.query, m.train, m.distance)).collect();
+
+    assert_eq!(pairs, vec![(0, 7, 20), (4, 3, 50)]);
+}
@@ -0,0 +1,137 @@
+use tinyslam::camera::{CameraModel, Pinhole};
+use tinyslam::frame::ScalePyramid;
+use tinyslam::geometry::{SE3, SO3};
+use tinyslam::linalg::{Vec2, Vec3};
+use tinyslam::optimizer::{optimize_pose, PoseObservation, PoseOptimizerConfig};
+use tinyslam::random::Rng;
+
+const BASELINE: f64 = 0.1;
+
+fn camera() -> Pinhole {
+    Pinhole::new(450.0, 450.0, 320.0, 240.0)
+}
+
+fn truth() -> SE3 {
+    SE3::new(SO3::exp(&Vec3::new(0.05, -0.2, 0.03)), Vec3::new(0.3, -0.1, 0.5))
+}
+
+fn pose_error(a: &SE3, b: &SE3) -> f64 {
+    (a.rotation.inverse() * b.rotation).log().norm() + (a.translation - b.translation).norm()
+}
+
+/// Observations of random points with up to half a pixel of noise, scaled
+/// by the octave. Every fifth observation is moved by 30 to 60 pixels.
+fn observations(rng: &mut Rng, count: usize, stereo: bool) -> (Vec<PoseObservation>, Vec<bool>) {
+    let camera = camera();
+    let scale = ScalePyramid::new(8, 1.2);
+    let camera_to_world = truth().inverse();
+
+    let mut observations = Vec::new();
+    let mut outlier = Vec::new();
+
+    for i in 0..count {
+        let pixel = Vec2::new(20.0 + 600.0 * rng.next_f64(), 20.0 + 440.0 * rng.next_f64());
+        let depth = 1.0 + 7.0 * rng.next_f64();
+        let octave = rng.below(4) as u32;
+        let sigma = scale.scale_factors[octave as usize];
+
+        let ray = camera.unproject(&pixel);
+        let point = camera_to_world.transform(&(ray * (depth / ray.z())));
+
+        let mut noise = || (rng.next_f64() - 0.5) * sigma;
+        let mut observed = pixel + Vec2::new(noise(), noise());
+        let right = stereo.then(|| pixel.x() - camera.fx * BASELINE / depth + noise());
+
+        let is_outlier = i % 5 == 0;
+        if is_outlier {
+            let angle = rng.next_f64() * std::f64::consts::TAU;
+            observed += Vec2::new(angle.cos(), angle.sin()) * (30.0 + 30.0 * rng.next_f64());
+        }
+
+        observations.push(PoseObservation { point, pixel: observed, right, octave });
+        outlier.push(is_outlier);
+    }
+
+    (observations, outlier)
+}
+
+fn perturbed(pose: &SE3) -> SE3 {
+    SE3::new(pose.rotation * SO3::exp(&Vec3::new(0.03, -0.02, 0.04)), pose.translation + Vec3::new(0.08, -0.05, 0.1))
+}
+
+#[test]
+fn recovers_a_perturbed_pose_and_flags_outliers() {
+    for stereo in [false, true] {
+        let mut rng = Rng::new(1);
+        let (observations, expected) = observations(&mut rng, 200, stereo);
+        let mut outliers = vec![false; observations.len()];
+
+        let (pose, inliers) = optimize_pose(
+            &PoseOptimizerConfig::default(),
+            &camera(),
+            BASELINE,
+            &ScalePyramid::new(8, 1.2),
+            &observations,
+            &mut outliers,
+            perturbed(&truth())
+        );
+
+        assert!(pose_error(&pose, &truth()) < 5e-3, "stereo {stereo}: error {}", pose_error(&pose, &truth()));
+        assert_eq!(outliers, expected, "stereo {stereo}");
+        assert_eq!(inliers, expected.iter().filter(|&&o| !o).count());
+    }
+}
+
+#[test]
+fn right_coordinates_reject_wrong_depths() {
+    let mut rng = Rng::new(2);
+    let (mut observations, _) = observations(&mut rng, 100, true);
+
+    // Keep the left observations exact, but give one a right coordinate
+    // for the wrong depth
+    let camera = camera();
+    for observation in observations.iter_mut() {
+        let point = truth().transform(&observation.point);
+        observation.pixel = camera.project(&point);
+        observation.right = Some(observation.pixel.x() - camera.fx * BASELINE / point.z());
+    }
+
+    observations[7].right = observations[7].right.map(|right| right - 15.0);
+
+    let mut outliers = vec![false; observations.len()];
+    let (pose, inliers) = optimize_pose(
+        &PoseOptimizerConfig::default(),
+        &camera,
+        BASELINE,
+        &ScalePyramid::new(8, 1.2),
+        &observations,
+        &mut outliers,
+        perturbed(&truth())
+    );
+
+    assert!(pose_error(&pose, &truth()) < 1e-6);
+    assert_eq!(inliers, 99);
+    assert!(outliers[7]);
+}
+
+#[test]
+fn excluded_observations_may_be_reinstated() {
+    let mut rng = Rng::new(3);
+    let (observations, expected) = observations(&mut rng, 100, false);
+
+    // Start with every good observation with an even index excluded
+    let mut outliers: Vec<bool> = expected.iter().enumerate().map(|(i, &o)| o || i % 2 == 0).collect();
+
+    let (_, inliers) = optimize_pose(
+        &PoseOptimizerConfig::default(),
+        &camera(),
+        0.0,
+        &ScalePyramid::new(8, 1.2),
+        &observations,
+        &mut outliers,
+        perturbed(&truth())
+    );
+
+    assert_eq!(outliers, expected);
+    assert_eq!(inliers, 80);
+}
@@ -0,0 +1,107 @@
+use std::time::Instant;
+
+use tiny_wgpu::Compute;
+use tinyslam::orb::{split_by_camera, CornerData, CornerDescriptor, OrbConfig, OrbProgram};
+use tinyslam::random::Rng;
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 480;
+
+fn feature(camera: u32, x: u32) -> (CornerData, CornerDescriptor) {
+    (
+        CornerData { x, y: 0, angle: 0, octave: 0, camera },
+        CornerDescriptor { bits: [x as u8; 32] }
+    )
+}
+
+#[test]
+fn splits_interleaved_features_by_camera() {
+    // The FAST workgroups of different cameras append in any order
+    let (corners, descriptors): (Vec<_>, Vec<_>) = [
+        feature(2, 10), feature(0, 11), feature(2, 12), feature(3, 13), feature(0, 14)
+    ].into_iter().unzip();
+
+    let split = split_by_camera(&corners, &descriptors, 4);
+
+    assert_eq!(split.len(), 4);
+
+    let xs: Vec<Vec<u32>> = split.iter().map(|(corners, _)| corners.iter().map(|c| c.x).collect()).collect();
+    assert_eq!(xs, vec![vec![11, 14], vec![], vec![10, 12], vec![13]]);
+
+    for (camera, (corners, descriptors)) in split.iter().enumerate() {
+        assert!(corners.iter().all(|c| c.camera == camera as u32));
+        assert!(corners.iter().zip(descriptors).all(|(c, d)| d.bits[0] == c.x as u8));
+    }
+}
+
+/// Blocks of random intensity, whose corners FAST detects
+fn blocks(rng: &mut Rng) -> Vec<u8> {
+    let values: Vec<u8> = (0..(WIDTH / 16) * (HEIGHT / 16)).map(|_| rng.below(256) as u8).collect();
+
+    (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+        .flat_map(|(x, y)| { let v = values[(y / 16 * (WIDTH / 16) + x / 16) as usize]; [v, v, v, 255] })
+        .collect()
+}
+
+fn program(compute: &Compute, cameras: u32) -> OrbProgram {
+    OrbProgram::new(OrbConfig {
+        image_size: wgpu::Extent3d { width: WIDTH, height: HEIGHT, depth_or_array_layers: 1 },
+        cameras,
+        max_features: 4000,
+        hierarchy_depth: 4,
+        initial_threshold: 0.1,
+        max_search_windows: 1,
+        undistortion: Vec::new()
+    }, compute)
+}
+
+#[test]
+#[ignore = "benchmark, requires a GPU adapter"]
+fn batched_extraction_outpaces_separate_programs() {
+    const CAMERAS: u32 = 4;
+    const ITERATIONS: u32 = 50;
+
+    let limits = wgpu::Limits { max_push_constant_size: 16, ..Default::default() };
+    let compute = pollster::block_on(Compute::new(wgpu::Features::PUSH_CONSTANTS, limits));
+
+    let mut rng = Rng::new(1);
+    let images: Vec<Vec<u8>> = (0..CAMERAS).map(|_| blocks(&mut rng)).collect();
+
+    let batched = program(&compute, CAMERAS);
+    let separate: Vec<OrbProgram> = (0..CAMERAS).map(|_| program(&compute, 1)).collect();
+
+    for (camera, image) in images.iter().enumerate() {
+        batched.write_camera_image(camera as u32, image);
+        separate[camera].write_input_image(image);
+    }
+
+    // Number of features found for each camera
+    let extract_batched = || {
+        let (corners, _) = batched.read_features(batched.extract_corners());
+        (0..CAMERAS).map(|camera| corners.iter().filter(|c| c.camera == camera).count()).collect::<Vec<_>>()
+    };
+
+    let extract_separate = || {
+        separate.iter().map(|program| program.read_features(program.extract_corners()).0.len()).collect::<Vec<_>>()
+    };
+
+    // Both find the same features, so they do the same work. This also
+    // warms up the pipelines before timing.
+    assert_eq!(extract_batched(), extract_separate());
+
+    let time = |extract: &dyn Fn() -> Vec<usize>| {
+        let start = Instant::now();
+        (0..ITERATIONS).for_each(|_| { extract(); });
+        (CAMERAS * ITERATIONS) as f64 / start.elapsed().as_secs_f64()
+    };
+
+    let batched_rate = time(&extract_batched);
+    let separate_rate = time(&extract_separate);
+
+    // One submission and readback instead of one per camera
+    assert!(
+        batched_rate > separate_rate,
+        "{batched_rate:.1} images/s batched, {separate_rate:.1} images/s with separate programs"
+    );
+}
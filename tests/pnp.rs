@@ -0,0 +1,187 @@
+use tinyslam::camera::{CameraModel, Pinhole, RadTan};
+use tinyslam::geometry::{SE3, SO3};
+use tinyslam::linalg::{Mat3, Vec2, Vec3};
+use tinyslam::pnp::{epnp, solve_pnp, PnpConfig};
+use tinyslam::random::Rng;
+
+struct Scene {
+    pose: SE3,
+    points: Vec<Vec3>,
+    pixels: Vec<Vec2>,
+    outlier: Vec<bool>
+}
+
+fn camera() -> RadTan {
+    RadTan::new(Pinhole::new(458.0, 457.0, 367.0, 248.0), [-0.28, 0.07, 0.0002, 0.00002, 0.0])
+}
+
+/// World points seen by a camera with pixel noise of up to `noise`, and a
+/// fraction of correspondences replaced with random pixels
+fn scene(seed: u64, count: usize, outlier_ratio: f64, noise: f64) -> Scene {
+    let mut rng = Rng::new(seed);
+    let camera = camera();
+    let pose = SE3::new(SO3::exp(&Vec3::new(0.1, -0.3, 0.05)), Vec3::new(0.5, -0.2, 1.0));
+    let camera_to_world = pose.inverse();
+
+    let mut points = Vec::new();
+    let mut pixels = Vec::new();
+    let mut outlier = Vec::new();
+
+    while points.len() < count {
+        let pixel = Vec2::new(rng.next_f64() * 700.0 + 20.0, rng.next_f64() * 440.0 + 20.0);
+        let depth = 2.0 + rng.next_f64() * 6.0;
+        let ray = camera.unproject(&pixel);
+        let point = camera_to_world.transform(&(ray * (depth / ray.z())));
+
+        let is_outlier = rng.next_f64() < outlier_ratio;
+        let observed = if is_outlier {
+            Vec2::new(rng.next_f64() * 752.0, rng.next_f64() * 480.0)
+        } else {
+            pixel + Vec2::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5) * (2.0 * noise)
+        };
+
+        points.push(point);
+        pixels.push(observed);
+        outlier.push(is_outlier);
+    }
+
+    Scene { pose, points, pixels, outlier }
+}
+
+fn rotation_error(a: &Mat3, b: &Mat3) -> f64 {
+    (((a.transpose() * *b).trace() - 1.0) / 2.0).clamp(-1.0, 1.0).acos()
+}
+
+#[test]
+fn epnp_is_exact_without_noise() {
+    let scene = scene(1, 6, 0.0, 0.0);
+    let camera = camera();
+    let normalized: Vec<Vec2> = scene.pixels.iter().map(|p| camera.unproject(p).hnormalize()).collect();
+
+    let pose = epnp(&scene.points, &normalized).expect("EPnP failed");
+
+    assert!(rotation_error(&pose.rotation.matrix(), &scene.pose.rotation.matrix()) < 1e-6);
+    assert!((pose.translation - scene.pose.translation).norm() < 1e-6);
+}
+
+#[test]
+fn ransac_rejects_outliers_and_refines() {
+    let scene = scene(2, 300, 0.3, 1.0);
+    let camera = camera();
+    let sigma2 = vec![1.0; scene.points.len()];
+
+    let result = solve_pnp(&camera, &scene.points, &scene.pixels, &sigma2, &PnpConfig::default(), &mut Rng::new(7))
+        .expect("no pose found");
+
+    let rotation_error = rotation_error(&result.pose.rotation.matrix(), &scene.pose.rotation.matrix());
+    let translation_error = (result.pose.translation - scene.pose.translation).norm();
+
+    assert!(rotation_error < 2e-3, "rotation error {rotation_error}");
+    assert!(translation_error < 1e-2, "translation error {translation_error}");
+
+    let missed = (0..scene.points.len()).filter(|&i| !scene.outlier[i] && !result.inliers[i]).count();
+    let accepted = (0..scene.points.len()).filter(|&i| scene.outlier[i] && result.inliers[i]).count();
+
+    assert!(missed <= 3, "{missed} inliers rejected");
+    assert!(accepted <= 3, "{accepted} outliers accepted");
+
+    // The covariance is symmetric positive definite and consistent with the error
+    for i in 0..6 {
+        assert!(result.covariance[(i, i)] > 0.0);
+        for j in 0..6 {
+            assert!((result.covariance[(i, j)] - result.covariance[(j, i)]).abs() < 1e-12);
+        }
+    }
+
+    let rotation_sigma = (0..3).map(|i| result.covariance[(i, i)]).sum::<f64>().sqrt();
+    assert!(rotation_sigma < 1e-2 && rotation_error < 10.0 * rotation_sigma);
+}
+
+#[test]
+fn seeded_results_are_reproducible() {
+    let scene = scene(3, 100, 0.4, 1.0);
+    let camera = camera();
+    let sigma2 = vec![1.0; scene.points.len()];
+    let config = PnpConfig::default();
+
+    let a = solve_pnp(&camera, &scene.points, &scene.pixels, &sigma2, &config, &mut Rng::new(11)).unwrap();
+    let b = solve_pnp(&camera, &scene.points, &scene.pixels, &sigma2, &config, &mut Rng::new(11)).unwrap();
+
+    assert_eq!(a.pose, b.pose);
+    assert_eq!(a.inliers, b.inliers);
+    assert_eq!(a.covariance, b.covariance);
+}
+
+/// Points on the world plane z = 0.5 x + 3 seen by the camera of `scene`,
+/// with the same noise and outlier model
+fn planar_scene(seed: u64, count: usize, outlier_ratio: f64, noise: f64) -> Scene {
+    let mut rng = Rng::new(seed);
+    let camera = camera();
+    let pose = SE3::new(SO3::exp(&Vec3::new(0.1, -0.3, 0.05)), Vec3::new(0.5, -0.2, 1.0));
+
+    let mut points = Vec::new();
+    let mut pixels = Vec::new();
+    let mut outlier = Vec::new();
+
+    while points.len() < count {
+        let (x, y) = (rng.next_f64() * 8.0 - 4.0, rng.next_f64() * 6.0 - 3.0);
+        let point = Vec3::new(x, y, 0.5 * x + 3.0);
+        let camera_point = pose.transform(&point);
+        let pixel = camera.project(&camera_point);
+
+        if camera_point.z() <= 0.0 || !(20.0..720.0).contains(&pixel.x()) || !(20.0..460.0).contains(&pixel.y()) {
+            continue;
+        }
+
+        let is_outlier = rng.next_f64() < outlier_ratio;
+        let observed = if is_outlier {
+            Vec2::new(rng.next_f64() * 752.0, rng.next_f64() * 480.0)
+        } else {
+            pixel + Vec2::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5) * (2.0 * noise)
+        };
+
+        points.push(point);
+        pixels.push(observed);
+        outlier.push(is_outlier);
+    }
+
+    Scene { pose, points, pixels, outlier }
+}
+
+#[test]
+fn epnp_handles_coplanar_points() {
+    let camera = camera();
+
+    for count in [4, 5, 50] {
+        let scene = planar_scene(4, count, 0.0, 0.0);
+        let normalized: Vec<Vec2> = scene.pixels.iter().map(|p| camera.unproject(p).hnormalize()).collect();
+
+        let pose = epnp(&scene.points, &normalized).expect("EPnP failed on a plane");
+
+        assert!(rotation_error(&pose.rotation.matrix(), &scene.pose.rotation.matrix()) < 1e-6, "{count} points");
+        assert!((pose.translation - scene.pose.translation).norm() < 1e-6, "{count} points");
+    }
+
+    // Collinear points leave the rotation about their line unknown
+    let line: Vec<Vec3> = (0..6).map(|i| Vec3::new(i as f64, 0.5 * i as f64, 3.0)).collect();
+    let normalized: Vec<Vec2> = line.iter().map(|p| p.hnormalize()).collect();
+    assert!(epnp(&line, &normalized).is_none());
+}
+
+#[test]
+fn ransac_recovers_the_pose_of_a_plane() {
+    let scene = planar_scene(5, 200, 0.3, 1.0);
+    let sigma2 = vec![1.0; scene.points.len()];
+
+    let result = solve_pnp(&camera(), &scene.points, &scene.pixels, &sigma2, &PnpConfig::default(), &mut Rng::new(7))
+        .expect("no pose found");
+
+    let rotation_error = rotation_error(&result.pose.rotation.matrix(), &scene.pose.rotation.matrix());
+    let translation_error = (result.pose.translation - scene.pose.translation).norm();
+
+    assert!(rotation_error < 5e-3, "rotation error {rotation_error}");
+    assert!(translation_error < 2e-2, "translation error {translation_error}");
+
+    let accepted = (0..scene.points.len()).filter(|&i| scene.outlier[i] && result.inliers[i]).count();
+    assert!(accepted <= 3, "{accepted} outliers accepted");
+}
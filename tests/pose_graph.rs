@@ -0,0 +1,213 @@
+use tinyslam::geometry::{Sim3, SE3, SO3};
+use tinyslam::linalg::{Mat6, Mat7, Vec3, Vec6, Vector};
+use tinyslam::optimizer::Huber;
+use tinyslam::pose_graph::{optimize_pose_graph, PoseGraph};
+use tinyslam::random::Rng;
+
+fn random_vector<const N: usize>(rng: &mut Rng, magnitude: f64) -> Vector<N> {
+    Vector::<N>::from_array(std::array::from_fn(|_| (2.0 * rng.next_f64() - 1.0) * magnitude))
+}
+
+fn se3_distance(a: &SE3, b: &SE3) -> f64 {
+    (a.rotation.matrix() - b.rotation.matrix()).norm() + (a.translation - b.translation).norm()
+}
+
+/// World to camera poses around a circle
+fn circle(count: usize) -> Vec<SE3> {
+    (0..count)
+        .map(|i| {
+            let angle = i as f64 * std::f64::consts::TAU / count as f64;
+            let rotation = SO3::exp(&Vec3::new(0.0, angle, 0.0));
+            let center = Vec3::new(5.0 * angle.cos(), 0.3 * angle.sin(), 5.0 * angle.sin());
+            SE3::new(rotation, center).inverse()
+        })
+        .collect()
+}
+
+/// Graph over `truth` whose vertices start from odometry drifting along
+/// the chain, with exact relative edges between consecutive poses and one
+/// closing the loop
+fn drifting_graph(truth: &[SE3], rng: &mut Rng) -> PoseGraph {
+    let mut graph = PoseGraph::new();
+    let mut drifted = truth[0];
+    graph.add_se3_vertex(drifted, true);
+
+    for i in 1..truth.len() {
+        let relative = truth[i] * truth[i - 1].inverse();
+        drifted = SE3::exp(&random_vector::<6>(rng, 0.02)) * relative * drifted;
+        graph.add_se3_vertex(drifted, false);
+    }
+
+    for i in 1..truth.len() {
+        graph.add_se3_edge(i - 1, i, truth[i - 1] * truth[i].inverse(), Mat6::identity());
+    }
+
+    let last = truth.len() - 1;
+    graph.add_se3_edge(last, 0, truth[last] * truth[0].inverse(), Mat6::identity());
+
+    graph
+}
+
+#[test]
+fn se3_graph_recovers_trajectory() {
+    let mut rng = Rng::new(1);
+    let truth = circle(30);
+    let mut graph = drifting_graph(&truth, &mut rng);
+
+    let summary = optimize_pose_graph(&mut graph, 30);
+    assert!(summary.final_cost < 1e-12 * summary.initial_cost, "{summary:?}");
+
+    for (estimate, truth) in graph.vertices.iter().zip(&truth) {
+        assert_eq!(estimate.scale, 1.0);
+        assert!(se3_distance(&estimate.to_se3(), truth) < 1e-6);
+    }
+}
+
+#[test]
+fn robust_kernel_limits_wrong_loop() {
+    let mut rng = Rng::new(2);
+    let truth = circle(30);
+
+    // A wrong loop between poses far apart on the circle
+    let wrong = SE3::exp(&Vec6::from_array([0.0, 0.0, 0.0, 1.0, 0.0, 0.0]));
+
+    // Odometry much more certain than loops, as the wrong loop can otherwise
+    // be satisfied by bending the whole trajectory
+    let mut plain = drifting_graph(&truth, &mut rng);
+
+    for edge in &mut plain.edges {
+        edge.information = edge.information * 100.0;
+    }
+
+    plain.add_se3_edge(0, 15, wrong, Mat6::identity());
+
+    let mut robust = plain.clone();
+    robust.set_kernel(Some(Huber { delta: 1.0 }));
+
+    optimize_pose_graph(&mut plain, 50);
+    optimize_pose_graph(&mut robust, 50);
+
+    let error = |graph: &PoseGraph| -> f64 {
+        graph.vertices.iter().zip(&truth).map(|(estimate, truth)| se3_distance(&estimate.to_se3(), truth)).sum()
+    };
+
+    assert!(error(&robust) < 0.5 * error(&plain), "{} vs {}", error(&robust), error(&plain));
+}
+
+#[test]
+fn sim3_vertices_absorb_scale_drift() {
+    let truth = circle(20);
+    let mut graph = PoseGraph::new();
+
+    // Rigid odometry where the second half was measured at twice the scale,
+    // so only similarity vertices can satisfy both halves
+    graph.add_se3_vertex(truth[0], true);
+
+    for pose in &truth[1..] {
+        graph.add_vertex(Sim3::from_se3(pose), false);
+    }
+
+    for i in 1..truth.len() {
+        let mut relative = Sim3::from_se3(&(truth[i - 1] * truth[i].inverse()));
+
+        if i >= 10 {
+            relative.scale = 2.0;
+        }
+
+        graph.add_edge(i - 1, i, relative, Mat7::identity());
+    }
+
+    let initial = graph.cost();
+    let summary = optimize_pose_graph(&mut graph, 30);
+
+    assert!(summary.final_cost < 1e-12 * initial, "{summary:?}");
+    assert!((graph.vertices[19].scale / graph.vertices[9].scale - 0.5f64.powi(10)).abs() < 1e-6);
+    assert_eq!(graph.vertices[0], Sim3::from_se3(&truth[0]));
+}
+
+#[test]
+fn g2o_round_trip() {
+    let mut rng = Rng::new(3);
+    let truth = circle(8);
+    let mut graph = drifting_graph(&truth, &mut rng);
+
+    // Correlated information, to check the conversion to g2o's error
+    let mut information = Mat6::identity() * 2.0;
+    information[(0, 4)] = 0.3;
+    information[(4, 0)] = 0.3;
+    information[(2, 2)] = 5.0;
+    graph.add_se3_edge(2, 6, truth[2] * truth[6].inverse(), information);
+
+    let mut text = Vec::new();
+    graph.write_g2o(&mut text).unwrap();
+
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.starts_with("VERTEX_SE3:QUAT 0 "));
+    assert!(text.ends_with("FIX 0\n"));
+
+    let read = PoseGraph::read_g2o(text.as_bytes()).unwrap();
+
+    assert_eq!(read.fixed, graph.fixed);
+    assert!(read.fixed_scale.iter().all(|&fixed| fixed));
+
+    for (a, b) in read.vertices.iter().zip(&graph.vertices) {
+        assert!(se3_distance(&a.to_se3(), &b.to_se3()) < 1e-9);
+    }
+
+    for (a, b) in read.edges.iter().zip(&graph.edges) {
+        assert_eq!((a.a, a.b), (b.a, b.b));
+        assert!(se3_distance(&a.measurement.to_se3(), &b.measurement.to_se3()) < 1e-9);
+        assert!((a.information - b.information).norm() < 1e-9);
+    }
+
+    assert!((read.cost() - graph.cost()).abs() < 1e-9 * graph.cost());
+}
+
+#[test]
+fn g2o_information_matches_g2o_error() {
+    let mut rng = Rng::new(4);
+
+    let pose_a = SE3::exp(&random_vector::<6>(&mut rng, 1.0));
+    let pose_b = SE3::exp(&random_vector::<6>(&mut rng, 1.0));
+    let measurement = pose_a.inverse() * pose_b * SE3::exp(&random_vector::<6>(&mut rng, 1e-4));
+
+    // Camera to world poses and the upper triangle of a diagonal information
+    let [qw, qx, qy, qz] = measurement.rotation.to_quaternion();
+    let [x, y, z] = measurement.translation.to_array();
+    let weights = [1.0, 2.0, 3.0, 40.0, 50.0, 60.0];
+    let upper: Vec<String> = (0..6).flat_map(|i| (i..6).map(move |j| if i == j { weights[i] } else { 0.0 })).map(|v| v.to_string()).collect();
+
+    let vertex = |id: usize, pose: &SE3| {
+        let [qw, qx, qy, qz] = pose.rotation.to_quaternion();
+        let [x, y, z] = pose.translation.to_array();
+        format!("VERTEX_SE3:QUAT {id} {x} {y} {z} {qx} {qy} {qz} {qw}\n")
+    };
+
+    let text = format!(
+        "{}{}EDGE_SE3:QUAT 0 1 {x} {y} {z} {qx} {qy} {qz} {qw} {}\n",
+        vertex(0, &pose_a),
+        vertex(1, &pose_b),
+        upper.join(" ")
+    );
+
+    let graph = PoseGraph::read_g2o(text.as_bytes()).unwrap();
+
+    // g2o's error: translation and quaternion vector part of Z^-1 T_a^-1 T_b
+    let delta = measurement.inverse() * pose_a.inverse() * pose_b;
+    let [_, dx, dy, dz] = delta.rotation.to_quaternion();
+    let error = [delta.translation[0], delta.translation[1], delta.translation[2], dx, dy, dz];
+    let expected: f64 = error.iter().zip(&weights).map(|(e, w)| w * e * e).sum();
+
+    assert!((graph.cost() - expected).abs() < 1e-3 * expected, "{} vs {expected}", graph.cost());
+}
+
+#[test]
+fn g2o_rejects_malformed_input() {
+    assert!(PoseGraph::read_g2o("VERTEX_SE3:QUAT 0 1 2 3".as_bytes()).is_err());
+    assert!(PoseGraph::read_g2o("VERTEX_SE2 0 1 2 3".as_bytes()).is_err());
+    assert!(PoseGraph::read_g2o("FIX 3".as_bytes()).is_err());
+
+    let graph = PoseGraph::read_g2o("# comment\n\nVERTEX_SE3:QUAT 5 0 0 0 0 0 0 1\nFIX 5\n".as_bytes()).unwrap();
+    assert_eq!(graph.vertices, vec![Sim3::identity()]);
+    assert_eq!(graph.fixed, vec![true]);
+}
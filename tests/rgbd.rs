@@ -0,0 +1,146 @@
+use std::sync::Arc;
+
+use tinyslam::camera::Pinhole;
+use tinyslam::frame::{Frame, ScalePyramid};
+use tinyslam::geometry::{SE3, SO3};
+use tinyslam::image::Image;
+use tinyslam::linalg::{Vec2, Vec3};
+use tinyslam::optimizer::{optimize_pose, PoseObservation, PoseOptimizerConfig};
+use tinyslam::orb::{CornerData, CornerDescriptor};
+use tinyslam::rgbd::{set_frame_depths, DepthImage, RgbdConfig};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+
+fn camera() -> Pinhole {
+    Pinhole::new(50.0, 50.0, 32.0, 24.0)
+}
+
+/// Keypoints on a grid of pixels
+fn frame() -> Frame {
+    let corners: Vec<CornerData> = (0..6)
+        .flat_map(|i| (0..4).map(move |j| CornerData { x: 4 + 10 * i, y: 6 + 11 * j, angle: 0, octave: 0, camera: 0 }))
+        .collect();
+    let descriptors = vec![CornerDescriptor { bits: [0; 32] }; corners.len()];
+
+    let size = wgpu::Extent3d { width: WIDTH, height: HEIGHT, depth_or_array_layers: 1 };
+    Frame::new(0, 0.0, Arc::new(camera()), size, ScalePyramid::new(4, 2.0), corners, descriptors)
+}
+
+/// Raw depths that increase along x, in TUM units, with a hole
+fn raw_depths() -> Image<u16> {
+    let mut data: Vec<u16> = (0..HEIGHT).flat_map(|_| (0..WIDTH).map(|x| 5000 + 250 * x as u16)).collect();
+    data[(17 * WIDTH + 14) as usize] = 0;
+
+    Image { width: WIDTH, height: HEIGHT, channels: 1, data }
+}
+
+#[test]
+fn depths_are_sampled_within_the_validity_range() {
+    let depth = DepthImage::from_raw(&raw_depths(), 5000.0);
+
+    assert!((depth.at(&Vec2::new(2.7, 3.2)).unwrap() - 1.1).abs() < 1e-6);
+    assert_eq!(depth.at(&Vec2::new(-0.5, 3.0)), None);
+    assert_eq!(depth.at(&Vec2::new(10.0, HEIGHT as f64)), None);
+
+    let config = RgbdConfig { max_depth: 3.0, ..RgbdConfig::default() };
+    let mut frame = frame();
+    set_frame_depths(&config, &mut frame, &depth);
+
+    for (i, keypoint) in frame.keypoints.iter().enumerate() {
+        let expected = 1.0 + 0.05 * keypoint.x();
+
+        match frame.depth(i) {
+            Some(d) => {
+                assert!((d - expected).abs() < 1e-6);
+
+                // The virtual right coordinate has the disparity of the depth
+                let right = frame.stereo.as_ref().unwrap().right[i].unwrap();
+                assert!((keypoint.x() - right - 50.0 * config.baseline / d).abs() < 1e-9);
+            }
+            None => assert!(expected > 3.0 || (keypoint.x() == 14.0 && keypoint.y() == 17.0), "keypoint {i} has no depth")
+        }
+    }
+
+    assert!(frame.depth(frame.keypoints.iter().position(|p| p.x() == 54.0).unwrap()).is_none());
+    assert!(frame.depth(0).is_some() && frame.is_close(0));
+}
+
+#[test]
+fn tum_depth_maps_load_from_16_bit_images() {
+    let raw = raw_depths();
+    let path = std::env::temp_dir().join(format!("tinyslam_depth_{}.pgm", std::process::id()));
+
+    let mut bytes = format!("P5\n{WIDTH} {HEIGHT}\n65535\n").into_bytes();
+    bytes.extend(raw.data.iter().flat_map(|d| d.to_be_bytes()));
+    std::fs::write(&path, &bytes).unwrap();
+
+    let depth = DepthImage::load_tum(&path, 5000.0).unwrap();
+
+    assert_eq!((depth.width, depth.height), (WIDTH, HEIGHT));
+    assert_eq!(depth.data, DepthImage::from_raw(&raw, 5000.0).data);
+
+    // 8 bit images are not depth maps
+    let mut bytes = format!("P5\n{WIDTH} {HEIGHT}\n255\n").into_bytes();
+    bytes.extend(std::iter::repeat_n(0, (WIDTH * HEIGHT) as usize));
+    std::fs::write(&path, &bytes).unwrap();
+
+    let error = DepthImage::load_tum(&path, 5000.0).err().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn tum_depth_maps_load_from_16_bit_png_images() {
+    // The depths of `raw_depths`, compressed with zlib and cycling through
+    // the row filters
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/depth16.png");
+    let depth = DepthImage::load_tum(&path, 5000.0).unwrap();
+
+    assert_eq!((depth.width, depth.height), (WIDTH, HEIGHT));
+    assert_eq!(depth.data, DepthImage::from_raw(&raw_depths(), 5000.0).data);
+    assert_eq!(depth.at(&Vec2::new(14.5, 17.5)), Some(0.0));
+}
+
+#[test]
+fn virtual_right_coordinates_constrain_the_pose() {
+    let config = RgbdConfig::default();
+    let depth = DepthImage::from_raw(&raw_depths(), config.depth_scale);
+
+    let mut frame = frame();
+    set_frame_depths(&config, &mut frame, &depth);
+
+    let truth = SE3::new(SO3::exp(&Vec3::new(0.02, -0.01, 0.03)), Vec3::new(0.1, -0.05, 0.2));
+    let camera = camera();
+
+    let observations: Vec<PoseObservation> = (0..frame.len())
+        .filter_map(|i| {
+            let d = frame.depth(i)?;
+            let normalized = camera.normalize(&frame.keypoints[i]);
+            let in_camera = Vec3::new(normalized.x(), normalized.y(), 1.0) * d;
+
+            Some(PoseObservation {
+                point: truth.inverse().transform(&in_camera),
+                pixel: frame.keypoints[i],
+                right: frame.stereo.as_ref().unwrap().right[i],
+                octave: 0
+            })
+        })
+        .collect();
+
+    let mut outliers = vec![false; observations.len()];
+    let (pose, inliers) = optimize_pose(
+        &PoseOptimizerConfig::default(),
+        &camera,
+        config.baseline,
+        &frame.scale,
+        &observations,
+        &mut outliers,
+        SE3::identity()
+    );
+
+    assert_eq!(inliers, observations.len());
+    assert!((pose.translation - truth.translation).norm() < 1e-6);
+    assert!((pose.rotation.matrix() - truth.rotation.matrix()).norm() < 1e-6);
+}
@@ -0,0 +1,216 @@
+use tinyslam::matcher::{filter_by_rotation, hamming_distance, Match};
+use tinyslam::orb::{CornerData, OrbConfig, OrbProgram};
+use tinyslam::random::Rng;
+use tiny_wgpu::Compute;
+
+fn range(rng: &mut Rng, min: f32, max: f32) -> f32 {
+    min + (max - min) * rng.next_f64() as f32
+}
+
+fn wrap_angle(angle: f32) -> f32 {
+    (angle + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU) - std::f32::consts::PI
+}
+
+fn corner(x: f32, y: f32, angle: f32) -> CornerData {
+    CornerData {
+        x: x.max(0.0) as u32,
+        y: y.max(0.0) as u32,
+        angle: (wrap_angle(angle) * 1000.0) as i32,
+        octave: 0,
+        camera: 0
+    }
+}
+
+/// Keypoints of a 640x480 image and of the same image rotated by `rotation`
+/// about its center. Orientation noise mimics the intensity centroid jitter.
+fn rotated_pair(rng: &mut Rng, count: usize, rotation: f32) -> (Vec<CornerData>, Vec<CornerData>) {
+    let (cx, cy) = (320.0, 240.0);
+    let (sin, cos) = rotation.sin_cos();
+
+    let mut original = Vec::new();
+    let mut rotated = Vec::new();
+
+    for _ in 0..count {
+        let x = range(rng, 100.0, 540.0);
+        let y = range(rng, 100.0, 380.0);
+        let angle = range(rng, -std::f32::consts::PI, std::f32::consts::PI);
+
+        let (dx, dy) = (x - cx, y - cy);
+        let noise = range(rng, -0.05, 0.05);
+
+        original.push(corner(x, y, angle));
+        rotated.push(corner(
+            cx + cos * dx - sin * dy,
+            cy + sin * dx + cos * dy,
+            angle + rotation + noise
+        ));
+    }
+
+    (original, rotated)
+}
+
+fn run_scene(rotation: f32, seed: u64) {
+    let mut rng = Rng::new(seed);
+    let inlier_count = 200;
+    let outlier_count = 60;
+
+    let (original, mut rotated) = rotated_pair(&mut rng, inlier_count, rotation);
+
+    let mut matches: Vec<Match> = (0..inlier_count as u32)
+        .map(|i| Match { query: i, train: i, distance: 10 })
+        .collect();
+
+    // Wrong correspondences have unrelated orientations
+    for i in 0..outlier_count {
+        let angle = range(&mut rng, -std::f32::consts::PI, std::f32::consts::PI);
+        rotated.push(corner(range(&mut rng, 0.0, 640.0), range(&mut rng, 0.0, 480.0), angle));
+
+        matches.push(Match {
+            query: (inlier_count + i) as u32,
+            train: rng.below(inlier_count) as u32,
+            distance: 40
+        });
+    }
+
+    let filtered = filter_by_rotation(&matches, &rotated, &original);
+
+    let kept_inliers = filtered.iter().filter(|m| (m.query as usize) < inlier_count).count();
+    let kept_outliers = filtered.len() - kept_inliers;
+
+    // Inliers straddling a bin edge may land in a bin below a tenth of the peak
+    assert!(kept_inliers * 10 >= inlier_count * 9, "rotation {rotation}: only {kept_inliers} inliers kept");
+    assert!(kept_outliers * 4 < outlier_count, "rotation {rotation}: {kept_outliers} outliers survived");
+}
+
+#[test]
+fn keeps_consistent_rotations() {
+    for (i, rotation) in [0.0f32, 0.4, 1.3, -2.0, 2.9].into_iter().enumerate() {
+        run_scene(rotation, 17 + i as u64);
+    }
+}
+
+#[test]
+fn handles_rotation_across_wraparound() {
+    // Orientations of the rotated image wrap from +pi to -pi
+    run_scene(std::f32::consts::PI - 0.01, 5);
+    run_scene(-std::f32::consts::PI + 0.01, 6);
+}
+
+#[test]
+fn empty_input_yields_no_matches() {
+    assert!(filter_by_rotation(&[], &[], &[]).is_empty());
+}
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 480;
+
+/// Grey rectangles on a dark background, whose corners FAST detects
+fn synthetic_image(rng: &mut Rng) -> Vec<f32> {
+    let mut image = vec![0.1; (WIDTH * HEIGHT) as usize];
+
+    for _ in 0..120 {
+        let (x0, y0) = (rng.below(WIDTH as usize - 40), rng.below(HEIGHT as usize - 40));
+        let (w, h) = (8 + rng.below(40), 8 + rng.below(40));
+        let value = 0.3 + 0.7 * rng.next_f64() as f32;
+
+        for y in y0..(y0 + h).min(HEIGHT as usize) {
+            for x in x0..(x0 + w).min(WIDTH as usize) {
+                image[y * WIDTH as usize + x] = value;
+            }
+        }
+    }
+
+    image
+}
+
+/// Rotates about the image center with bilinear sampling
+fn rotate_image(image: &[f32], rotation: f32) -> Vec<f32> {
+    let (cx, cy) = (WIDTH as f32 / 2.0, HEIGHT as f32 / 2.0);
+    let (sin, cos) = rotation.sin_cos();
+    let sample = |x: i32, y: i32| {
+        if x < 0 || y < 0 || x >= WIDTH as i32 || y >= HEIGHT as i32 { 0.1 } else { image[(y as u32 * WIDTH + x as u32) as usize] }
+    };
+
+    (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+            let sx = cx + cos * dx + sin * dy;
+            let sy = cy - sin * dx + cos * dy;
+
+            let (x0, y0) = (sx.floor() as i32, sy.floor() as i32);
+            let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+
+            sample(x0, y0) * (1.0 - fx) * (1.0 - fy) + sample(x0 + 1, y0) * fx * (1.0 - fy) +
+                sample(x0, y0 + 1) * (1.0 - fx) * fy + sample(x0 + 1, y0 + 1) * fx * fy
+        })
+        .collect()
+}
+
+fn rgba(image: &[f32]) -> Vec<u8> {
+    image.iter().flat_map(|&v| { let v = (v * 255.0) as u8; [v, v, v, 255] }).collect()
+}
+
+#[test]
+#[ignore = "requires a GPU adapter"]
+fn keeps_matches_of_rotated_synthetic_images() {
+    let limits = wgpu::Limits { max_push_constant_size: 16, ..Default::default() };
+    let compute = pollster::block_on(Compute::new(wgpu::Features::PUSH_CONSTANTS, limits));
+
+    let program = OrbProgram::new(OrbConfig {
+        image_size: wgpu::Extent3d { width: WIDTH, height: HEIGHT, depth_or_array_layers: 1 },
+        cameras: 1,
+        max_features: 2000,
+        hierarchy_depth: 3,
+        initial_threshold: 0.1,
+        max_search_windows: 1,
+        undistortion: Vec::new()
+    }, &compute);
+
+    let mut rng = Rng::new(11);
+    let image = synthetic_image(&mut rng);
+    let rotation = 0.6f32;
+
+    let extract = |image: &[f32]| {
+        program.write_input_image(&rgba(image));
+        let count = program.extract_corners();
+        program.read_features(count)
+    };
+
+    let (corners, descriptors) = extract(&image);
+    let (rotated_corners, rotated_descriptors) = extract(&rotate_image(&image, rotation));
+
+    // Best match of each rotated keypoint, with the ratio test
+    let matches: Vec<Match> = rotated_descriptors.iter()
+        .enumerate()
+        .filter_map(|(query, descriptor)| {
+            let mut distances: Vec<(u32, u32)> = descriptors.iter()
+                .enumerate()
+                .map(|(train, other)| (hamming_distance(descriptor, other), train as u32))
+                .collect();
+            distances.sort_unstable();
+
+            let [(best, train), (second, _), ..] = distances[..] else { return None };
+            (best <= 50 && (best as f32) < 0.8 * second as f32).then_some(Match { query: query as u32, train, distance: best })
+        })
+        .collect();
+
+    let filtered = filter_by_rotation(&matches, &rotated_corners, &corners);
+
+    let (sin, cos) = rotation.sin_cos();
+    let consistent = |m: &Match| {
+        let [x, y] = corners[m.train as usize].scaled_position();
+        let [rx, ry] = rotated_corners[m.query as usize].scaled_position();
+        let (dx, dy) = (x - WIDTH as f32 / 2.0, y - HEIGHT as f32 / 2.0);
+        let predicted = (WIDTH as f32 / 2.0 + cos * dx - sin * dy, HEIGHT as f32 / 2.0 + sin * dx + cos * dy);
+        let tolerance = 3.0 * (1 << corners[m.train as usize].octave) as f32;
+
+        (predicted.0 - rx).abs() < tolerance && (predicted.1 - ry).abs() < tolerance
+    };
+
+    let before = matches.iter().filter(|m| consistent(m)).count() as f32 / matches.len() as f32;
+    let after = filtered.iter().filter(|m| consistent(m)).count() as f32 / filtered.len() as f32;
+
+    assert!(filtered.len() >= 30, "only {} matches kept", filtered.len());
+    assert!(after >= before && after > 0.8, "{before} of matches consistent before filtering, {after} after");
+}
@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use tinyslam::camera::Pinhole;
+use tinyslam::frame::{Frame, ScalePyramid};
+use tinyslam::geometry::SE3;
+use tinyslam::image::Image;
+use tinyslam::map::Map;
+use tinyslam::orb::{CornerData, CornerDescriptor};
+use tinyslam::random::Rng;
+use tinyslam::stereo::{initialize_stereo_map, match_stereo, GrayPyramid, StereoConfig};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+const BASELINE: f64 = 0.1;
+
+/// Smooth texture, so sub-pixel shifts are well defined
+fn texture(x: f64, y: f64) -> f64 {
+    128.0 + 50.0 * (0.31 * x + 0.17 * y).sin() + 40.0 * (0.23 * y - 0.11 * x).cos() + 20.0 * (0.019 * x + 0.41 * y).sin()
+}
+
+fn image(shift: f64) -> Image<u8> {
+    let data = (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| texture(x as f64 + shift, y as f64).round() as u8))
+        .collect();
+
+    Image { width: WIDTH, height: HEIGHT, channels: 1, data }
+}
+
+fn random_descriptor(rng: &mut Rng) -> CornerDescriptor {
+    CornerDescriptor { bits: std::array::from_fn(|_| rng.next_u64() as u8) }
+}
+
+fn frame(corners: Vec<CornerData>, descriptors: Vec<CornerDescriptor>) -> Frame {
+    let camera = Arc::new(Pinhole::new(300.0, 300.0, 160.0, 120.0));
+    let size = wgpu::Extent3d { width: WIDTH, height: HEIGHT, depth_or_array_layers: 1 };
+    Frame::new(0, 0.0, camera, size, ScalePyramid::new(4, 2.0), corners, descriptors)
+}
+
+/// Left keypoints on a grid, the same keypoints `disparity` pixels to the
+/// left in the right image, and right keypoints with unrelated descriptors
+/// on the same rows. The last left keypoint has no match.
+fn stereo_pair(rng: &mut Rng, disparity: f64) -> (Frame, Frame) {
+    let mut left = (Vec::new(), Vec::new());
+    let mut right = (Vec::new(), Vec::new());
+
+    for (k, (x, y)) in (0..5).flat_map(|i| (0..4).map(move |j| (100 + 40 * i, 30 + 50 * j))).enumerate() {
+        let octave = (k % 3 == 0) as u32;
+        let descriptor = random_descriptor(rng);
+
+        left.0.push(CornerData { x: x >> octave, y: y >> octave, angle: 0, octave, camera: 0 });
+        left.1.push(descriptor);
+
+        let right_x = (x as f64 - disparity).round() as u32;
+        right.0.push(CornerData { x: right_x >> octave, y: y >> octave, angle: 0, octave, camera: 0 });
+        right.1.push(descriptor);
+
+        right.0.push(CornerData { x: right_x - 7, y, angle: 0, octave: 0, camera: 0 });
+        right.1.push(random_descriptor(rng));
+    }
+
+    left.0.push(CornerData { x: 200, y: 200, angle: 0, octave: 0, camera: 0 });
+    left.1.push(random_descriptor(rng));
+
+    (frame(left.0, left.1), frame(right.0, right.1))
+}
+
+#[test]
+fn stereo_matches_have_sub_pixel_disparity() {
+    let mut rng = Rng::new(1);
+    let disparity = 12.4;
+
+    let (mut left, right) = stereo_pair(&mut rng, disparity);
+    let right_x = match_stereo(&StereoConfig::default(), &left, &right, &GrayPyramid::new(&image(0.0), 4), &GrayPyramid::new(&image(disparity), 4));
+
+    assert!(right_x.last().unwrap().is_none());
+
+    let matched: Vec<(usize, f64)> = right_x.iter().enumerate().filter_map(|(i, x)| x.map(|x| (i, x))).collect();
+    assert!(matched.len() >= 16, "{} matches", matched.len());
+
+    for &(i, x) in &matched {
+        let error = left.keypoints[i].x() - disparity - x;
+        assert!(error.abs() < 0.25 * (1 << left.octave(i)) as f64, "keypoint {i} off by {error}");
+    }
+
+    left.set_stereo(BASELINE, 40.0 * BASELINE, right_x);
+
+    let (i, _) = matched[0];
+    let depth = left.depth(i).unwrap();
+    assert!((depth - 300.0 * BASELINE / disparity).abs() < 0.1);
+    assert!(left.is_close(i));
+}
+
+#[test]
+fn stereo_frames_unproject_and_count_close_points() {
+    let mut rng = Rng::new(2);
+    let (mut left, _) = stereo_pair(&mut rng, 10.0);
+
+    // Disparities of 10 pixels, except one keypoint at 30 m and one unmatched
+    let mut right_x: Vec<Option<f64>> = left.keypoints.iter().map(|p| Some(p.x() - 10.0)).collect();
+    right_x[1] = Some(left.keypoints[1].x() - 1.0);
+    right_x[2] = None;
+
+    left.set_stereo(BASELINE, 4.0, right_x);
+    left.map_points[0] = Some(7);
+
+    assert_eq!(left.depth(1), Some(30.0));
+    assert!(!left.is_close(1) && !left.is_close(2));
+    assert_eq!(left.close_point_counts(), (1, left.len() - 3));
+
+    // No point without a pose
+    assert!(left.unproject_stereo(0).is_none());
+
+    left.pose = Some(SE3::identity());
+    let point = left.unproject_stereo(0).unwrap();
+    let expected_depth = 300.0 * BASELINE / 10.0;
+
+    assert!((point.z() - expected_depth).abs() < 1e-12);
+    assert!((point.x() - (left.keypoints[0].x() - 160.0) / 300.0 * expected_depth).abs() < 1e-12);
+}
+
+#[test]
+fn stereo_initialisation_creates_points_from_depths() {
+    let mut rng = Rng::new(3);
+    let (mut left, _) = stereo_pair(&mut rng, 10.0);
+
+    let right_x: Vec<Option<f64>> = left.keypoints.iter().enumerate().map(|(i, p)| (i % 2 == 0).then(|| p.x() - 10.0)).collect();
+    left.set_stereo(BASELINE, 4.0, right_x);
+
+    let config = StereoConfig { min_initial_points: 12, ..StereoConfig::default() };
+    let mut map = Map::new();
+    assert!(initialize_stereo_map(&config, &mut map, left.clone()).is_none());
+
+    let config = StereoConfig { min_initial_points: 11, ..StereoConfig::default() };
+    let id = initialize_stereo_map(&config, &mut map, left).unwrap();
+
+    assert_eq!(map.point_count(), 11);
+
+    let keyframe = map.keyframe(id).unwrap();
+
+    for (i, point) in keyframe.frame.map_points.iter().enumerate() {
+        assert_eq!(point.is_some(), i % 2 == 0);
+
+        if let Some(point) = point {
+            let position = map.point(*point).unwrap().position;
+            assert!((position - keyframe.frame.unproject_stereo(i).unwrap()).norm() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+mod common;
+
+use common::{build_map, pose_at, pose_error, Observation, SyntheticMap, World, LEVELS_UP};
+use tinyslam::frame::Frame;
+use tinyslam::geometry::{SE3, SO3};
+use tinyslam::linalg::Vec3;
+use tinyslam::random::Rng;
+use tinyslam::tracking::{search_by_projection, Tracker, TrackerConfig, TrackingState};
+
+/// Two keyframes half a metre apart, and a tracker started from the first
+struct Scene {
+    rng: Rng,
+    world: World,
+    map: SyntheticMap,
+    tracker: Tracker
+}
+
+/// A tracked frame, with the world point each keypoint observes
+struct Tracked {
+    frame: Frame,
+    state: TrackingState,
+    points: Vec<usize>
+}
+
+/// Camera at the origin looking away from the world
+fn facing_away() -> SE3 {
+    SE3::new(SO3::exp(&Vec3::new(0.0, std::f64::consts::PI, 0.0)), Vec3::zeros())
+}
+
+fn config() -> TrackerConfig {
+    // Features are grouped at the level the keyframes of the map used
+    TrackerConfig { levels_up: LEVELS_UP, ..Default::default() }
+}
+
+fn scene(seed: u64) -> Scene {
+    let mut rng = Rng::new(seed);
+    let world = World::new(&mut rng, 400, 4.0);
+    let vocabulary = world.vocabulary(&mut rng);
+    let map = build_map(&world, &vocabulary, &mut rng, &[pose_at(0.0), pose_at(0.5)]);
+
+    let mut tracker = Tracker::new(config(), vocabulary);
+    let first = map.keyframes[0];
+    tracker.initialize(map.map.keyframe(first).unwrap().frame.clone(), first);
+
+    Scene { rng, world, map, tracker }
+}
+
+impl Scene {
+    fn observe(&mut self, id: u64, x: f64) -> Observation {
+        self.world.observe(&mut self.rng, id, &pose_at(x))
+    }
+
+    fn track(&mut self, id: u64, x: f64) -> Tracked {
+        let Observation { frame, points } = self.observe(id, x);
+        let (frame, state) = self.tracker.track(frame, &self.map.map);
+        Tracked { frame, state, points }
+    }
+
+    /// Number of matched keypoints, checking each matched the map point of
+    /// the world point it observes
+    fn correct_matches(&self, tracked: &Tracked) -> usize {
+        let mut matched = 0;
+
+        for (i, point) in tracked.frame.map_points.iter().enumerate() {
+            if let Some(point) = point {
+                assert_eq!(Some(*point), self.map.points[tracked.points[i]], "keypoint {i}");
+                matched += 1;
+            }
+        }
+
+        matched
+    }
+
+    fn assert_tracked(&self, tracked: &Tracked, x: f64) {
+        let id = tracked.frame.id;
+
+        assert_eq!(tracked.state, TrackingState::Ok, "frame {id}");
+        assert!(pose_error(&tracked.frame.pose.unwrap(), &pose_at(x)) < 0.01, "frame {id}");
+        assert!(self.correct_matches(tracked) > 200, "frame {id}");
+        assert_eq!(self.tracker.last_frame().map(|frame| frame.id), Some(id));
+    }
+}
+
+#[test]
+fn fresh_trackers_are_not_initialised() {
+    let mut rng = Rng::new(1);
+    let world = World::new(&mut rng, 100, 4.0);
+    let vocabulary = world.vocabulary(&mut rng);
+    let map = build_map(&world, &vocabulary, &mut rng, &[pose_at(0.0)]);
+
+    let mut tracker = Tracker::new(config(), vocabulary);
+    let (frame, state) = tracker.track(world.observe(&mut rng, 1, &pose_at(0.1)).frame, &map.map);
+
+    assert_eq!(state, TrackingState::NotInitialised);
+    assert!(frame.pose.is_none());
+}
+
+#[test]
+fn first_frame_tracks_against_the_reference_keyframe() {
+    let mut scene = scene(2);
+
+    // Without a velocity the motion model is not tried
+    let tracked = scene.track(10, 0.1);
+    scene.assert_tracked(&tracked, 0.1);
+}
+
+#[test]
+fn motion_model_tracks_without_a_reference_keyframe() {
+    let mut scene = scene(3);
+    assert_eq!(scene.track(10, 0.1).state, TrackingState::Ok);
+
+    // Reference keyframe tracking would fail, so only the motion model can
+    // track the following frames
+    scene.tracker.set_reference_keyframe(999);
+
+    for (id, x) in [(11, 0.2), (12, 0.3), (13, 0.4)] {
+        let tracked = scene.track(id, x);
+        scene.assert_tracked(&tracked, x);
+    }
+}
+
+#[test]
+fn abrupt_motion_falls_back_to_the_reference_keyframe() {
+    // The frame is 80 centimetres behind the constant velocity prediction,
+    // so its points are at least 36 pixels from where the motion model
+    // searches, beyond even the doubled radius
+    let abrupt = |reference: Option<u64>| {
+        let mut scene = scene(4);
+        assert_eq!(scene.track(10, 0.1).state, TrackingState::Ok);
+        assert_eq!(scene.track(11, 0.2).state, TrackingState::Ok);
+
+        if let Some(reference) = reference {
+            scene.tracker.set_reference_keyframe(reference);
+        }
+
+        let tracked = scene.track(12, -0.5);
+        (scene, tracked)
+    };
+
+    let (scene, tracked) = abrupt(None);
+    scene.assert_tracked(&tracked, -0.5);
+
+    // Only the fallback could track it
+    let (scene, tracked) = abrupt(Some(999));
+    assert_eq!(tracked.state, TrackingState::Lost);
+    assert!(tracked.frame.pose.is_none());
+    assert!(tracked.frame.map_points.iter().all(Option::is_none));
+    assert_eq!(scene.tracker.last_frame().map(|frame| frame.id), Some(11));
+}
+
+#[test]
+fn search_by_projection_matches_the_points_of_the_last_frame() {
+    let mut scene = scene(5);
+    let last = scene.track(10, 0.1);
+    let Observation { mut frame, points } = scene.observe(11, 0.2);
+
+    // Predicted five centimetres off, at most six pixels at these depths
+    frame.pose = Some(pose_at(0.25));
+
+    let matches = search_by_projection(&config(), &frame, &last.frame, &scene.map.map, 15.0);
+
+    let shared = last.frame.map_points.iter()
+        .zip(&last.points)
+        .filter(|(point, world_point)| point.is_some() && points.contains(world_point))
+        .count();
+
+    assert!(matches.len() > shared * 9 / 10, "{} of {shared} points matched", matches.len());
+
+    for m in &matches {
+        assert_eq!(points[m.train as usize], last.points[m.query as usize]);
+    }
+
+    // Each keypoint is matched at most once
+    let mut trains: Vec<u32> = matches.iter().map(|m| m.train).collect();
+    trains.sort_unstable();
+    trains.dedup();
+    assert_eq!(trains.len(), matches.len());
+
+    // Nothing projects into a camera facing away
+    frame.pose = Some(facing_away());
+    assert!(search_by_projection(&config(), &frame, &last.frame, &scene.map.map, 15.0).is_empty());
+}
+
+#[test]
+fn lost_frames_are_relocalised_against_keyframe_database_candidates() {
+    let mut scene = scene(6);
+    assert_eq!(scene.track(10, 0.1).state, TrackingState::Ok);
+
+    // A camera facing away sees none of the map
+    let away = facing_away();
+    let frame = scene.world.observe(&mut scene.rng, 11, &away).frame;
+    let (frame, state) = scene.tracker.track(frame, &scene.map.map);
+
+    assert_eq!(state, TrackingState::Lost);
+    assert!(frame.pose.is_none());
+
+    // Still lost while relocalisation finds no candidate
+    let frame = scene.world.observe(&mut scene.rng, 12, &away).frame;
+    assert_eq!(scene.tracker.track(frame, &scene.map.map).1, TrackingState::Lost);
+    assert_eq!(scene.tracker.last_frame().map(|frame| frame.id), Some(10));
+
+    // Once lost, frames are localised against the keyframes the database
+    // proposes rather than tracked from the last frame
+    let relocalised = scene.track(13, 0.45);
+    assert_eq!(relocalised.state, TrackingState::Relocalised);
+    assert!(pose_error(&relocalised.frame.pose.unwrap(), &pose_at(0.45)) < 0.01);
+    assert!(scene.correct_matches(&relocalised) >= 50);
+    assert_eq!(scene.tracker.last_frame().map(|frame| frame.id), Some(13));
+
+    // The keyframe it was localised against becomes the reference
+    let reference = scene.tracker.reference_keyframe().unwrap();
+    assert!(scene.map.keyframes.contains(&reference));
+
+    // Tracking resumes normally from the next frame
+    let tracked = scene.track(14, 0.5);
+    scene.assert_tracked(&tracked, 0.5);
+}
@@ -0,0 +1,133 @@
+use tinyslam::orb::CornerDescriptor;
+use tinyslam::random::Rng;
+use tinyslam::vocabulary::{ScoringType, Vocabulary, WeightingType};
+
+fn descriptor(byte: u8) -> CornerDescriptor {
+    CornerDescriptor { bits: [byte; 32] }
+}
+
+fn node(parent: u32, leaf: bool, byte: u8, weight: &str) -> String {
+    format!("{} {} {} {}\n", parent, leaf as u32, vec![byte.to_string(); 32].join(" "), weight)
+}
+
+/// Two levels with two branches each: nodes 1 and 2 under the root, words 0
+/// and 1 under node 1, and words 2 and 3 under node 2. Word 3 has no weight.
+fn small_vocabulary_text() -> String {
+    let mut text = String::from("2 2 0 0\n");
+    text += &node(0, false, 0x00, "0");
+    text += &node(0, false, 0xff, "0");
+    text += &node(1, true, 0x00, "0.5");
+    text += &node(1, true, 0x01, "1");
+    text += &node(2, true, 0xff, "2");
+    text += &node(2, true, 0xfe, "0");
+    text
+}
+
+#[test]
+fn text_format_round_trips() {
+    let text = small_vocabulary_text();
+    let vocabulary = Vocabulary::read_text(text.as_bytes()).unwrap();
+
+    assert_eq!((vocabulary.branching_factor(), vocabulary.depth(), vocabulary.word_count()), (2, 2, 4));
+    assert_eq!([0x00, 0x01, 0xff, 0xfe].map(|b| vocabulary.word(&descriptor(b))), [0, 1, 2, 3]);
+
+    let mut written = Vec::new();
+    vocabulary.write_text(&mut written).unwrap();
+    assert_eq!(String::from_utf8(written).unwrap(), text);
+
+    // Nodes must follow their parents, and the header must be complete
+    let orphan = format!("2 2 0 0\n{}", node(3, true, 0, "1"));
+    assert!(Vocabulary::read_text(orphan.as_bytes()).is_err());
+    assert!(Vocabulary::read_text("2 2 0\n".as_bytes()).is_err());
+    assert!(Vocabulary::read_text("2 2 3 0\n".as_bytes()).is_err());
+
+    // Every descent must end at a word
+    let invalid_data = |text: &str| Vocabulary::read_text(text.as_bytes()).err().unwrap().kind() == std::io::ErrorKind::InvalidData;
+    assert!(invalid_data("2 2 0 0\n"));
+
+    let truncated: String = text.lines().take(4).map(|line| format!("{line}\n")).collect();
+    assert!(invalid_data(&truncated));
+}
+
+#[test]
+fn transform_weights_words_and_indexes_features() {
+    let vocabulary = Vocabulary::read_text(small_vocabulary_text().as_bytes()).unwrap();
+    let descriptors = [0x00, 0x00, 0x01, 0xff, 0xfe].map(descriptor);
+
+    let (bow, features) = vocabulary.transform(&descriptors, 1);
+
+    // Term frequencies are summed, then L1 normalised; zero weight words are dropped
+    let words: Vec<_> = bow.0.into_iter().collect();
+    assert_eq!(words, vec![(0, 0.25), (1, 0.25), (2, 0.5)]);
+
+    let nodes: Vec<_> = features.0.into_iter().collect();
+    assert_eq!(nodes, vec![(1, vec![0, 1, 2]), (2, vec![3])]);
+}
+
+#[test]
+fn scores_follow_the_scoring_type() {
+    let text = small_vocabulary_text();
+    let l1 = Vocabulary::read_text(text.as_bytes()).unwrap();
+    let l2 = Vocabulary::read_text(text.replacen("2 2 0 0", "2 2 1 0", 1).as_bytes()).unwrap();
+
+    let a = [0x00, 0x01, 0xff, 0xff].map(descriptor);
+    let b = [0xff].map(descriptor);
+    let c = [0x00].map(descriptor);
+
+    for vocabulary in [&l1, &l2] {
+        let (va, vb, vc) = (vocabulary.transform(&a, 0).0, vocabulary.transform(&b, 0).0, vocabulary.transform(&c, 0).0);
+
+        assert!((vocabulary.score(&va, &va) - 1.0).abs() < 1e-6);
+        assert_eq!(vocabulary.score(&vb, &vc), 0.0);
+        assert!(vocabulary.score(&va, &vb) > vocabulary.score(&va, &vc));
+    }
+
+    // Weights 0.5, 1 and 4 normalise to 0.5 / 5.5 and so on
+    let (va, vb) = (l1.transform(&a, 0).0, l1.transform(&b, 0).0);
+    assert!((l1.score(&va, &vb) - 4.0 / 5.5).abs() < 1e-12);
+}
+
+#[test]
+fn trained_vocabulary_separates_places() {
+    let mut rng = Rng::new(3);
+
+    let places: Vec<CornerDescriptor> = (0..3)
+        .map(|_| CornerDescriptor { bits: std::array::from_fn(|_| rng.below(256) as u8) })
+        .collect();
+
+    // A few bit flips away from the features of a place
+    let image = |place: usize, rng: &mut Rng| -> Vec<CornerDescriptor> {
+        (0..40)
+            .map(|_| {
+                let mut d = places[place];
+                for _ in 0..8 {
+                    let bit = rng.below(256);
+                    d.bits[bit / 8] ^= 1 << (bit % 8);
+                }
+                d
+            })
+            .collect()
+    };
+
+    let training: Vec<_> = (0..6).map(|i| image(i % 3, &mut rng)).collect();
+
+    let mut vocabulary = Vocabulary::new(3, 2, WeightingType::Tf, ScoringType::L1Norm);
+    vocabulary.create(&training, &mut rng);
+
+    assert!(vocabulary.word_count() > 0 && vocabulary.word_count() <= 9);
+
+    let mut text = Vec::new();
+    vocabulary.write_text(&mut text).unwrap();
+    let loaded = Vocabulary::read_text(text.as_slice()).unwrap();
+
+    for place in 0..3 {
+        let query = image(place, &mut rng);
+        let (bow, _) = loaded.transform(&query, 1);
+
+        let scores: Vec<f64> = training.iter().map(|t| loaded.score(&bow, &loaded.transform(t, 1).0)).collect();
+        let best = (0..scores.len()).max_by(|&a, &b| scores[a].total_cmp(&scores[b])).unwrap();
+
+        assert_eq!(best % 3, place, "{scores:?}");
+        assert_eq!(vocabulary.transform(&query, 1).0.0, bow.0);
+    }
+}